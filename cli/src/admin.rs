@@ -1,58 +1,206 @@
 use anyhow::{Context, Result};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use mux_core::admin::{HelloParams, HelloResult, Method, Request, Response, PROTOCOL_VERSION};
+use serde::de::DeserializeOwned;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::{TcpStream, UnixStream};
+use tokio_rustls::rustls::{
+    self, Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName,
+};
+use tokio_rustls::TlsConnector;
 
-pub async fn send_to_unix_socket(
-    socket_path: &str,
-    command: &str,
-    content: &str,
-) -> Result<String> {
-    let mut stream = UnixStream::connect(socket_path).await.context(format!(
-        "Failed to connect to Unix socket at {}",
-        socket_path
-    ))?;
-
-    // Send the command
-    let mut message = command.to_string();
-    message.push('\n');
-
-    if command == "apply" {
-        // Add the configuration content
-        message.push_str(content);
-        message.push('\n');
-    }
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
 
-    stream.write_all(message.as_bytes()).await?;
-    stream.flush().await?;
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
 
-    // Read the response
-    let mut response = String::new();
-    stream.read_to_string(&mut response).await?;
+/// How to prove who we are to the daemon's TCP admin server
+#[derive(Debug, Default)]
+pub struct Credentials {
+    pub token: Option<String>,
+    pub tls: Option<Tls>,
+}
 
-    Ok(response)
+/// TLS settings; without a CA certificate the system's roots are trusted
+#[derive(Debug, Default)]
+pub struct Tls {
+    pub ca_cert: Option<PathBuf>,
+    /// Client certificate and key, for daemons that use mTLS
+    pub client_cert: Option<(PathBuf, PathBuf)>,
 }
 
-pub async fn send_to_tcp(host: &str, port: u16, command: &str, content: &str) -> Result<String> {
-    let mut stream = TcpStream::connect(format!("{}:{}", host, port))
-        .await
-        .context(format!("Failed to connect to {}:{}", host, port))?;
+/// A connection to the daemon's admin server, past the protocol handshake
+pub struct Client {
+    stream: BufReader<Box<dyn Stream>>,
+    next_id: u64,
+    // Set once the connection fails, after which every call fails straight
+    // away; `top` checks it through `is_closed` to stop refreshing
+    closed: bool,
+}
 
-    // Send the command
-    let mut message = command.to_string();
-    message.push('\n');
+impl Client {
+    /// Connect over the Unix socket if one is given, TCP otherwise
+    pub async fn connect(
+        socket: Option<&str>,
+        host: &str,
+        port: u16,
+        credentials: &Credentials,
+    ) -> Result<Self> {
+        let stream: Box<dyn Stream> = match socket {
+            Some(socket_path) => Box::new(UnixStream::connect(socket_path).await.context(
+                format!("Failed to connect to Unix socket at {}", socket_path),
+            )?),
+            None => {
+                let stream = TcpStream::connect(format!("{}:{}", host, port))
+                    .await
+                    .context(format!("Failed to connect to {}:{}", host, port))?;
+                match &credentials.tls {
+                    Some(tls) => {
+                        let name = ServerName::try_from(host)
+                            .context(format!("Invalid server name {}", host))?;
+                        Box::new(
+                            TlsConnector::from(Arc::new(tls.client_config()?))
+                                .connect(name, stream)
+                                .await
+                                .context(format!("TLS handshake with {}:{} failed", host, port))?,
+                        )
+                    }
+                    None => Box::new(stream),
+                }
+            }
+        };
+
+        let mut client = Self {
+            stream: BufReader::new(stream),
+            next_id: 1,
+            closed: false,
+        };
+        let hello: HelloResult = client
+            .call(Method::Hello(HelloParams {
+                protocol: PROTOCOL_VERSION,
+                client: Some(format!("sonos-mux {}", mux_core::version())),
+                token: credentials.token.clone(),
+            }))
+            .await
+            .context("Protocol handshake failed")?;
+        if hello.protocol != PROTOCOL_VERSION {
+            anyhow::bail!(
+                "{} speaks protocol version {}, expected {}",
+                hello.server,
+                hello.protocol,
+                PROTOCOL_VERSION
+            );
+        }
+        Ok(client)
+    }
 
-    if command == "apply" {
-        // Add the configuration content
-        message.push_str(content);
-        message.push('\n');
+    /// Whether the connection has failed, rather than just a request
+    pub fn is_closed(&self) -> bool {
+        self.closed
     }
 
-    stream.write_all(message.as_bytes()).await?;
-    stream.flush().await?;
+    /// Send one request and wait for its result
+    pub async fn call<T: DeserializeOwned>(&mut self, method: Method) -> Result<T> {
+        if self.closed {
+            anyhow::bail!("The connection to the daemon is closed");
+        }
+        let id = self.next_id;
+        self.next_id += 1;
 
-    // Read the response
-    let mut response = String::new();
-    stream.read_to_string(&mut response).await?;
+        let mut line = serde_json::to_string(&Request::new(id, &method))?;
+        line.push('\n');
+        let line = match self.exchange(&line).await {
+            Ok(Some(line)) => line,
+            Ok(None) => {
+                self.closed = true;
+                anyhow::bail!("The daemon closed the connection");
+            }
+            Err(e) => {
+                self.closed = true;
+                return Err(e.into());
+            }
+        };
+        let response: Response =
+            serde_json::from_str(&line).context("Invalid response from daemon")?;
+        if response.id != id {
+            anyhow::bail!("Response to request {} instead of {}", response.id, id);
+        }
+        Ok(response.into_result()?)
+    }
+}
+
+impl Client {
+    // Send a request line and read the response line, if the daemon sends one
+    async fn exchange(&mut self, request: &str) -> std::io::Result<Option<String>> {
+        let stream = self.stream.get_mut();
+        stream.write_all(request.as_bytes()).await?;
+        stream.flush().await?;
 
-    Ok(response)
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line))
+    }
+}
+
+impl Tls {
+    fn client_config(&self) -> Result<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        match &self.ca_cert {
+            Some(path) => {
+                for certificate in read_certificates(path)? {
+                    roots.add(&certificate)?;
+                }
+            }
+            None => {
+                for certificate in rustls_native_certs::load_native_certs()
+                    .context("Failed to load the system's CA certificates")?
+                {
+                    // Skip the odd certificate rustls can't parse
+                    let _ = roots.add(&Certificate(certificate.0));
+                }
+            }
+        }
+
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        Ok(match &self.client_cert {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(read_certificates(cert)?, read_key(key)?)
+                .map_err(|e: rustls::Error| anyhow::anyhow!("{}: {}", cert.display(), e))?,
+            None => builder.with_no_client_auth(),
+        })
+    }
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = std::io::BufReader::new(
+        File::open(path).context(format!("Failed to open {}", path.display()))?,
+    );
+    let certificates: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certificates.is_empty() {
+        anyhow::bail!("{}: no certificates", path.display());
+    }
+    Ok(certificates)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader = std::io::BufReader::new(
+        File::open(path).context(format!("Failed to open {}", path.display()))?,
+    );
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => continue,
+        }
+    }
+    anyhow::bail!("{}: no private key", path.display())
 }
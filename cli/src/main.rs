@@ -1,14 +1,22 @@
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use mux_core::Config;
+use clap::{Args, Parser, Subcommand};
+use mux_core::admin::{ConfigParams, InputParams, LoadedConfig, Method};
+use mux_core::config::{Plan, CURRENT_VERSION};
+use mux_core::{Config, InputStatus, OutputLevels, OutputStatus, RouteStatus};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::fs;
-use std::io::{self, Read};
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use thiserror::Error;
+use std::time::Duration;
+use toml_edit::DocumentMut;
 
 mod admin;
+mod plan;
 mod scanner;
+mod status;
+mod top;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -23,6 +31,29 @@ enum Commands {
     Validate {
         /// Path to the configuration file
         config_file: PathBuf,
+
+        /// Output format (text or json)
+        #[arg(short, long, default_value = "text")]
+        format: String,
+    },
+
+    /// Print the JSON Schema for configuration files
+    Schema {
+        /// Write the schema to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Upgrade configuration files to the current schema version in place,
+    /// keeping comments and formatting
+    Migrate {
+        /// Configuration files to migrate
+        #[arg(required = true)]
+        config_files: Vec<PathBuf>,
+
+        /// Print the migrated files instead of rewriting them
+        #[arg(long)]
+        dry_run: bool,
     },
 
     /// Display version information
@@ -40,27 +71,128 @@ enum Commands {
         /// Path to the configuration file (use "-" for stdin)
         config_file: String,
 
-        /// Unix socket path
-        #[arg(short, long)]
-        socket: Option<String>,
+        #[command(flatten)]
+        daemon: Daemon,
 
-        /// TCP port for admin commands
-        #[arg(short, long, default_value = "8383")]
-        port: u16,
+        /// Show what applying would change on the daemon without applying it
+        #[arg(long)]
+        dry_run: bool,
 
-        /// Host for TCP connection
-        #[arg(short, long, default_value = "127.0.0.1")]
-        host: String,
+        /// Output format for the change plan (text or json)
+        #[arg(short, long, default_value = "text")]
+        format: String,
     },
+
+    /// Show what a running daemon is doing
+    Status {
+        #[command(subcommand)]
+        what: StatusCommand,
+
+        #[command(flatten)]
+        daemon: Daemon,
+
+        /// Output format (table or json)
+        #[arg(short, long, default_value = "table", global = true)]
+        format: String,
+    },
+
+    /// Watch levels, ducking and room health live, muting routes and
+    /// nudging their gain from the keyboard; prints plain tables when not
+    /// on a terminal
+    Top {
+        #[command(flatten)]
+        daemon: Daemon,
+
+        /// How often to refresh, in milliseconds
+        #[arg(long, default_value = "500")]
+        interval_ms: u64,
+
+        /// Stop after this many refreshes
+        #[arg(short = 'n', long)]
+        iterations: Option<u64>,
+    },
+
+    /// Play an input from the start on a running daemon, e.g. an on-demand
+    /// chime
+    Play {
+        /// ID of the input
+        input: String,
+
+        #[command(flatten)]
+        daemon: Daemon,
+    },
+}
+
+#[derive(Subcommand)]
+enum StatusCommand {
+    /// Inputs, whether they are running, their buffer depths and levels
+    Inputs,
+    /// Outputs, the inputs mixed into each and their health
+    Outputs,
+    /// Gain, priority and current ducking of every input on every output
+    Routes,
+    /// Levels of every output's mix and of the inputs in it
+    Levels,
+    /// The configuration the daemon has loaded, with secrets redacted
+    Config,
+}
+
+/// Where to reach the daemon's admin server
+#[derive(Args)]
+struct Daemon {
+    /// Unix socket path
+    #[arg(short, long, global = true)]
+    socket: Option<String>,
+
+    /// TCP port for admin commands
+    #[arg(short, long, default_value = "8383", global = true)]
+    port: u16,
+
+    /// Host for TCP connection
+    #[arg(long, default_value = "127.0.0.1", global = true)]
+    host: String,
+
+    /// Token for a daemon that requires one over TCP
+    #[arg(long, env = "SONOS_MUX_TOKEN", hide_env_values = true, global = true)]
+    token: Option<String>,
+
+    /// Connect over TLS
+    #[arg(long, global = true)]
+    tls: bool,
+
+    /// CA certificate to verify the daemon with, in PEM; implies --tls
+    #[arg(long, global = true)]
+    ca_cert: Option<PathBuf>,
+
+    /// Client certificate for a daemon that uses mTLS, in PEM; implies --tls
+    #[arg(long, requires = "client_key", global = true)]
+    client_cert: Option<PathBuf>,
+
+    /// Private key for --client-cert, in PEM
+    #[arg(long, requires = "client_cert", global = true)]
+    client_key: Option<PathBuf>,
 }
 
-#[derive(Error, Debug)]
-enum CliError {
-    #[error("I/O error: {0}")]
-    Io(#[from] io::Error),
+impl Daemon {
+    // Where the daemon is, for display
+    fn target(&self) -> String {
+        match &self.socket {
+            Some(socket) => socket.clone(),
+            None => format!("{}:{}", self.host, self.port),
+        }
+    }
 
-    #[error("Config error: {0}")]
-    Config(#[from] mux_core::ConfigError),
+    async fn connect(&self) -> Result<admin::Client> {
+        let tls = self.tls || self.ca_cert.is_some() || self.client_cert.is_some();
+        let credentials = admin::Credentials {
+            token: self.token.clone(),
+            tls: tls.then(|| admin::Tls {
+                ca_cert: self.ca_cert.clone(),
+                client_cert: self.client_cert.clone().zip(self.client_key.clone()),
+            }),
+        };
+        admin::Client::connect(self.socket.as_deref(), &self.host, self.port, &credentials).await
+    }
 }
 
 #[tokio::main]
@@ -68,18 +200,71 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match &cli.command {
-        Commands::Validate { config_file } => {
-            println!("Validating configuration file: {}", config_file.display());
-
-            match Config::load(config_file) {
-                Ok(_) => {
-                    println!("Configuration is valid!");
-                    exit(0);
-                }
+        Commands::Validate {
+            config_file,
+            format,
+        } => {
+            let source = match fs::read_to_string(config_file) {
+                Ok(source) => source,
                 Err(err) => {
-                    eprintln!("Error: {}", err);
+                    eprintln!("Error: {}: {}", config_file.display(), err);
                     exit(1);
                 }
+            };
+            let report = Config::check_at(&source, config_file);
+
+            match format.to_lowercase().as_str() {
+                "json" => {
+                    let output = serde_json::json!({
+                        "file": config_file,
+                        "valid": !report.has_errors(),
+                        "diagnostics": report.diagnostics,
+                    });
+                    println!("{}", serde_json::to_string_pretty(&output)?);
+                }
+                _ => {
+                    eprint!("{}", report.render(&config_file.display().to_string()));
+                    let errors = report.errors().count();
+                    let warnings = report.warnings().count();
+                    if errors > 0 {
+                        eprintln!(
+                            "Configuration is invalid: {} error(s), {} warning(s)",
+                            errors, warnings
+                        );
+                    } else if warnings > 0 {
+                        println!("Configuration is valid with {} warning(s)", warnings);
+                    } else {
+                        println!("Configuration is valid!");
+                    }
+                }
+            }
+
+            exit(if report.has_errors() { 1 } else { 0 });
+        }
+        Commands::Schema { output } => {
+            let schema = mux_core::config::schema_json();
+            match output {
+                Some(path) => {
+                    fs::write(path, schema + "\n")
+                        .context(format!("Failed to write schema to {}", path.display()))?;
+                    println!("Schema written to {}", path.display());
+                }
+                None => println!("{}", schema),
+            }
+        }
+        Commands::Migrate {
+            config_files,
+            dry_run,
+        } => {
+            let mut failed = false;
+            for path in config_files {
+                if let Err(err) = migrate_file(path, *dry_run) {
+                    eprintln!("Error: {}: {:#}", path.display(), err);
+                    failed = true;
+                }
+            }
+            if failed {
+                exit(1);
             }
         }
         Commands::Version => {
@@ -112,9 +297,9 @@ async fn main() -> Result<()> {
         },
         Commands::Apply {
             config_file,
-            socket,
-            port,
-            host,
+            daemon,
+            dry_run,
+            format,
         } => {
             // Read the configuration
             let config_content = if config_file == "-" {
@@ -125,25 +310,38 @@ async fn main() -> Result<()> {
                 fs::read_to_string(config_file)?
             };
 
-            // Validate the configuration
-            match Config::from_reader(config_content.as_bytes()) {
+            // Validate the configuration, resolving includes next to the file
+            let validated = if config_file == "-" {
+                Config::from_reader(config_content.as_bytes())
+            } else {
+                Config::check_at(&config_content, config_file).into_result()
+            };
+            match validated {
                 Ok(_) => {
                     // Configuration is valid, now send it to the daemon
-                    let result = if let Some(socket_path) = socket {
-                        admin::send_to_unix_socket(socket_path, "apply", &config_content).await
+                    let params = ConfigParams {
+                        config: config_content,
+                    };
+                    let method = if *dry_run {
+                        Method::Diff(params)
                     } else {
-                        admin::send_to_tcp(host, *port, "apply", &config_content).await
+                        Method::Apply(params)
                     };
+                    let result = async {
+                        let mut client = daemon.connect().await?;
+                        client.call::<Plan>(method).await
+                    }
+                    .await;
 
                     match result {
-                        Ok(response) => {
-                            println!("{}", response);
-                            if !response.contains("\"success\":true") {
-                                exit(1);
+                        Ok(plan) => {
+                            print_plan(&plan, format)?;
+                            if !*dry_run {
+                                println!("Configuration applied");
                             }
                         }
                         Err(err) => {
-                            eprintln!("Error sending configuration to daemon: {}", err);
+                            eprintln!("Error: {:#}", err);
                             exit(1);
                         }
                     }
@@ -154,7 +352,166 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        Commands::Status {
+            what,
+            daemon,
+            format,
+        } => {
+            let json = format.eq_ignore_ascii_case("json");
+            let result = match what {
+                StatusCommand::Inputs => {
+                    show::<Vec<InputStatus>>(daemon, Method::Inputs, json, |v| status::inputs(v))
+                        .await
+                }
+                StatusCommand::Outputs => {
+                    show::<Vec<OutputStatus>>(daemon, Method::Outputs, json, |v| status::outputs(v))
+                        .await
+                }
+                StatusCommand::Routes => {
+                    show::<Vec<RouteStatus>>(daemon, Method::Routes, json, |v| status::routes(v))
+                        .await
+                }
+                StatusCommand::Levels => {
+                    show::<Vec<OutputLevels>>(daemon, Method::Levels, json, |v| status::levels(v))
+                        .await
+                }
+                StatusCommand::Config => {
+                    show::<LoadedConfig>(daemon, Method::Config, json, status::config).await
+                }
+            };
+            if let Err(err) = result {
+                eprintln!("Error: {:#}", err);
+                exit(1);
+            }
+        }
+        Commands::Top {
+            daemon,
+            interval_ms,
+            iterations,
+        } => {
+            let result = async {
+                let client = daemon.connect().await?;
+                let interval = Duration::from_millis(*interval_ms);
+                top::run(client, daemon.target(), interval, *iterations).await
+            }
+            .await;
+            if let Err(err) = result {
+                eprintln!("Error: {:#}", err);
+                exit(1);
+            }
+        }
+        Commands::Play { input, daemon } => {
+            let result = async {
+                let mut client = daemon.connect().await?;
+                client
+                    .call::<InputStatus>(Method::Play(InputParams {
+                        input: input.clone(),
+                    }))
+                    .await
+            }
+            .await;
+            match result {
+                Ok(status) => println!("Playing {} on {}", status.id, status.outputs.join(", ")),
+                Err(err) => {
+                    eprintln!("Error: {:#}", err);
+                    exit(1);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Print the change plan the daemon returned for `apply`
+fn print_plan(plan: &Plan, format: &str) -> Result<()> {
+    match format.to_lowercase().as_str() {
+        "json" => println!("{}", serde_json::to_string_pretty(plan)?),
+        _ => {
+            let color = io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            print!("{}", plan::render(plan, color));
+        }
     }
+    Ok(())
+}
 
+// Ask the daemon for one kind of status and print it as a table or JSON
+async fn show<T>(
+    daemon: &Daemon,
+    method: Method,
+    json: bool,
+    render: impl Fn(&T) -> String,
+) -> Result<()>
+where
+    T: DeserializeOwned + Serialize,
+{
+    let mut client = daemon.connect().await?;
+    let value: T = client.call(method).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    } else {
+        print!("{}", render(&value));
+    }
     Ok(())
 }
+
+// Migrate one file, rewriting it atomically unless `dry_run` is set
+fn migrate_file(path: &Path, dry_run: bool) -> Result<()> {
+    let source = fs::read_to_string(path).context("Failed to read file")?;
+    let mut document: DocumentMut = source.parse().context("Failed to parse TOML")?;
+    let migrated = mux_core::config::migrate(document.as_table_mut())?;
+
+    if dry_run {
+        print!("{}", document);
+        return Ok(());
+    }
+    if !migrated.is_outdated() {
+        println!(
+            "{} is already at version {}",
+            path.display(),
+            CURRENT_VERSION
+        );
+        return Ok(());
+    }
+
+    // Write next to the original and rename over it, keeping its permissions
+    let file_name = path.file_name().context("Not a file")?.to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.migrate", file_name));
+    fs::write(&temp, document.to_string()).context("Failed to write migrated file")?;
+    fs::set_permissions(&temp, fs::metadata(path)?.permissions())?;
+    fs::rename(&temp, path).context("Failed to replace file")?;
+
+    println!(
+        "Migrated {} from version {} to {}:",
+        path.display(),
+        migrated.from,
+        CURRENT_VERSION
+    );
+    for change in &migrated.changes {
+        println!("  - {}", change);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_arguments() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["cli", "status", "levels", "--format", "json", "-p", "9"]);
+        let Commands::Status {
+            what: StatusCommand::Levels,
+            daemon,
+            format,
+        } = cli.command
+        else {
+            panic!("Expected `status levels`");
+        };
+        assert_eq!(format, "json");
+        assert_eq!(daemon.port, 9);
+    }
+}
@@ -0,0 +1,183 @@
+use mux_core::config::{Change, Levels, Plan};
+use std::fmt::Write;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Render a change plan as a diff: `+` added, `-` removed, `~` changed,
+/// grouped by section, followed by the rooms the change interrupts
+pub fn render(plan: &Plan, color: bool) -> String {
+    if plan.is_empty() {
+        return "No changes\n".to_string();
+    }
+
+    let paint = |code: &str, text: &str| {
+        if color {
+            format!("{}{}{}", code, text, RESET)
+        } else {
+            text.to_string()
+        }
+    };
+
+    let mut out = String::new();
+    let mut section = "";
+    for change in &plan.changes {
+        let (heading, sign, text) = describe(change);
+        if heading != section {
+            section = heading;
+            let _ = writeln!(out, "{}", paint(BOLD, heading));
+        }
+        let code = match sign {
+            '+' => GREEN,
+            '-' => RED,
+            _ => YELLOW,
+        };
+        let _ = writeln!(out, "{}", paint(code, &format!("  {} {}", sign, text)));
+    }
+
+    out.push('\n');
+    if plan.interrupted.is_empty() {
+        out.push_str("No rooms are interrupted\n");
+    } else {
+        let _ = writeln!(
+            out,
+            "{}",
+            paint(
+                &format!("{}{}", BOLD, RED),
+                &format!("Interrupts: {}", plan.interrupted.join(", "))
+            )
+        );
+    }
+    out
+}
+
+// Section, sign and description of one change
+fn describe(change: &Change) -> (&'static str, char, String) {
+    match change {
+        Change::InputAdded { id, kind } => ("inputs", '+', format!("{} ({})", id, kind)),
+        Change::InputRemoved { id, kind } => ("inputs", '-', format!("{} ({})", id, kind)),
+        Change::InputRestarted { id, kind } => {
+            ("inputs", '~', format!("{} ({}): restarted", id, kind))
+        }
+        Change::OutputAdded { id, kind } => ("outputs", '+', format!("{} ({})", id, kind)),
+        Change::OutputRemoved { id, kind } => ("outputs", '-', format!("{} ({})", id, kind)),
+        Change::OutputRestarted { id, kind } => {
+            ("outputs", '~', format!("{} ({}): restarted", id, kind))
+        }
+        Change::RouteAdded {
+            input,
+            output,
+            levels,
+        } => (
+            "routes",
+            '+',
+            format!(
+                "{} -> {}: gain_db {}, duck_db {}, priority {}",
+                input, output, levels.gain_db, levels.duck_db, levels.priority
+            ),
+        ),
+        Change::RouteRemoved { input, output } => {
+            ("routes", '-', format!("{} -> {}", input, output))
+        }
+        Change::RouteRegained {
+            input,
+            output,
+            from,
+            to,
+        } => (
+            "routes",
+            '~',
+            format!("{} -> {}: {}", input, output, regained(from, to)),
+        ),
+        Change::RoomRepointed { room, from, to } => (
+            "rooms",
+            '~',
+            format!("{}: re-pointed from {} to {}", room, from, to),
+        ),
+        Change::RoomRegrouped { room, from, to } => (
+            "rooms",
+            '~',
+            format!("{}: regrouped from {} to {}", room, group(from), group(to)),
+        ),
+    }
+}
+
+// Only the settings that changed, as `name from -> to`
+fn regained(from: &Levels, to: &Levels) -> String {
+    let mut parts = Vec::new();
+    if from.gain_db != to.gain_db {
+        parts.push(format!("gain_db {} -> {}", from.gain_db, to.gain_db));
+    }
+    if from.duck_db != to.duck_db {
+        parts.push(format!("duck_db {} -> {}", from.duck_db, to.duck_db));
+    }
+    if from.priority != to.priority {
+        parts.push(format!("priority {} -> {}", from.priority, to.priority));
+    }
+    parts.join(", ")
+}
+
+fn group(rooms: &[String]) -> String {
+    if rooms.is_empty() {
+        "alone".to_string()
+    } else {
+        format!("with {}", rooms.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let levels = |gain_db| Levels {
+            gain_db,
+            duck_db: 0.0,
+            priority: 0,
+        };
+        let plan = Plan {
+            changes: vec![
+                Change::InputRestarted {
+                    id: "chime".into(),
+                    kind: "file".into(),
+                },
+                Change::InputAdded {
+                    id: "tv".into(),
+                    kind: "http".into(),
+                },
+                Change::RouteRegained {
+                    input: "music".into(),
+                    output: "kitchen".into(),
+                    from: levels(0.0),
+                    to: levels(-3.0),
+                },
+                Change::RoomRegrouped {
+                    room: "Kitchen".into(),
+                    from: vec!["Office".into()],
+                    to: vec![],
+                },
+            ],
+            interrupted: vec!["Kitchen".into(), "Office".into()],
+        };
+
+        assert_eq!(
+            render(&plan, false),
+            "inputs
+  ~ chime (file): restarted
+  + tv (http)
+routes
+  ~ music -> kitchen: gain_db 0 -> -3
+rooms
+  ~ Kitchen: regrouped from with Office to alone
+
+Interrupts: Kitchen, Office
+"
+        );
+        assert!(render(&plan, true).contains("\x1b[32m  + tv (http)\x1b[0m"));
+        assert_eq!(render(&Plan::default(), true), "No changes\n");
+    }
+}
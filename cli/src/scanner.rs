@@ -1,5 +1,6 @@
 use anyhow::Result;
-use mux_core::{Config, Input, Output, Route};
+use mux_core::config::Codec;
+use mux_core::{Config, Input, InputKind, Output, OutputKind, Route};
 use std::time::Duration;
 use tokio::time::sleep;
 
@@ -8,12 +9,7 @@ pub async fn scan() -> Result<Config> {
 
     // In a real implementation, we would use sonor to discover Sonos devices
     // For now, we'll create a sample configuration with mock data
-    let mut config = Config {
-        inputs: vec![],
-        outputs: vec![],
-        routes: vec![],
-        logging: None,
-    };
+    let mut config = Config::default();
 
     // Simulate a scan delay
     sleep(Duration::from_millis(500)).await;
@@ -21,41 +17,38 @@ pub async fn scan() -> Result<Config> {
     // Add a default silence input
     config.inputs.push(Input {
         id: "silence".to_string(),
-        kind: "silence".to_string(),
-        device: None,
-        path: None,
-        loop_playback: None,
-        url: None,
+        title: None,
+        kind: InputKind::Silence {},
     });
 
     // Add a default ALSA input (for Roon)
     config.inputs.push(Input {
         id: "roon_main".to_string(),
-        kind: "alsa".to_string(),
-        device: Some("hw:Loopback,1".to_string()),
-        path: None,
-        loop_playback: None,
-        url: None,
+        title: None,
+        kind: InputKind::Alsa {
+            device: "hw:Loopback,1".to_string(),
+            format: None,
+        },
     });
 
     // Add a default HTTP input (for streaming)
     config.inputs.push(Input {
         id: "web_radio".to_string(),
-        kind: "http".to_string(),
-        device: None,
-        path: None,
-        loop_playback: None,
-        url: Some("http://example.com/stream".to_string()),
+        title: None,
+        kind: InputKind::Http {
+            url: "http://example.com/stream".to_string(),
+        },
     });
 
     // Add a default file input
     config.inputs.push(Input {
         id: "alert_sound".to_string(),
-        kind: "file".to_string(),
-        device: None,
-        path: Some("/path/to/alert.mp3".to_string()),
-        loop_playback: Some(false),
-        url: None,
+        title: None,
+        kind: InputKind::File {
+            path: "/path/to/alert.mp3".to_string(),
+            loop_playback: false,
+            on_demand: false,
+        },
     });
 
     // Simulate finding Sonos devices
@@ -64,30 +57,36 @@ pub async fn scan() -> Result<Config> {
     for room in discovered_rooms {
         config.outputs.push(Output {
             id: room.to_lowercase().replace(' ', "_"),
-            kind: "sonos".to_string(),
-            room: Some(room.to_string()),
-            buffer_sec: Some(5),
-            host: None,
-            port: None,
+            kind: OutputKind::Sonos {
+                room: room.to_string(),
+                buffer_sec: 5,
+                codec: Codec::Mp3,
+                bitrate_kbps: 128,
+                quality: None,
+            },
         });
     }
 
     // Create default routes
     for output in &config.outputs {
         config.routes.push(Route {
+            id: None,
             input: "silence".to_string(),
             outputs: vec![output.id.clone()],
             gain_db: 0.0,
             duck_db: 0.0,
+            priority: None,
         });
     }
 
     // Add a sample route for Roon to all outputs
     config.routes.push(Route {
+        id: None,
         input: "roon_main".to_string(),
         outputs: config.outputs.iter().map(|o| o.id.clone()).collect(),
         gain_db: 0.0,
         duck_db: 0.0,
+        priority: None,
     });
 
     Ok(config)
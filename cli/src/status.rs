@@ -0,0 +1,242 @@
+use mux_core::admin::LoadedConfig;
+use mux_core::{InputStatus, Level, OutputLevels, OutputStatus, RouteStatus};
+use std::fmt::Write;
+
+/// Inputs, followed by why any that failed did
+pub fn inputs(inputs: &[InputStatus]) -> String {
+    let mut out = table(
+        &[
+            "INPUT", "KIND", "RUNNING", "BUFFERED", "RMS", "PEAK", "OUTPUTS",
+        ],
+        inputs
+            .iter()
+            .map(|input| {
+                vec![
+                    input.id.clone(),
+                    input.kind.clone(),
+                    match input.error {
+                        Some(_) => "failed".to_string(),
+                        None => yes_no(input.running),
+                    },
+                    input.buffered.to_string(),
+                    db(input.level.rms_db),
+                    db(input.level.peak_db),
+                    list(&input.outputs),
+                ]
+            })
+            .collect(),
+    );
+    for input in inputs {
+        if let Some(error) = &input.error {
+            let _ = writeln!(out, "\n{}: {}", input.id, error);
+        }
+    }
+    out
+}
+
+pub fn outputs(outputs: &[OutputStatus]) -> String {
+    table(
+        &[
+            "OUTPUT",
+            "KIND",
+            "ROOM",
+            "HEALTHY",
+            "TRANSPORT",
+            "CLIENTS",
+            "RMS",
+            "PEAK",
+            "INPUTS",
+        ],
+        outputs
+            .iter()
+            .map(|output| {
+                vec![
+                    output.id.clone(),
+                    output.kind.clone(),
+                    output.room.clone().unwrap_or_else(|| "-".to_string()),
+                    output.healthy.map_or("-".to_string(), yes_no),
+                    output
+                        .transport
+                        .map_or("-".to_string(), |t| t.as_str().to_string()),
+                    output.clients.map_or("-".to_string(), |c| c.to_string()),
+                    db(output.level.rms_db),
+                    db(output.level.peak_db),
+                    list(&output.inputs),
+                ]
+            })
+            .collect(),
+    )
+}
+
+pub fn routes(routes: &[RouteStatus]) -> String {
+    table(
+        &[
+            "INPUT",
+            "OUTPUT",
+            "GAIN",
+            "PRIORITY",
+            "DUCK",
+            "DUCKED",
+            "ACTIVE",
+            "MUTED",
+            "UNDERRUNS",
+        ],
+        routes
+            .iter()
+            .map(|route| {
+                let source = &route.source;
+                vec![
+                    source.input.clone(),
+                    route.output.clone(),
+                    db(source.gain_db),
+                    source.priority.to_string(),
+                    db(source.duck_db),
+                    if source.ducked_by > 0.0 {
+                        db(source.ducked_by)
+                    } else {
+                        "-".to_string()
+                    },
+                    yes_no(source.active),
+                    yes_no(source.muted),
+                    source.underruns.to_string(),
+                ]
+            })
+            .collect(),
+    )
+}
+
+/// Each output's mix, followed by the sources in it
+pub fn levels(levels: &[OutputLevels]) -> String {
+    let mut rows = Vec::new();
+    for output in levels {
+        rows.push(level_row(&output.output, "(mix)", &output.level, None));
+        for source in &output.sources {
+            rows.push(level_row(
+                "",
+                &source.input,
+                &source.level,
+                Some(source.gain_db - source.ducked_by),
+            ));
+        }
+    }
+    table(&["OUTPUT", "INPUT", "RMS", "PEAK", "GAIN"], rows)
+}
+
+fn level_row(output: &str, input: &str, level: &Level, gain_db: Option<f32>) -> Vec<String> {
+    vec![
+        output.to_string(),
+        input.to_string(),
+        db(level.rms_db),
+        db(level.peak_db),
+        gain_db.map_or("-".to_string(), db),
+    ]
+}
+
+pub fn config(loaded: &LoadedConfig) -> String {
+    let mut out = String::new();
+    for (i, file) in loaded.files.iter().enumerate() {
+        let label = if i == 0 { "# File:" } else { "# Included:" };
+        let _ = writeln!(out, "{} {}", label, file);
+    }
+    if let (Some(id), Some(source)) = (loaded.history_id, &loaded.source) {
+        let _ = writeln!(out, "# History entry #{} ({})", id, source);
+    }
+    out.push('\n');
+    out.push_str(&loaded.config);
+    out
+}
+
+// Left-aligned columns, two spaces apart
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    let headers = headers.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+    for row in std::iter::once(&headers).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let _ = writeln!(out, "{}", line.trim_end());
+    }
+    out
+}
+
+pub(crate) fn db(value: f32) -> String {
+    format!("{:.1} dB", value)
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn list(items: &[String]) -> String {
+    if items.is_empty() {
+        "-".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mux_core::SourceStatus;
+
+    #[test]
+    fn test_tables() {
+        let source = |input: &str, ducked_by, active| SourceStatus {
+            input: input.to_string(),
+            gain_db: -6.0,
+            priority: 0,
+            duck_db: 0.0,
+            ducked_by,
+            active,
+            level: Level {
+                rms_db: -20.0,
+                peak_db: -3.0,
+            },
+            muted: false,
+            underruns: 2,
+        };
+        let levels = vec![OutputLevels {
+            output: "kitchen".to_string(),
+            level: Level::SILENT,
+            sources: vec![source("music", 12.0, true), source("doorbell", 0.0, false)],
+        }];
+
+        assert_eq!(
+            super::levels(&levels),
+            "\
+OUTPUT   INPUT     RMS       PEAK      GAIN
+kitchen  (mix)     -60.0 dB  -60.0 dB  -
+         music     -20.0 dB  -3.0 dB   -18.0 dB
+         doorbell  -20.0 dB  -3.0 dB   -6.0 dB
+"
+        );
+
+        let routes: Vec<_> = levels[0]
+            .sources
+            .iter()
+            .map(|source| RouteStatus {
+                output: "kitchen".to_string(),
+                source: source.clone(),
+            })
+            .collect();
+        assert_eq!(
+            super::routes(&routes),
+            "\
+INPUT     OUTPUT   GAIN     PRIORITY  DUCK    DUCKED   ACTIVE  MUTED  UNDERRUNS
+music     kitchen  -6.0 dB  0         0.0 dB  12.0 dB  yes     no     2
+doorbell  kitchen  -6.0 dB  0         0.0 dB  -        no      no     2
+"
+        );
+    }
+}
@@ -0,0 +1,475 @@
+use crate::admin::Client;
+use crate::status;
+use anyhow::Result;
+use mux_core::admin::{Method, RouteParams};
+use mux_core::config::GAIN_DB_RANGE;
+use mux_core::{Level, OutputStatus, RouteStatus};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Row, Table, TableState};
+use ratatui::Frame;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+// How much one key press nudges a route's gain, in dB
+const GAIN_STEP_DB: f32 = 1.0;
+
+// Width of the level meters, in cells
+const METER_WIDTH: usize = 20;
+
+/// Everything `top` shows, fetched in one refresh
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub outputs: Vec<OutputStatus>,
+    pub routes: Vec<RouteStatus>,
+}
+
+impl Snapshot {
+    async fn fetch(client: &mut Client) -> Result<Self> {
+        Ok(Self {
+            outputs: client.call(Method::Outputs).await?,
+            routes: client.call(Method::Routes).await?,
+        })
+    }
+}
+
+/// What a key press asks for
+#[derive(Debug, PartialEq)]
+enum Action {
+    Quit,
+    Refresh,
+    SetRoute(RouteParams),
+}
+
+/// The dashboard's state between refreshes
+pub struct App {
+    target: String,
+    snapshot: Snapshot,
+    routes: TableState,
+    // Result of the last key press, or why the last refresh failed
+    message: Option<String>,
+}
+
+impl App {
+    pub fn new(target: String) -> Self {
+        Self {
+            target,
+            snapshot: Snapshot::default(),
+            routes: TableState::default().with_selected(Some(0)),
+            message: None,
+        }
+    }
+
+    fn update(&mut self, snapshot: Snapshot) {
+        let last = snapshot.routes.len().saturating_sub(1);
+        self.routes
+            .select(Some(self.routes.selected().unwrap_or(0).min(last)));
+        self.snapshot = snapshot;
+    }
+
+    fn selected(&self) -> Option<&RouteStatus> {
+        self.snapshot.routes.get(self.routes.selected()?)
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+        let adjust = |route: &RouteStatus, gain_db: Option<f32>, muted: Option<bool>| {
+            Action::SetRoute(RouteParams {
+                input: route.source.input.clone(),
+                output: route.output.clone(),
+                gain_db,
+                muted,
+            })
+        };
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => Some(Action::Quit),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                Some(Action::Quit)
+            }
+            KeyCode::Char('r') => Some(Action::Refresh),
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.routes.select_previous();
+                None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                let last = self.snapshot.routes.len().saturating_sub(1);
+                self.routes.select(Some(
+                    self.routes.selected().map_or(0, |i| (i + 1).min(last)),
+                ));
+                None
+            }
+            KeyCode::Char('m') | KeyCode::Char(' ') => {
+                let route = self.selected()?;
+                Some(adjust(route, None, Some(!route.source.muted)))
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                let route = self.selected()?;
+                Some(adjust(
+                    route,
+                    Some((route.source.gain_db + GAIN_STEP_DB).min(*GAIN_DB_RANGE.end())),
+                    None,
+                ))
+            }
+            KeyCode::Char('-') | KeyCode::Char('_') => {
+                let route = self.selected()?;
+                Some(adjust(
+                    route,
+                    Some((route.source.gain_db - GAIN_STEP_DB).max(*GAIN_DB_RANGE.start())),
+                    None,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let outputs_height = self.snapshot.outputs.len() as u16 + 3;
+        let [outputs_area, routes_area, footer_area] = Layout::vertical([
+            Constraint::Length(outputs_height),
+            Constraint::Min(4),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let header = |cells: &[&'static str]| {
+            Row::new(cells.iter().copied()).style(Style::new().add_modifier(Modifier::BOLD))
+        };
+
+        let outputs = Table::new(
+            self.snapshot.outputs.iter().map(|output| {
+                Row::new(vec![
+                    Cell::from(output.id.clone()),
+                    Cell::from(output.kind.clone()),
+                    Cell::from(output.room.clone().unwrap_or_else(|| "-".to_string())),
+                    Cell::from(match output.transport {
+                        Some(transport) => transport.as_str(),
+                        None => "-",
+                    }),
+                    match output.healthy {
+                        Some(true) => Cell::from("yes").style(Style::new().fg(Color::Green)),
+                        Some(false) => Cell::from("no").style(Style::new().fg(Color::Red)),
+                        None => Cell::from("-"),
+                    },
+                    Cell::from(output.clients.map_or("-".to_string(), |c| c.to_string())),
+                    meter_cell(&output.level),
+                    Cell::from(status::db(output.level.rms_db)),
+                ])
+            }),
+            [
+                Constraint::Min(10),
+                Constraint::Length(6),
+                Constraint::Min(10),
+                Constraint::Length(16),
+                Constraint::Length(7),
+                Constraint::Length(7),
+                Constraint::Length(METER_WIDTH as u16),
+                Constraint::Length(9),
+            ],
+        )
+        .header(header(&[
+            "OUTPUT",
+            "KIND",
+            "ROOM",
+            "TRANSPORT",
+            "HEALTHY",
+            "CLIENTS",
+            "MIX",
+            "RMS",
+        ]))
+        .block(Block::bordered().title(format!(" sonos-mux top: {} ", self.target)));
+        frame.render_widget(outputs, outputs_area);
+
+        let routes = Table::new(
+            self.snapshot.routes.iter().map(|route| {
+                let source = &route.source;
+                let gain = if source.muted {
+                    Cell::from("muted").style(Style::new().fg(Color::Red))
+                } else {
+                    Cell::from(status::db(source.gain_db))
+                };
+                Row::new(vec![
+                    Cell::from(source.input.clone()),
+                    Cell::from(route.output.clone()),
+                    meter_cell(&source.level),
+                    Cell::from(status::db(source.level.rms_db)),
+                    gain,
+                    if source.ducked_by > 0.0 {
+                        Cell::from(status::db(-source.ducked_by))
+                            .style(Style::new().fg(Color::Yellow))
+                    } else {
+                        Cell::from("-")
+                    },
+                    Cell::from(source.underruns.to_string()).style(if source.underruns > 0 {
+                        Style::new().fg(Color::Red)
+                    } else {
+                        Style::new()
+                    }),
+                ])
+            }),
+            [
+                Constraint::Min(10),
+                Constraint::Min(10),
+                Constraint::Length(METER_WIDTH as u16),
+                Constraint::Length(9),
+                Constraint::Length(9),
+                Constraint::Length(9),
+                Constraint::Length(9),
+            ],
+        )
+        .header(header(&[
+            "INPUT",
+            "OUTPUT",
+            "LEVEL",
+            "RMS",
+            "GAIN",
+            "DUCKED",
+            "UNDERRUNS",
+        ]))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(" Inputs "));
+        frame.render_stateful_widget(routes, routes_area, &mut self.routes);
+
+        let footer = match &self.message {
+            Some(message) => Line::from(message.as_str()),
+            None => Line::from("↑/↓ select  m mute  +/- gain  r refresh  q quit")
+                .style(Style::new().add_modifier(Modifier::DIM)),
+        };
+        frame.render_widget(footer, footer_area);
+    }
+}
+
+/// Show the daemon's state until the user quits, or `iterations` times
+pub async fn run(
+    mut client: Client,
+    target: String,
+    interval: Duration,
+    iterations: Option<u64>,
+) -> Result<()> {
+    if !io::stdout().is_terminal() {
+        return plain(&mut client, interval, iterations).await;
+    }
+
+    let mut app = App::new(target);
+    let mut terminal = ratatui::init();
+    let result = interactive(&mut client, &mut app, &mut terminal, interval, iterations).await;
+    ratatui::restore();
+    result
+}
+
+async fn interactive(
+    client: &mut Client,
+    app: &mut App,
+    terminal: &mut ratatui::DefaultTerminal,
+    interval: Duration,
+    iterations: Option<u64>,
+) -> Result<()> {
+    let mut refreshes = 0;
+    loop {
+        match Snapshot::fetch(client).await {
+            Ok(snapshot) => app.update(snapshot),
+            // A dropped connection won't come back
+            Err(err) if client.is_closed() => return Err(err),
+            Err(err) => app.message = Some(format!("Refresh failed: {:#}", err)),
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+        refreshes += 1;
+        if iterations.is_some_and(|n| refreshes >= n) {
+            return Ok(());
+        }
+
+        // Handle keys until the next refresh is due
+        let next = Instant::now() + interval;
+        while let Some(timeout) = next.checked_duration_since(Instant::now()) {
+            let event = tokio::task::block_in_place(|| -> io::Result<Option<Event>> {
+                if event::poll(timeout)? {
+                    event::read().map(Some)
+                } else {
+                    Ok(None)
+                }
+            })?;
+            let action = match event {
+                Some(Event::Key(key)) => app.key(key),
+                Some(Event::Resize(..)) => Some(Action::Refresh),
+                _ => None,
+            };
+            match action {
+                Some(Action::Quit) => return Ok(()),
+                Some(Action::Refresh) => break,
+                Some(Action::SetRoute(params)) => {
+                    app.message = Some(
+                        match client.call::<RouteStatus>(Method::SetRoute(params)).await {
+                            Ok(route) => format!(
+                                "{} -> {}: {}{}",
+                                route.source.input,
+                                route.output,
+                                status::db(route.source.gain_db),
+                                if route.source.muted { ", muted" } else { "" }
+                            ),
+                            Err(err) => format!("Error: {:#}", err),
+                        },
+                    );
+                    break;
+                }
+                // Selection moved
+                None => {
+                    terminal.draw(|frame| app.draw(frame))?;
+                }
+            }
+        }
+    }
+}
+
+// Print the status tables every `interval`, for pipes and logs
+async fn plain(client: &mut Client, interval: Duration, iterations: Option<u64>) -> Result<()> {
+    let mut refreshes = 0;
+    loop {
+        let snapshot = Snapshot::fetch(client).await?;
+        let mut out = io::stdout().lock();
+        writeln!(out, "{}", status::outputs(&snapshot.outputs))?;
+        writeln!(out, "{}", status::routes(&snapshot.routes))?;
+        out.flush()?;
+        drop(out);
+
+        refreshes += 1;
+        if iterations.is_some_and(|n| refreshes >= n) {
+            return Ok(());
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+// A bar from -60 to 0 dBFS: solid up to the RMS level, shaded up to the peak
+fn meter(level: &Level) -> String {
+    let cells = |db: f32| {
+        let fraction = ((db - Level::SILENT.rms_db) / -Level::SILENT.rms_db).clamp(0.0, 1.0);
+        (fraction * METER_WIDTH as f32).round() as usize
+    };
+    let rms = cells(level.rms_db);
+    let peak = cells(level.peak_db).max(rms);
+    format!(
+        "{}{}{}",
+        "█".repeat(rms),
+        "▒".repeat(peak - rms),
+        " ".repeat(METER_WIDTH - peak)
+    )
+}
+
+fn meter_cell(level: &Level) -> Cell<'static> {
+    let color = if level.peak_db > -3.0 {
+        Color::Red
+    } else if level.peak_db > -12.0 {
+        Color::Yellow
+    } else {
+        Color::Green
+    };
+    Cell::from(meter(level)).style(Style::new().fg(color))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mux_core::SourceStatus;
+    use mux_core::TransportState;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn route(input: &str, gain_db: f32, muted: bool) -> RouteStatus {
+        RouteStatus {
+            output: "kitchen".to_string(),
+            source: SourceStatus {
+                input: input.to_string(),
+                gain_db,
+                priority: 0,
+                duck_db: 0.0,
+                ducked_by: 0.0,
+                active: true,
+                level: Level {
+                    rms_db: -30.0,
+                    peak_db: -15.0,
+                },
+                muted,
+                underruns: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_dashboard() {
+        assert_eq!(meter(&Level::SILENT), " ".repeat(METER_WIDTH));
+        assert_eq!(
+            meter(&Level {
+                rms_db: -30.0,
+                peak_db: -15.0
+            }),
+            format!("{}{}{}", "█".repeat(10), "▒".repeat(5), " ".repeat(5))
+        );
+
+        let mut app = App::new("localhost:8383".to_string());
+        app.update(Snapshot {
+            outputs: vec![OutputStatus {
+                id: "kitchen".to_string(),
+                kind: "sonos".to_string(),
+                room: Some("Kitchen".to_string()),
+                inputs: vec!["music".to_string(), "doorbell".to_string()],
+                level: Level::SILENT,
+                healthy: Some(true),
+                transport: Some(TransportState::Playing),
+                clients: Some(2),
+                file: None,
+                frames: None,
+                underruns: None,
+                jitter_ms: None,
+                error: None,
+            }],
+            routes: vec![route("music", -6.0, false), route("doorbell", 0.0, true)],
+        });
+
+        let press = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let set = |input: &str, gain_db, muted| {
+            Some(Action::SetRoute(RouteParams {
+                input: input.to_string(),
+                output: "kitchen".to_string(),
+                gain_db,
+                muted,
+            }))
+        };
+        assert_eq!(
+            app.key(press(KeyCode::Char('+'))),
+            set("music", Some(-5.0), None)
+        );
+        assert_eq!(app.key(press(KeyCode::Down)), None);
+        assert_eq!(app.key(press(KeyCode::Down)), None);
+        assert_eq!(
+            app.key(press(KeyCode::Char('m'))),
+            set("doorbell", None, Some(false))
+        );
+        assert_eq!(
+            app.key(press(KeyCode::Char('-'))),
+            set("doorbell", Some(-1.0), None)
+        );
+        assert_eq!(app.key(press(KeyCode::Char('q'))), Some(Action::Quit));
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 12)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .chunks(100)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>() + "\n")
+            .collect();
+        assert!(
+            screen.contains("sonos-mux top: localhost:8383"),
+            "{}",
+            screen
+        );
+        assert!(screen.contains("PLAYING"), "{}", screen);
+        assert!(screen.contains("music"), "{}", screen);
+        assert!(screen.contains("muted"), "{}", screen);
+    }
+}
@@ -0,0 +1,506 @@
+//! The admin protocol spoken between `muxd` and its clients
+//!
+//! Requests and responses are JSON-RPC 2.0 objects, one per line. A
+//! connection starts with a `hello` request that agrees on the protocol
+//! version and may then carry any number of requests:
+//!
+//! ```text
+//! -> {"jsonrpc":"2.0","id":1,"method":"hello","params":{"protocol":1}}
+//! <- {"jsonrpc":"2.0","id":1,"result":{"protocol":1,"server":"muxd 0.1.0"}}
+//! -> {"jsonrpc":"2.0","id":2,"method":"diff","params":{"config":"..."}}
+//! <- {"jsonrpc":"2.0","id":2,"result":{"changes":[],"interrupted":[]}}
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// Version of the protocol implemented by this build
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Every method the daemon understands
+pub const METHODS: &[&str] = &[
+    "hello",
+    "version",
+    "reload",
+    "apply",
+    "diff",
+    "history",
+    "rollback",
+    "stats",
+    "inputs",
+    "outputs",
+    "routes",
+    "levels",
+    "config",
+    "set_route",
+    "play",
+];
+
+/// A request, with its parameters already checked
+#[derive(Debug, Clone, PartialEq)]
+pub enum Method {
+    /// Agree on the protocol version; must be the first request
+    Hello(HelloParams),
+    Version,
+    /// Load the configuration from a file on the daemon's host and apply it
+    Reload(ReloadParams),
+    /// Apply the configuration sent along
+    Apply(ConfigParams),
+    /// Plan what applying the configuration sent along would change
+    Diff(ConfigParams),
+    /// List the last applied configurations, newest first
+    History,
+    /// Go back to an earlier configuration from the history
+    Rollback(RollbackParams),
+    /// Health of the Sonos rooms
+    Stats,
+    /// Inputs being mixed, with their buffer depths and levels
+    Inputs,
+    /// Outputs, with the inputs mixed into each and their health
+    Outputs,
+    /// Every input-output pair being mixed, with gain and ducking
+    Routes,
+    /// Levels of every output's mix and of the sources in it
+    Levels,
+    /// The loaded configuration, with secrets redacted
+    Config,
+    /// Change a route's gain or mute it, until the daemon restarts
+    SetRoute(RouteParams),
+    /// Play an input from the start, e.g. an on-demand chime
+    Play(InputParams),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HelloParams {
+    pub protocol: u32,
+
+    /// Name and version of the client, for logging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+
+    /// Access token, when the daemon requires one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HelloResult {
+    pub protocol: u32,
+    pub server: String,
+    /// What the connection is allowed to do
+    pub role: Role,
+}
+
+/// What a client is allowed to do; each role includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Inspect the daemon, without changing anything
+    Read,
+    /// Also apply, reload and roll back configurations, and adjust routes
+    Control,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Read => "read",
+            Role::Control => "control",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Role::Read),
+            "control" => Ok(Role::Control),
+            _ => Err(format!("Unknown role `{}`, expected read or control", s)),
+        }
+    }
+}
+
+/// Result of `config`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoadedConfig {
+    /// Files the configuration was read from, the main one first
+    pub files: Vec<String>,
+    /// Its entry in the daemon's history, if it keeps one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_id: Option<u64>,
+    /// When it was applied, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied_at: Option<u64>,
+    /// What applied it, e.g. `startup` or `apply`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The configuration as TOML, with includes merged and secrets redacted
+    pub config: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReloadParams {
+    /// File to load instead of the daemon's `--config`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigParams {
+    /// Configuration file contents, in TOML
+    pub config: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RollbackParams {
+    /// How many entries to go back in the history
+    #[serde(default = "default_rollback")]
+    pub n: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteParams {
+    pub input: String,
+    pub output: String,
+    /// New gain, in dB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain_db: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub muted: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputParams {
+    pub input: String,
+}
+
+fn default_rollback() -> usize {
+    1
+}
+
+impl Method {
+    /// Build a method from its name and parameters as received
+    pub fn parse(method: &str, params: Value) -> Result<Self, RpcError> {
+        let params = if params.is_null() { json!({}) } else { params };
+        Ok(match method {
+            "hello" => Method::Hello(typed(params)?),
+            "version" => Method::Version,
+            "reload" => Method::Reload(typed(params)?),
+            "apply" => Method::Apply(typed(params)?),
+            "diff" => Method::Diff(typed(params)?),
+            "history" => Method::History,
+            "rollback" => Method::Rollback(typed(params)?),
+            "stats" => Method::Stats,
+            "inputs" => Method::Inputs,
+            "outputs" => Method::Outputs,
+            "routes" => Method::Routes,
+            "levels" => Method::Levels,
+            "config" => Method::Config,
+            "set_route" => Method::SetRoute(typed(params)?),
+            "play" => Method::Play(typed(params)?),
+            _ => {
+                return Err(RpcError::new(
+                    RpcError::METHOD_NOT_FOUND,
+                    format!("Unknown method: {}", method),
+                ))
+            }
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Hello(_) => "hello",
+            Method::Version => "version",
+            Method::Reload(_) => "reload",
+            Method::Apply(_) => "apply",
+            Method::Diff(_) => "diff",
+            Method::History => "history",
+            Method::Rollback(_) => "rollback",
+            Method::Stats => "stats",
+            Method::Inputs => "inputs",
+            Method::Outputs => "outputs",
+            Method::Routes => "routes",
+            Method::Levels => "levels",
+            Method::Config => "config",
+            Method::SetRoute(_) => "set_route",
+            Method::Play(_) => "play",
+        }
+    }
+
+    /// The role a connection needs to make this request
+    pub fn required_role(&self) -> Role {
+        match self {
+            Method::Reload(_)
+            | Method::Apply(_)
+            | Method::Rollback(_)
+            | Method::SetRoute(_)
+            | Method::Play(_) => Role::Control,
+            _ => Role::Read,
+        }
+    }
+
+    pub fn params(&self) -> Value {
+        let params = match self {
+            Method::Hello(params) => serde_json::to_value(params),
+            Method::Reload(params) => serde_json::to_value(params),
+            Method::Apply(params) | Method::Diff(params) => serde_json::to_value(params),
+            Method::Rollback(params) => serde_json::to_value(params),
+            Method::SetRoute(params) => serde_json::to_value(params),
+            Method::Play(params) => serde_json::to_value(params),
+            Method::Version
+            | Method::History
+            | Method::Stats
+            | Method::Inputs
+            | Method::Outputs
+            | Method::Routes
+            | Method::Levels
+            | Method::Config => return Value::Null,
+        };
+        params.unwrap_or(Value::Null)
+    }
+}
+
+fn typed<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+/// A request as sent over the wire
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub id: Value,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+impl Request {
+    pub fn new(id: u64, method: &Method) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id: id.into(),
+            method: method.name().to_string(),
+            params: method.params(),
+        }
+    }
+
+    /// Parse one line, returning the request ID along with any error so the
+    /// reply can refer to it
+    pub fn parse(line: &str) -> Result<(Value, Method), (Value, RpcError)> {
+        let value: Value = serde_json::from_str(line).map_err(|e| {
+            (
+                Value::Null,
+                RpcError::new(RpcError::PARSE_ERROR, format!("Parse error: {}", e)),
+            )
+        })?;
+        let id = value.get("id").cloned().unwrap_or(Value::Null);
+
+        let request: Request = serde_json::from_value(value).map_err(|e| {
+            (
+                id.clone(),
+                RpcError::new(RpcError::INVALID_REQUEST, format!("Invalid request: {}", e)),
+            )
+        })?;
+        if request.jsonrpc != "2.0" || !(request.id.is_number() || request.id.is_string()) {
+            return Err((
+                id,
+                RpcError::new(
+                    RpcError::INVALID_REQUEST,
+                    "Invalid request: expected `jsonrpc = \"2.0\"` and a number or string `id`",
+                ),
+            ));
+        }
+
+        match Method::parse(&request.method, request.params) {
+            Ok(method) => Ok((request.id, method)),
+            Err(e) => Err((request.id, e)),
+        }
+    }
+}
+
+/// A reply to one request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Result(Value),
+    Error(RpcError),
+}
+
+impl Response {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            outcome: match result {
+                Ok(value) => Outcome::Result(value),
+                Err(error) => Outcome::Error(error),
+            },
+        }
+    }
+
+    /// The result, deserialized into the type the method returns
+    pub fn into_result<T: DeserializeOwned>(self) -> Result<T, RpcError> {
+        match self.outcome {
+            Outcome::Result(value) => serde_json::from_value(value).map_err(|e| {
+                RpcError::new(RpcError::INTERNAL_ERROR, format!("Invalid result: {}", e))
+            }),
+            Outcome::Error(error) => Err(error),
+        }
+    }
+}
+
+/// An error reply, with a JSON-RPC error code
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+    /// A request other than `hello` came before the handshake
+    pub const HANDSHAKE_REQUIRED: i64 = -32000;
+    /// The client speaks a protocol version the daemon doesn't
+    pub const UNSUPPORTED_PROTOCOL: i64 = -32001;
+    /// The configuration sent or loaded is invalid
+    pub const INVALID_CONFIG: i64 = -32002;
+    /// The daemon can't do this right now, e.g. while shutting down
+    pub const UNAVAILABLE: i64 = -32003;
+    /// The daemon requires credentials and none or wrong ones were given
+    pub const UNAUTHORIZED: i64 = -32004;
+    /// The connection's role doesn't allow the request
+    pub const FORBIDDEN: i64 = -32005;
+    /// The configuration failed to start and the previous one kept running
+    pub const ROLLED_BACK: i64 = -32006;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let method = Method::Apply(ConfigParams {
+            config: "[[inputs]]\n\nid = \"a\"\n".to_string(),
+        });
+        let line = serde_json::to_string(&Request::new(7, &method)).unwrap();
+        assert!(!line.contains('\n'));
+        assert_eq!(Request::parse(&line), Ok((json!(7), method)));
+
+        let method = Method::SetRoute(RouteParams {
+            input: "music".to_string(),
+            output: "kitchen".to_string(),
+            gain_db: None,
+            muted: Some(true),
+        });
+        let line = serde_json::to_string(&Request::new(8, &method)).unwrap();
+        assert!(line.ends_with(r#""params":{"input":"music","muted":true,"output":"kitchen"}}"#));
+        assert_eq!(Request::parse(&line), Ok((json!(8), method)));
+
+        let method = Method::Play(InputParams {
+            input: "chime".to_string(),
+        });
+        assert_eq!(method.required_role(), Role::Control);
+        let line = serde_json::to_string(&Request::new(9, &method)).unwrap();
+        assert_eq!(Request::parse(&line), Ok((json!(9), method)));
+
+        let line = r#"{"jsonrpc":"2.0","id":"a","method":"rollback"}"#;
+        assert_eq!(
+            Request::parse(line),
+            Ok((json!("a"), Method::Rollback(RollbackParams { n: 1 })))
+        );
+
+        let response = Response::new(
+            json!(7),
+            Ok(json!({"protocol": 1, "server": "muxd", "role": "read"})),
+        );
+        let line = serde_json::to_string(&response).unwrap();
+        assert_eq!(
+            line,
+            r#"{"jsonrpc":"2.0","id":7,"result":{"protocol":1,"role":"read","server":"muxd"}}"#
+        );
+        let response: Response = serde_json::from_str(&line).unwrap();
+        let hello: HelloResult = response.into_result().unwrap();
+        assert_eq!(hello.protocol, 1);
+        assert_eq!(hello.role, Role::Read);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let code = |line: &str| Request::parse(line).unwrap_err().1.code;
+        assert_eq!(code("{"), RpcError::PARSE_ERROR);
+        assert_eq!(
+            code(r#"{"id":1,"method":"stats"}"#),
+            RpcError::INVALID_REQUEST
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","method":"stats"}"#),
+            RpcError::INVALID_REQUEST
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":1,"method":"restart"}"#),
+            RpcError::METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":1,"method":"apply","params":{"file":"x"}}"#),
+            RpcError::INVALID_PARAMS
+        );
+
+        // The ID is kept so the error can be matched to the request
+        let (id, _) = Request::parse(r#"{"jsonrpc":"2.0","id":3,"method":"x"}"#).unwrap_err();
+        assert_eq!(id, json!(3));
+    }
+}
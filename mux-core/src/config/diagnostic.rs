@@ -0,0 +1,428 @@
+use super::{Config, ConfigError, Secrets};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// 1-based line/column position of a diagnostic in its source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+/// A single problem found while checking a configuration file
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+
+    /// Stable identifier for the kind of problem, e.g. `duplicate-id`
+    pub code: &'static str,
+
+    pub message: String,
+
+    /// File the problem was found in, when the configuration was loaded from
+    /// one or includes others
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+
+    /// Key path the problem refers to, e.g. `routes[1].outputs[0]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    /// Byte range in the source text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<Range<usize>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+/// A file that contributed to a configuration
+#[derive(Debug)]
+struct SourceFile {
+    name: Option<String>,
+    source: String,
+}
+
+/// Where an entry of the merged configuration was defined: the index of its
+/// file and its key path there
+pub(crate) type Origins = HashMap<String, (usize, String)>;
+
+/// Result of checking a configuration file: every diagnostic found, plus the
+/// parsed configuration when there were no errors
+#[derive(Debug)]
+pub struct Report {
+    // The checked file first, then every file it includes
+    files: Vec<SourceFile>,
+    origins: Origins,
+    pub diagnostics: Vec<Diagnostic>,
+    config: Option<Config>,
+    // First error encountered, kept in its typed form for `into_result`
+    error: Option<ConfigError>,
+    // Interpolated values to keep out of messages
+    secrets: Secrets,
+}
+
+impl Report {
+    pub(crate) fn new(name: Option<String>, source: &str) -> Self {
+        Self {
+            files: vec![SourceFile {
+                name,
+                source: source.to_string(),
+            }],
+            origins: Origins::new(),
+            diagnostics: Vec::new(),
+            config: None,
+            error: None,
+            secrets: Secrets::default(),
+        }
+    }
+
+    pub(crate) fn set_secrets(&mut self, secrets: Secrets) {
+        self.secrets = secrets;
+    }
+
+    /// Add an included file, returning its index
+    pub(crate) fn add_file(&mut self, name: String, source: &str) -> usize {
+        self.files.push(SourceFile {
+            name: Some(name),
+            source: source.to_string(),
+        });
+        self.files.len() - 1
+    }
+
+    pub(crate) fn set_origins(&mut self, origins: Origins) {
+        self.origins = origins;
+    }
+
+    /// The name of the file a key path of the merged configuration came
+    /// from, if it came from one
+    pub(crate) fn file_of(&self, path: &str) -> Option<&str> {
+        self.files[self.resolve(path).0].name.as_deref()
+    }
+
+    // Map a key path in the merged configuration to its file and the path
+    // within that file
+    fn resolve(&self, path: &str) -> (usize, String) {
+        let (head, rest) = match path.find('.') {
+            Some(i) => path.split_at(i),
+            None => (path, ""),
+        };
+        if let Some((file, origin)) = self.origins.get(head) {
+            return (*file, format!("{}{}", origin, rest));
+        }
+        // A path into an array as a whole, e.g. `inputs`
+        let key = head.split('[').next().unwrap_or(head);
+        match self.origins.get(key) {
+            Some((file, _)) => (*file, path.to_string()),
+            None => (0, path.to_string()),
+        }
+    }
+
+    /// Record an error at a key path of the merged configuration, whose span
+    /// (if any) is relative to the file that path came from
+    pub(crate) fn error(
+        &mut self,
+        error: ConfigError,
+        path: Option<String>,
+        span: Option<Range<usize>>,
+        suggestion: Option<String>,
+    ) {
+        let (file, path) = match path {
+            Some(path) => {
+                let (file, path) = self.resolve(&path);
+                (file, Some(path))
+            }
+            None => (0, None),
+        };
+        self.error_at(file, error, path, span, suggestion);
+    }
+
+    /// Record an error at a key path of a specific file
+    pub(crate) fn error_at(
+        &mut self,
+        file: usize,
+        error: ConfigError,
+        path: Option<String>,
+        span: Option<Range<usize>>,
+        suggestion: Option<String>,
+    ) {
+        self.push(
+            file,
+            Severity::Error,
+            error.code(),
+            error.to_string(),
+            path,
+            span,
+            suggestion,
+        );
+        if self.error.is_none() {
+            self.error = Some(self.secrets.redact_error(error));
+        }
+    }
+
+    pub(crate) fn warning(
+        &mut self,
+        code: &'static str,
+        message: String,
+        path: Option<String>,
+        span: Option<Range<usize>>,
+        suggestion: Option<String>,
+    ) {
+        let (file, path) = match path {
+            Some(path) => {
+                let (file, path) = self.resolve(&path);
+                (file, Some(path))
+            }
+            None => (0, None),
+        };
+        self.push(
+            file,
+            Severity::Warning,
+            code,
+            message,
+            path,
+            span,
+            suggestion,
+        );
+    }
+
+    /// Record a warning at a key path of a specific file
+    pub(crate) fn warning_at(
+        &mut self,
+        file: usize,
+        code: &'static str,
+        message: String,
+        path: Option<String>,
+        span: Option<Range<usize>>,
+        suggestion: Option<String>,
+    ) {
+        self.push(
+            file,
+            Severity::Warning,
+            code,
+            message,
+            path,
+            span,
+            suggestion,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push(
+        &mut self,
+        file: usize,
+        severity: Severity,
+        code: &'static str,
+        message: String,
+        path: Option<String>,
+        span: Option<Range<usize>>,
+        suggestion: Option<String>,
+    ) {
+        let file = &self.files[file];
+        let location = span.as_ref().map(|span| locate(&file.source, span));
+        self.diagnostics.push(Diagnostic {
+            severity,
+            code,
+            message: self.secrets.redact(&message),
+            file: file.name.clone(),
+            path,
+            span,
+            location,
+            suggestion: suggestion.map(|suggestion| self.secrets.redact(&suggestion)),
+        });
+    }
+
+    pub(crate) fn set_config(&mut self, config: Config) {
+        self.config = Some(config);
+    }
+
+    /// The parsed configuration, if it had no errors
+    pub fn config(&self) -> Option<&Config> {
+        self.config.as_ref()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error.is_some()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Warning)
+    }
+
+    /// Return the configuration, or the first error found
+    pub fn into_result(self) -> Result<Config, ConfigError> {
+        match (self.error, self.config) {
+            (Some(error), _) => Err(error),
+            (None, Some(config)) => Ok(config),
+            (None, None) => Err(ConfigError::Validation(
+                "Configuration could not be parsed".to_string(),
+            )),
+        }
+    }
+
+    /// Render all diagnostics compiler-style, with source snippets
+    ///
+    /// `file_name` names the checked file when the report doesn't know it.
+    pub fn render(&self, file_name: &str) -> String {
+        let mut out = String::new();
+        for diagnostic in &self.diagnostics {
+            self.render_one(&mut out, file_name, diagnostic);
+        }
+        out
+    }
+
+    fn render_one(&self, out: &mut String, file_name: &str, diagnostic: &Diagnostic) {
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let _ = writeln!(
+            out,
+            "{}[{}]: {}",
+            severity, diagnostic.code, diagnostic.message
+        );
+
+        let file = self
+            .files
+            .iter()
+            .find(|file| file.name == diagnostic.file)
+            .unwrap_or(&self.files[0]);
+        let file_name = diagnostic.file.as_deref().unwrap_or(file_name);
+
+        let gutter = diagnostic
+            .location
+            .map(|location| location.line.to_string().len())
+            .unwrap_or(1);
+        match diagnostic.location {
+            Some(location) => {
+                let _ = writeln!(
+                    out,
+                    "{:gutter$}--> {}:{}:{}",
+                    "", file_name, location.line, location.column
+                );
+                let text = file.source.lines().nth(location.line - 1).unwrap_or("");
+                let end_column = if location.end_line == location.line {
+                    location.end_column
+                } else {
+                    text.chars().count() + 1
+                };
+                let width = end_column.saturating_sub(location.column).max(1);
+                let _ = writeln!(out, "{:gutter$} |", "");
+                let _ = writeln!(out, "{} | {}", location.line, text);
+                let _ = writeln!(
+                    out,
+                    "{:gutter$} | {:pad$}{}",
+                    "",
+                    "",
+                    "^".repeat(width),
+                    pad = location.column - 1
+                );
+            }
+            None => {
+                let _ = writeln!(out, "{:gutter$}--> {}", "", file_name);
+            }
+        }
+
+        if let Some(path) = &diagnostic.path {
+            let _ = writeln!(out, "{:gutter$} = note: at `{}`", "", path);
+        }
+        if let Some(suggestion) = &diagnostic.suggestion {
+            let _ = writeln!(out, "{:gutter$} = help: {}", "", suggestion);
+        }
+        out.push('\n');
+    }
+}
+
+// Convert a byte range into 1-based line/column numbers
+fn locate(source: &str, span: &Range<usize>) -> Location {
+    let (line, column) = line_column(source, span.start);
+    let (end_line, end_column) = line_column(source, span.end.max(span.start));
+    Location {
+        line,
+        column,
+        end_line,
+        end_column,
+    }
+}
+
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+/// Suggest the closest candidate to `name`, if any is close enough
+pub(crate) fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    candidates
+        .into_iter()
+        .map(|candidate| (strsim::jaro_winkler(name, candidate), candidate))
+        .filter(|(score, _)| *score > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, candidate)| format!("did you mean `{}`?", candidate))
+}
+
+/// Pull the offending name and the accepted alternatives out of a serde
+/// "unknown field"/"unknown variant" message and suggest the closest one
+pub(crate) fn suggest_from_message(message: &str) -> Option<String> {
+    let rest = message
+        .strip_prefix("unknown field `")
+        .or_else(|| message.strip_prefix("unknown variant `"))?;
+    let (name, expected) = rest.split_once('`')?;
+    let candidates: Vec<&str> = expected.split('`').skip(1).step_by(2).collect();
+    did_you_mean(name, candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_column() {
+        let source = "a = 1\nbb = \"x\"\n";
+        assert_eq!(line_column(source, 0), (1, 1));
+        assert_eq!(line_column(source, 6), (2, 1));
+        assert_eq!(line_column(source, 11), (2, 6));
+    }
+
+    #[test]
+    fn test_suggestions() {
+        assert_eq!(
+            did_you_mean("kitchn", ["living_room", "kitchen"]),
+            Some("did you mean `kitchen`?".to_string())
+        );
+        assert_eq!(did_you_mean("garage", ["living_room", "kitchen"]), None);
+        assert_eq!(
+            suggest_from_message("unknown field `devcie`, expected `device` or `format`"),
+            Some("did you mean `device`?".to_string())
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use super::{Config, OutputKind};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Mix settings of one input on one output
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Levels {
+    pub gain_db: f32,
+    pub duck_db: f32,
+    pub priority: i32,
+}
+
+/// One difference between the running configuration and a new one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    InputAdded {
+        id: String,
+        kind: String,
+    },
+    InputRemoved {
+        id: String,
+        kind: String,
+    },
+    /// The input's settings changed, so it is stopped and started again
+    InputRestarted {
+        id: String,
+        kind: String,
+    },
+    OutputAdded {
+        id: String,
+        kind: String,
+    },
+    OutputRemoved {
+        id: String,
+        kind: String,
+    },
+    /// The output's settings changed, so its stream is restarted
+    OutputRestarted {
+        id: String,
+        kind: String,
+    },
+    RouteAdded {
+        input: String,
+        output: String,
+        levels: Levels,
+    },
+    RouteRemoved {
+        input: String,
+        output: String,
+    },
+    /// The gain, ducking or priority of a route changed; the input keeps
+    /// playing and the mix takes the new levels from the next buffer
+    RouteRegained {
+        input: String,
+        output: String,
+        from: Levels,
+        to: Levels,
+    },
+    /// The room is switched to the stream of another output
+    RoomRepointed {
+        room: String,
+        from: String,
+        to: String,
+    },
+    /// The other rooms playing the same mix as this one changed
+    RoomRegrouped {
+        room: String,
+        from: Vec<String>,
+        to: Vec<String>,
+    },
+}
+
+/// Everything applying a configuration would change, in the order inputs,
+/// outputs, routes, rooms
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+
+    /// Rooms whose playback is interrupted by the change, sorted
+    pub interrupted: Vec<String>,
+}
+
+impl Plan {
+    /// Whether the configurations describe the same setup
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl Config {
+    /// Plan the changes needed to go from this configuration to `next`
+    pub fn diff(&self, next: &Config) -> Plan {
+        let mut changes = Vec::new();
+        let mut interrupted = BTreeSet::new();
+
+        let old_rooms = rooms(self);
+        let new_rooms = rooms(next);
+        let old_routes = routes(self);
+        let new_routes = routes(next);
+
+        // Rooms an input plays in, before the change
+        let rooms_of = |input: &str| -> Vec<String> {
+            old_routes
+                .keys()
+                .filter(|(i, _)| i == input)
+                .filter_map(|(_, output)| room_of(self, output))
+                .collect()
+        };
+
+        for input in &self.inputs {
+            match next.inputs.iter().find(|i| i.id == input.id) {
+                None => {
+                    interrupted.extend(rooms_of(&input.id));
+                    changes.push(Change::InputRemoved {
+                        id: input.id.clone(),
+                        kind: input.kind.name().to_string(),
+                    });
+                }
+                Some(new) if new != input => {
+                    interrupted.extend(rooms_of(&input.id));
+                    changes.push(Change::InputRestarted {
+                        id: input.id.clone(),
+                        kind: new.kind.name().to_string(),
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        for input in &next.inputs {
+            if !self.inputs.iter().any(|i| i.id == input.id) {
+                changes.push(Change::InputAdded {
+                    id: input.id.clone(),
+                    kind: input.kind.name().to_string(),
+                });
+            }
+        }
+
+        for output in &self.outputs {
+            match next.outputs.iter().find(|o| o.id == output.id) {
+                None => {
+                    interrupted.extend(room_of(self, &output.id));
+                    changes.push(Change::OutputRemoved {
+                        id: output.id.clone(),
+                        kind: output.kind.name().to_string(),
+                    });
+                }
+                Some(new) if new != output => {
+                    interrupted.extend(room_of(self, &output.id));
+                    changes.push(Change::OutputRestarted {
+                        id: output.id.clone(),
+                        kind: new.kind.name().to_string(),
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        for output in &next.outputs {
+            if !self.outputs.iter().any(|o| o.id == output.id) {
+                changes.push(Change::OutputAdded {
+                    id: output.id.clone(),
+                    kind: output.kind.name().to_string(),
+                });
+            }
+        }
+
+        for ((input, output), from) in &old_routes {
+            match new_routes.get(&(input.clone(), output.clone())) {
+                None => changes.push(Change::RouteRemoved {
+                    input: input.clone(),
+                    output: output.clone(),
+                }),
+                Some(to) if to != from => changes.push(Change::RouteRegained {
+                    input: input.clone(),
+                    output: output.clone(),
+                    from: *from,
+                    to: *to,
+                }),
+                Some(_) => {}
+            }
+        }
+        for ((input, output), levels) in &new_routes {
+            if !old_routes.contains_key(&(input.clone(), output.clone())) {
+                changes.push(Change::RouteAdded {
+                    input: input.clone(),
+                    output: output.clone(),
+                    levels: *levels,
+                });
+            }
+        }
+
+        let old_groups = groups(&old_rooms, &old_routes);
+        let new_groups = groups(&new_rooms, &new_routes);
+        for (room, from) in &old_rooms {
+            let Some(to) = new_rooms.get(room) else {
+                continue;
+            };
+            let room_name = next.redact(room);
+            if from != to {
+                interrupted.insert(room_name.clone());
+                changes.push(Change::RoomRepointed {
+                    room: room_name.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+            }
+            if old_groups[room] != new_groups[room] {
+                interrupted.insert(room_name.clone());
+                changes.push(Change::RoomRegrouped {
+                    room: room_name,
+                    from: old_groups[room].iter().map(|r| self.redact(r)).collect(),
+                    to: new_groups[room].iter().map(|r| next.redact(r)).collect(),
+                });
+            }
+        }
+
+        Plan {
+            changes,
+            interrupted: interrupted.into_iter().collect(),
+        }
+    }
+}
+
+// Sonos room of an output, redacted for display
+fn room_of(config: &Config, output_id: &str) -> Option<String> {
+    config
+        .outputs
+        .iter()
+        .find(|o| o.id == output_id)
+        .and_then(|o| match &o.kind {
+            OutputKind::Sonos { room, .. } => Some(config.redact(room)),
+            _ => None,
+        })
+}
+
+// Output driving each Sonos room; the first one wins, like the daemon
+fn rooms(config: &Config) -> BTreeMap<String, String> {
+    let mut rooms = BTreeMap::new();
+    for output in &config.outputs {
+        if let OutputKind::Sonos { room, .. } = &output.kind {
+            rooms
+                .entry(room.clone())
+                .or_insert_with(|| output.id.clone());
+        }
+    }
+    rooms
+}
+
+// Mix settings of every (input, output) pair; only the first route from an
+// input to an output is mixed, like in `Router`
+fn routes(config: &Config) -> BTreeMap<(String, String), Levels> {
+    let mut routes = BTreeMap::new();
+    for route in &config.routes {
+        let levels = Levels {
+            gain_db: route.gain_db,
+            duck_db: route.duck_db.abs(),
+            priority: route.effective_priority(),
+        };
+        for output in &route.outputs {
+            routes
+                .entry((route.input.clone(), output.clone()))
+                .or_insert(levels);
+        }
+    }
+    routes
+}
+
+// The other rooms receiving exactly the same mix as each room
+fn groups(
+    rooms: &BTreeMap<String, String>,
+    routes: &BTreeMap<(String, String), Levels>,
+) -> HashMap<String, Vec<String>> {
+    let mix = |output: &str| -> String {
+        routes
+            .iter()
+            .filter(|((_, o), _)| o == output)
+            .map(|((input, _), levels)| format!("{}:{:?}", input, levels))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let mixes: BTreeMap<&String, String> = rooms
+        .iter()
+        .map(|(room, output)| (room, mix(output)))
+        .collect();
+
+    mixes
+        .iter()
+        .map(|(&room, m)| {
+            let peers = mixes
+                .iter()
+                .filter(|(&other, other_mix)| other != room && !m.is_empty() && *other_mix == m)
+                .map(|(&other, _)| other.clone())
+                .collect();
+            (room.clone(), peers)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Route;
+
+    const RUNNING: &str = r#"
+[[inputs]]
+id = "music"
+kind = "alsa"
+
+[[inputs]]
+id = "chime"
+kind = "file"
+path = "chime.pcm"
+
+[[inputs]]
+id = "radio"
+kind = "http"
+url = "http://radio.example/stream"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[outputs]]
+id = "office"
+kind = "sonos"
+room = "Office"
+
+[[outputs]]
+id = "den"
+kind = "sonos"
+room = "Den"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen", "office"]
+
+[[routes]]
+input = "chime"
+outputs = ["kitchen", "office"]
+duck_db = 12
+
+[[routes]]
+input = "radio"
+outputs = ["den"]
+"#;
+
+    #[test]
+    fn test_unchanged() {
+        let config = Config::check(RUNNING).into_result().unwrap();
+        let plan = config.diff(&config.clone());
+        assert!(plan.is_empty());
+        assert!(plan.interrupted.is_empty());
+    }
+
+    #[test]
+    fn test_change_plan() {
+        let running = Config::check(RUNNING).into_result().unwrap();
+        let next = Config::check(
+            &RUNNING
+                .replace("chime.pcm", "chime2.pcm")
+                .replace(
+                    "id = \"radio\"\nkind = \"http\"",
+                    "id = \"tv\"\nkind = \"http\"",
+                )
+                .replace("input = \"radio\"", "input = \"tv\"")
+                .replace(
+                    "id = \"den\"\nkind = \"sonos\"\nroom = \"Den\"",
+                    "id = \"den2\"\nkind = \"sonos\"\nroom = \"Den\"",
+                )
+                .replace("outputs = [\"den\"]", "outputs = [\"den2\"]")
+                .replace("duck_db = 12", "duck_db = -20"),
+        )
+        .into_result()
+        .unwrap();
+
+        let plan = running.diff(&next);
+        let levels = |gain_db, duck_db, priority| Levels {
+            gain_db,
+            duck_db,
+            priority,
+        };
+        assert_eq!(
+            plan.changes,
+            vec![
+                Change::InputRestarted {
+                    id: "chime".into(),
+                    kind: "file".into()
+                },
+                Change::InputRemoved {
+                    id: "radio".into(),
+                    kind: "http".into()
+                },
+                Change::InputAdded {
+                    id: "tv".into(),
+                    kind: "http".into()
+                },
+                Change::OutputRemoved {
+                    id: "den".into(),
+                    kind: "sonos".into()
+                },
+                Change::OutputAdded {
+                    id: "den2".into(),
+                    kind: "sonos".into()
+                },
+                Change::RouteRegained {
+                    input: "chime".into(),
+                    output: "kitchen".into(),
+                    from: levels(0.0, 12.0, 1),
+                    to: levels(0.0, 20.0, 1),
+                },
+                Change::RouteRegained {
+                    input: "chime".into(),
+                    output: "office".into(),
+                    from: levels(0.0, 12.0, 1),
+                    to: levels(0.0, 20.0, 1),
+                },
+                Change::RouteRemoved {
+                    input: "radio".into(),
+                    output: "den".into()
+                },
+                Change::RouteAdded {
+                    input: "tv".into(),
+                    output: "den2".into(),
+                    levels: levels(0.0, 0.0, 0),
+                },
+                Change::RoomRepointed {
+                    room: "Den".into(),
+                    from: "den".into(),
+                    to: "den2".into()
+                },
+            ]
+        );
+        assert_eq!(plan.interrupted, vec!["Den", "Kitchen", "Office"]);
+    }
+
+    #[test]
+    fn test_regrouped_rooms() {
+        let running = Config::check(RUNNING).into_result().unwrap();
+        let mut next = running.clone();
+        next.routes[0].outputs = vec!["kitchen".to_string()];
+        next.routes.push(Route {
+            id: None,
+            input: "music".to_string(),
+            outputs: vec!["office".to_string()],
+            gain_db: -6.0,
+            duck_db: 0.0,
+            priority: None,
+        });
+
+        let plan = running.diff(&next);
+        assert!(plan.changes.contains(&Change::RoomRegrouped {
+            room: "Kitchen".into(),
+            from: vec!["Office".into()],
+            to: vec![],
+        }));
+        assert_eq!(plan.interrupted, vec!["Kitchen", "Office"]);
+
+        // Changing a gain is applied live and interrupts nothing
+        let mut next = running.clone();
+        next.routes[2].gain_db = -3.0;
+        let plan = running.diff(&next);
+        assert_eq!(plan.changes.len(), 1);
+        assert!(plan.interrupted.is_empty());
+    }
+}
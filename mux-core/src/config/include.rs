@@ -0,0 +1,722 @@
+use super::diagnostic::Origins;
+use super::interpolate::interpolate;
+use super::migrate::{migrate, CURRENT_VERSION};
+use super::{ConfigError, Report};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{ArrayOfTables, ImDocument, InlineTable, Item, Table, Value};
+
+/// Top-level arrays whose entries are merged by `id` across files
+const MERGED_ARRAYS: &[&str] = &["inputs", "outputs", "routes"];
+
+/// The tables of one file, in the order they are merged
+struct Layer {
+    file: usize,
+    table: Table,
+}
+
+/// Loads a file and everything it includes, depth first
+struct Loader<'a> {
+    report: &'a mut Report,
+    layers: Vec<Layer>,
+    // Files currently being loaded, to detect cycles
+    stack: Vec<PathBuf>,
+    loaded: HashSet<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+/// Resolve the `include` list of `root` (recursively) and merge every file
+/// beneath it, returning the merged table and the paths of all files read
+///
+/// Includes are relative to the directory of `path`, or to the working
+/// directory when the configuration didn't come from a file.
+pub(crate) fn resolve(
+    report: &mut Report,
+    root: Table,
+    path: Option<&Path>,
+) -> (Table, Vec<PathBuf>) {
+    let mut loader = Loader {
+        report,
+        layers: Vec::new(),
+        stack: Vec::new(),
+        loaded: HashSet::new(),
+        files: Vec::new(),
+    };
+
+    let path = path.map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    if let Some(path) = &path {
+        loader.loaded.insert(path.clone());
+        loader.stack.push(path.clone());
+        loader.files.push(path.clone());
+    }
+    loader.load(0, root, path.as_deref());
+
+    let Loader {
+        report,
+        layers,
+        files,
+        ..
+    } = loader;
+    let (merged, origins) = merge(report, layers);
+    report.set_origins(origins);
+    (merged, files)
+}
+
+impl Loader<'_> {
+    fn load(&mut self, file: usize, mut table: Table, path: Option<&Path>) {
+        // Each file is migrated on its own, since they may be written for
+        // different versions
+        let version_span = table.get("version").and_then(Item::span);
+        match migrate(&mut table) {
+            Ok(migrated) if migrated.is_outdated() => {
+                let name = path
+                    .and_then(Path::file_name)
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "<file>".to_string());
+                self.report.warning_at(
+                    file,
+                    "deprecated-version",
+                    format!(
+                        "Config version {} is deprecated and was migrated to version {}",
+                        migrated.from, CURRENT_VERSION
+                    ),
+                    Some("version".to_string()),
+                    version_span,
+                    Some(format!(
+                        "run `sonos-mux migrate {}` to update the file",
+                        name
+                    )),
+                );
+            }
+            Ok(_) => {}
+            Err(error) => {
+                self.report
+                    .error_at(file, error, Some("version".to_string()), version_span, None);
+                // Keep going so the rest of the file is still checked
+                table.remove("version");
+            }
+        }
+
+        let dir = path
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        for (i, pattern, span) in include_patterns(self.report, file, &table) {
+            let key = format!("include[{}]", i);
+            let pattern = match interpolate(&pattern, &|name| std::env::var(name).ok()) {
+                Ok((pattern, _)) => pattern,
+                Err(message) => {
+                    self.report.error_at(
+                        file,
+                        ConfigError::Interpolation(message),
+                        Some(key),
+                        span,
+                        None,
+                    );
+                    continue;
+                }
+            };
+
+            let paths = match expand_pattern(&dir, &pattern) {
+                Ok(paths) => paths,
+                Err(message) => {
+                    self.report.error_at(
+                        file,
+                        ConfigError::Include(message),
+                        Some(key),
+                        span,
+                        None,
+                    );
+                    continue;
+                }
+            };
+
+            for included in paths {
+                let canonical = fs::canonicalize(&included).unwrap_or_else(|_| included.clone());
+                if self.stack.contains(&canonical) {
+                    self.report.error_at(
+                        file,
+                        ConfigError::Include(format!("{} includes itself", included.display())),
+                        Some(key.clone()),
+                        span.clone(),
+                        None,
+                    );
+                    continue;
+                }
+                // A file included from several places is only merged once
+                if !self.loaded.insert(canonical.clone()) {
+                    continue;
+                }
+
+                let source = match fs::read_to_string(&included) {
+                    Ok(source) => source,
+                    Err(e) => {
+                        self.report.error_at(
+                            file,
+                            ConfigError::Include(format!(
+                                "Failed to read {}: {}",
+                                included.display(),
+                                e
+                            )),
+                            Some(key.clone()),
+                            span.clone(),
+                            None,
+                        );
+                        continue;
+                    }
+                };
+
+                let index = self
+                    .report
+                    .add_file(included.display().to_string(), &source);
+                self.files.push(canonical.clone());
+
+                match ImDocument::parse(source) {
+                    Ok(document) => {
+                        self.stack.push(canonical.clone());
+                        self.load(index, document.as_table().clone(), Some(&canonical));
+                        self.stack.pop();
+                    }
+                    Err(e) => self.report.error_at(
+                        index,
+                        ConfigError::Toml(e.message().to_string()),
+                        None,
+                        e.span(),
+                        None,
+                    ),
+                }
+            }
+        }
+
+        self.layers.push(Layer { file, table });
+    }
+}
+
+// The `include` entries of a file with their positions
+fn include_patterns(
+    report: &mut Report,
+    file: usize,
+    table: &Table,
+) -> Vec<(usize, String, Option<std::ops::Range<usize>>)> {
+    let Some(item) = table.get("include") else {
+        return Vec::new();
+    };
+    let Some(array) = item.as_array() else {
+        report.error_at(
+            file,
+            ConfigError::Include("`include` must be an array of paths".to_string()),
+            Some("include".to_string()),
+            item.span(),
+            None,
+        );
+        return Vec::new();
+    };
+
+    let mut patterns = Vec::new();
+    for (i, value) in array.iter().enumerate() {
+        match value.as_str() {
+            Some(pattern) => patterns.push((i, pattern.to_string(), value.span())),
+            None => report.error_at(
+                file,
+                ConfigError::Include("`include` entries must be strings".to_string()),
+                Some(format!("include[{}]", i)),
+                value.span(),
+                None,
+            ),
+        }
+    }
+    patterns
+}
+
+// Paths matched by an include pattern, in sorted order; a pattern without
+// wildcards must name an existing file
+fn expand_pattern(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let path = dir.join(pattern);
+    if !pattern.contains(['*', '?', '[']) {
+        if !path.is_file() {
+            return Err(format!("Included file {} does not exist", path.display()));
+        }
+        return Ok(vec![path]);
+    }
+
+    let pattern = path.to_string_lossy();
+    let entries = glob::glob(&pattern)
+        .map_err(|e| format!("Invalid include pattern `{}`: {}", pattern, e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// One entry of a merged array
+struct Element {
+    file: usize,
+    path: String,
+    table: ElementTable,
+}
+
+enum ElementTable {
+    Table(Table),
+    Inline(InlineTable),
+}
+
+impl ElementTable {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            ElementTable::Table(table) => table.get(key).and_then(Item::as_value),
+            ElementTable::Inline(table) => table.get(key),
+        }
+    }
+
+    fn id(&self) -> Option<&str> {
+        self.get("id").and_then(Value::as_str)
+    }
+}
+
+/// A top-level entry of the merged table
+enum Entry {
+    /// Replaced wholesale by later files
+    Whole(usize, Item),
+    /// Merged by `id`
+    Elements(Vec<Element>),
+}
+
+// Merge layers in order, later files overriding earlier ones:
+//
+// - entries of `inputs`, `outputs` and `routes` replace the entry with the
+//   same `id` from an earlier file in place, or are appended; `remove = true`
+//   drops the earlier entry instead
+// - routes without an `id` are always appended
+// - any other top-level key is replaced as a whole
+fn merge(report: &mut Report, layers: Vec<Layer>) -> (Table, Origins) {
+    let mut entries: Vec<(String, Entry)> = Vec::new();
+
+    for layer in layers {
+        for (key, item) in layer.table.into_iter() {
+            let key = key.to_string();
+            if key == "include" && layer.file != 0 {
+                continue;
+            }
+
+            let elements = if MERGED_ARRAYS.contains(&key.as_str()) {
+                elements(layer.file, &key, &item)
+            } else {
+                None
+            };
+            let position = entries.iter().position(|(k, _)| *k == key);
+
+            let Some(elements) = elements else {
+                let entry = Entry::Whole(layer.file, item);
+                match position {
+                    Some(i) => entries[i].1 = entry,
+                    None => entries.push((key, entry)),
+                }
+                continue;
+            };
+
+            let merged = match position {
+                Some(i) => match &mut entries[i].1 {
+                    Entry::Elements(merged) => merged,
+                    entry => {
+                        *entry = Entry::Elements(Vec::new());
+                        let Entry::Elements(merged) = entry else {
+                            unreachable!()
+                        };
+                        merged
+                    }
+                },
+                None => {
+                    entries.push((key, Entry::Elements(Vec::new())));
+                    let Some((_, Entry::Elements(merged))) = entries.last_mut() else {
+                        unreachable!()
+                    };
+                    merged
+                }
+            };
+            merge_elements(report, merged, elements);
+        }
+    }
+
+    let mut merged = Table::new();
+    let mut origins = Origins::new();
+    for (key, entry) in entries {
+        match entry {
+            Entry::Whole(file, item) => {
+                origins.insert(key.clone(), (file, key.clone()));
+                merged.insert(&key, item);
+            }
+            Entry::Elements(elements) => {
+                if let Some(last) = elements.last() {
+                    origins.insert(key.clone(), (last.file, key.clone()));
+                }
+                let inline = elements
+                    .iter()
+                    .any(|element| matches!(element.table, ElementTable::Inline(_)));
+
+                let mut tables = ArrayOfTables::new();
+                let mut values = toml_edit::Array::new();
+                for (i, element) in elements.into_iter().enumerate() {
+                    origins.insert(format!("{}[{}]", key, i), (element.file, element.path));
+                    match (element.table, inline) {
+                        (ElementTable::Table(table), false) => tables.push(table),
+                        (ElementTable::Table(table), true) => {
+                            values.push(table.into_inline_table())
+                        }
+                        (ElementTable::Inline(table), _) => values.push(table),
+                    }
+                }
+                let item = if inline {
+                    Item::Value(Value::Array(values))
+                } else {
+                    Item::ArrayOfTables(tables)
+                };
+                merged.insert(&key, item);
+            }
+        }
+    }
+
+    (merged, origins)
+}
+
+// The entries of an array of tables, or `None` if `item` isn't one
+fn elements(file: usize, key: &str, item: &Item) -> Option<Vec<Element>> {
+    let element = |i: usize, table: ElementTable| Element {
+        file,
+        path: format!("{}[{}]", key, i),
+        table,
+    };
+    match item {
+        Item::ArrayOfTables(array) => Some(
+            array
+                .iter()
+                .enumerate()
+                .map(|(i, table)| element(i, ElementTable::Table(table.clone())))
+                .collect(),
+        ),
+        Item::Value(Value::Array(array)) => array
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let table = value.as_inline_table()?.clone();
+                Some(element(i, ElementTable::Inline(table)))
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+fn merge_elements(report: &mut Report, merged: &mut Vec<Element>, elements: Vec<Element>) {
+    // Only entries from earlier files are overridden; duplicates within one
+    // file are left for validation to report
+    let earlier: Vec<Option<String>> = merged
+        .iter()
+        .map(|element| element.table.id().map(str::to_string))
+        .collect();
+    let find = |id: &str| earlier.iter().position(|e| e.as_deref() == Some(id));
+
+    let mut removed = HashSet::new();
+    for element in elements {
+        let remove = element.table.get("remove").and_then(Value::as_bool) == Some(true);
+        let id = element.table.id().map(str::to_string);
+
+        match (id, remove) {
+            (Some(id), true) => match find(&id) {
+                Some(i) => {
+                    removed.insert(i);
+                }
+                None => {
+                    let span = element.table.get("id").and_then(Value::span);
+                    report.error_at(
+                        element.file,
+                        ConfigError::IdNotFound(id),
+                        Some(format!("{}.id", element.path)),
+                        span,
+                        None,
+                    );
+                }
+            },
+            (Some(id), false) => match find(&id) {
+                Some(i) => merged[i] = element,
+                None => merged.push(element),
+            },
+            (None, _) => merged.push(element),
+        }
+    }
+
+    let mut i = 0;
+    merged.retain(|_| {
+        i += 1;
+        !removed.contains(&(i - 1))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, ConfigError, InputKind};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn write(dir: &Path, name: &str, content: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn fixture() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            "common.toml",
+            r#"
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+
+[[inputs]]
+id = "chime"
+kind = "silence"
+
+[[routes]]
+id = "chimes"
+input = "chime"
+outputs = ["kitchen", "office"]
+duck_db = 12.0
+"#,
+        );
+        write(
+            dir.path(),
+            "rooms/kitchen.toml",
+            r#"
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "roon_main"
+outputs = ["kitchen"]
+"#,
+        );
+        write(
+            dir.path(),
+            "rooms/office.toml",
+            r#"
+include = ["../common.toml"]
+
+[[outputs]]
+id = "office"
+kind = "sonos"
+room = "Office"
+"#,
+        );
+        dir
+    }
+
+    #[test]
+    fn test_includes_and_overlays() {
+        let dir = fixture();
+        write(
+            dir.path(),
+            "garage.toml",
+            r#"
+include = ["common.toml", "rooms/*.toml"]
+
+# This host's sound card
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+device = "hw:2,0"
+
+[[routes]]
+id = "chimes"
+input = "chime"
+outputs = ["kitchen"]
+
+[[inputs]]
+id = "radio"
+kind = "http"
+url = "http://radio.example/live"
+
+[[routes]]
+input = "radio"
+outputs = ["office"]
+"#,
+        );
+
+        let config = Config::load(dir.path().join("garage.toml")).unwrap();
+
+        let inputs: Vec<_> = config.inputs.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(inputs, vec!["roon_main", "chime", "radio"]);
+        match &config.inputs[0].kind {
+            InputKind::Alsa { device, .. } => assert_eq!(device, "hw:2,0"),
+            other => panic!("unexpected kind {:?}", other),
+        }
+
+        let outputs: Vec<_> = config.outputs.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(outputs, vec!["kitchen", "office"]);
+
+        // The route with an ID is replaced in place, the others are appended
+        let routes: Vec<_> = config
+            .routes
+            .iter()
+            .map(|r| (r.input.as_str(), r.outputs.join(",")))
+            .collect();
+        assert_eq!(
+            routes,
+            vec![
+                ("chime", "kitchen".to_string()),
+                ("roon_main", "kitchen".to_string()),
+                ("radio", "office".to_string()),
+            ]
+        );
+
+        // common.toml is included twice but only read once
+        assert_eq!(config.files.len(), 4);
+        assert_eq!(config.include, vec!["common.toml", "rooms/*.toml"]);
+    }
+
+    #[test]
+    fn test_remove_entries() {
+        let dir = fixture();
+        write(
+            dir.path(),
+            "nas.toml",
+            r#"
+include = ["rooms/office.toml"]
+
+[[inputs]]
+id = "roon_main"
+remove = true
+
+[[routes]]
+id = "chimes"
+remove = true
+
+[[outputs]]
+id = "garage"
+remove = true
+"#,
+        );
+
+        let report = Config::check_file(dir.path().join("nas.toml"));
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, "unknown-id");
+        assert_eq!(errors[0].path.as_deref(), Some("outputs[0].id"));
+
+        fs::write(
+            dir.path().join("nas.toml"),
+            r#"
+include = ["rooms/office.toml"]
+
+[[inputs]]
+id = "roon_main"
+remove = true
+
+[[routes]]
+id = "chimes"
+remove = true
+"#,
+        )
+        .unwrap();
+        let config = Config::load(dir.path().join("nas.toml")).unwrap();
+        let inputs: Vec<_> = config.inputs.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(inputs, vec!["chime"]);
+        assert!(config.routes.is_empty());
+    }
+
+    #[test]
+    fn test_errors_name_their_file() {
+        let dir = fixture();
+        write(
+            dir.path(),
+            "rooms/bad.toml",
+            r#"
+[[outputs]]
+id = "bathroom"
+kind = "sonos"
+room = "Bathroom"
+buffer_sec = 0
+"#,
+        );
+        write(
+            dir.path(),
+            "nuc.toml",
+            r#"
+include = ["rooms/*.toml", "missing.toml"]
+
+[[routes]]
+input = "roon_main"
+outputs = ["bathroom", "cellar"]
+"#,
+        );
+
+        let report = Config::check_file(dir.path().join("nuc.toml"));
+        let errors: Vec<_> = report
+            .errors()
+            .map(|e| {
+                let file = e.file.as_deref().unwrap_or_default();
+                let name = Path::new(file).file_name().unwrap().to_string_lossy();
+                (e.code, name.to_string(), e.path.clone().unwrap_or_default())
+            })
+            .collect();
+
+        assert_eq!(
+            errors,
+            vec![
+                ("include", "nuc.toml".to_string(), "include[1]".to_string()),
+                (
+                    "invalid-value",
+                    "bad.toml".to_string(),
+                    "outputs[0].buffer_sec".to_string()
+                ),
+                (
+                    "unknown-id",
+                    "nuc.toml".to_string(),
+                    "routes[0].outputs[1]".to_string()
+                ),
+            ]
+        );
+        assert_eq!(report.errors().nth(1).unwrap().location.unwrap().line, 6);
+        assert!(report.render("nuc.toml").contains("bad.toml:6:"));
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = TempDir::new().unwrap();
+        write(dir.path(), "a.toml", "include = [\"b.toml\"]\n");
+        write(dir.path(), "b.toml", "include = [\"a.toml\"]\n");
+
+        assert!(matches!(
+            Config::load(dir.path().join("a.toml")),
+            Err(ConfigError::Include(_))
+        ));
+    }
+
+    #[test]
+    fn test_files_read_next_to_their_config() {
+        let dir = TempDir::new().unwrap();
+        write(dir.path(), "nuc.toml", "include = [\"radio/*.toml\"]\n");
+        write(
+            dir.path(),
+            "radio/stream.toml",
+            "[[inputs]]\nid = \"radio\"\nkind = \"http\"\nurl_file = \"url\"\n",
+        );
+        write(
+            dir.path(),
+            "radio/url",
+            "http://radio.example/live?token=hunter22\n",
+        );
+
+        let config = Config::load(dir.path().join("nuc.toml")).unwrap();
+        match &config.inputs[0].kind {
+            InputKind::Http { url } => assert_eq!(config.redact(url), "********"),
+            other => panic!("unexpected kind {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use super::ConfigError;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use toml_edit::{Item, TableLike, Value};
+
+/// Suffix that makes a key read its value from a file, e.g. `url_file`
+const FILE_SUFFIX: &str = "_file";
+
+/// Secrets shorter than this are too ambiguous to scrub from free text
+const MIN_REDACTED_LEN: usize = 4;
+
+const REDACTED: &str = "********";
+
+/// Values read through `*_file` keys or from the environment variables a
+/// config lists in `secret_env`, which must not be shown in output or logs
+#[derive(Clone, Default)]
+pub struct Secrets(Vec<String>);
+
+impl Secrets {
+    pub(crate) fn add(&mut self, value: &str) {
+        let value = value.trim();
+        if value.len() >= MIN_REDACTED_LEN && !self.0.iter().any(|s| s == value) {
+            self.0.push(value.to_string());
+            // Replace longer secrets first so one containing another is
+            // redacted completely
+            self.0.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Replace every secret in `text` with a placeholder
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for secret in &self.0 {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+        text
+    }
+
+    /// Redact the message carried by `error`
+    pub(crate) fn redact_error(&self, error: ConfigError) -> ConfigError {
+        if self.is_empty() {
+            return error;
+        }
+        match error {
+            ConfigError::Toml(message) => ConfigError::Toml(self.redact(&message)),
+            ConfigError::Validation(message) => ConfigError::Validation(self.redact(&message)),
+            ConfigError::UnknownInputKind(kind) => {
+                ConfigError::UnknownInputKind(self.redact(&kind))
+            }
+            ConfigError::UnknownOutputKind(kind) => {
+                ConfigError::UnknownOutputKind(self.redact(&kind))
+            }
+            ConfigError::DuplicateId(id) => ConfigError::DuplicateId(self.redact(&id)),
+            ConfigError::IdNotFound(id) => ConfigError::IdNotFound(self.redact(&id)),
+            ConfigError::Interpolation(message) => {
+                ConfigError::Interpolation(self.redact(&message))
+            }
+            ConfigError::Include(message) => ConfigError::Include(self.redact(&message)),
+            ConfigError::UnsupportedVersion(message) => {
+                ConfigError::UnsupportedVersion(self.redact(&message))
+            }
+            error @ ConfigError::Io(_) => error,
+        }
+    }
+}
+
+// Never print the secrets themselves
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secrets({} redacted)", self.0.len())
+    }
+}
+
+/// What expanding a configuration needs to know about it
+pub(crate) struct Expansion<'a> {
+    /// Environment variables whose values are secrets
+    pub secret_env: &'a [String],
+    /// The directory relative `*_file` paths at a key path are read from:
+    /// that of the file the key came from
+    pub dir: &'a dyn Fn(&str) -> PathBuf,
+}
+
+/// Expand `${VAR}`/`${VAR:-default}` in every string value below `item` and
+/// resolve `*_file` keys, returning the key path of each failure. Values
+/// read from files, and those of the variables in `secret_env`, are added
+/// to `secrets`
+pub(crate) fn expand(
+    item: &mut Item,
+    path: &str,
+    expansion: &Expansion,
+    secrets: &mut Secrets,
+) -> Vec<(String, ConfigError)> {
+    let mut expander = Expander {
+        expansion,
+        secrets,
+        errors: Vec::new(),
+    };
+    expander.item(item, path);
+    expander.errors
+}
+
+struct Expander<'a> {
+    expansion: &'a Expansion<'a>,
+    secrets: &'a mut Secrets,
+    errors: Vec<(String, ConfigError)>,
+}
+
+impl Expander<'_> {
+    fn item(&mut self, item: &mut Item, path: &str) {
+        match item {
+            Item::Value(value) => self.value(value, path),
+            Item::Table(table) => self.table(table, path),
+            Item::ArrayOfTables(array) => {
+                for (i, table) in array.iter_mut().enumerate() {
+                    self.table(table, &format!("{}[{}]", path, i));
+                }
+            }
+            Item::None => {}
+        }
+    }
+
+    fn value(&mut self, value: &mut Value, path: &str) {
+        match value {
+            Value::String(string) if string.value().contains('$') => {
+                match interpolate(string.value(), &|name| std::env::var(name).ok()) {
+                    Ok((expanded, substituted)) => {
+                        for (name, value) in &substituted {
+                            if self.expansion.secret_env.contains(name) {
+                                self.secrets.add(value);
+                            }
+                        }
+                        *value = Value::from(expanded);
+                    }
+                    Err(message) => self
+                        .errors
+                        .push((path.to_string(), ConfigError::Interpolation(message))),
+                }
+            }
+            Value::Array(array) => {
+                for (i, value) in array.iter_mut().enumerate() {
+                    self.value(value, &format!("{}[{}]", path, i));
+                }
+            }
+            Value::InlineTable(table) => self.table(table, path),
+            _ => {}
+        }
+    }
+
+    fn table(&mut self, table: &mut dyn TableLike, path: &str) {
+        let join = |key: &str| {
+            if path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", path, key)
+            }
+        };
+
+        for (key, item) in table.iter_mut() {
+            let path = join(&key);
+            self.item(item, &path);
+        }
+
+        // Resolve `*_file` keys once their paths have been expanded
+        let file_keys: Vec<String> = table
+            .iter()
+            .filter(|(key, item)| key.ends_with(FILE_SUFFIX) && item.is_str())
+            .map(|(key, _)| key.to_string())
+            .collect();
+
+        for key in file_keys {
+            let target = &key[..key.len() - FILE_SUFFIX.len()];
+            let file = table.get(&key).and_then(Item::as_str).unwrap_or_default();
+
+            if table.contains_key(target) {
+                table.remove(&key);
+                self.errors.push((
+                    join(&key),
+                    ConfigError::Interpolation(format!("`{}` and `{}` are both set", target, key)),
+                ));
+                continue;
+            }
+
+            let value = match fs::read_to_string((self.expansion.dir)(&join(&key)).join(file)) {
+                Ok(contents) => {
+                    let contents = contents.trim_end_matches(['\n', '\r']).to_string();
+                    self.secrets.add(&contents);
+                    contents
+                }
+                Err(e) => {
+                    self.errors.push((
+                        join(&key),
+                        ConfigError::Interpolation(format!(
+                            "Failed to read `{}` from {}: {}",
+                            target, file, e
+                        )),
+                    ));
+                    // Keep the target key set so the error isn't followed by
+                    // a "missing field" one
+                    file.to_string()
+                }
+            };
+            table.remove(&key);
+            table.insert(target, Item::Value(Value::from(value)));
+        }
+    }
+}
+
+/// Expand `${VAR}` and `${VAR:-default}` references in `input`, with `$${`
+/// producing a literal `${`
+///
+/// Returns the expanded string and the name and value of each variable
+/// that came from `lookup`.
+pub(crate) fn interpolate(
+    input: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(String, Vec<(String, String)>), String> {
+    let mut output = String::with_capacity(input.len());
+    let mut substituted = Vec::new();
+    let mut rest = input;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = after;
+            continue;
+        }
+        let Some(after) = rest.strip_prefix("${") else {
+            output.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("Unterminated `${{` in \"{}\"", input))?;
+        let reference = &after[..end];
+        rest = &after[end + 1..];
+
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid variable name `{}`", name));
+        }
+
+        match (lookup(name), default) {
+            (Some(value), _) => {
+                output.push_str(&value);
+                substituted.push((name.to_string(), value));
+            }
+            (None, Some(default)) => output.push_str(default),
+            (None, None) => {
+                return Err(format!("Environment variable `{}` is not set", name));
+            }
+        }
+    }
+    output.push_str(rest);
+
+    Ok((output, substituted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "TOKEN" => Some("s3cr3t-token".to_string()),
+            "DEVICE" => Some("hw:2,0".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let (expanded, substituted) =
+            interpolate("http://radio/live?token=${TOKEN}", &lookup).unwrap();
+        assert_eq!(expanded, "http://radio/live?token=s3cr3t-token");
+        assert_eq!(
+            substituted,
+            vec![("TOKEN".to_string(), "s3cr3t-token".to_string())]
+        );
+
+        let (expanded, _) = interpolate("${MISSING:-hw:Loopback,1}", &lookup).unwrap();
+        assert_eq!(expanded, "hw:Loopback,1");
+
+        let (expanded, substituted) = interpolate("cost $5, literal $${TOKEN}", &lookup).unwrap();
+        assert_eq!(expanded, "cost $5, literal ${TOKEN}");
+        assert!(substituted.is_empty());
+    }
+
+    #[test]
+    fn test_interpolate_errors() {
+        assert!(interpolate("${MISSING}", &lookup)
+            .unwrap_err()
+            .contains("MISSING"));
+        assert!(interpolate("${TOKEN", &lookup).is_err());
+        assert!(interpolate("${BAD NAME}", &lookup).is_err());
+    }
+
+    #[test]
+    fn test_redact() {
+        let mut secrets = Secrets::default();
+        secrets.add("s3cr3t-token");
+        secrets.add("abc");
+
+        assert_eq!(
+            secrets.redact("GET /live?token=s3cr3t-token&abc=1"),
+            "GET /live?token=********&abc=1"
+        );
+        assert!(!format!("{:?}", secrets).contains("s3cr3t"));
+    }
+}
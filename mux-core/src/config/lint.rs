@@ -0,0 +1,408 @@
+use super::{Config, OutputKind, Route};
+use crate::mixer::{db_to_lin, lin_to_db};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Names of every lint, usable in `[lints] allow`
+pub const LINTS: &[&str] = &[
+    "unrouted-output",
+    "unused-input",
+    "duplicate-route",
+    "duplicate-room",
+    "ineffective-duck",
+    "clipping-gain",
+];
+
+/// Lint settings from the `[lints]` table
+///
+/// Each `allow` entry is either a lint name, which silences it everywhere, or
+/// `name:id`, which silences it for a single input or output.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Lints {
+    /// Lints to silence, as `name` or `name:id`
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl Lints {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty()
+    }
+
+    /// Whether `code` is allowed for `subject`
+    pub fn allows(&self, code: &str, subject: &str) -> bool {
+        self.allow.iter().any(|entry| match entry.split_once(':') {
+            Some((name, id)) => name == code && id == subject,
+            None => entry == code,
+        })
+    }
+}
+
+/// A configuration that is valid but probably not what was intended
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub code: &'static str,
+
+    /// ID of the input or output the lint is about
+    pub subject: String,
+
+    pub message: String,
+
+    /// Key path the lint points at, e.g. `routes[2]`
+    pub path: String,
+}
+
+impl Config {
+    /// Run every lint that isn't allowed by the `[lints]` table
+    pub fn lint(&self) -> Vec<Lint> {
+        let mut lints = Vec::new();
+
+        // Routes feeding each output
+        let mut output_routes: HashMap<&str, Vec<&Route>> = HashMap::new();
+        for route in &self.routes {
+            for output_id in &route.outputs {
+                output_routes.entry(output_id).or_default().push(route);
+            }
+        }
+
+        for (i, output) in self.outputs.iter().enumerate() {
+            if !output_routes.contains_key(output.id.as_str()) {
+                lints.push(Lint {
+                    code: "unrouted-output",
+                    subject: output.id.clone(),
+                    message: format!(
+                        "Output '{}' has no routes and will not be started",
+                        output.id
+                    ),
+                    path: format!("outputs[{}].id", i),
+                });
+            }
+        }
+
+        let routed: HashSet<&str> = self
+            .routes
+            .iter()
+            .filter(|route| !route.outputs.is_empty())
+            .map(|route| route.input.as_str())
+            .collect();
+        for (i, input) in self.inputs.iter().enumerate() {
+            if !routed.contains(input.id.as_str()) {
+                lints.push(Lint {
+                    code: "unused-input",
+                    subject: input.id.clone(),
+                    message: format!("Input '{}' is not routed to any output", input.id),
+                    path: format!("inputs[{}].id", i),
+                });
+            }
+        }
+
+        // Only the first route from an input to an output is used
+        let mut seen = HashSet::new();
+        for (i, route) in self.routes.iter().enumerate() {
+            for (j, output_id) in route.outputs.iter().enumerate() {
+                if !seen.insert((route.input.as_str(), output_id.as_str())) {
+                    lints.push(Lint {
+                        code: "duplicate-route",
+                        subject: route.input.clone(),
+                        message: format!(
+                            "Input '{}' is already routed to '{}'; this route is ignored there",
+                            route.input, output_id
+                        ),
+                        path: format!("routes[{}].outputs[{}]", i, j),
+                    });
+                }
+            }
+        }
+
+        let mut rooms: HashMap<&str, &str> = HashMap::new();
+        for (i, output) in self.outputs.iter().enumerate() {
+            if let OutputKind::Sonos { room, .. } = &output.kind {
+                if let Some(first) = rooms.insert(room, &output.id) {
+                    rooms.insert(room, first);
+                    lints.push(Lint {
+                        code: "duplicate-room",
+                        subject: output.id.clone(),
+                        message: format!(
+                            "Outputs '{}' and '{}' both drive room '{}'",
+                            first, output.id, room
+                        ),
+                        path: format!("outputs[{}].room", i),
+                    });
+                }
+            }
+        }
+
+        for (i, route) in self.routes.iter().enumerate() {
+            if route.duck_db == 0.0 {
+                continue;
+            }
+            let priority = route.effective_priority();
+            let shared = route.outputs.iter().any(|output_id| {
+                output_routes.get(output_id.as_str()).is_some_and(|routes| {
+                    routes
+                        .iter()
+                        .any(|r| r.input != route.input && r.effective_priority() < priority)
+                })
+            });
+            if !shared {
+                lints.push(Lint {
+                    code: "ineffective-duck",
+                    subject: route.input.clone(),
+                    message: format!(
+                        "Route from '{}' sets duck_db but shares no output with a lower-priority input",
+                        route.input
+                    ),
+                    path: format!("routes[{}].duck_db", i),
+                });
+            }
+        }
+
+        for (i, output) in self.outputs.iter().enumerate() {
+            let Some(routes) = output_routes.get(output.id.as_str()) else {
+                continue;
+            };
+            let peak = worst_case_gain(routes);
+            if peak > 1.0 {
+                lints.push(Lint {
+                    code: "clipping-gain",
+                    subject: output.id.clone(),
+                    message: format!(
+                        "Sources routed to '{}' can sum to {:+.1} dB and clip",
+                        output.id,
+                        lin_to_db(peak)
+                    ),
+                    path: format!("outputs[{}].id", i),
+                });
+            }
+        }
+
+        let allow = self.lints.clone().unwrap_or_default();
+        lints.retain(|lint| !allow.allows(lint.code, &lint.subject));
+        lints
+    }
+}
+
+// Highest linear gain the routes to one output can sum to with every source at
+// full scale, taking ducking into account
+fn worst_case_gain(routes: &[&Route]) -> f32 {
+    // Only the first route from each input is mixed
+    let mut used = HashSet::new();
+    let routes: Vec<&Route> = routes
+        .iter()
+        .copied()
+        .filter(|route| used.insert(route.input.as_str()))
+        .collect();
+
+    let mut tiers: Vec<i32> = routes.iter().map(|r| r.effective_priority()).collect();
+    tiers.sort_unstable();
+    tiers.dedup();
+
+    // For each possible top tier, assume everything at or below it is playing
+    tiers
+        .iter()
+        .map(|&top| {
+            routes
+                .iter()
+                .filter(|route| route.effective_priority() <= top)
+                .map(|route| {
+                    let ducked_by = routes
+                        .iter()
+                        .filter(|other| {
+                            let p = other.effective_priority();
+                            p > route.effective_priority() && p <= top
+                        })
+                        .map(|other| other.duck_db.abs())
+                        .fold(0.0, f32::max);
+                    db_to_lin(route.gain_db - ducked_by)
+                })
+                .sum::<f32>()
+        })
+        .fold(0.0, f32::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(config: &Config) -> Vec<&'static str> {
+        config.lint().iter().map(|lint| lint.code).collect()
+    }
+
+    #[test]
+    fn test_clean_config() {
+        let config = Config::check(
+            r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[inputs]]
+id = "alert"
+kind = "silence"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+gain_db = -6.0
+
+[[routes]]
+input = "alert"
+outputs = ["kitchen"]
+gain_db = -6.0
+duck_db = 12.0
+"#,
+        )
+        .into_result()
+        .unwrap();
+
+        assert!(codes(&config).is_empty());
+    }
+
+    #[test]
+    fn test_all_lints() {
+        let config = Config::check(
+            r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[inputs]]
+id = "spare"
+kind = "silence"
+
+[[inputs]]
+id = "chime"
+kind = "silence"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[outputs]]
+id = "kitchen2"
+kind = "sonos"
+room = "Kitchen"
+
+[[outputs]]
+id = "office"
+kind = "sonos"
+room = "Office"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen", "kitchen"]
+
+[[routes]]
+input = "chime"
+outputs = ["office"]
+duck_db = 12.0
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+gain_db = 6.0
+"#,
+        )
+        .into_result()
+        .unwrap();
+
+        let lints = config.lint();
+        let found: Vec<_> = lints.iter().map(|l| (l.code, l.path.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("unrouted-output", "outputs[1].id"),
+                ("unused-input", "inputs[1].id"),
+                ("duplicate-route", "routes[0].outputs[1]"),
+                ("duplicate-route", "routes[2].outputs[0]"),
+                ("duplicate-room", "outputs[1].room"),
+                ("ineffective-duck", "routes[1].duck_db"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clipping_gain_respects_ducking() {
+        let mut config = Config::check(
+            r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[inputs]]
+id = "alert"
+kind = "silence"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+
+[[routes]]
+input = "alert"
+outputs = ["kitchen"]
+duck_db = 20.0
+"#,
+        )
+        .into_result()
+        .unwrap();
+
+        // Music ducked by 20 dB under a full-scale alert still clips slightly
+        assert_eq!(codes(&config), vec!["clipping-gain"]);
+
+        config.routes[1].gain_db = -3.0;
+        assert!(codes(&config).is_empty());
+
+        // Either sign ducks
+        config.routes[1].duck_db = -20.0;
+        assert!(codes(&config).is_empty());
+
+        // At equal priority the two inputs mix instead
+        config.routes[1].priority = Some(0);
+        assert_eq!(codes(&config), vec!["ineffective-duck", "clipping-gain"]);
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let mut config = Config::check(
+            r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[inputs]]
+id = "spare"
+kind = "silence"
+
+[[inputs]]
+id = "backup"
+kind = "silence"
+
+[lints]
+allow = ["unused-input:spare"]
+"#,
+        )
+        .into_result()
+        .unwrap();
+
+        let subjects: Vec<_> = config.lint().into_iter().map(|l| l.subject).collect();
+        assert_eq!(subjects, vec!["music", "backup"]);
+
+        config.lints = Some(Lints {
+            allow: vec!["unused-input".to_string()],
+        });
+        assert!(codes(&config).is_empty());
+    }
+}
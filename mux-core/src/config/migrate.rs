@@ -0,0 +1,226 @@
+use super::ConfigError;
+use toml_edit::{InlineTable, Item, Key, Table, Value};
+
+/// Schema version written by this build; files without a `version` key are
+/// version 1
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Upgrades a configuration from one schema version to the next
+pub struct Migration {
+    /// Version this migration upgrades from
+    pub from: u32,
+    pub description: &'static str,
+    apply: fn(&mut Table) -> Vec<String>,
+}
+
+/// Every migration, in version order
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    from: 1,
+    description: "rename `loop_playback` to `loop` on file inputs",
+    apply: v1_to_v2,
+}];
+
+/// What `migrate` did to a configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migrated {
+    /// Version the configuration was written for
+    pub from: u32,
+    /// Human-readable list of every edit, e.g. `inputs[1]: renamed ...`
+    pub changes: Vec<String>,
+}
+
+impl Migrated {
+    /// Whether the configuration was older than the current version
+    pub fn is_outdated(&self) -> bool {
+        self.from < CURRENT_VERSION
+    }
+}
+
+/// Upgrade the root table of a configuration file to `CURRENT_VERSION` in
+/// place, preserving comments and formatting
+pub fn migrate(root: &mut Table) -> Result<Migrated, ConfigError> {
+    let from = version(root)?;
+
+    let mut changes = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= from) {
+        changes.extend((migration.apply)(root));
+    }
+
+    if from < CURRENT_VERSION {
+        match root.get_mut("version").and_then(Item::as_value_mut) {
+            // Keep any comment after the old version number
+            Some(value) => {
+                let decor = value.decor().clone();
+                *value = Value::from(i64::from(CURRENT_VERSION));
+                *value.decor_mut() = decor;
+            }
+            None => {
+                root.insert("version", toml_edit::value(i64::from(CURRENT_VERSION)));
+            }
+        }
+        changes.push(format!("set `version` to {}", CURRENT_VERSION));
+    }
+
+    Ok(Migrated { from, changes })
+}
+
+// The schema version a configuration declares
+fn version(root: &Table) -> Result<u32, ConfigError> {
+    let Some(item) = root.get("version") else {
+        return Ok(1);
+    };
+    let version = item
+        .as_integer()
+        .and_then(|version| u32::try_from(version).ok())
+        .filter(|version| *version >= 1)
+        .ok_or_else(|| {
+            ConfigError::UnsupportedVersion("`version` must be a positive integer".to_string())
+        })?;
+
+    if version > CURRENT_VERSION {
+        return Err(ConfigError::UnsupportedVersion(format!(
+            "version {} is newer than this build supports ({})",
+            version, CURRENT_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+fn v1_to_v2(root: &mut Table) -> Vec<String> {
+    let mut changes = Vec::new();
+    let mut rename = |i: usize, renamed: bool| {
+        if renamed {
+            changes.push(format!("inputs[{}]: renamed `loop_playback` to `loop`", i));
+        }
+    };
+
+    match root.get_mut("inputs") {
+        Some(Item::ArrayOfTables(inputs)) => {
+            for (i, input) in inputs.iter_mut().enumerate() {
+                rename(i, rename_key(input, "loop_playback", "loop"));
+            }
+        }
+        Some(Item::Value(Value::Array(inputs))) => {
+            for (i, input) in inputs.iter_mut().enumerate() {
+                if let Some(input) = input.as_inline_table_mut() {
+                    rename(i, rename_inline_key(input, "loop_playback", "loop"));
+                }
+            }
+        }
+        _ => {}
+    }
+    changes
+}
+
+// Rename a key in place, keeping its position and comments
+fn rename_key(table: &mut Table, from: &str, to: &str) -> bool {
+    if !table.contains_key(from) || table.contains_key(to) {
+        return false;
+    }
+
+    let entries: Vec<(Key, Item)> = table
+        .iter()
+        .filter_map(|(key, _)| table.get_key_value(key))
+        .map(|(key, item)| (key.clone(), item.clone()))
+        .collect();
+    table.clear();
+    for (key, item) in entries {
+        table.insert_formatted(&renamed(key, from, to), item);
+    }
+    true
+}
+
+fn rename_inline_key(table: &mut InlineTable, from: &str, to: &str) -> bool {
+    if !table.contains_key(from) || table.contains_key(to) {
+        return false;
+    }
+
+    let entries: Vec<(Key, Value)> = table
+        .iter()
+        .filter_map(|(key, _)| table.get_key_value(key))
+        .filter_map(|(key, item)| Some((key.clone(), item.as_value()?.clone())))
+        .collect();
+    table.clear();
+    for (key, value) in entries {
+        table.insert_formatted(&renamed(key, from, to), value);
+    }
+    true
+}
+
+fn renamed(key: Key, from: &str, to: &str) -> Key {
+    if key.get() == from {
+        Key::new(to).with_leaf_decor(key.leaf_decor().clone())
+    } else {
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml_edit::DocumentMut;
+
+    #[test]
+    fn test_migrate_v1() {
+        let mut document: DocumentMut = r#"# Chimes
+[[inputs]]
+id = "alert"
+kind = "file"
+path = "alert.pcm"
+loop_playback = false # play once
+on_demand = true
+
+[[inputs]]
+id = "ambient"
+kind = "file"
+path = "ambient.pcm"
+"#
+        .parse()
+        .unwrap();
+
+        let migrated = migrate(document.as_table_mut()).unwrap();
+
+        assert_eq!(migrated.from, 1);
+        assert_eq!(
+            migrated.changes,
+            vec![
+                "inputs[0]: renamed `loop_playback` to `loop`",
+                "set `version` to 2"
+            ]
+        );
+        assert_eq!(
+            document.to_string(),
+            r#"version = 2
+# Chimes
+[[inputs]]
+id = "alert"
+kind = "file"
+path = "alert.pcm"
+loop = false # play once
+on_demand = true
+
+[[inputs]]
+id = "ambient"
+kind = "file"
+path = "ambient.pcm"
+"#
+        );
+
+        // Migrating again changes nothing
+        let migrated = migrate(document.as_table_mut()).unwrap();
+        assert!(!migrated.is_outdated());
+        assert!(migrated.changes.is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut document: DocumentMut = "version = 99\n".parse().unwrap();
+        assert!(matches!(
+            migrate(document.as_table_mut()),
+            Err(ConfigError::UnsupportedVersion(_))
+        ));
+
+        let mut document: DocumentMut = "version = \"2\"\n".parse().unwrap();
+        assert!(migrate(document.as_table_mut()).is_err());
+    }
+}
@@ -0,0 +1,2573 @@
+mod diagnostic;
+mod diff;
+mod include;
+mod interpolate;
+mod lint;
+mod migrate;
+mod schema;
+
+pub use diagnostic::{Diagnostic, Location, Report, Severity};
+pub use diff::{Change, Levels, Plan};
+pub use interpolate::Secrets;
+pub use lint::{Lint, Lints, LINTS};
+pub use migrate::{migrate, Migrated, Migration, CURRENT_VERSION, MIGRATIONS};
+pub use schema::{schema, schema_json};
+
+use diagnostic::{did_you_mean, suggest_from_message};
+use schemars::JsonSchema;
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::net::SocketAddr;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use toml_edit::{ImDocument, Item, Table, Value};
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("TOML parsing error: {0}")]
+    Toml(String),
+
+    #[error("Validation error: {0}")]
+    Validation(String),
+
+    #[error("Unknown input kind: {0}")]
+    UnknownInputKind(String),
+
+    #[error("Unknown output kind: {0}")]
+    UnknownOutputKind(String),
+
+    #[error("Duplicate ID: {0}")]
+    DuplicateId(String),
+
+    #[error("Referenced ID not found: {0}")]
+    IdNotFound(String),
+
+    #[error("Interpolation error: {0}")]
+    Interpolation(String),
+
+    #[error("Include error: {0}")]
+    Include(String),
+
+    #[error("Unsupported config version: {0}")]
+    UnsupportedVersion(String),
+}
+
+impl ConfigError {
+    /// Stable identifier used for diagnostics
+    pub fn code(&self) -> &'static str {
+        match self {
+            ConfigError::Io(_) => "io",
+            ConfigError::Toml(_) => "invalid-toml",
+            ConfigError::Validation(_) => "invalid-value",
+            ConfigError::UnknownInputKind(_) | ConfigError::UnknownOutputKind(_) => "unknown-kind",
+            ConfigError::DuplicateId(_) => "duplicate-id",
+            ConfigError::IdNotFound(_) => "unknown-id",
+            ConfigError::Interpolation(_) => "interpolation",
+            ConfigError::Include(_) => "include",
+            ConfigError::UnsupportedVersion(_) => "unsupported-version",
+        }
+    }
+}
+
+/// Top-level sonos-mux configuration
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct Config {
+    /// Schema version the file is written for; older versions are migrated
+    /// when loaded
+    #[serde(default = "default_version")]
+    #[schemars(range(min = 1, max = 2))]
+    pub version: u32,
+
+    /// Other config files to merge beneath this one, relative to it; glob
+    /// patterns are expanded in sorted order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Environment variables holding secrets, whose values are redacted
+    /// wherever they are substituted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secret_env: Vec<String>,
+
+    /// Audio sources that can be routed to outputs
+    #[serde(default)]
+    pub inputs: Vec<Input>,
+
+    /// Destinations that receive a mix of routed inputs
+    #[serde(default)]
+    pub outputs: Vec<Output>,
+
+    /// Connections from one input to one or more outputs
+    #[serde(default)]
+    pub routes: Vec<Route>,
+
+    #[serde(default)]
+    pub logging: Option<Logging>,
+
+    #[serde(default)]
+    pub lints: Option<Lints>,
+
+    /// Values read through `*_file` keys or substituted from `secret_env`
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub secrets: Secrets,
+
+    /// Every file the configuration was read from, including included ones
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub files: Vec<PathBuf>,
+
+    /// Warnings found while loading, for callers that only get the `Config`
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            include: Vec::new(),
+            secret_env: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            routes: Vec::new(),
+            logging: None,
+            lints: None,
+            secrets: Secrets::default(),
+            files: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+/// Known top-level keys
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "version",
+    "include",
+    "secret_env",
+    "inputs",
+    "outputs",
+    "routes",
+    "logging",
+    "lints",
+];
+
+/// Known values for an input's `kind` key
+pub const INPUT_KINDS: &[&str] = &["alsa", "file", "http", "silence"];
+
+/// Known values for an output's `kind` key
+pub const OUTPUT_KINDS: &[&str] = &[
+    "sonos", "upnp", "alsa", "http", "icecast", "rtp", "file", "null",
+];
+
+/// Accepted range for route gains, in dB
+pub const GAIN_DB_RANGE: RangeInclusive<f32> = -96.0..=24.0;
+
+/// Largest accepted ducking amount, in dB of either sign
+pub const DUCK_DB_MAX: f32 = 96.0;
+
+/// Accepted range for Sonos buffer sizes, in seconds
+pub const BUFFER_SEC_RANGE: RangeInclusive<u32> = 1..=60;
+
+/// Largest accepted playback delay for local sound cards, in milliseconds
+pub const MAX_LATENCY_MS: u32 = 10_000;
+
+/// An audio source
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct Input {
+    /// Unique ID that routes refer to
+    #[schemars(length(min = 1))]
+    pub id: String,
+
+    /// What to tell listeners is playing while this input is the one heard,
+    /// when the input doesn't say itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(flatten)]
+    pub kind: InputKind,
+}
+
+/// Kind-specific input settings, tagged by the `kind` key
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum InputKind {
+    /// Capture from an ALSA device, e.g. a Roon Bridge loopback
+    Alsa {
+        /// ALSA device name
+        #[serde(default = "default_alsa_device")]
+        device: String,
+
+        /// Sample format of the device, if not S16LE
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        format: Option<SampleFormat>,
+    },
+
+    /// Play raw S16LE 44.1 kHz stereo PCM from a file
+    File {
+        /// Path to the file
+        #[schemars(length(min = 1))]
+        path: String,
+
+        /// Start again from the beginning when the file ends
+        #[serde(default, rename = "loop")]
+        loop_playback: bool,
+
+        /// Only play when triggered with the `play` admin method, instead
+        /// of at startup
+        #[serde(default)]
+        on_demand: bool,
+    },
+
+    /// Decode a remote HTTP audio stream
+    Http {
+        /// Stream URL
+        #[schemars(length(min = 1))]
+        url: String,
+    },
+
+    /// Digital silence, to keep streams alive
+    Silence {},
+}
+
+impl InputKind {
+    /// The value of the `kind` key for this input
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputKind::Alsa { .. } => "alsa",
+            InputKind::File { .. } => "file",
+            InputKind::Http { .. } => "http",
+            InputKind::Silence {} => "silence",
+        }
+    }
+}
+
+/// An audio destination
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct Output {
+    /// Unique ID that routes refer to
+    #[schemars(length(min = 1))]
+    pub id: String,
+
+    #[serde(flatten)]
+    pub kind: OutputKind,
+}
+
+/// Kind-specific output settings, tagged by the `kind` key
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum OutputKind {
+    /// A Sonos room playing our stream
+    Sonos {
+        /// Room name exactly as shown in the Sonos app
+        #[schemars(length(min = 1))]
+        room: String,
+
+        /// Buffer size in seconds
+        #[serde(default = "default_buffer_sec")]
+        #[schemars(range(min = 1, max = 60))]
+        buffer_sec: u32,
+
+        /// How to encode the stream rooms play; every room plays the
+        /// same one, encoded as the first routed room asks
+        #[serde(default = "default_codec")]
+        codec: Codec,
+
+        /// Bitrate in kbps, for MP3, AAC and Opus
+        #[serde(default = "default_bitrate_kbps")]
+        bitrate_kbps: u32,
+
+        /// Variable bitrate quality in place of `bitrate_kbps`: 0 (best) to
+        /// 9 for MP3, 1 to 5 (best) for AAC
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quality: Option<u8>,
+    },
+
+    /// A UPnP/DLNA MediaRenderer playing our stream, found by its friendly
+    /// name or UDN
+    Upnp {
+        /// Friendly name, as the device shows it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+
+        /// Unique device name, e.g. `uuid:5f9ec1b3-...`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        udn: Option<String>,
+
+        /// How to encode the stream renderers play; see `sonos`
+        #[serde(default = "default_codec")]
+        codec: Codec,
+
+        /// Bitrate in kbps, for MP3, AAC and Opus
+        #[serde(default = "default_bitrate_kbps")]
+        bitrate_kbps: u32,
+
+        /// Variable bitrate quality in place of `bitrate_kbps`: 0 (best) to
+        /// 9 for MP3, 1 to 5 (best) for AAC
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quality: Option<u8>,
+    },
+
+    /// Play the mix on a local sound card
+    Alsa {
+        /// ALSA device name, e.g. `hw:1,0` or `plughw:CARD=Amp`
+        #[serde(default = "default_alsa_device")]
+        #[schemars(length(min = 1))]
+        device: String,
+
+        /// Frames the device takes per period
+        #[serde(default = "default_period_frames")]
+        #[schemars(range(min = 1))]
+        period_frames: u32,
+
+        /// Frames the device buffers, at least two periods
+        #[serde(default = "default_buffer_frames")]
+        #[schemars(range(min = 2))]
+        buffer_frames: u32,
+
+        /// Delay playback by this many milliseconds, e.g. to line up with
+        /// Sonos rooms, which play our stream a few seconds late
+        #[serde(default)]
+        #[schemars(range(max = 10000))]
+        latency_ms: u32,
+    },
+
+    /// A plain HTTP stream endpoint
+    Http {
+        /// Address to listen on, with `port`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        host: Option<String>,
+
+        /// Port of a server of the output's own; without one the stream is
+        /// served by the main stream server
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(range(min = 1))]
+        port: Option<u16>,
+
+        /// Path to serve the stream at, `/streams/<id>` if not set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+
+        /// How to encode the stream
+        #[serde(default = "default_codec")]
+        codec: Codec,
+
+        /// Bitrate in kbps, for MP3, AAC and Opus
+        #[serde(default = "default_bitrate_kbps")]
+        bitrate_kbps: u32,
+
+        /// Variable bitrate quality in place of `bitrate_kbps`: 0 (best) to
+        /// 9 for MP3, 1 to 5 (best) for AAC
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quality: Option<u8>,
+
+        /// Serve the stream as HLS, a rolling playlist at
+        /// `<path>/index.m3u8` of short segments, in place of one long
+        /// response; needs `mp3` or `aac`
+        #[serde(default)]
+        hls: bool,
+
+        /// Length of each HLS segment in seconds
+        #[serde(default = "default_segment_sec")]
+        #[schemars(range(min = 1, max = 60))]
+        segment_sec: u32,
+
+        /// HLS segments listed in the playlist
+        #[serde(default = "default_window")]
+        #[schemars(range(min = 3))]
+        window: usize,
+    },
+
+    /// Publish the mix to an Icecast or Shoutcast server, as a source
+    Icecast {
+        /// Mount to publish to, e.g. `http://radio.example.com:8000/family.mp3`
+        #[schemars(length(min = 1))]
+        url: String,
+
+        /// Source user
+        #[serde(default = "default_source_user")]
+        user: String,
+
+        /// Source password
+        password: String,
+
+        /// `put` for Icecast 2.4 and later, `source` for older servers
+        #[serde(default)]
+        method: SourceMethod,
+
+        /// How to encode the stream
+        #[serde(default = "default_codec")]
+        codec: Codec,
+
+        /// Bitrate in kbps, for MP3, AAC and Opus
+        #[serde(default = "default_bitrate_kbps")]
+        bitrate_kbps: u32,
+
+        /// Variable bitrate quality in place of `bitrate_kbps`: 0 (best) to
+        /// 9 for MP3, 1 to 5 (best) for AAC
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quality: Option<u8>,
+
+        /// Station name shown to listeners
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+
+        /// Station description shown to listeners
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        genre: Option<String>,
+
+        /// List the stream in public directories
+        #[serde(default)]
+        public: bool,
+    },
+
+    /// Send the mix as RTP to a unicast or multicast address
+    Rtp {
+        /// Where to send, e.g. `239.255.77.1:5004`
+        #[schemars(length(min = 1))]
+        address: String,
+
+        /// How the audio is carried
+        #[serde(default)]
+        payload: RtpPayload,
+
+        /// Audio per packet in milliseconds; 5 for `l16`, which has to fit
+        /// a packet in an Ethernet frame, and 20 for `opus` if not set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(range(min = 1))]
+        ptime_ms: Option<u32>,
+
+        /// Router hops a packet may take; 1 keeps multicast on the local
+        /// network
+        #[serde(default = "default_ttl")]
+        #[schemars(range(min = 1, max = 255))]
+        ttl: u32,
+
+        /// Opus bitrate in kbps
+        #[serde(default = "default_bitrate_kbps")]
+        bitrate_kbps: u32,
+
+        /// Write an SDP file describing the stream here, for receivers
+        /// like GStreamer or ffplay
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sdp: Option<String>,
+    },
+
+    /// Record the mix to files on disk
+    File {
+        /// Where to write; strftime-style fields like `%Y-%m-%d` are
+        /// filled in from the local time whenever a file is started
+        #[schemars(length(min = 1))]
+        path: String,
+
+        /// Container to write, if not the one the path's extension names
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        format: Option<Codec>,
+
+        /// Bitrate in kbps, for MP3, AAC and Opus
+        #[serde(default = "default_bitrate_kbps")]
+        bitrate_kbps: u32,
+
+        /// Variable bitrate quality in place of `bitrate_kbps`: 0 (best) to
+        /// 9 for MP3, 1 to 5 (best) for AAC
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quality: Option<u8>,
+
+        /// Start a new file after this many seconds
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(range(min = 1))]
+        rotate_sec: Option<u64>,
+
+        /// Start a new file once the current one reaches this many MB
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(range(min = 1))]
+        rotate_mb: Option<u64>,
+
+        /// Delete the oldest recordings beyond this many
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(range(min = 1))]
+        keep_files: Option<usize>,
+    },
+
+    /// Take the mix in real time and discard it, for tests and benchmarks
+    Null {
+        /// Audio the simulated device holds, in milliseconds; the mix
+        /// underruns when it arrives later than this allows
+        #[serde(default = "default_buffer_ms")]
+        #[schemars(range(min = 1))]
+        buffer_ms: u32,
+    },
+}
+
+/// How an output's mix is encoded, for recordings and streams
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Wav,
+    Flac,
+    Mp3,
+    /// Opus in Ogg
+    Opus,
+    /// FLAC in Ogg
+    #[serde(rename = "ogg_flac")]
+    OggFlac,
+    /// AAC-LC in ADTS frames
+    Aac,
+}
+
+/// Sample format of a capture device, converted to S16LE as it is read
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleFormat {
+    #[default]
+    S16le,
+    /// 24-bit samples packed in 3 bytes
+    S24le,
+    S32le,
+    F32le,
+}
+
+impl SampleFormat {
+    /// Bytes per sample
+    pub fn width(&self) -> usize {
+        match self {
+            SampleFormat::S16le => 2,
+            SampleFormat::S24le => 3,
+            SampleFormat::S32le | SampleFormat::F32le => 4,
+        }
+    }
+}
+
+/// How a source client asks to publish a mount
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceMethod {
+    #[default]
+    Put,
+    Source,
+}
+
+/// How an `rtp` output carries its audio
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RtpPayload {
+    /// Uncompressed 16-bit PCM, as RTP/AVP payload type 10
+    #[default]
+    L16,
+    Opus,
+}
+
+impl RtpPayload {
+    /// Packet times the payload can carry, in milliseconds
+    pub fn ptimes(&self) -> &'static [u32] {
+        match self {
+            // 5 ms is 882 bytes; more than 8 ms overflows a 1500 byte MTU
+            RtpPayload::L16 => &[1, 2, 3, 4, 5, 6, 7, 8],
+            // The frame lengths the Opus encoder takes
+            RtpPayload::Opus => &[10, 20],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RtpPayload::L16 => "l16",
+            RtpPayload::Opus => "opus",
+        }
+    }
+
+    /// The packet time used when an output doesn't set one
+    pub fn default_ptime(&self) -> u32 {
+        match self {
+            RtpPayload::L16 => 5,
+            RtpPayload::Opus => 20,
+        }
+    }
+}
+
+impl Codec {
+    pub const ALL: [Codec; 6] = [
+        Codec::Wav,
+        Codec::Flac,
+        Codec::Mp3,
+        Codec::Opus,
+        Codec::OggFlac,
+        Codec::Aac,
+    ];
+
+    /// MIME type of the encoded audio
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Codec::Wav => "audio/wav",
+            Codec::Flac => "audio/flac",
+            Codec::Mp3 => "audio/mpeg",
+            Codec::Opus | Codec::OggFlac => "audio/ogg",
+            Codec::Aac => "audio/aac",
+        }
+    }
+
+    /// Extension of a file in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Codec::Wav => "wav",
+            Codec::Flac => "flac",
+            Codec::Mp3 => "mp3",
+            Codec::Opus => "opus",
+            Codec::OggFlac => "oga",
+            Codec::Aac => "aac",
+        }
+    }
+
+    /// Variable bitrate qualities the codec takes, if it takes any
+    pub fn qualities(&self) -> Option<RangeInclusive<u8>> {
+        match self {
+            Codec::Mp3 => Some(0..=9),
+            Codec::Aac => Some(1..=5),
+            Codec::Wav | Codec::Flac | Codec::Opus | Codec::OggFlac => None,
+        }
+    }
+
+    /// The format a path's extension names, if any
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "wav" => Some(Codec::Wav),
+            "flac" => Some(Codec::Flac),
+            "mp3" => Some(Codec::Mp3),
+            "opus" => Some(Codec::Opus),
+            "oga" => Some(Codec::OggFlac),
+            "aac" => Some(Codec::Aac),
+            _ => None,
+        }
+    }
+}
+
+impl Output {
+    /// Where an `http` output's stream is served, or its playlist for HLS
+    pub fn stream_path(&self) -> Option<String> {
+        match &self.kind {
+            OutputKind::Http { path, hls, .. } => {
+                let path = path
+                    .clone()
+                    .unwrap_or_else(|| format!("/streams/{}", self.id));
+                Some(match hls {
+                    true => format!("{}/{}", path, crate::hls::PLAYLIST),
+                    false => path,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl OutputKind {
+    /// Codec, bitrate and quality, for outputs that encode their mix
+    pub fn encoding(&self) -> Option<(Codec, u32, Option<u8>)> {
+        match self {
+            OutputKind::Sonos {
+                codec,
+                bitrate_kbps,
+                quality,
+                ..
+            }
+            | OutputKind::Upnp {
+                codec,
+                bitrate_kbps,
+                quality,
+                ..
+            }
+            | OutputKind::Http {
+                codec,
+                bitrate_kbps,
+                quality,
+                ..
+            }
+            | OutputKind::Icecast {
+                codec,
+                bitrate_kbps,
+                quality,
+                ..
+            } => Some((*codec, *bitrate_kbps, *quality)),
+            OutputKind::File {
+                path,
+                format,
+                bitrate_kbps,
+                quality,
+                ..
+            } => format
+                .or_else(|| Codec::from_path(path))
+                .map(|codec| (codec, *bitrate_kbps, *quality)),
+            OutputKind::Alsa { .. } | OutputKind::Rtp { .. } | OutputKind::Null { .. } => None,
+        }
+    }
+
+    /// The value of the `kind` key for this output
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputKind::Sonos { .. } => "sonos",
+            OutputKind::Upnp { .. } => "upnp",
+            OutputKind::Alsa { .. } => "alsa",
+            OutputKind::Http { .. } => "http",
+            OutputKind::Icecast { .. } => "icecast",
+            OutputKind::Rtp { .. } => "rtp",
+            OutputKind::File { .. } => "file",
+            OutputKind::Null { .. } => "null",
+        }
+    }
+}
+
+/// Sends one input to one or more outputs
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// Optional ID, so that files including this one can replace the route
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// ID of the input to route
+    pub input: String,
+
+    /// IDs of the outputs to send the input to
+    pub outputs: Vec<String>,
+
+    /// Gain applied to the input, in dB
+    #[serde(default)]
+    #[schemars(range(min = -96.0, max = 24.0))]
+    pub gain_db: f32,
+
+    /// How far lower-priority inputs on the same outputs are attenuated
+    /// while this one is playing, in dB; either sign means the same
+    #[serde(default)]
+    #[schemars(range(min = -96.0, max = 96.0))]
+    pub duck_db: f32,
+
+    /// Mixing tier: while this input plays it ducks routes with a lower
+    /// priority, and mixes with routes of equal priority. Defaults to 1 when
+    /// `duck_db` is set and 0 otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+}
+
+impl Route {
+    /// The priority this route mixes at, after applying the default
+    pub fn effective_priority(&self) -> i32 {
+        match self.priority {
+            Some(priority) => priority,
+            None if self.duck_db != 0.0 => 1,
+            None => 0,
+        }
+    }
+}
+
+/// Daemon logging settings
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Logging {
+    /// Log level
+    #[serde(default = "default_log_level")]
+    #[schemars(regex(pattern = r"^(error|warn|info|debug|trace)$"))]
+    pub level: String,
+
+    /// File to log to instead of stderr
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_alsa_device() -> String {
+    "default".to_string()
+}
+
+fn default_buffer_sec() -> u32 {
+    3
+}
+
+fn default_codec() -> Codec {
+    Codec::Mp3
+}
+
+fn default_bitrate_kbps() -> u32 {
+    128
+}
+
+fn default_source_user() -> String {
+    "source".to_string()
+}
+
+fn default_segment_sec() -> u32 {
+    6
+}
+
+fn default_window() -> usize {
+    5
+}
+
+fn default_ttl() -> u32 {
+    1
+}
+
+fn default_buffer_ms() -> u32 {
+    200
+}
+
+fn default_period_frames() -> u32 {
+    1024
+}
+
+fn default_buffer_frames() -> u32 {
+    4096
+}
+
+/// A validation problem and where it was found
+struct Problem {
+    path: String,
+    error: ConfigError,
+    suggestion: Option<String>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::check_file(path).into_result()
+    }
+
+    pub fn from_reader<R: std::io::Read>(mut reader: R) -> Result<Self, ConfigError> {
+        let mut content = String::new();
+        reader
+            .read_to_string(&mut content)
+            .map_err(ConfigError::Io)?;
+
+        Self::check(&content).into_result()
+    }
+
+    /// Parse and validate `source`, collecting every problem found instead of
+    /// stopping at the first one
+    ///
+    /// Includes are resolved relative to the working directory.
+    pub fn check(source: &str) -> Report {
+        Self::check_source(source, None)
+    }
+
+    /// Read, parse and validate the file at `path` and everything it includes
+    pub fn check_file<P: AsRef<Path>>(path: P) -> Report {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(source) => Self::check_source(&source, Some(path)),
+            Err(e) => {
+                let mut report = Report::new(Some(path.display().to_string()), "");
+                report.error(ConfigError::Io(e), None, None, None);
+                report
+            }
+        }
+    }
+
+    /// Check `source` as if it were the contents of the file at `path`, so
+    /// that its includes resolve next to that file
+    pub fn check_at<P: AsRef<Path>>(source: &str, path: P) -> Report {
+        Self::check_source(source, Some(path.as_ref()))
+    }
+
+    fn check_source(source: &str, path: Option<&Path>) -> Report {
+        let mut report = Report::new(path.map(|path| path.display().to_string()), source);
+
+        let document = match ImDocument::parse(source) {
+            Ok(document) => document,
+            Err(e) => {
+                report.error(
+                    ConfigError::Toml(e.message().to_string()),
+                    None,
+                    e.span(),
+                    None,
+                );
+                return report;
+            }
+        };
+
+        // Merge included files beneath this one; every span below is relative
+        // to the file the spanned entry came from
+        let (merged, files) = include::resolve(&mut report, document.as_table().clone(), path);
+        let root = &merged;
+
+        // Expand `${VAR}` and `*_file` references before anything is parsed,
+        // reading files next to the config file that names them
+        let secret_env = string_array(root.get("secret_env"));
+        let dir = |path: &str| {
+            report
+                .file_of(path)
+                .and_then(|file| Path::new(file).parent())
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| PathBuf::from("."))
+        };
+        let expansion = interpolate::Expansion {
+            secret_env: &secret_env,
+            dir: &dir,
+        };
+        let mut secrets = Secrets::default();
+        let mut expanded = Item::Table(root.clone());
+        let failures = interpolate::expand(&mut expanded, "", &expansion, &mut secrets);
+        report.set_secrets(secrets.clone());
+        for (path, error) in failures {
+            let span = span_of(root, &path);
+            report.error(error, Some(path), span, None);
+        }
+        let Item::Table(expanded) = expanded else {
+            unreachable!("the document root is a table");
+        };
+
+        // IDs of entries that failed to parse, so references to them aren't
+        // reported a second time as dangling
+        let mut broken = HashSet::new();
+
+        let mut config = Config {
+            secrets,
+            files,
+            ..Default::default()
+        };
+        for (key, item) in expanded.iter() {
+            match key {
+                "inputs" => {
+                    config.inputs = parse_array(
+                        &mut report,
+                        &mut broken,
+                        key,
+                        item,
+                        Some((INPUT_KINDS, ConfigError::UnknownInputKind)),
+                    );
+                }
+                "outputs" => {
+                    config.outputs = parse_array(
+                        &mut report,
+                        &mut broken,
+                        key,
+                        item,
+                        Some((OUTPUT_KINDS, ConfigError::UnknownOutputKind)),
+                    );
+                }
+                "routes" => {
+                    config.routes = parse_array(&mut report, &mut broken, key, item, None);
+                }
+                // Problems with the list itself were reported while resolving it
+                "version" => {
+                    if let Some(version) = parse_item(&mut report, key, item) {
+                        config.version = version;
+                    }
+                }
+                "include" => config.include = string_array(Some(item)),
+                "secret_env" => {
+                    config.secret_env = parse_item(&mut report, key, item).unwrap_or_default()
+                }
+                "logging" => config.logging = parse_item(&mut report, key, item),
+                "lints" => config.lints = parse_item(&mut report, key, item),
+                _ => {
+                    let span = root.get_key_value(key).and_then(|(k, _)| k.span());
+                    report.warning(
+                        "unknown-key",
+                        format!("Unknown top-level key `{}` is ignored", key),
+                        Some(key.to_string()),
+                        span,
+                        did_you_mean(key, TOP_LEVEL_KEYS.iter().copied()),
+                    );
+                }
+            }
+        }
+
+        for problem in config.problems() {
+            if let ConfigError::IdNotFound(id) = &problem.error {
+                if broken.contains(id) {
+                    continue;
+                }
+            }
+            let span = span_of(root, &problem.path);
+            report.error(problem.error, Some(problem.path), span, problem.suggestion);
+        }
+
+        if report.has_errors() {
+            return report;
+        }
+
+        if let Some(lints) = &config.lints {
+            for (i, entry) in lints.allow.iter().enumerate() {
+                let name = entry.split(':').next().unwrap_or_default();
+                if !LINTS.contains(&name) {
+                    let path = format!("lints.allow[{}]", i);
+                    report.warning(
+                        "unknown-lint",
+                        format!("Unknown lint `{}` in allow list", name),
+                        Some(path.clone()),
+                        span_of(root, &path),
+                        did_you_mean(name, LINTS.iter().copied()),
+                    );
+                }
+            }
+        }
+
+        for lint in config.lint() {
+            let span = span_of(root, &lint.path);
+            report.warning(lint.code, lint.message, Some(lint.path), span, None);
+        }
+
+        config.warnings = report.warnings().cloned().collect();
+        report.set_config(config);
+        report
+    }
+
+    /// Replace any interpolated secret in `text`, for output and logs
+    pub fn redact(&self, text: &str) -> String {
+        self.secrets.redact(text)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self.problems().into_iter().next() {
+            Some(problem) => Err(problem.error),
+            None => Ok(()),
+        }
+    }
+
+    // Every validation problem, in the order `validate` reports them
+    fn problems(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        // Check for duplicate IDs
+        let mut input_ids = HashSet::new();
+        let mut output_ids = HashSet::new();
+
+        for (i, input) in self.inputs.iter().enumerate() {
+            if !input_ids.insert(input.id.as_str()) {
+                problems.push(Problem {
+                    path: format!("inputs[{}].id", i),
+                    error: ConfigError::DuplicateId(input.id.clone()),
+                    suggestion: None,
+                });
+            }
+        }
+
+        for (i, output) in self.outputs.iter().enumerate() {
+            if !output_ids.insert(output.id.as_str()) {
+                problems.push(Problem {
+                    path: format!("outputs[{}].id", i),
+                    error: ConfigError::DuplicateId(output.id.clone()),
+                    suggestion: None,
+                });
+            }
+        }
+
+        let mut route_ids = HashSet::new();
+        for (i, route) in self.routes.iter().enumerate() {
+            if let Some(id) = &route.id {
+                if !route_ids.insert(id.as_str()) {
+                    problems.push(Problem {
+                        path: format!("routes[{}].id", i),
+                        error: ConfigError::DuplicateId(id.clone()),
+                        suggestion: None,
+                    });
+                }
+            }
+        }
+
+        // Check kind-specific requirements
+        for (i, input) in self.inputs.iter().enumerate() {
+            let empty = match &input.kind {
+                InputKind::File { path, .. } if path.is_empty() => Some("path"),
+                InputKind::Http { url } if url.is_empty() => Some("url"),
+                _ => None,
+            };
+            if let Some(field) = empty {
+                problems.push(Problem {
+                    path: format!("inputs[{}].{}", i, field),
+                    error: ConfigError::Validation(format!(
+                        "Input '{}' has an empty {}",
+                        input.id, field
+                    )),
+                    suggestion: None,
+                });
+            }
+        }
+
+        // Streams on the same server need paths of their own
+        let mut stream_paths = HashSet::new();
+        for (i, output) in self.outputs.iter().enumerate() {
+            if let (OutputKind::Http { port, .. }, Some(path)) =
+                (&output.kind, output.stream_path())
+            {
+                // The main stream's path depends on its codec
+                let taken = port.is_none()
+                    && Codec::ALL
+                        .iter()
+                        .any(|codec| path == crate::stream::main_path(*codec));
+                if taken || !stream_paths.insert((*port, path.clone())) {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].path", i),
+                        error: ConfigError::Validation(format!(
+                            "Output '{}' streams at {}, which is already in use",
+                            output.id, path
+                        )),
+                        suggestion: None,
+                    });
+                }
+            }
+        }
+
+        for (i, output) in self.outputs.iter().enumerate() {
+            if let OutputKind::File {
+                path,
+                format,
+                rotate_sec,
+                rotate_mb,
+                keep_files,
+                ..
+            } = &output.kind
+            {
+                let mut invalid = |field: &str, message: String| {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].{}", i, field),
+                        error: ConfigError::Validation(format!(
+                            "Output '{}' {}",
+                            output.id, message
+                        )),
+                        suggestion: None,
+                    })
+                };
+                if path.is_empty() {
+                    invalid("path", "has an empty path".to_string());
+                } else if let Err(e) = crate::output::file::check_template(path) {
+                    invalid("path", format!("has an invalid path template: {}", e));
+                } else if format.is_none() && Codec::from_path(path).is_none() {
+                    invalid(
+                        "format",
+                        "needs a format, as its path doesn't end in .wav, .flac or .mp3"
+                            .to_string(),
+                    );
+                }
+                if *rotate_sec == Some(0) {
+                    invalid("rotate_sec", "has rotate_sec 0".to_string());
+                }
+                if *rotate_mb == Some(0) {
+                    invalid("rotate_mb", "has rotate_mb 0".to_string());
+                }
+                if *keep_files == Some(0) {
+                    invalid("keep_files", "has keep_files 0".to_string());
+                }
+            }
+            if let OutputKind::Http {
+                host,
+                port,
+                path,
+                codec,
+                hls,
+                segment_sec,
+                window,
+                ..
+            } = &output.kind
+            {
+                let mut invalid = |field: &str, error: String, suggestion: Option<&str>| {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].{}", i, field),
+                        error: ConfigError::Validation(format!("Output '{}' {}", output.id, error)),
+                        suggestion: suggestion.map(str::to_string),
+                    })
+                };
+                if *hls {
+                    if !matches!(codec, Codec::Mp3 | Codec::Aac) {
+                        invalid(
+                            "codec",
+                            format!("serves HLS as {}, which HLS can't carry", codec.extension()),
+                            Some("use codec \"aac\" or \"mp3\""),
+                        );
+                    }
+                    if !(1..=60).contains(segment_sec) {
+                        invalid(
+                            "segment_sec",
+                            format!("has segment_sec {} outside 1..=60", segment_sec),
+                            None,
+                        );
+                    }
+                    if *window < 3 {
+                        invalid(
+                            "window",
+                            format!("has window {}, less than the 3 segments HLS needs", window),
+                            None,
+                        );
+                    }
+                }
+                if host.is_some() && port.is_none() {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].host", i),
+                        error: ConfigError::Validation(format!(
+                            "Output '{}' sets host without a port to listen on",
+                            output.id
+                        )),
+                        suggestion: Some(
+                            "add a port, or drop host to use the main stream server".to_string(),
+                        ),
+                    });
+                }
+                if let Some(path) = path.as_deref().filter(|p| !p.starts_with('/')) {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].path", i),
+                        error: ConfigError::Validation(format!(
+                            "Output '{}' has path '{}', which doesn't start with /",
+                            output.id, path
+                        )),
+                        suggestion: None,
+                    });
+                }
+            }
+            if let OutputKind::Upnp { name, udn, .. } = &output.kind {
+                let error = match (name.as_deref(), udn.as_deref()) {
+                    (None, None) => Some("needs a name or udn to find its device by"),
+                    (Some(_), Some(_)) => Some("sets both name and udn; use one"),
+                    (Some(""), None) => Some("has an empty name"),
+                    (None, Some("")) => Some("has an empty udn"),
+                    _ => None,
+                };
+                if let Some(error) = error {
+                    problems.push(Problem {
+                        path: format!("outputs[{}]", i),
+                        error: ConfigError::Validation(format!("Output '{}' {}", output.id, error)),
+                        suggestion: None,
+                    });
+                }
+            }
+            if let OutputKind::Alsa {
+                device,
+                period_frames,
+                buffer_frames,
+                latency_ms,
+            } = &output.kind
+            {
+                let mut invalid = |field: &str, error: String| {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].{}", i, field),
+                        error: ConfigError::Validation(format!("Output '{}' {}", output.id, error)),
+                        suggestion: None,
+                    })
+                };
+                if device.is_empty() {
+                    invalid("device", "has an empty device".to_string());
+                }
+                if *period_frames == 0 {
+                    invalid("period_frames", "has period_frames 0".to_string());
+                } else if *buffer_frames < period_frames.saturating_mul(2) {
+                    invalid(
+                        "buffer_frames",
+                        format!(
+                            "has buffer_frames {}, less than two periods of {}",
+                            buffer_frames, period_frames
+                        ),
+                    );
+                }
+                if *latency_ms > MAX_LATENCY_MS {
+                    invalid(
+                        "latency_ms",
+                        format!(
+                            "has latency_ms {}, more than {}",
+                            latency_ms, MAX_LATENCY_MS
+                        ),
+                    );
+                }
+            }
+            if let OutputKind::Icecast { url, password, .. } = &output.kind {
+                let mount = url
+                    .strip_prefix("http://")
+                    .and_then(|rest| rest.find('/').map(|i| &rest[i..]));
+                if !matches!(mount, Some(mount) if mount.len() > 1) {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].url", i),
+                        error: ConfigError::Validation(format!(
+                            "Output '{}' has url '{}', which is not an http:// URL with a mount",
+                            output.id,
+                            self.redact(url)
+                        )),
+                        suggestion: Some(
+                            "use a URL like http://radio.example.com:8000/family.mp3".to_string(),
+                        ),
+                    });
+                }
+                if password.is_empty() {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].password", i),
+                        error: ConfigError::Validation(format!(
+                            "Output '{}' has an empty password",
+                            output.id
+                        )),
+                        suggestion: None,
+                    });
+                }
+            }
+            if let Some((codec, _, Some(quality))) = output.kind.encoding() {
+                let field = match output.kind {
+                    OutputKind::File { .. } => "format",
+                    _ => "codec",
+                };
+                let error = match codec.qualities() {
+                    Some(qualities) if qualities.contains(&quality) => None,
+                    Some(qualities) => Some((
+                        format!(
+                            "has quality {}, outside {}..={}",
+                            quality,
+                            qualities.start(),
+                            qualities.end()
+                        ),
+                        None,
+                    )),
+                    None => Some((
+                        format!("sets quality, which its {} doesn't take", field),
+                        Some("drop quality, or use mp3 or aac".to_string()),
+                    )),
+                };
+                if let Some((error, suggestion)) = error {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].quality", i),
+                        error: ConfigError::Validation(format!("Output '{}' {}", output.id, error)),
+                        suggestion,
+                    });
+                }
+            }
+            if let OutputKind::Rtp {
+                address,
+                payload,
+                ptime_ms,
+                ttl,
+                ..
+            } = &output.kind
+            {
+                let mut invalid = |field: &str, error: String, suggestion: Option<String>| {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].{}", i, field),
+                        error: ConfigError::Validation(format!("Output '{}' {}", output.id, error)),
+                        suggestion,
+                    })
+                };
+                match address.parse::<SocketAddr>() {
+                    Ok(addr) if addr.port() != 0 => {}
+                    _ => invalid(
+                        "address",
+                        format!("has address '{}', which is not an IP and port", address),
+                        Some("use an address like 239.255.77.1:5004".to_string()),
+                    ),
+                }
+                let ptimes = payload.ptimes();
+                if let Some(ptime_ms) = ptime_ms.filter(|ptime| !ptimes.contains(ptime)) {
+                    let allowed: Vec<_> = ptimes.iter().map(|ptime| ptime.to_string()).collect();
+                    invalid(
+                        "ptime_ms",
+                        format!(
+                            "has ptime_ms {}, which {} packets can't carry",
+                            ptime_ms,
+                            payload.name()
+                        ),
+                        Some(format!("use one of {}", allowed.join(", "))),
+                    );
+                }
+                if !(1..=255).contains(ttl) {
+                    invalid("ttl", format!("has ttl {} outside 1..=255", ttl), None);
+                }
+            }
+            if let OutputKind::Null { buffer_ms: 0 } = &output.kind {
+                problems.push(Problem {
+                    path: format!("outputs[{}].buffer_ms", i),
+                    error: ConfigError::Validation(format!(
+                        "Output '{}' has buffer_ms 0",
+                        output.id
+                    )),
+                    suggestion: None,
+                });
+            }
+            if let OutputKind::Sonos {
+                room, buffer_sec, ..
+            } = &output.kind
+            {
+                if room.is_empty() {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].room", i),
+                        error: ConfigError::Validation(format!(
+                            "Output '{}' has an empty room",
+                            output.id
+                        )),
+                        suggestion: None,
+                    });
+                }
+                if !BUFFER_SEC_RANGE.contains(buffer_sec) {
+                    problems.push(Problem {
+                        path: format!("outputs[{}].buffer_sec", i),
+                        error: ConfigError::Validation(format!(
+                            "Output '{}' has buffer_sec {} outside {}..={}",
+                            output.id,
+                            buffer_sec,
+                            BUFFER_SEC_RANGE.start(),
+                            BUFFER_SEC_RANGE.end()
+                        )),
+                        suggestion: None,
+                    });
+                }
+            }
+        }
+
+        for (i, route) in self.routes.iter().enumerate() {
+            if !GAIN_DB_RANGE.contains(&route.gain_db) {
+                problems.push(Problem {
+                    path: format!("routes[{}].gain_db", i),
+                    error: ConfigError::Validation(format!(
+                        "gain_db {} is outside {}..={}",
+                        route.gain_db,
+                        GAIN_DB_RANGE.start(),
+                        GAIN_DB_RANGE.end()
+                    )),
+                    suggestion: None,
+                });
+            }
+            if route.duck_db.abs() > DUCK_DB_MAX {
+                problems.push(Problem {
+                    path: format!("routes[{}].duck_db", i),
+                    error: ConfigError::Validation(format!(
+                        "duck_db {} is larger than {} dB",
+                        route.duck_db, DUCK_DB_MAX
+                    )),
+                    suggestion: None,
+                });
+            }
+        }
+
+        // Check that referenced IDs exist
+        for (i, route) in self.routes.iter().enumerate() {
+            if !input_ids.contains(route.input.as_str()) {
+                problems.push(Problem {
+                    path: format!("routes[{}].input", i),
+                    error: ConfigError::IdNotFound(route.input.clone()),
+                    suggestion: did_you_mean(&route.input, input_ids.iter().copied()),
+                });
+            }
+
+            for (j, output_id) in route.outputs.iter().enumerate() {
+                if !output_ids.contains(output_id.as_str()) {
+                    problems.push(Problem {
+                        path: format!("routes[{}].outputs[{}]", i, j),
+                        error: ConfigError::IdNotFound(output_id.clone()),
+                        suggestion: did_you_mean(output_id, output_ids.iter().copied()),
+                    });
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+// Known kinds for an array's elements, and the error for an unknown one
+type KindCheck = (&'static [&'static str], fn(String) -> ConfigError);
+
+// Deserialize every element of an array of tables separately so that one bad
+// entry doesn't hide problems in the others
+fn parse_array<T: DeserializeOwned>(
+    report: &mut Report,
+    broken: &mut HashSet<String>,
+    key: &str,
+    item: &Item,
+    kinds: Option<KindCheck>,
+) -> Vec<T> {
+    let elements: Vec<(Value, Option<Range<usize>>)> = match item {
+        Item::ArrayOfTables(array) => array
+            .iter()
+            .map(|table| (table_value(table), table.span()))
+            .collect(),
+        Item::Value(Value::Array(array)) => array
+            .iter()
+            .map(|value| (value.clone(), value.span()))
+            .collect(),
+        _ => {
+            report.error(
+                ConfigError::Toml(format!("`{}` must be an array of tables", key)),
+                Some(key.to_string()),
+                item.span(),
+                None,
+            );
+            return Vec::new();
+        }
+    };
+
+    let mut parsed = Vec::new();
+    for (i, (value, span)) in elements.into_iter().enumerate() {
+        let path = format!("{}[{}]", key, i);
+        let id = value
+            .as_inline_table()
+            .and_then(|table| table.get("id"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        if let (Some((known, error)), Some(table)) = (kinds, value.as_inline_table()) {
+            if let Some(kind) = table.get("kind") {
+                let name = kind.as_str().unwrap_or_default();
+                if !known.contains(&name) {
+                    report.error(
+                        error(name.to_string()),
+                        Some(format!("{}.kind", path)),
+                        kind.span(),
+                        did_you_mean(name, known.iter().copied()),
+                    );
+                    broken.extend(id);
+                    continue;
+                }
+            }
+        }
+
+        match deserialize(report, &path, value, span) {
+            Some(element) => parsed.push(element),
+            None => broken.extend(id),
+        }
+    }
+    parsed
+}
+
+// The strings in a top-level list such as `include`, before it is checked
+fn string_array(item: Option<&Item>) -> Vec<String> {
+    item.and_then(Item::as_array)
+        .map(|array| {
+            array
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_item<T: DeserializeOwned>(report: &mut Report, key: &str, item: &Item) -> Option<T> {
+    let value = match item {
+        Item::Table(table) => table_value(table),
+        Item::Value(value) => value.clone(),
+        _ => return None,
+    };
+    deserialize(report, key, value, item.span())
+}
+
+fn deserialize<T: DeserializeOwned>(
+    report: &mut Report,
+    path: &str,
+    value: Value,
+    fallback_span: Option<Range<usize>>,
+) -> Option<T> {
+    let table = value.as_inline_table().cloned();
+
+    match T::deserialize(value.into_deserializer()) {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            let message = e.message().to_string();
+            // Errors raised after the fields were buffered (e.g. for `kind`
+            // specific fields) carry no span, so point at the field by name
+            let field_span = table.as_ref().and_then(|table| {
+                let name = message.split('`').nth(1)?;
+                let (key, _) = table.get_key_value(name)?;
+                key.span()
+            });
+            let span = e.span().or(field_span).or(fallback_span);
+            let suggestion = suggest_from_message(&message);
+            report.error(
+                ConfigError::Toml(message),
+                Some(path.to_string()),
+                span,
+                suggestion,
+            );
+            None
+        }
+    }
+}
+
+fn table_value(table: &Table) -> Value {
+    Value::InlineTable(table.clone().into_inline_table())
+}
+
+// A position in the document while resolving a key path
+enum Node<'a> {
+    Table(&'a Table),
+    InlineTable(&'a toml_edit::InlineTable),
+    Tables(&'a toml_edit::ArrayOfTables),
+    Array(&'a toml_edit::Array),
+    Leaf,
+}
+
+impl<'a> Node<'a> {
+    fn from_item(item: &'a Item) -> Self {
+        match item {
+            Item::Table(table) => Node::Table(table),
+            Item::ArrayOfTables(array) => Node::Tables(array),
+            Item::Value(value) => Node::from_value(value),
+            Item::None => Node::Leaf,
+        }
+    }
+
+    fn from_value(value: &'a Value) -> Self {
+        match value {
+            Value::InlineTable(table) => Node::InlineTable(table),
+            Value::Array(array) => Node::Array(array),
+            _ => Node::Leaf,
+        }
+    }
+}
+
+// Resolve a key path like `routes[1].outputs[0]` to a span in the document,
+// falling back to the closest enclosing span that is known
+fn span_of(root: &Table, path: &str) -> Option<Range<usize>> {
+    let mut node = Node::Table(root);
+    let mut span = None;
+
+    for segment in path.split('.') {
+        let (name, indices) = match segment.find('[') {
+            Some(i) => (&segment[..i], &segment[i..]),
+            None => (segment, ""),
+        };
+
+        let (key_span, next) = match node {
+            Node::Table(table) => match table.get_key_value(name) {
+                Some((key, item)) => (key.span(), (item.span(), Node::from_item(item))),
+                None => return span,
+            },
+            Node::InlineTable(table) => match table.get_key_value(name) {
+                Some((key, item)) => (key.span(), (item.span(), Node::from_item(item))),
+                None => return span,
+            },
+            _ => return span,
+        };
+        span = next.0.or(key_span).or(span);
+        node = next.1;
+
+        for index in indices
+            .split(['[', ']'])
+            .filter_map(|i| i.parse::<usize>().ok())
+        {
+            node = match node {
+                Node::Tables(array) => match array.get(index) {
+                    Some(table) => {
+                        span = table.span().or(span);
+                        Node::Table(table)
+                    }
+                    None => return span,
+                },
+                Node::Array(array) => match array.get(index) {
+                    Some(value) => {
+                        span = value.span().or(span);
+                        Node::from_value(value)
+                    }
+                    None => return span,
+                },
+                _ => return span,
+            };
+        }
+    }
+
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn create_temp_config(content: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_happy_path_parse() {
+        let content = r#"
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+device = "hw:Loopback,1"
+
+[[outputs]]
+id = "living_room"
+kind = "sonos"
+room = "Living Room"
+buffer_sec = 5
+
+[[routes]]
+input = "roon_main"
+outputs = ["living_room"]
+"#;
+
+        let file = create_temp_config(content);
+        let config = Config::load(file.path()).unwrap();
+
+        assert_eq!(config.inputs.len(), 1);
+        assert_eq!(config.inputs[0].id, "roon_main");
+        assert_eq!(
+            config.inputs[0].kind,
+            InputKind::Alsa {
+                device: "hw:Loopback,1".to_string(),
+                format: None,
+            }
+        );
+
+        assert_eq!(config.outputs.len(), 1);
+        assert_eq!(config.outputs[0].id, "living_room");
+        assert_eq!(
+            config.outputs[0].kind,
+            OutputKind::Sonos {
+                room: "Living Room".to_string(),
+                buffer_sec: 5,
+                codec: Codec::Mp3,
+                bitrate_kbps: 128,
+                quality: None,
+            }
+        );
+
+        assert_eq!(config.routes.len(), 1);
+        assert_eq!(config.routes[0].input, "roon_main");
+        assert_eq!(config.routes[0].outputs, vec!["living_room"]);
+    }
+
+    #[test]
+    fn test_unknown_kind() {
+        let content = r#"
+[[inputs]]
+id = "invalid"
+kind = "invalid_kind"
+"#;
+
+        let file = create_temp_config(content);
+        let result = Config::load(file.path());
+
+        assert!(result.is_err());
+        match result {
+            Err(ConfigError::UnknownInputKind(kind)) => assert_eq!(kind, "invalid_kind"),
+            _ => panic!("Expected UnknownInputKind error"),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_id() {
+        let content = r#"
+[[inputs]]
+id = "duplicate"
+kind = "alsa"
+
+[[inputs]]
+id = "duplicate"
+kind = "alsa"
+"#;
+
+        let file = create_temp_config(content);
+        let result = Config::load(file.path());
+
+        assert!(result.is_err());
+        match result {
+            Err(ConfigError::DuplicateId(id)) => assert_eq!(id, "duplicate"),
+            _ => panic!("Expected DuplicateId error"),
+        }
+    }
+
+    #[test]
+    fn test_referenced_id_not_found() {
+        let content = r#"
+[[inputs]]
+id = "input1"
+kind = "alsa"
+
+[[outputs]]
+id = "output1"
+kind = "sonos"
+room = "Office"
+
+[[routes]]
+input = "non_existent"
+outputs = ["output1"]
+"#;
+
+        let file = create_temp_config(content);
+        let result = Config::load(file.path());
+
+        assert!(result.is_err());
+        match result {
+            Err(ConfigError::IdNotFound(id)) => assert_eq!(id, "non_existent"),
+            _ => panic!("Expected IdNotFound error"),
+        }
+    }
+
+    #[test]
+    fn test_field_from_other_kind_rejected() {
+        let content = r#"
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+url = "http://example.com/stream.mp3"
+"#;
+
+        let file = create_temp_config(content);
+        let result = Config::load(file.path());
+
+        match result {
+            Err(ConfigError::Toml(message)) => assert!(message.contains("url")),
+            _ => panic!("Expected Toml error"),
+        }
+    }
+
+    #[test]
+    fn test_missing_required_field() {
+        let content = r#"
+[[inputs]]
+id = "alert"
+kind = "file"
+"#;
+
+        let file = create_temp_config(content);
+        let result = Config::load(file.path());
+
+        match result {
+            Err(ConfigError::Toml(message)) => assert!(message.contains("path")),
+            _ => panic!("Expected Toml error"),
+        }
+    }
+
+    #[test]
+    fn test_kind_defaults() {
+        let content = r#"
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+
+[[inputs]]
+id = "alert"
+kind = "file"
+path = "alert.pcm"
+title = "Doorbell"
+
+[[inputs]]
+id = "usb"
+kind = "alsa"
+format = "s24le"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[outputs]]
+id = "bench"
+kind = "null"
+"#;
+
+        let file = create_temp_config(content);
+        let config = Config::load(file.path()).unwrap();
+
+        assert_eq!(
+            config.inputs[0].kind,
+            InputKind::Alsa {
+                device: "default".to_string(),
+                format: None,
+            }
+        );
+        assert_eq!(
+            config.inputs[1].kind,
+            InputKind::File {
+                path: "alert.pcm".to_string(),
+                loop_playback: false,
+                on_demand: false,
+            }
+        );
+        assert_eq!(config.inputs[0].title, None);
+        assert_eq!(config.inputs[1].title.as_deref(), Some("Doorbell"));
+        assert_eq!(
+            config.inputs[2].kind,
+            InputKind::Alsa {
+                device: "default".to_string(),
+                format: Some(SampleFormat::S24le),
+            }
+        );
+        assert_eq!(
+            config.outputs[0].kind,
+            OutputKind::Sonos {
+                room: "Kitchen".to_string(),
+                buffer_sec: 3,
+                codec: Codec::Mp3,
+                bitrate_kbps: 128,
+                quality: None,
+            }
+        );
+        assert_eq!(config.outputs[1].kind, OutputKind::Null { buffer_ms: 200 });
+    }
+
+    #[test]
+    fn test_check_collects_all_errors() {
+        let content = r#"
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+devcie = "hw:Loopback,1"
+
+[[inputs]]
+id = "alert"
+kind = "fiel"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+"#;
+
+        let report = Config::check(content);
+        let errors: Vec<_> = report.errors().collect();
+
+        assert_eq!(errors.len(), 2);
+        assert!(report.config().is_none());
+
+        assert_eq!(errors[0].code, "invalid-toml");
+        assert_eq!(errors[0].path.as_deref(), Some("inputs[0]"));
+        assert_eq!(errors[0].location.unwrap().line, 5);
+        assert_eq!(
+            errors[0].suggestion.as_deref(),
+            Some("did you mean `device`?")
+        );
+
+        assert_eq!(errors[1].code, "unknown-kind");
+        assert_eq!(errors[1].location.unwrap().line, 9);
+        assert_eq!(
+            errors[1].suggestion.as_deref(),
+            Some("did you mean `file`?")
+        );
+    }
+
+    #[test]
+    fn test_check_reference_spans() {
+        let content = r#"
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "roon_mian"
+outputs = ["kitchen", "kitchn"]
+"#;
+
+        let report = Config::check(content);
+        let errors: Vec<_> = report.errors().collect();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].path.as_deref(), Some("routes[0].input"));
+        assert_eq!(errors[0].location.unwrap().line, 12);
+        assert_eq!(
+            errors[1].location.map(|l| (l.line, l.column)),
+            Some((13, 23))
+        );
+        assert_eq!(
+            errors[1].suggestion.as_deref(),
+            Some("did you mean `kitchen`?")
+        );
+
+        let rendered = report.render("config.toml");
+        assert!(rendered.contains("--> config.toml:13:23"));
+        assert!(rendered.contains("help: did you mean `kitchen`?"));
+    }
+
+    #[test]
+    fn test_check_warns_on_unknown_key() {
+        let content = r#"
+version = 2
+
+[loging]
+level = "debug"
+"#;
+
+        let report = Config::check(content);
+
+        assert!(!report.has_errors());
+        assert!(report.config().is_some());
+        let warnings: Vec<_> = report.warnings().collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].suggestion.as_deref(),
+            Some("did you mean `logging`?")
+        );
+    }
+
+    #[test]
+    fn test_value_ranges() {
+        let content = r#"
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+buffer_sec = 0
+
+[[routes]]
+input = "roon_main"
+outputs = ["kitchen"]
+gain_db = 40.0
+"#;
+
+        let report = Config::check(content);
+        let paths: Vec<_> = report.errors().filter_map(|e| e.path.as_deref()).collect();
+
+        assert_eq!(paths, vec!["outputs[0].buffer_sec", "routes[0].gain_db"]);
+    }
+
+    #[test]
+    fn test_http_output() {
+        let content = r#"
+[[outputs]]
+id = "garage"
+kind = "http"
+
+[[outputs]]
+id = "vlc"
+kind = "http"
+path = "/streams/garage"
+codec = "flac"
+
+[[outputs]]
+id = "legacy"
+kind = "http"
+path = "/stream.flac"
+
+[[outputs]]
+id = "remote"
+kind = "http"
+host = "127.0.0.1"
+path = "streams/remote"
+
+[[outputs]]
+id = "own_port"
+kind = "http"
+port = 8001
+path = "/streams/garage"
+"#;
+
+        let report = Config::check(content);
+        let paths: Vec<_> = report.errors().filter_map(|e| e.path.as_deref()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "outputs[1].path",
+                "outputs[2].path",
+                "outputs[3].host",
+                "outputs[3].path"
+            ]
+        );
+
+        let config = Config::check(content.split("[[outputs]]\nid = \"vlc\"").next().unwrap())
+            .into_result()
+            .unwrap();
+        assert_eq!(
+            config.outputs[0].stream_path().as_deref(),
+            Some("/streams/garage")
+        );
+        match &config.outputs[0].kind {
+            OutputKind::Http {
+                codec,
+                bitrate_kbps,
+                ..
+            } => {
+                assert_eq!(*codec, Codec::Mp3);
+                assert_eq!(*bitrate_kbps, 128);
+            }
+            other => panic!("Unexpected output kind {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_upnp_output() {
+        let content = r#"
+[[outputs]]
+id = "avr"
+kind = "upnp"
+name = "Denon AVR"
+
+[[outputs]]
+id = "wiim"
+kind = "upnp"
+udn = "uuid:5f9ec1b3-ed59-79bb-4530-745e1c44e2b6"
+
+[[outputs]]
+id = "both"
+kind = "upnp"
+name = "WiiM"
+udn = "uuid:5f9ec1b3"
+
+[[outputs]]
+id = "neither"
+kind = "upnp"
+
+[[outputs]]
+id = "blank"
+kind = "upnp"
+name = ""
+"#;
+
+        let report = Config::check(content);
+        let errors: Vec<_> = report.errors().map(|e| e.message.clone()).collect();
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].contains("Output 'both'"));
+        assert!(errors[1].contains("Output 'neither'"));
+        assert!(errors[2].contains("has an empty name"));
+
+        let config = Config::check(content.split("[[outputs]]\nid = \"both\"").next().unwrap())
+            .into_result()
+            .unwrap();
+        assert_eq!(config.outputs[1].kind.name(), "upnp");
+    }
+
+    #[test]
+    fn test_alsa_output() {
+        let content = r#"
+[[outputs]]
+id = "garage"
+kind = "alsa"
+
+[[outputs]]
+id = "amp"
+kind = "alsa"
+device = "hw:1,0"
+period_frames = 512
+buffer_frames = 2048
+latency_ms = 2000
+
+[[outputs]]
+id = "short"
+kind = "alsa"
+period_frames = 1024
+buffer_frames = 1024
+latency_ms = 60000
+"#;
+
+        let report = Config::check(content);
+        let paths: Vec<_> = report.errors().filter_map(|e| e.path.as_deref()).collect();
+        assert_eq!(
+            paths,
+            vec!["outputs[2].buffer_frames", "outputs[2].latency_ms"]
+        );
+
+        let config = Config::check(content.split("[[outputs]]\nid = \"short\"").next().unwrap())
+            .into_result()
+            .unwrap();
+        assert_eq!(
+            config.outputs[0].kind,
+            OutputKind::Alsa {
+                device: "default".to_string(),
+                period_frames: 1024,
+                buffer_frames: 4096,
+                latency_ms: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_icecast_output() {
+        let content = r#"
+[[outputs]]
+id = "family"
+kind = "icecast"
+url = "http://radio.example.com:8000/family.mp3"
+password = "hackme"
+
+[[outputs]]
+id = "no_mount"
+kind = "icecast"
+url = "http://radio.example.com:8000/"
+password = "hackme"
+
+[[outputs]]
+id = "tls"
+kind = "icecast"
+url = "https://radio.example.com/family.mp3"
+password = ""
+"#;
+
+        let report = Config::check(content);
+        let paths: Vec<_> = report.errors().filter_map(|e| e.path.as_deref()).collect();
+        assert_eq!(
+            paths,
+            vec!["outputs[1].url", "outputs[2].url", "outputs[2].password"]
+        );
+
+        let config = Config::check(
+            content
+                .split("[[outputs]]\nid = \"no_mount\"")
+                .next()
+                .unwrap(),
+        )
+        .into_result()
+        .unwrap();
+        match &config.outputs[0].kind {
+            OutputKind::Icecast {
+                user,
+                method,
+                codec,
+                public,
+                ..
+            } => {
+                assert_eq!(user, "source");
+                assert_eq!(*method, SourceMethod::Put);
+                assert_eq!(*codec, Codec::Mp3);
+                assert!(!public);
+            }
+            other => panic!("Unexpected output kind {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rtp_output() {
+        let content = r#"
+[[outputs]]
+id = "porch"
+kind = "rtp"
+address = "239.255.77.1:5004"
+
+[[outputs]]
+id = "hostname"
+kind = "rtp"
+address = "porch.local:5004"
+ttl = 0
+
+[[outputs]]
+id = "opus"
+kind = "rtp"
+address = "[ff02::1]:5004"
+payload = "opus"
+ptime_ms = 5
+"#;
+
+        let report = Config::check(content);
+        let paths: Vec<_> = report.errors().filter_map(|e| e.path.as_deref()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "outputs[1].address",
+                "outputs[1].ttl",
+                "outputs[2].ptime_ms"
+            ]
+        );
+        let ptime = report
+            .errors()
+            .find(|e| e.path.as_deref() == Some("outputs[2].ptime_ms"))
+            .unwrap();
+        assert_eq!(ptime.suggestion.as_deref(), Some("use one of 10, 20"));
+
+        let config = Config::check(
+            content
+                .split("\n[[outputs]]\nid = \"hostname\"")
+                .next()
+                .unwrap(),
+        )
+        .into_result()
+        .unwrap();
+        match &config.outputs[0].kind {
+            OutputKind::Rtp {
+                payload,
+                ptime_ms,
+                ttl,
+                ..
+            } => {
+                assert_eq!(*payload, RtpPayload::L16);
+                assert_eq!(*ptime_ms, None);
+                assert_eq!(*ttl, 1);
+            }
+            other => panic!("Unexpected output kind {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_file_output() {
+        let content = r#"
+[[outputs]]
+id = "archive"
+kind = "file"
+path = "/var/lib/sonos-mux/%Y-%m-%d/kitchen-%H%M.flac"
+rotate_sec = 3600
+keep_files = 48
+
+[[outputs]]
+id = "raw"
+kind = "file"
+path = "/tmp/raw.pcm"
+
+[[outputs]]
+id = "broken"
+kind = "file"
+path = "/tmp/%Q.wav"
+rotate_mb = 0
+"#;
+
+        let report = Config::check(content);
+        let paths: Vec<_> = report.errors().filter_map(|e| e.path.as_deref()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "outputs[1].format",
+                "outputs[2].path",
+                "outputs[2].rotate_mb"
+            ]
+        );
+
+        // The format can also be given outright
+        let report = Config::check(&content.replace(
+            "path = \"/tmp/raw.pcm\"",
+            "path = \"/tmp/raw.pcm\"\nformat = \"wav\"",
+        ));
+        assert_eq!(report.errors().count(), 2);
+
+        let config = Config::check(content.split("[[outputs]]\nid = \"raw\"").next().unwrap())
+            .into_result()
+            .unwrap();
+        match &config.outputs[0].kind {
+            OutputKind::File {
+                format,
+                bitrate_kbps,
+                rotate_sec,
+                ..
+            } => {
+                assert_eq!(*format, None);
+                assert_eq!(*bitrate_kbps, 128);
+                assert_eq!(*rotate_sec, Some(3600));
+            }
+            other => panic!("Unexpected output kind {:?}", other),
+        }
+        assert_eq!(Codec::from_path("/var/lib/kitchen.FLAC"), Some(Codec::Flac));
+    }
+
+    #[test]
+    fn test_codec_quality() {
+        let content = r#"
+[[outputs]]
+id = "living_room"
+kind = "sonos"
+room = "Living Room"
+codec = "aac"
+quality = 4
+
+[[outputs]]
+id = "stream"
+kind = "http"
+codec = "ogg_flac"
+quality = 5
+
+[[outputs]]
+id = "recorder"
+kind = "file"
+path = "/var/lib/muxd/%Y.mp3"
+quality = 10
+
+[[outputs]]
+id = "family"
+kind = "icecast"
+url = "http://radio.example.com:8000/family.opus"
+password = "hackme"
+codec = "opus"
+bitrate_kbps = 96
+"#;
+
+        let report = Config::check(content);
+        let paths: Vec<_> = report.errors().filter_map(|e| e.path.as_deref()).collect();
+        assert_eq!(paths, vec!["outputs[1].quality", "outputs[2].quality"]);
+
+        let config: Config = toml::from_str(content).unwrap();
+        let encodings: Vec<_> = config
+            .outputs
+            .iter()
+            .map(|output| output.kind.encoding())
+            .collect();
+        assert_eq!(
+            encodings,
+            vec![
+                Some((Codec::Aac, 128, Some(4))),
+                Some((Codec::OggFlac, 128, Some(5))),
+                Some((Codec::Mp3, 128, Some(10))),
+                Some((Codec::Opus, 96, None)),
+            ]
+        );
+        assert_eq!(Codec::from_path("kitchen.opus"), Some(Codec::Opus));
+    }
+
+    #[test]
+    fn test_hls_output() {
+        let content = r#"
+[[outputs]]
+id = "garage"
+kind = "http"
+codec = "aac"
+hls = true
+
+[[outputs]]
+id = "office"
+kind = "http"
+codec = "flac"
+hls = true
+segment_sec = 0
+window = 2
+"#;
+
+        let report = Config::check(content);
+        let paths: Vec<_> = report.errors().filter_map(|e| e.path.as_deref()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "outputs[1].codec",
+                "outputs[1].segment_sec",
+                "outputs[1].window"
+            ]
+        );
+
+        let config: Config = toml::from_str(content).unwrap();
+        match &config.outputs[0].kind {
+            OutputKind::Http {
+                segment_sec,
+                window,
+                ..
+            } => assert_eq!((*segment_sec, *window), (6, 5)),
+            other => panic!("Unexpected output kind {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_interpolation() {
+        std::env::set_var("MUX_TEST_ALSA_DEVICE", "hw:Loopback,1");
+        std::env::remove_var("MUX_TEST_UNSET_ROOM");
+
+        let mut secret = NamedTempFile::new().unwrap();
+        writeln!(secret, "http://radio.example/live?token=hunter22").unwrap();
+
+        let content = format!(
+            r#"
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+device = "${{MUX_TEST_ALSA_DEVICE}}"
+
+[[inputs]]
+id = "radio"
+kind = "http"
+url_file = "{}"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "${{MUX_TEST_UNSET_ROOM:-Kitchen}}"
+
+[[routes]]
+input = "roon_main"
+outputs = ["kitchen"]
+
+[[routes]]
+input = "radio"
+outputs = ["kitchen"]
+"#,
+            secret.path().display()
+        );
+
+        let config = Config::from_reader(content.as_bytes()).unwrap();
+
+        match &config.inputs[0].kind {
+            InputKind::Alsa { device, .. } => assert_eq!(device, "hw:Loopback,1"),
+            other => panic!("unexpected kind {:?}", other),
+        }
+        match &config.inputs[1].kind {
+            InputKind::Http { url } => {
+                assert_eq!(url, "http://radio.example/live?token=hunter22");
+                assert_eq!(config.redact(url), "********");
+            }
+            other => panic!("unexpected kind {:?}", other),
+        }
+        match &config.outputs[0].kind {
+            OutputKind::Sonos { room, .. } => assert_eq!(room, "Kitchen"),
+            other => panic!("unexpected kind {:?}", other),
+        }
+        // Only secrets are redacted, not every substituted value
+        assert_eq!(
+            config.redact("Kitchen on hw:Loopback,1"),
+            "Kitchen on hw:Loopback,1"
+        );
+    }
+
+    #[test]
+    fn test_interpolation_errors() {
+        std::env::remove_var("MUX_TEST_MISSING_URL");
+        std::env::set_var("MUX_TEST_SECRET_ID", "private-room");
+
+        let content = r#"
+secret_env = ["MUX_TEST_SECRET_ID"]
+
+[[inputs]]
+id = "radio"
+kind = "http"
+url = "${MUX_TEST_MISSING_URL}"
+
+[[inputs]]
+id = "stream"
+kind = "http"
+url = "http://a"
+url_file = "/nonexistent/secret"
+
+[[routes]]
+input = "${MUX_TEST_SECRET_ID}"
+outputs = []
+"#;
+
+        let report = Config::check(content);
+        let errors: Vec<_> = report.errors().collect();
+
+        assert_eq!(errors[0].code, "interpolation");
+        assert_eq!(errors[0].path.as_deref(), Some("inputs[0].url"));
+        assert_eq!(errors[0].location.unwrap().line, 7);
+        assert_eq!(errors[1].path.as_deref(), Some("inputs[1].url_file"));
+        assert_eq!(errors[2].code, "unknown-id");
+        assert!(!errors[2].message.contains("private-room"));
+        assert!(matches!(
+            report.into_result(),
+            Err(ConfigError::Interpolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_migrates_old_version() {
+        let content = r#"
+[[inputs]]
+id = "alert"
+kind = "file"
+path = "alert.pcm"
+loop_playback = true
+"#;
+
+        let report = Config::check(content);
+        assert!(!report.has_errors());
+        let warnings: Vec<_> = report.warnings().map(|w| w.code).collect();
+        assert_eq!(warnings[0], "deprecated-version");
+
+        let config = report.into_result().unwrap();
+        assert_eq!(config.version, CURRENT_VERSION);
+        assert_eq!(config.warnings[0].code, "deprecated-version");
+        match &config.inputs[0].kind {
+            InputKind::File { loop_playback, .. } => assert!(loop_playback),
+            other => panic!("unexpected kind {:?}", other),
+        }
+
+        let report = Config::check("version = 3\n");
+        assert_eq!(
+            report.errors().next().map(|e| e.code),
+            Some("unsupported-version")
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use super::Config;
+use schemars::schema::{RootSchema, Schema, SingleOrVec};
+use serde_json::json;
+
+/// JSON Schema describing the configuration file format
+pub fn schema() -> RootSchema {
+    let mut root = schemars::schema_for!(Config);
+
+    // `id` sits next to the flattened `kind` enum, but each variant denies
+    // unknown fields, so copy the shared properties into every variant
+    for name in ["Input", "Output"] {
+        if let Some(Schema::Object(definition)) = root.definitions.get_mut(name) {
+            let shared = definition.object.clone().unwrap_or_default();
+            let variants = definition
+                .subschemas
+                .as_mut()
+                .and_then(|subschemas| subschemas.one_of.as_mut());
+            for variant in variants.into_iter().flatten() {
+                if let Schema::Object(variant) = variant {
+                    let object = variant.object();
+                    object.properties.extend(shared.properties.clone());
+                    object.required.extend(shared.required.iter().cloned());
+                }
+            }
+        }
+    }
+
+    // A file layered over an included one can drop an entry by ID
+    let removal = json!({
+        "description": "Removes the entry with this ID from an included file",
+        "type": "object",
+        "required": ["id", "remove"],
+        "properties": {
+            "id": { "type": "string" },
+            "remove": { "const": true }
+        },
+        "additionalProperties": false
+    });
+    root.definitions.insert(
+        "Removal".to_string(),
+        serde_json::from_value(removal).expect("valid schema"),
+    );
+    for key in ["inputs", "outputs", "routes"] {
+        let Some(Schema::Object(property)) = root.schema.object().properties.get_mut(key) else {
+            continue;
+        };
+        if let Some(SingleOrVec::Single(items)) = property.array().items.as_mut() {
+            let entry = serde_json::to_value(&**items).expect("schema serializes to JSON");
+            **items = serde_json::from_value(json!({
+                "anyOf": [entry, { "$ref": "#/definitions/Removal" }]
+            }))
+            .expect("valid schema");
+        }
+    }
+
+    root
+}
+
+/// The configuration schema as pretty-printed JSON, for editors such as
+/// taplo or VS Code
+pub fn schema_json() -> String {
+    serde_json::to_string_pretty(&schema()).expect("schema serializes to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator() -> jsonschema::JSONSchema {
+        let schema = serde_json::to_value(schema()).unwrap();
+        jsonschema::JSONSchema::compile(&schema).unwrap()
+    }
+
+    fn toml_to_json(source: &str) -> serde_json::Value {
+        let value: toml::Value = toml::from_str(source).unwrap();
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_example_config_matches_schema() {
+        let source = include_str!("../../../examples/config.toml");
+        assert!(validator().is_valid(&toml_to_json(source)));
+    }
+
+    #[test]
+    fn test_schema_rejects_per_kind_mistakes() {
+        let validator = validator();
+
+        let wrong_field = toml_to_json(
+            r#"
+[[inputs]]
+id = "roon_main"
+kind = "alsa"
+url = "http://example.com/stream.mp3"
+"#,
+        );
+        assert!(!validator.is_valid(&wrong_field));
+
+        let removal = toml_to_json(
+            r#"
+[[outputs]]
+id = "kitchen"
+remove = true
+"#,
+        );
+        assert!(validator.is_valid(&removal));
+
+        let missing_room = toml_to_json(
+            r#"
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+"#,
+        );
+        assert!(!validator.is_valid(&missing_room));
+
+        let out_of_range = toml_to_json(
+            r#"
+[[routes]]
+input = "roon_main"
+outputs = ["kitchen"]
+gain_db = 40.0
+"#,
+        );
+        assert!(!validator.is_valid(&out_of_range));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::config::Codec;
 use crate::input::Frame;
 use thiserror::Error;
 
@@ -10,6 +11,48 @@ pub enum EncoderError {
     Encode(String),
 }
 
+/// Turns an output's mix into a stream of some codec
+pub trait Encoder: Send {
+    /// MIME type of the encoded audio
+    fn content_type(&self) -> &'static str;
+
+    /// What every listener has to get before the first encoded audio, e.g.
+    /// a FLAC STREAMINFO or Ogg header pages
+    fn header(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Encode interleaved stereo samples, returning whatever is ready
+    fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<u8>, EncoderError>;
+
+    /// Encode whatever is still buffered, e.g. on shutdown
+    fn flush(&mut self) -> Result<Vec<u8>, EncoderError>;
+}
+
+/// An encoder for `codec`; `quality` asks MP3 and AAC for variable bitrate
+/// in place of `bitrate_kbps`
+pub fn create_encoder(
+    codec: Codec,
+    bitrate_kbps: u32,
+    quality: Option<u8>,
+) -> Result<Box<dyn Encoder>, EncoderError> {
+    Ok(match codec {
+        Codec::Wav => Box::new(WavStream),
+        // A live stream has no known length, which FLAC allows
+        Codec::Flac => Box::new(Flac::new()),
+        Codec::Mp3 => Box::new(match quality {
+            Some(quality) => Lame::vbr(quality)?,
+            None => Lame::new(bitrate_kbps as i32)?,
+        }),
+        Codec::Opus => Box::new(OggOpus::new(bitrate_kbps)?),
+        Codec::OggFlac => Box::new(OggFlac::new()),
+        Codec::Aac => Box::new(match quality {
+            Some(quality) => Aac::vbr(quality)?,
+            None => Aac::new(bitrate_kbps),
+        }),
+    })
+}
+
 // For Sprint 2, we'll create a mock implementation of the MP3 encoder
 // This allows us to test the pipeline without requiring the LAME library
 pub struct Lame {
@@ -25,6 +68,19 @@ impl Lame {
         })
     }
 
+    /// Variable bitrate at LAME's `-V` quality, 0 being the best and 9 the
+    /// smallest; the mock encodes at the average bitrate each one gives
+    pub fn vbr(quality: u8) -> Result<Self, EncoderError> {
+        const AVERAGE_KBPS: [i32; 10] = [245, 225, 190, 175, 165, 130, 115, 100, 85, 65];
+        match AVERAGE_KBPS.get(quality as usize) {
+            Some(bitrate) => Self::new(*bitrate),
+            None => Err(EncoderError::Initialization(format!(
+                "MP3 quality {} is outside 0..=9",
+                quality
+            ))),
+        }
+    }
+
     // Encode interleaved stereo samples to a mock MP3 format
     pub fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<u8>, EncoderError> {
         // In a real implementation, this would use LAME to encode the PCM data to MP3
@@ -64,6 +120,607 @@ impl Lame {
     }
 }
 
+/// Opus encoder producing one packet per frame, for RTP. Opus runs at
+/// 48 kHz, so the mix is resampled on the way in. Like `Lame` it is a mock
+/// for now: packets have a real table-of-contents byte and the size the
+/// bitrate calls for, but carry no audio
+pub struct Opus {
+    bitrate_kbps: u32,
+    frame_ms: u32,
+    // Resampled stereo samples not yet in a packet
+    pending: Vec<Frame>,
+    // The last stereo frame of the mix, and how far past it the next
+    // resampled frame falls, in 480ths of a frame
+    previous: [Frame; 2],
+    phase: u32,
+}
+
+impl Opus {
+    /// The rate Opus encodes at, and that its timestamps count in
+    pub const SAMPLE_RATE: u32 = 48000;
+
+    /// Frame lengths in milliseconds, of those Opus has, that CELT can
+    /// code on its own
+    pub const FRAME_MS: &'static [u32] = &[10, 20];
+
+    pub fn new(bitrate_kbps: u32, frame_ms: u32) -> Result<Self, EncoderError> {
+        if !Self::FRAME_MS.contains(&frame_ms) {
+            return Err(EncoderError::Initialization(format!(
+                "Opus can't use {} ms frames",
+                frame_ms
+            )));
+        }
+        Ok(Self {
+            bitrate_kbps,
+            frame_ms,
+            pending: Vec::new(),
+            previous: [0; 2],
+            phase: 0,
+        })
+    }
+
+    /// 48 kHz stereo frames in each packet
+    pub fn frame_samples(&self) -> usize {
+        (Self::SAMPLE_RATE * self.frame_ms / 1000) as usize
+    }
+
+    /// Encode interleaved 44.1 kHz stereo samples, returning a packet for
+    /// every whole frame
+    pub fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<Vec<u8>>, EncoderError> {
+        self.resample(pcm);
+
+        let mut packets = Vec::new();
+        let frame = self.frame_samples() * 2;
+        while self.pending.len() >= frame {
+            self.pending.drain(..frame);
+            // CELT-only fullband, 10 or 20 ms, stereo, one frame
+            let config = if self.frame_ms == 10 { 30 } else { 31 };
+            let mut packet = vec![(config << 3) | 0x04];
+            packet.resize((self.bitrate_kbps * self.frame_ms / 8) as usize, 0);
+            packets.push(packet);
+        }
+        Ok(packets)
+    }
+
+    // Linear interpolation from 44.1 to 48 kHz: each resampled frame lies
+    // 441/480 of a frame past the one before, so the phase stays exact
+    fn resample(&mut self, pcm: &[Frame]) {
+        for next in pcm.chunks_exact(2) {
+            while self.phase < 480 {
+                for (previous, next) in self.previous.iter().zip(next) {
+                    let (previous, next) = (i32::from(*previous), i32::from(*next));
+                    let sample = previous + (next - previous) * self.phase as i32 / 480;
+                    self.pending.push(sample as Frame);
+                }
+                self.phase += 441;
+            }
+            self.phase -= 480;
+            self.previous = [next[0], next[1]];
+        }
+    }
+}
+
+/// Lossless FLAC encoder for 44.1 kHz 16-bit stereo. Every block is stored
+/// verbatim rather than predicted, so files are about as large as WAV, but
+/// they carry per-frame checksums and play anywhere FLAC does
+#[derive(Default)]
+pub struct Flac {
+    pending: Vec<Frame>,
+    frame_number: u64,
+    samples: u64,
+}
+
+impl Flac {
+    /// Stereo frames per FLAC block
+    pub const BLOCK_SIZE: usize = 4096;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The stream marker and STREAMINFO block, which has to be written again
+    /// over the first bytes of the file once `samples` is final
+    pub fn header(&self) -> Vec<u8> {
+        let mut header = b"fLaC".to_vec();
+        // Last metadata block, type STREAMINFO, 34 bytes long
+        header.extend_from_slice(&[0x80, 0, 0, 34]);
+        header.extend_from_slice(&(Self::BLOCK_SIZE as u16).to_be_bytes());
+        header.extend_from_slice(&(Self::BLOCK_SIZE as u16).to_be_bytes());
+        // Smallest and largest frame sizes, unknown
+        header.extend_from_slice(&[0; 6]);
+        // Sample rate, channels - 1, bits per sample - 1 and total samples
+        let format = (44100u64 << 44) | (1 << 41) | (15 << 36) | (self.samples & 0xF_FFFF_FFFF);
+        header.extend_from_slice(&format.to_be_bytes());
+        // MD5 of the audio, unknown
+        header.extend_from_slice(&[0; 16]);
+        header
+    }
+
+    /// Encode interleaved stereo samples, returning whole FLAC frames
+    pub fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<u8>, EncoderError> {
+        Ok(self.encode_frames(pcm)?.concat())
+    }
+
+    /// Encode whatever is left as a final, shorter frame
+    pub fn flush(&mut self) -> Result<Vec<u8>, EncoderError> {
+        Ok(self.flush_frame().unwrap_or_default())
+    }
+
+    /// Like `encode`, with each FLAC frame on its own
+    fn encode_frames(&mut self, pcm: &[Frame]) -> Result<Vec<Vec<u8>>, EncoderError> {
+        if !pcm.len().is_multiple_of(2) {
+            return Err(EncoderError::Encode(
+                "Odd number of samples in stereo audio".to_string(),
+            ));
+        }
+        self.pending.extend_from_slice(pcm);
+
+        let mut frames = Vec::new();
+        let block = Self::BLOCK_SIZE * 2;
+        while self.pending.len() >= block {
+            let rest = self.pending.split_off(block);
+            let pcm = std::mem::replace(&mut self.pending, rest);
+            let mut frame = Vec::new();
+            self.encode_frame(&pcm, &mut frame);
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    fn flush_frame(&mut self) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let pcm = std::mem::take(&mut self.pending);
+        let mut frame = Vec::new();
+        self.encode_frame(&pcm, &mut frame);
+        Some(frame)
+    }
+
+    /// Stereo frames encoded so far
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    fn encode_frame(&mut self, pcm: &[Frame], out: &mut Vec<u8>) {
+        let start = out.len();
+        let block_size = pcm.len() / 2;
+
+        // Sync code, fixed block size, size in 16 bits at the end of the
+        // header, 44.1 kHz, independent left and right, 16 bits per sample
+        out.extend_from_slice(&[0xFF, 0xF8, 0x79, 0x18]);
+        push_utf8_number(out, self.frame_number);
+        out.extend_from_slice(&((block_size - 1) as u16).to_be_bytes());
+        out.push(crc8(&out[start..]));
+
+        for channel in 0..2 {
+            // Verbatim subframe with no wasted bits
+            out.push(0x02);
+            for sample in pcm.iter().skip(channel).step_by(2) {
+                out.extend_from_slice(&sample.to_be_bytes());
+            }
+        }
+
+        let crc = crc16(&out[start..]);
+        out.extend_from_slice(&crc.to_be_bytes());
+
+        self.frame_number += 1;
+        self.samples += block_size as u64;
+    }
+}
+
+// FLAC codes frame numbers the way UTF-8 codes characters, up to 36 bits
+fn push_utf8_number(out: &mut Vec<u8>, n: u64) {
+    if n < 0x80 {
+        out.push(n as u8);
+        return;
+    }
+    let len = match n {
+        0..=0x7FF => 2,
+        0x800..=0xFFFF => 3,
+        0x1_0000..=0x1F_FFFF => 4,
+        0x20_0000..=0x3FF_FFFF => 5,
+        0x400_0000..=0x7FFF_FFFF => 6,
+        _ => 7,
+    };
+    let prefix = (0xFF00u16 >> len) as u8;
+    out.push(prefix | (n >> (6 * (len - 1))) as u8);
+    for i in (0..len - 1).rev() {
+        out.push(0x80 | ((n >> (6 * i)) & 0x3F) as u8);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+impl Encoder for Lame {
+    fn content_type(&self) -> &'static str {
+        "audio/mpeg"
+    }
+
+    fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<u8>, EncoderError> {
+        Lame::encode(self, pcm)
+    }
+
+    fn flush(&mut self) -> Result<Vec<u8>, EncoderError> {
+        Lame::flush(self)
+    }
+}
+
+impl Encoder for Flac {
+    fn content_type(&self) -> &'static str {
+        "audio/flac"
+    }
+
+    fn header(&self) -> Vec<u8> {
+        Flac::header(self)
+    }
+
+    fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<u8>, EncoderError> {
+        Flac::encode(self, pcm)
+    }
+
+    fn flush(&mut self) -> Result<Vec<u8>, EncoderError> {
+        Flac::flush(self)
+    }
+}
+
+/// 16-bit PCM in a WAV header that never ends
+pub struct WavStream;
+
+impl Encoder for WavStream {
+    fn content_type(&self) -> &'static str {
+        "audio/wav"
+    }
+
+    fn header(&self) -> Vec<u8> {
+        wav_stream_header()
+    }
+
+    fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<u8>, EncoderError> {
+        Ok(pcm.iter().flat_map(|s| s.to_le_bytes()).collect())
+    }
+
+    fn flush(&mut self) -> Result<Vec<u8>, EncoderError> {
+        Ok(Vec::new())
+    }
+}
+
+/// Opus in Ogg pages, one 20 ms packet to a page, as RFC 7845 lays out
+pub struct OggOpus {
+    encoder: Opus,
+    ogg: OggStream,
+    header: Vec<u8>,
+    // 48 kHz samples encoded, which is what Opus granule positions count
+    granule: u64,
+}
+
+impl OggOpus {
+    // Samples a decoder drops at the start, as libopus asks for
+    const PRE_SKIP: u16 = 312;
+
+    pub fn new(bitrate_kbps: u32) -> Result<Self, EncoderError> {
+        let mut ogg = OggStream::new();
+
+        let mut head = b"OpusHead".to_vec();
+        // Version 1, stereo, pre-skip, the rate the audio was encoded at,
+        // no gain, channel mapping family 0
+        head.extend_from_slice(&[1, 2]);
+        head.extend_from_slice(&Self::PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&Opus::SAMPLE_RATE.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        let mut header = ogg.page(&head, 0, BEGINNING_OF_STREAM);
+        header.extend(ogg.page(&vorbis_comment(b"OpusTags"), 0, 0));
+
+        Ok(Self {
+            encoder: Opus::new(bitrate_kbps, 20)?,
+            ogg,
+            header,
+            granule: 0,
+        })
+    }
+}
+
+impl Encoder for OggOpus {
+    fn content_type(&self) -> &'static str {
+        "audio/ogg"
+    }
+
+    fn header(&self) -> Vec<u8> {
+        self.header.clone()
+    }
+
+    fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<u8>, EncoderError> {
+        let mut pages = Vec::new();
+        for packet in self.encoder.encode(pcm)? {
+            self.granule += self.encoder.frame_samples() as u64;
+            pages.extend(self.ogg.page(&packet, self.granule, 0));
+        }
+        Ok(pages)
+    }
+
+    fn flush(&mut self) -> Result<Vec<u8>, EncoderError> {
+        Ok(Vec::new())
+    }
+}
+
+/// FLAC in Ogg pages, one FLAC frame to a page
+pub struct OggFlac {
+    encoder: Flac,
+    ogg: OggStream,
+    header: Vec<u8>,
+}
+
+impl OggFlac {
+    pub fn new() -> Self {
+        let encoder = Flac::new();
+        let mut ogg = OggStream::new();
+
+        // The mapping's own header, then the stream marker and STREAMINFO,
+        // which is no longer the last metadata block
+        let mut first = vec![0x7F];
+        first.extend_from_slice(b"FLAC");
+        // Version 1.0, followed by one more header packet
+        first.extend_from_slice(&[1, 0, 0, 1]);
+        let mut streaminfo = encoder.header();
+        streaminfo[4] &= 0x7F;
+        first.extend(streaminfo);
+        let mut header = ogg.page(&first, 0, BEGINNING_OF_STREAM);
+
+        // The VORBIS_COMMENT block the mapping requires, as the last one
+        let comment = vorbis_comment(b"");
+        let mut block = vec![0x84];
+        block.extend_from_slice(&(comment.len() as u32).to_be_bytes()[1..]);
+        block.extend(comment);
+        header.extend(ogg.page(&block, 0, 0));
+
+        Self {
+            encoder,
+            ogg,
+            header,
+        }
+    }
+}
+
+impl Default for OggFlac {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder for OggFlac {
+    fn content_type(&self) -> &'static str {
+        "audio/ogg"
+    }
+
+    fn header(&self) -> Vec<u8> {
+        self.header.clone()
+    }
+
+    fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<u8>, EncoderError> {
+        let mut pages = Vec::new();
+        for frame in self.encoder.encode_frames(pcm)? {
+            pages.extend(self.ogg.page(&frame, self.encoder.samples(), 0));
+        }
+        Ok(pages)
+    }
+
+    fn flush(&mut self) -> Result<Vec<u8>, EncoderError> {
+        Ok(match self.encoder.flush_frame() {
+            Some(frame) => self.ogg.page(&frame, self.encoder.samples(), END_OF_STREAM),
+            None => Vec::new(),
+        })
+    }
+}
+
+/// AAC-LC in ADTS frames. Like `Lame` it is a mock for now: frames have real
+/// ADTS headers and the size the bitrate calls for, but carry no audio
+pub struct Aac {
+    bitrate_kbps: u32,
+    pending: usize,
+}
+
+impl Aac {
+    // Stereo frames of the mix in each AAC frame
+    const FRAME_SAMPLES: usize = 1024;
+
+    pub fn new(bitrate_kbps: u32) -> Self {
+        Self {
+            bitrate_kbps,
+            pending: 0,
+        }
+    }
+
+    /// Variable bitrate at one of the five VBR modes AAC encoders offer, 1
+    /// being the smallest; the mock encodes at the bitrate each one aims for
+    pub fn vbr(quality: u8) -> Result<Self, EncoderError> {
+        const AVERAGE_KBPS: [u32; 5] = [64, 80, 96, 128, 192];
+        match quality
+            .checked_sub(1)
+            .and_then(|i| AVERAGE_KBPS.get(i as usize))
+        {
+            Some(bitrate) => Ok(Self::new(*bitrate)),
+            None => Err(EncoderError::Initialization(format!(
+                "AAC quality {} is outside 1..=5",
+                quality
+            ))),
+        }
+    }
+}
+
+impl Encoder for Aac {
+    fn content_type(&self) -> &'static str {
+        "audio/aac"
+    }
+
+    fn encode(&mut self, pcm: &[Frame]) -> Result<Vec<u8>, EncoderError> {
+        self.pending += pcm.len() / 2;
+        let mut encoded = Vec::new();
+        let length = 7 + self.bitrate_kbps as usize * 1000 / 8 * Self::FRAME_SAMPLES / 44100;
+        while self.pending >= Self::FRAME_SAMPLES {
+            self.pending -= Self::FRAME_SAMPLES;
+            // No CRC, AAC-LC, 44.1 kHz, stereo, the frame's length and a
+            // variable bitrate's buffer fullness
+            encoded.extend_from_slice(&[
+                0xFF,
+                0xF1,
+                0x50,
+                0x80 | (length >> 11) as u8,
+                (length >> 3) as u8,
+                ((length & 7) << 5) as u8 | 0x1F,
+                0xFC,
+            ]);
+            encoded.resize(encoded.len() + length - 7, 0);
+        }
+        Ok(encoded)
+    }
+
+    fn flush(&mut self) -> Result<Vec<u8>, EncoderError> {
+        Ok(Vec::new())
+    }
+}
+
+const CONTINUED_PACKET: u8 = 0x01;
+const BEGINNING_OF_STREAM: u8 = 0x02;
+const END_OF_STREAM: u8 = 0x04;
+
+// Pages of a single logical Ogg stream
+struct OggStream {
+    serial: u32,
+    sequence: u32,
+}
+
+impl OggStream {
+    fn new() -> Self {
+        // Only has to differ from other streams a listener might chain
+        let serial = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|t| t.subsec_nanos())
+            .unwrap_or_default();
+        Self {
+            serial,
+            sequence: 0,
+        }
+    }
+
+    // Pages holding one whole packet. A page takes at most 255 lacing
+    // values, so longer packets carry on in pages flagged as continued;
+    // only the page the packet ends on gets its granule position and any
+    // end-of-stream flag
+    fn page(&mut self, packet: &[u8], granule: u64, flags: u8) -> Vec<u8> {
+        let mut lacing = vec![255u8; packet.len() / 255];
+        lacing.push((packet.len() % 255) as u8);
+
+        let mut pages = Vec::new();
+        let mut body = packet;
+        let chunks = lacing.chunks(255).count();
+        for (i, lacing) in lacing.chunks(255).enumerate() {
+            let last = i + 1 == chunks;
+            let mut page_flags = match i {
+                0 => flags & BEGINNING_OF_STREAM,
+                _ => CONTINUED_PACKET,
+            };
+            if last {
+                page_flags |= flags & END_OF_STREAM;
+            }
+            let (data, rest) = body.split_at(lacing.iter().map(|&n| n as usize).sum());
+            body = rest;
+
+            let mut page = b"OggS".to_vec();
+            page.push(0);
+            page.push(page_flags);
+            // -1 marks a page on which no packet ends
+            let granule = if last { granule } else { u64::MAX };
+            page.extend_from_slice(&granule.to_le_bytes());
+            page.extend_from_slice(&self.serial.to_le_bytes());
+            page.extend_from_slice(&self.sequence.to_le_bytes());
+            // The checksum, filled in once the page is complete
+            page.extend_from_slice(&[0; 4]);
+            page.push(lacing.len() as u8);
+            page.extend_from_slice(lacing);
+            page.extend_from_slice(data);
+            let crc = ogg_crc(&page);
+            page[22..26].copy_from_slice(&crc.to_le_bytes());
+
+            self.sequence += 1;
+            pages.extend(page);
+        }
+        pages
+    }
+}
+
+// Ogg's CRC-32: polynomial 0x04C11DB7, not reflected, starting from zero
+fn ogg_crc(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |mut crc, &byte| {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+// A Vorbis comment header naming us as the vendor, with no comments
+fn vorbis_comment(magic: &[u8]) -> Vec<u8> {
+    let vendor = format!("muxd {}", crate::version());
+    let mut comment = magic.to_vec();
+    comment.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    comment.extend_from_slice(vendor.as_bytes());
+    comment.extend_from_slice(&0u32.to_le_bytes());
+    comment
+}
+
+// A WAV header for 44.1 kHz 16-bit stereo with the largest sizes allowed,
+// since a live stream has no end
+fn wav_stream_header() -> Vec<u8> {
+    // Whole frames, leaving room for the rest of the RIFF chunk
+    let data_len = (u32::MAX - 36) & !3;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(data_len + 36).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, two channels, sample rate, byte rate, block align, sample size
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&44100u32.to_le_bytes());
+    header.extend_from_slice(&(44100u32 * 4).to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    header
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,4 +778,159 @@ mod tests {
             expected_size
         );
     }
+
+    #[test]
+    fn test_opus_packets() {
+        assert!(Opus::new(128, 15).is_err());
+
+        let mut encoder = Opus::new(128, 20).unwrap();
+        assert!(encoder.encode(&[0; 800 * 2]).unwrap().is_empty());
+        let packets = encoder.encode(&[0; 1000 * 2]).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0][0], 0xFC);
+        assert_eq!(packets[0].len(), 320);
+
+        // A second of the mix is exactly a second at 48 kHz
+        let mut encoder = Opus::new(64, 10).unwrap();
+        assert_eq!(encoder.frame_samples(), 480);
+        assert_eq!(encoder.encode(&vec![0; 44100 * 2]).unwrap().len(), 100);
+        assert!(encoder.pending.is_empty());
+    }
+
+    // Every packet in an Ogg stream, with its page's granule position;
+    // the ogg crate checks each page's CRC
+    fn ogg_packets(stream: Vec<u8>) -> Vec<(Vec<u8>, u64)> {
+        let mut reader = ogg::PacketReader::new(std::io::Cursor::new(stream));
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet().unwrap() {
+            let granule = packet.absgp_page();
+            packets.push((packet.data, granule));
+        }
+        packets
+    }
+
+    #[test]
+    fn test_ogg_opus() {
+        let mut encoder = create_encoder(Codec::Opus, 64, None).unwrap();
+        assert_eq!(encoder.content_type(), "audio/ogg");
+        let mut stream = encoder.header();
+        stream.extend(encoder.encode(&vec![0; 882 * 2 * 3]).unwrap());
+
+        let packets = ogg_packets(stream);
+        assert_eq!(packets.len(), 5);
+        assert!(packets[0].0.starts_with(b"OpusHead\x01\x02"));
+        assert!(packets[1].0.starts_with(b"OpusTags"));
+        assert_eq!(packets[2].0.len(), 64 * 20 / 8);
+        let granules: Vec<_> = packets.iter().map(|(_, granule)| *granule).collect();
+        assert_eq!(granules, [0, 0, 960, 1920, 2880]);
+    }
+
+    #[test]
+    fn test_ogg_pages_split_long_packets() {
+        let mut ogg = OggStream::new();
+        let long: Vec<u8> = (0..70_000u32).map(|i| i as u8).collect();
+        let mut stream = ogg.page(b"first", 0, BEGINNING_OF_STREAM);
+        stream.extend(ogg.page(&long, 100, 0));
+        stream.extend(ogg.page(&[7; 255 * 255], 200, END_OF_STREAM));
+        assert_eq!(ogg.sequence, 5);
+
+        let packets = ogg_packets(stream);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[1], (long, 100));
+        assert_eq!(packets[2], (vec![7; 255 * 255], 200));
+    }
+
+    #[test]
+    fn test_ogg_flac_round_trip() {
+        let pcm: Vec<Frame> = (0..Flac::BLOCK_SIZE * 3)
+            .map(|i| (i as i32 * 5 - 10000) as i16)
+            .collect();
+        let mut encoder = create_encoder(Codec::OggFlac, 0, None).unwrap();
+        let mut stream = encoder.header();
+        stream.extend(encoder.encode(&pcm).unwrap());
+        stream.extend(encoder.flush().unwrap());
+
+        let packets = ogg_packets(stream);
+        assert_eq!(packets.len(), 4);
+        assert!(packets[0].0.starts_with(b"\x7fFLAC\x01\x00\x00\x01fLaC"));
+        // VORBIS_COMMENT, the last metadata block
+        assert_eq!(packets[1].0[0], 0x84);
+        assert_eq!(packets[3].1, pcm.len() as u64 / 2);
+
+        // The packets after the mapping's header make a plain FLAC stream
+        let mut flac = packets[0].0[9..].to_vec();
+        flac[4] |= 0x80;
+        for (packet, _) in &packets[2..] {
+            flac.extend_from_slice(packet);
+        }
+        let mut reader = claxon::FlacReader::new(std::io::Cursor::new(flac)).unwrap();
+        let decoded: Vec<Frame> = reader.samples().map(|s| s.unwrap() as Frame).collect();
+        assert_eq!(decoded, pcm);
+    }
+
+    #[test]
+    fn test_aac_adts_frames() {
+        assert!(Aac::vbr(0).is_err());
+        let mut encoder = create_encoder(Codec::Aac, 128, None).unwrap();
+        assert_eq!(encoder.content_type(), "audio/aac");
+        let data = encoder.encode(&vec![0; 1500 * 2]).unwrap();
+        let length = 7 + 16000 * 1024 / 44100;
+        assert_eq!(data.len(), length);
+        assert_eq!(&data[..3], &[0xFF, 0xF1, 0x50]);
+        let framed =
+            ((data[3] as usize & 3) << 11) | ((data[4] as usize) << 3) | (data[5] as usize >> 5);
+        assert_eq!(framed, length);
+
+        // The rest of the first buffer completes the second frame
+        assert_eq!(encoder.encode(&vec![0; 548 * 2]).unwrap().len(), length);
+    }
+
+    #[test]
+    fn test_encoders_by_codec() {
+        let pcm = vec![1; 4410 * 2];
+        let mut wav = create_encoder(Codec::Wav, 128, None).unwrap();
+        assert_eq!(wav.header().len(), 44);
+        assert_eq!(wav.encode(&pcm).unwrap().len(), pcm.len() * 2);
+
+        // VBR quality 2 averages 190 kbps in place of the bitrate
+        let mut mp3 = create_encoder(Codec::Mp3, 128, Some(2)).unwrap();
+        assert_eq!(mp3.content_type(), "audio/mpeg");
+        assert_eq!(mp3.encode(&pcm).unwrap().len(), 190 * 1000 / 8 / 10);
+        assert!(create_encoder(Codec::Mp3, 128, Some(10)).is_err());
+
+        let flac = create_encoder(Codec::Flac, 128, None).unwrap();
+        assert!(flac.header().starts_with(b"fLaC"));
+    }
+
+    #[test]
+    fn test_flac_round_trip() {
+        // A block and a half of a ramp, so that the last frame is short
+        let pcm: Vec<Frame> = (0..Flac::BLOCK_SIZE * 3)
+            .map(|i| (i as i32 * 7 - 20000) as i16)
+            .collect();
+
+        let mut encoder = Flac::new();
+        let mut body = encoder.encode(&pcm[..1000]).unwrap();
+        assert!(body.is_empty(), "Encoded before a whole block was ready");
+        body.extend(encoder.encode(&pcm[1000..]).unwrap());
+        body.extend(encoder.flush().unwrap());
+        assert_eq!(encoder.samples(), pcm.len() as u64 / 2);
+
+        let mut file = encoder.header();
+        file.extend(body);
+        let mut reader = claxon::FlacReader::new(std::io::Cursor::new(file)).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(info.sample_rate, 44100);
+        assert_eq!(info.channels, 2);
+        assert_eq!(info.bits_per_sample, 16);
+        assert_eq!(info.samples, Some(pcm.len() as u64 / 2));
+
+        // claxon checks every frame's CRC while decoding
+        let decoded: Vec<Frame> = reader.samples().map(|s| s.unwrap() as Frame).collect();
+        assert_eq!(decoded, pcm);
+
+        let mut number = Vec::new();
+        push_utf8_number(&mut number, 0x800);
+        assert_eq!(number, [0xE0, 0xA0, 0x80]);
+    }
 }
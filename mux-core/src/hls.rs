@@ -0,0 +1,236 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Name of the playlist, next to its segments
+pub const PLAYLIST: &str = "index.m3u8";
+
+// Segments kept past the end of the playlist, for players that fetched the
+// playlist just before they dropped out of it
+const SPARE_SEGMENTS: usize = 2;
+
+/// Cuts a stream of encoded audio into segments no longer than a target
+/// length, keeping a rolling HLS playlist of the latest ones
+pub struct Playlist {
+    content_type: String,
+    extension: String,
+    target: Duration,
+    window: usize,
+    segments: Mutex<Segments>,
+}
+
+#[derive(Default)]
+struct Segments {
+    // Media sequence number of the first segment kept
+    first: u64,
+    done: VecDeque<Segment>,
+    // The segment being filled
+    data: Vec<u8>,
+    frames: u64,
+    // Stereo frames in every segment before the one being filled
+    frames_before: u64,
+}
+
+struct Segment {
+    duration: Duration,
+    data: Arc<Vec<u8>>,
+}
+
+impl Playlist {
+    /// Segments of `target` length, listing the latest `window` of them;
+    /// `extension` names the segments' files, e.g. `aac`
+    pub fn new(content_type: &str, extension: &str, target: Duration, window: usize) -> Self {
+        Self {
+            content_type: content_type.to_string(),
+            extension: extension.to_string(),
+            target,
+            window,
+            segments: Mutex::new(Segments::default()),
+        }
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// Add encoded audio holding `frames` stereo frames of the mix. A
+    /// segment ends once it is as long as the target, or before audio that
+    /// would take it past the target; callers keep each send within
+    /// `frames_left` so segments come out the target length
+    pub fn send(&self, data: &[u8], frames: u64) {
+        let mut segments = self.segments.lock().unwrap();
+        if segments.frames > 0 && segments.frames + frames > self.target_frames() {
+            self.cut(&mut segments);
+        }
+        segments.data.extend_from_slice(data);
+        segments.frames += frames;
+        if segments.frames >= self.target_frames() {
+            self.cut(&mut segments);
+        }
+    }
+
+    /// Stereo frames the segment being filled takes before it is cut
+    pub fn frames_left(&self) -> u64 {
+        let segments = self.segments.lock().unwrap();
+        self.target_frames() - segments.frames
+    }
+
+    fn target_frames(&self) -> u64 {
+        self.target.as_millis() as u64 * 44_100 / 1000
+    }
+
+    // End the segment being filled and add it to the playlist
+    fn cut(&self, segments: &mut Segments) {
+        // Packed audio has no timestamps of its own, so each segment starts
+        // with an ID3 tag giving its first sample's, on the 90 kHz MPEG clock
+        let timestamp = segments.frames_before * 90_000 / 44_100;
+        let mut segment = timestamp_tag(timestamp);
+        segment.append(&mut segments.data);
+        let frames = std::mem::take(&mut segments.frames);
+        segments.frames_before += frames;
+        segments.done.push_back(Segment {
+            duration: frames_duration(frames),
+            data: Arc::new(segment),
+        });
+        while segments.done.len() > self.window + SPARE_SEGMENTS {
+            segments.done.pop_front();
+            segments.first += 1;
+        }
+    }
+
+    /// The playlist listing the latest segments
+    pub fn playlist(&self) -> String {
+        let segments = self.segments.lock().unwrap();
+        let skip = segments.done.len().saturating_sub(self.window);
+        let listed: Vec<_> = segments.done.iter().skip(skip).collect();
+
+        // Fixed for the life of the stream, as RFC 8216 asks; segments are
+        // cut so none is longer
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+            self.target.as_secs(),
+            segments.first + skip as u64
+        );
+        for (i, segment) in listed.iter().enumerate() {
+            let _ = write!(
+                playlist,
+                "#EXTINF:{:.3},\n{}.{}\n",
+                segment.duration.as_secs_f64(),
+                segments.first + (skip + i) as u64,
+                self.extension
+            );
+        }
+        playlist
+    }
+
+    /// The segment a file name in the playlist names, while it is kept
+    pub fn segment(&self, name: &str) -> Option<Arc<Vec<u8>>> {
+        let sequence: u64 = name
+            .strip_suffix(&self.extension)?
+            .strip_suffix('.')?
+            .parse()
+            .ok()?;
+        let segments = self.segments.lock().unwrap();
+        let index = sequence.checked_sub(segments.first)?;
+        segments
+            .done
+            .get(usize::try_from(index).ok()?)
+            .map(|segment| segment.data.clone())
+    }
+
+    /// How long a segment stays available once it is cut
+    pub fn retention(&self) -> Duration {
+        self.target * (self.window + SPARE_SEGMENTS) as u32
+    }
+
+    /// How often a new segment is cut
+    pub fn target(&self) -> Duration {
+        self.target
+    }
+}
+
+fn frames_duration(frames: u64) -> Duration {
+    Duration::from_secs_f64(frames as f64 / 44_100.0)
+}
+
+// An ID3v2.4 tag with the PRIV frame HLS reads a packed audio segment's
+// timestamp from
+fn timestamp_tag(timestamp: u64) -> Vec<u8> {
+    let mut frame = b"com.apple.streaming.transportStreamTimestamp\0".to_vec();
+    // 33 bits, in the low bits of 8 bytes
+    frame.extend_from_slice(&(timestamp & 0x1_FFFF_FFFF).to_be_bytes());
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend_from_slice(&syncsafe(10 + frame.len() as u32));
+    tag.extend_from_slice(b"PRIV");
+    tag.extend_from_slice(&syncsafe(frame.len() as u32));
+    tag.extend_from_slice(&[0, 0]);
+    tag.extend(frame);
+    tag
+}
+
+// ID3 sizes leave the top bit of every byte clear
+fn syncsafe(n: u32) -> [u8; 4] {
+    [
+        (n >> 21) as u8 & 0x7F,
+        (n >> 14) as u8 & 0x7F,
+        (n >> 7) as u8 & 0x7F,
+        n as u8 & 0x7F,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling_playlist() {
+        let playlist = Playlist::new("audio/aac", "aac", Duration::from_secs(2), 3);
+        assert_eq!(
+            playlist.playlist(),
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:2\n#EXT-X-MEDIA-SEQUENCE:0\n"
+        );
+
+        // A second at a time, so a segment is cut every other send
+        for i in 0..14u8 {
+            playlist.send(&[i], 44_100);
+        }
+        assert_eq!(
+            playlist.playlist(),
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:2\n#EXT-X-MEDIA-SEQUENCE:4\n\
+             #EXTINF:2.000,\n4.aac\n#EXTINF:2.000,\n5.aac\n#EXTINF:2.000,\n6.aac\n"
+        );
+
+        // Segments just out of the playlist are kept a while
+        assert!(playlist.segment("1.aac").is_none());
+        assert!(playlist.segment("2.aac").is_some());
+        assert!(playlist.segment("7.aac").is_none());
+        assert!(playlist.segment("6.mp3").is_none());
+
+        // Each segment is its audio after a timestamp tag, 12 s in for the
+        // seventh
+        let segment = playlist.segment("6.aac").unwrap();
+        assert_eq!(segment.len(), 73 + 2);
+        assert_eq!(&segment[..10], b"ID3\x04\x00\x00\x00\x00\x00\x3F");
+        assert_eq!(&segment[10..14], b"PRIV");
+        assert_eq!(&segment[65..73], &(12u64 * 90_000).to_be_bytes());
+        assert_eq!(&segment[73..], &[12, 13]);
+
+        // Audio that would run a segment past the target starts the next
+        // one, so the target duration never changes
+        assert_eq!(playlist.frames_left(), 44_100 * 2);
+        for i in 14..17u8 {
+            playlist.send(&[i], 44_100 * 3 / 2);
+        }
+        assert_eq!(playlist.frames_left(), 44_100 / 2);
+        let listed = playlist.playlist();
+        assert!(listed.contains("#EXT-X-TARGETDURATION:2\n"), "{}", listed);
+        assert!(
+            listed.ends_with("#EXTINF:1.500,\n7.aac\n#EXTINF:1.500,\n8.aac\n"),
+            "{}",
+            listed
+        );
+        assert_eq!(playlist.retention(), Duration::from_secs(10));
+    }
+}
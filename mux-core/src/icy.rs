@@ -0,0 +1,169 @@
+/// Audio bytes between metadata blocks in the streams we serve
+pub const METAINT: usize = 16_000;
+
+// A block's length is one byte counting 16-byte units
+const MAX_BLOCK: usize = 255 * 16;
+
+/// A metadata block announcing `title`, with its length byte
+pub fn metadata(title: &str) -> Vec<u8> {
+    // Titles too long for a block are cut short
+    let mut end = title.len().min(MAX_BLOCK - "StreamTitle='';".len());
+    while !title.is_char_boundary(end) {
+        end -= 1;
+    }
+    let text = format!("StreamTitle='{}';", &title[..end]);
+    let units = text.len().div_ceil(16);
+    let mut block = vec![units as u8];
+    block.extend_from_slice(text.as_bytes());
+    block.resize(1 + units * 16, 0);
+    block
+}
+
+/// The `StreamTitle` in a metadata block, without its length byte
+pub fn stream_title(block: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(block);
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    let end = text[start..]
+        .find("';")
+        .map_or(text.len(), |end| start + end);
+    let title = text[start..end].trim_end_matches('\0').trim();
+    Some(title.to_string())
+}
+
+/// Puts a metadata block after every `METAINT` bytes of audio: the title
+/// when it changed, an empty block otherwise
+#[derive(Default)]
+pub struct Writer {
+    // Audio bytes since the last block
+    written: usize,
+    sent: Option<String>,
+}
+
+impl Writer {
+    /// `data` with the blocks it crosses, announcing `title`
+    pub fn interleave(&mut self, data: &[u8], title: Option<&str>) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + 1);
+        let mut data = data;
+        while !data.is_empty() {
+            let n = data.len().min(METAINT - self.written);
+            out.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            self.written += n;
+            if self.written < METAINT {
+                break;
+            }
+            self.written = 0;
+            match title {
+                Some(title) if self.sent.as_deref() != Some(title) => {
+                    out.extend(metadata(title));
+                    self.sent = Some(title.to_string());
+                }
+                _ => out.push(0),
+            }
+        }
+        out
+    }
+}
+
+/// Takes the metadata blocks out of a stream with `metaint` bytes of audio
+/// between them, keeping the titles
+pub struct Reader {
+    metaint: usize,
+    // Audio bytes left before the next block
+    audio: usize,
+    // The block being read, once its length is known
+    block: Option<(usize, Vec<u8>)>,
+}
+
+impl Reader {
+    pub fn new(metaint: usize) -> Self {
+        Self {
+            metaint,
+            audio: metaint,
+            block: None,
+        }
+    }
+
+    /// Read more of the stream, returning its audio and the latest title
+    /// it announced, if any
+    pub fn push(&mut self, mut data: &[u8]) -> (Vec<u8>, Option<String>) {
+        let mut audio = Vec::with_capacity(data.len());
+        let mut title = None;
+        while !data.is_empty() {
+            if self.audio > 0 {
+                let n = data.len().min(self.audio);
+                audio.extend_from_slice(&data[..n]);
+                data = &data[n..];
+                self.audio -= n;
+                continue;
+            }
+            let (len, block) = self.block.get_or_insert_with(|| {
+                let len = usize::from(data[0]) * 16;
+                data = &data[1..];
+                (len, Vec::new())
+            });
+            let n = data.len().min(*len - block.len());
+            block.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if block.len() < *len {
+                continue;
+            }
+            if *len > 0 {
+                title = stream_title(block).or(title);
+            }
+            self.block = None;
+            self.audio = self.metaint;
+        }
+        (audio, title)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let block = metadata("Miles Davis - So What");
+        assert_eq!(block[0], 3);
+        assert_eq!(block.len(), 49);
+        assert_eq!(
+            stream_title(&block[1..]).as_deref(),
+            Some("Miles Davis - So What")
+        );
+        assert_eq!(
+            stream_title(b"StreamTitle='';StreamUrl='';").as_deref(),
+            Some("")
+        );
+        assert_eq!(stream_title(b"StreamUrl='';"), None);
+
+        // Long titles are cut to fit a block
+        let block = metadata(&"é".repeat(4000));
+        assert_eq!(block.len(), 1 + MAX_BLOCK);
+        assert!(stream_title(&block[1..]).unwrap().starts_with("éé"));
+
+        // Blocks come every METAINT bytes, and only carry a title when it
+        // changes
+        let mut writer = Writer::default();
+        let mut stream = Vec::new();
+        for title in ["One", "One", "Two"] {
+            stream.extend(writer.interleave(&[7; METAINT / 2], Some(title)));
+            stream.extend(writer.interleave(&[7; METAINT / 2], Some(title)));
+        }
+        assert_eq!(stream.len(), 3 * METAINT + 2 * 33 + 1);
+        assert_eq!(stream[METAINT], 2);
+        assert_eq!(stream[2 * METAINT + 33], 0);
+
+        // Read back in awkward pieces
+        let mut reader = Reader::new(METAINT);
+        let mut audio = Vec::new();
+        let mut titles = Vec::new();
+        for chunk in stream.chunks(1000) {
+            let (data, title) = reader.push(chunk);
+            audio.extend(data);
+            titles.extend(title);
+        }
+        assert_eq!(audio, vec![7; 3 * METAINT]);
+        assert_eq!(titles, ["One", "Two"]);
+    }
+}
@@ -4,6 +4,7 @@ use std::thread;
 use std::time::Duration;
 
 use super::{AudioBuffer, AudioInput, InputError};
+use crate::config::SampleFormat;
 
 // Constants for our audio format
 const SAMPLE_RATE: u32 = 44100;
@@ -13,6 +14,7 @@ const BUFFER_SIZE: usize = 1024; // Number of frames per buffer
 #[derive(Debug)]
 pub struct AlsaInput {
     device_name: String,
+    format: SampleFormat,
     running: Arc<Mutex<bool>>,
     thread_handle: Option<thread::JoinHandle<()>>,
 }
@@ -22,6 +24,7 @@ impl Clone for AlsaInput {
         // We don't clone the thread handle, just create a new instance
         Self {
             device_name: self.device_name.clone(),
+            format: self.format,
             running: Arc::new(Mutex::new(false)),
             thread_handle: None,
         }
@@ -29,9 +32,10 @@ impl Clone for AlsaInput {
 }
 
 impl AlsaInput {
-    pub fn new(device_name: &str) -> Result<Self, InputError> {
+    pub fn new(device_name: &str, format: SampleFormat) -> Result<Self, InputError> {
         Ok(AlsaInput {
             device_name: device_name.to_string(),
+            format,
             running: Arc::new(Mutex::new(false)),
             thread_handle: None,
         })
@@ -52,6 +56,7 @@ impl AudioInput for AlsaInput {
 
         let running_clone = Arc::clone(&self.running);
         let device_name = self.device_name.clone();
+        let format = self.format;
 
         // Create a thread that generates a sine wave
         let thread_handle = thread::spawn(move || {
@@ -63,17 +68,18 @@ impl AudioInput for AlsaInput {
             let mut phase = 0.0;
 
             while *running_clone.lock().unwrap() {
-                let mut buffer = Vec::with_capacity(BUFFER_SIZE * CHANNELS as usize);
+                // Samples as the device would deliver them
+                let mut captured = Vec::with_capacity(BUFFER_SIZE * CHANNELS as usize * 4);
 
                 // Generate one buffer of sine wave samples
                 for _ in 0..BUFFER_SIZE {
                     // Calculate the sample value
                     let sample = (phase * 2.0 * std::f32::consts::PI).sin();
 
-                    // Convert to i16 and add to buffer (both channels)
-                    let sample_i16 = (sample * 8192.0) as i16; // Not too loud
-                    buffer.push(sample_i16); // Left channel
-                    buffer.push(sample_i16); // Right channel
+                    // Add to the buffer (both channels), not too loud
+                    for _ in 0..CHANNELS {
+                        push_sample(&mut captured, format, sample * 0.25);
+                    }
 
                     // Increment phase
                     phase += frequency / sample_rate;
@@ -83,7 +89,7 @@ impl AudioInput for AlsaInput {
                 }
 
                 // Send the buffer
-                match sender.send(buffer) {
+                match sender.send(to_s16(format, &captured)) {
                     Ok(_) => {}
                     Err(_) => {
                         // Receiver dropped, exit the loop
@@ -125,6 +131,33 @@ impl AudioInput for AlsaInput {
     }
 }
 
+// Convert samples captured in `format` to S16LE, dropping any partial sample
+// at the end
+fn to_s16(format: SampleFormat, data: &[u8]) -> AudioBuffer {
+    data.chunks_exact(format.width())
+        .map(|b| match format {
+            SampleFormat::S16le => i16::from_le_bytes([b[0], b[1]]),
+            SampleFormat::S24le => i16::from_le_bytes([b[1], b[2]]),
+            SampleFormat::S32le => i16::from_le_bytes([b[2], b[3]]),
+            SampleFormat::F32le => {
+                let sample = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                (sample * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            }
+        })
+        .collect()
+}
+
+// Append a sample in -1.0..1.0 as the mock device would capture it
+fn push_sample(data: &mut Vec<u8>, format: SampleFormat, sample: f32) {
+    let full = (f64::from(sample) * 2_147_483_648.0).clamp(i32::MIN as f64, i32::MAX as f64) as i32;
+    match format {
+        SampleFormat::S16le => data.extend_from_slice(&full.to_le_bytes()[2..]),
+        SampleFormat::S24le => data.extend_from_slice(&full.to_le_bytes()[1..]),
+        SampleFormat::S32le => data.extend_from_slice(&full.to_le_bytes()),
+        SampleFormat::F32le => data.extend_from_slice(&sample.to_le_bytes()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,7 +166,7 @@ mod tests {
 
     #[test]
     fn test_alsa_input() {
-        let mut input = AlsaInput::new("test_device").unwrap();
+        let mut input = AlsaInput::new("test_device", SampleFormat::S16le).unwrap();
         let (sender, receiver) = unbounded();
 
         input.start(sender).unwrap();
@@ -163,4 +196,27 @@ mod tests {
 
         assert!(frame_count >= 1000, "Failed to receive 1000 frames");
     }
+
+    #[test]
+    fn test_sample_formats() {
+        // Half scale, then a negative quarter, in every format
+        for format in [
+            SampleFormat::S16le,
+            SampleFormat::S24le,
+            SampleFormat::S32le,
+            SampleFormat::F32le,
+        ] {
+            let mut data = Vec::new();
+            push_sample(&mut data, format, 0.5);
+            push_sample(&mut data, format, -0.25);
+            assert_eq!(data.len(), 2 * format.width());
+            assert_eq!(to_s16(format, &data), vec![16384, -8192], "{:?}", format);
+        }
+
+        // Little-endian 24-bit keeps its top two bytes
+        assert_eq!(
+            to_s16(SampleFormat::S24le, &[0xFF, 0x34, 0x12, 0x00]),
+            vec![0x1234]
+        );
+    }
 }
@@ -1,11 +1,15 @@
 use super::{AudioBuffer, AudioInput, InputError};
 use crossbeam_channel::Sender;
-use std::{fs, path::PathBuf, thread};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::{fs, thread};
 
 #[derive(Debug)]
 pub struct FileInput {
     path: PathBuf,
     loop_playback: bool,
+    // From the file's ID3 tag, if it has one
+    title: Option<String>,
     thread_handle: Option<thread::JoinHandle<()>>,
     running: bool,
 }
@@ -16,6 +20,7 @@ impl Clone for FileInput {
         Self {
             path: self.path.clone(),
             loop_playback: self.loop_playback,
+            title: self.title.clone(),
             thread_handle: None,
             running: false,
         }
@@ -32,9 +37,20 @@ impl FileInput {
             )));
         }
 
+        let title = match read_tag(&path) {
+            Ok(tag) => tag.as_deref().and_then(tag_title),
+            Err(e) => {
+                return Err(InputError::Initialization(format!(
+                    "Failed to read {:?}: {}",
+                    path, e
+                )))
+            }
+        };
+
         Ok(Self {
             path,
             loop_playback,
+            title,
             thread_handle: None,
             running: false,
         })
@@ -60,8 +76,10 @@ impl AudioInput for FileInput {
                 }
             };
 
-            let mut frames = Vec::with_capacity(data.len() / 2);
-            for c in data.chunks_exact(2) {
+            // A tag in front of the audio isn't played
+            let audio = &data[tag_len(&data).unwrap_or(0).min(data.len())..];
+            let mut frames = Vec::with_capacity(audio.len() / 2);
+            for c in audio.chunks_exact(2) {
                 frames.push(i16::from_le_bytes([c[0], c[1]]));
             }
 
@@ -88,4 +106,142 @@ impl AudioInput for FileInput {
         }
         Ok(())
     }
+
+    fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+}
+
+// The ID3v2 tag at the start of a file, if there is one
+fn read_tag(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let mut file = fs::File::open(path)?;
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    let Some(len) = tag_len(&header) else {
+        return Ok(None);
+    };
+    let mut tag = header.to_vec();
+    file.take(len as u64 - 10).read_to_end(&mut tag)?;
+    Ok(Some(tag))
+}
+
+// Length of the ID3v2 tag, header included, that `data` starts with
+fn tag_len(data: &[u8]) -> Option<usize> {
+    if data.len() < 10 || &data[..3] != b"ID3" {
+        return None;
+    }
+    Some(10 + syncsafe(&data[6..10]))
+}
+
+// "Artist - Title" from the TPE1 and TIT2 frames of an ID3v2.3 or v2.4 tag,
+// or whichever of them it has
+fn tag_title(tag: &[u8]) -> Option<String> {
+    let version = tag[3];
+    if !matches!(version, 3 | 4) {
+        return None;
+    }
+    let (mut artist, mut title) = (None, None);
+    let mut frames = &tag[10..];
+    while frames.len() >= 10 && frames[0] != 0 {
+        let size = match version {
+            4 => syncsafe(&frames[4..8]),
+            _ => u32::from_be_bytes([frames[4], frames[5], frames[6], frames[7]]) as usize,
+        };
+        let Some(body) = frames.get(10..10 + size) else {
+            break;
+        };
+        match &frames[..4] {
+            b"TPE1" => artist = text(body),
+            b"TIT2" => title = text(body),
+            _ => {}
+        }
+        frames = &frames[10 + size..];
+    }
+    match (artist, title) {
+        (Some(artist), Some(title)) => Some(format!("{} - {}", artist, title)),
+        (artist, title) => title.or(artist),
+    }
+}
+
+// The first string of a text frame, in whichever encoding it says
+fn text(body: &[u8]) -> Option<String> {
+    let (&encoding, text) = body.split_first()?;
+    let text = match encoding {
+        0 => text.iter().map(|&b| char::from(b)).collect(),
+        1 | 2 => {
+            let mut units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            // Without a byte order mark UTF-16 is big endian
+            match units.first() {
+                Some(0xFFFE) => {
+                    units.remove(0);
+                    units.iter_mut().for_each(|u| *u = u.swap_bytes());
+                }
+                Some(0xFEFF) => {
+                    units.remove(0);
+                }
+                _ => {}
+            }
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+    let text = text.split('\0').next().unwrap_or_default().trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, &b| (size << 7) | usize::from(b & 0x7F))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use std::io::Write;
+
+    fn frame(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&[0, 0, 0, body.len() as u8, 0, 0]);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    #[test]
+    fn test_tagged_file() {
+        let mut frames = frame(b"TIT2", b"\x03So What");
+        // UTF-16 with a little-endian byte order mark
+        let mut artist = vec![1, 0xFF, 0xFE];
+        artist.extend("Miles Davis".encode_utf16().flat_map(u16::to_le_bytes));
+        frames.extend(frame(b"TPE1", &artist));
+        // Padding
+        frames.extend([0; 10]);
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"ID3\x04\x00\x00\x00\x00\x00").unwrap();
+        file.write_all(&[frames.len() as u8]).unwrap();
+        file.write_all(&frames).unwrap();
+        file.write_all(&[1, 0, 2, 0]).unwrap();
+
+        let mut input = FileInput::new(file.path().to_str().unwrap(), false).unwrap();
+        assert_eq!(input.title().as_deref(), Some("Miles Davis - So What"));
+
+        // Only the audio after the tag is played
+        let (sender, receiver) = unbounded();
+        input.start(sender).unwrap();
+        assert_eq!(receiver.recv().unwrap(), vec![1, 2]);
+        input.stop().unwrap();
+
+        // Untagged files have no title
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[1, 0, 2, 0]).unwrap();
+        let input = FileInput::new(file.path().to_str().unwrap(), false).unwrap();
+        assert_eq!(input.title(), None);
+    }
 }
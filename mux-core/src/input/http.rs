@@ -1,19 +1,45 @@
 use super::{AudioBuffer, AudioInput, InputError};
+use crate::icy;
 use crossbeam_channel::Sender;
-use std::{thread, time::Duration};
+use log::debug;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// How long the input waits between buffers, and the title reader between
+// checks that it is still wanted
+const POLL: Duration = Duration::from_millis(100);
+
+// How long to wait for the server, and before connecting again once it
+// drops the stream
+const TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY: Duration = Duration::from_secs(10);
+
+// Longest response head we read before giving up on the server
+const MAX_HEAD: usize = 8192;
 
 #[derive(Debug)]
 pub struct HttpInput {
+    id: String,
     url: String,
+    titles: Arc<Titles>,
     thread_handle: Option<thread::JoinHandle<()>>,
     running: bool,
 }
 
 impl Clone for HttpInput {
     fn clone(&self) -> Self {
-        // We don't clone the thread handle, just create a new instance
+        // We don't clone the thread handle, just create a new instance;
+        // copies share the one connection reading titles
         Self {
+            id: self.id.clone(),
             url: self.url.clone(),
+            titles: self.titles.clone(),
             thread_handle: None,
             running: false,
         }
@@ -21,9 +47,11 @@ impl Clone for HttpInput {
 }
 
 impl HttpInput {
-    pub fn new(url: &str) -> Result<Self, InputError> {
+    pub fn new(id: &str, url: &str) -> Result<Self, InputError> {
         Ok(Self {
+            id: id.to_string(),
             url: url.to_string(),
+            titles: Arc::new(Titles::default()),
             thread_handle: None,
             running: false,
         })
@@ -36,19 +64,18 @@ impl AudioInput for HttpInput {
             return Ok(());
         }
 
-        let _url = self.url.clone();
+        self.titles.start(&self.id, &self.url);
         self.running = true;
 
         self.thread_handle = Some(thread::spawn(move || {
             // This is a simplified implementation - in a real implementation we would:
-            // 1. Connect to the HTTP stream using reqwest or similar
-            // 2. Parse ICY headers if present
-            // 3. Decode the audio stream (MP3, etc.) to PCM
-            // 4. Send PCM frames to the sender
-
-            // For now, just send silence as a placeholder
+            // 1. Decode the audio stream (MP3, etc.) to PCM
+            // 2. Send PCM frames to the sender
+            //
+            // For now, send silence as a placeholder; the stream is only
+            // read for the titles in its ICY metadata
             while sender.send(vec![0; 1024]).is_ok() {
-                thread::sleep(Duration::from_millis(100));
+                thread::sleep(POLL);
             }
         }));
 
@@ -56,10 +83,245 @@ impl AudioInput for HttpInput {
     }
 
     fn stop(&mut self) -> Result<(), InputError> {
+        if self.running {
+            self.titles.stop();
+        }
         self.running = false;
         if let Some(handle) = self.thread_handle.take() {
             let _ = handle.join();
         }
         Ok(())
     }
+
+    fn title(&self) -> Option<String> {
+        self.titles.title.lock().unwrap().clone()
+    }
+}
+
+// The stream's ICY title, read on a thread of its own so looking up,
+// connecting to and waiting on the server never hold up the audio. Every
+// copy of the input shares it, and the thread runs while any of them plays
+#[derive(Debug, Default)]
+struct Titles {
+    // As the stream last announced it
+    title: Mutex<Option<String>>,
+    reader: Mutex<Reader>,
+}
+
+#[derive(Debug, Default)]
+struct Reader {
+    // Copies of the input playing
+    playing: usize,
+    // Set to end the running thread
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl Titles {
+    fn start(self: &Arc<Self>, id: &str, url: &str) {
+        let mut reader = self.reader.lock().unwrap();
+        reader.playing += 1;
+        if reader.stop.is_some() {
+            return;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        reader.stop = Some(stop.clone());
+
+        let (titles, id, url) = (self.clone(), id.to_string(), url.to_string());
+        thread::spawn(move || titles.read(&id, &url, &stop));
+    }
+
+    // The thread isn't waited for, as it may be busy connecting; it ends
+    // as soon as it next checks the flag
+    fn stop(&self) {
+        let mut reader = self.reader.lock().unwrap();
+        reader.playing = reader.playing.saturating_sub(1);
+        if reader.playing == 0 {
+            if let Some(stop) = reader.stop.take() {
+                stop.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn read(&self, id: &str, url: &str, stop: &AtomicBool) {
+        while !stop.load(Ordering::Relaxed) {
+            match IcyStream::open(url) {
+                Ok(mut icy) => {
+                    while !stop.load(Ordering::Relaxed) {
+                        match icy.read_title() {
+                            Ok(Some(latest)) => *self.title.lock().unwrap() = Some(latest),
+                            Ok(None) => {}
+                            Err(e) => {
+                                debug!("Lost titles for input '{}': {}", id, e);
+                                break;
+                            }
+                        }
+                    }
+                }
+                // Streams without titles don't grow them
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    debug!("No titles for input '{}': {}", id, e);
+                    return;
+                }
+                Err(e) => debug!("No titles for input '{}': {}", id, e),
+            }
+
+            let retry = Instant::now() + RETRY;
+            while !stop.load(Ordering::Relaxed) && Instant::now() < retry {
+                thread::sleep(POLL);
+            }
+        }
+    }
+}
+
+// A remote stream, asked for ICY metadata
+struct IcyStream {
+    socket: TcpStream,
+    reader: icy::Reader,
+}
+
+impl IcyStream {
+    // Fails as unsupported for streams that can't carry titles
+    fn open(url: &str) -> io::Result<Self> {
+        let unsupported = |why: &str| io::Error::new(io::ErrorKind::Unsupported, why);
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| unsupported("Only http:// streams are read for titles"))?;
+        let (host, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let addr = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No address"))?;
+
+        let mut socket = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        socket.set_read_timeout(Some(TIMEOUT))?;
+        write!(
+            socket,
+            "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: sonos-mux/{}\r\nIcy-MetaData: 1\r\n\r\n",
+            path,
+            host,
+            crate::version()
+        )?;
+
+        // ICY servers answer `ICY 200 OK` rather than HTTP
+        let head = read_head(&mut socket)?;
+        let status = head.lines().next().unwrap_or_default();
+        if status.split_whitespace().nth(1) != Some("200") {
+            return Err(io::Error::other(format!("Server answered '{}'", status)));
+        }
+        let metaint = head
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("icy-metaint"))
+            .and_then(|(_, value)| value.trim().parse().ok())
+            .filter(|metaint| *metaint > 0)
+            .ok_or_else(|| unsupported("The stream has no ICY metadata"))?;
+
+        // Reads only wait a moment, so the reader notices the input stop
+        socket.set_read_timeout(Some(POLL / 2))?;
+        Ok(Self {
+            socket,
+            reader: icy::Reader::new(metaint),
+        })
+    }
+
+    // The latest title in whatever the server has sent since
+    fn read_title(&mut self) -> io::Result<Option<String>> {
+        let mut buffer = [0u8; 16384];
+        match self.socket.read(&mut buffer) {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => Ok(self.reader.push(&buffer[..n]).1),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Read up to the blank line ending a response's headers, a byte at a time
+// so none of the stream is taken with it
+fn read_head(socket: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::new();
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > MAX_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Response head too long",
+            ));
+        }
+        socket.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_icy_title() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/radio", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let request = read_head(&mut socket).unwrap();
+            assert!(request.starts_with("GET /radio HTTP/1.0\r\n"));
+            assert!(request.contains("Icy-MetaData: 1\r\n"));
+            socket
+                .write_all(b"ICY 200 OK\r\nicy-metaint: 8\r\n\r\n")
+                .unwrap();
+            socket.write_all(&[0; 8]).unwrap();
+            socket
+                .write_all(&icy::metadata("Miles Davis - So What"))
+                .unwrap();
+            socket.write_all(&[0; 8]).unwrap();
+            // Hold the stream open until the input goes
+            let _ = socket.read(&mut [0]);
+        });
+
+        let mut input = HttpInput::new("radio", &url).unwrap();
+        let (sender, receiver) = unbounded();
+        input.start(sender).unwrap();
+        let mut title = None;
+        for _ in 0..50 {
+            title = input.title();
+            if title.is_some() {
+                break;
+            }
+            thread::sleep(POLL);
+        }
+        assert_eq!(title.as_deref(), Some("Miles Davis - So What"));
+
+        // Copies of the input share the one connection, which stays up
+        // while any of them plays
+        let mut copy = input.clone();
+        assert_eq!(copy.title(), title);
+        let (copy_sender, copy_receiver) = unbounded();
+        copy.start(copy_sender).unwrap();
+        drop(copy_receiver);
+        copy.stop().unwrap();
+        assert_eq!(input.titles.reader.lock().unwrap().playing, 1);
+        assert!(input.titles.reader.lock().unwrap().stop.is_some());
+
+        drop(receiver);
+        input.stop().unwrap();
+        server.join().unwrap();
+    }
 }
@@ -3,6 +3,7 @@ pub mod file;
 pub mod http;
 pub mod silence;
 
+use crate::config::InputKind;
 use crossbeam_channel::Sender;
 use std::fmt;
 use thiserror::Error;
@@ -24,6 +25,12 @@ pub enum InputError {
 pub trait AudioInput: Send + fmt::Debug + AudioInputClone {
     fn start(&mut self, sender: Sender<AudioBuffer>) -> Result<(), InputError>;
     fn stop(&mut self) -> Result<(), InputError>;
+
+    /// What the input is playing, where it knows, e.g. from a stream's ICY
+    /// metadata or a file's tags
+    fn title(&self) -> Option<String> {
+        None
+    }
 }
 
 // Trait to enable cloning Box<dyn AudioInput>
@@ -49,33 +56,18 @@ impl Clone for Box<dyn AudioInput> {
 
 // Factory function to create an audio input from config
 pub fn create_input(config: &crate::config::Input) -> Result<Box<dyn AudioInput>, InputError> {
-    match config.kind.as_str() {
-        "alsa" => {
-            let device = config
-                .device
-                .clone()
-                .unwrap_or_else(|| "default".to_string());
-            Ok(Box::new(alsa::AlsaInput::new(&device)?))
-        }
-        "file" => {
-            let path = config
-                .path
-                .clone()
-                .ok_or_else(|| InputError::Initialization("File path not specified".to_string()))?;
-            let loop_playback = config.loop_playback.unwrap_or(false);
-            Ok(Box::new(file::FileInput::new(&path, loop_playback)?))
-        }
-        "http" => {
-            let url = config
-                .url
-                .clone()
-                .ok_or_else(|| InputError::Initialization("HTTP URL not specified".to_string()))?;
-            Ok(Box::new(http::HttpInput::new(&url)?))
-        }
-        "silence" => Ok(Box::new(silence::SilenceInput::new())),
-        _ => Err(InputError::Initialization(format!(
-            "Unsupported input kind: {}",
-            config.kind
-        ))),
+    match &config.kind {
+        InputKind::Alsa { device, format } => Ok(Box::new(alsa::AlsaInput::new(
+            device,
+            format.unwrap_or_default(),
+        )?)),
+        // The router holds on-demand inputs back until they are played
+        InputKind::File {
+            path,
+            loop_playback,
+            on_demand: _,
+        } => Ok(Box::new(file::FileInput::new(path, *loop_playback)?)),
+        InputKind::Http { url } => Ok(Box::new(http::HttpInput::new(&config.id, url)?)),
+        InputKind::Silence {} => Ok(Box::new(silence::SilenceInput::new())),
     }
 }
@@ -1,6 +1,9 @@
 // sonos-mux core library
+pub mod admin;
 pub mod config;
 pub mod encoder;
+pub mod hls;
+pub mod icy;
 pub mod input;
 pub mod mixer;
 pub mod output;
@@ -11,13 +14,17 @@ pub mod stream;
 mod tests;
 
 // Re-export main types for convenience
-pub use config::{Config, ConfigError, Input, Logging, Output, Route};
-pub use encoder::{EncoderError, Lame};
+pub use config::{
+    Config, ConfigError, Diagnostic, Input, InputKind, Logging, Output, OutputKind, Report, Route,
+    Severity,
+};
+pub use encoder::{create_encoder, Encoder, EncoderError, Flac, Lame, Opus};
 pub use input::{AudioBuffer, AudioInput, InputError};
-pub use mixer::{db_to_lin, lin_to_db, Mixer, Source};
+pub use mixer::{db_to_lin, lin_to_db, Level, Mixer, Source, SourceStatus};
 pub use output::sonos::{SonosManager, SonosOutput};
-pub use output::{AudioOutput, OutputError};
-pub use routing::Router;
+pub use output::upnp::{UpnpManager, UpnpOutput};
+pub use output::{AudioOutput, OutputError, PcmSink, SinkStatus, TransportState};
+pub use routing::{InputStatus, OutputLevels, OutputStatus, RouteStatus, Router};
 pub use stream::{HttpStreamer, StreamError};
 
 #[derive(Debug, thiserror::Error)]
@@ -1,35 +1,100 @@
 use crate::input::{AudioBuffer, AudioInput};
 use crossbeam_channel::{unbounded, Receiver};
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::time::sleep;
 
+// Floor for measured levels, in dBFS
+const SILENCE_DB: f32 = -60.0;
+
+/// Loudness of a block of samples, in dBFS
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    pub rms_db: f32,
+    pub peak_db: f32,
+}
+
+impl Level {
+    pub const SILENT: Level = Level {
+        rms_db: SILENCE_DB,
+        peak_db: SILENCE_DB,
+    };
+
+    pub fn measure(samples: &[i16]) -> Self {
+        if samples.is_empty() {
+            return Self::SILENT;
+        }
+
+        let mut sum_squares = 0.0f64;
+        let mut peak = 0.0f64;
+        for &sample in samples {
+            let sample = f64::from(sample) / 32768.0;
+            sum_squares += sample * sample;
+            peak = peak.max(sample.abs());
+        }
+        let rms = (sum_squares / samples.len() as f64).sqrt();
+
+        Self {
+            rms_db: to_dbfs(rms),
+            peak_db: to_dbfs(peak),
+        }
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self::SILENT
+    }
+}
+
+fn to_dbfs(lin: f64) -> f32 {
+    if lin > 0.0 {
+        ((20.0 * lin.log10()) as f32).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
 pub struct Source {
+    pub input_id: String,      // ID of the input feeding this source, for status
+    pub title: Option<String>, // Shown as playing when the input doesn't say
     pub gain_db: f32,
-    pub duck_priority: bool,
-    pub duck_db: f32, // Amount to duck other sources by
+    pub priority: i32, // Sources duck those with a lower priority
+    pub duck_db: f32,  // Amount to duck lower-priority sources by
     pub inner: Box<dyn AudioInput>,
     pub receiver: Option<Receiver<AudioBuffer>>,
     pub buffer: AudioBuffer,
     pub pos: usize,
     pub is_active: bool, // Tracks whether this source is outputting audio
+    pub level: Level,    // Level of the frames last pulled, before gain
+    pub muted: bool,     // Muted sources are pulled but not mixed
+    pub on_demand: bool, // Only started when played, not with the mixer
+    pub underruns: u64,  // Rounds the source ran dry while others played
+    delivered: bool,     // Whether the source had frames in the last round
+    received: bool,      // Whether the input sent anything since it started
+    ended: bool,         // Whether the input closed its channel by itself
 }
 
 impl Source {
-    pub fn new(
-        gain_db: f32,
-        duck_priority: bool,
-        duck_db: f32,
-        input: Box<dyn AudioInput>,
-    ) -> Self {
+    pub fn new(gain_db: f32, priority: i32, duck_db: f32, input: Box<dyn AudioInput>) -> Self {
         Self {
+            input_id: String::new(),
+            title: None,
             gain_db,
-            duck_priority,
-            duck_db,
+            priority,
+            duck_db: duck_db.abs(),
             inner: input,
             receiver: None,
             buffer: Vec::new(),
             pos: 0,
             is_active: false,
+            level: Level::SILENT,
+            muted: false,
+            on_demand: false,
+            underruns: 0,
+            delivered: false,
+            received: false,
+            ended: false,
         }
     }
 
@@ -37,13 +102,33 @@ impl Source {
         let (sender, receiver) = unbounded();
         self.inner.start(sender)?;
         self.receiver = Some(receiver);
+        self.received = false;
+        self.ended = false;
         Ok(())
     }
 
     fn stop(&mut self) -> Result<(), crate::input::InputError> {
-        self.inner.stop()?;
+        // Closing the channel first lets the input thread exit
         self.receiver = None;
-        Ok(())
+        self.buffer.clear();
+        self.pos = 0;
+        self.inner.stop()
+    }
+
+    /// Whether the input has been started
+    pub fn is_running(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    /// Whether the input stopped before sending any audio, e.g. a file it
+    /// could not read; only seen once the source is mixed
+    pub fn failed(&self) -> bool {
+        self.ended && !self.received
+    }
+
+    /// Buffers received from the input but not mixed yet
+    pub fn buffered(&self) -> usize {
+        self.receiver.as_ref().map_or(0, Receiver::len)
     }
 
     fn next_frames(&mut self) -> Option<&[i16]> {
@@ -54,30 +139,37 @@ impl Source {
                     Ok(new_buffer) => {
                         self.buffer = new_buffer;
                         self.pos = 0;
+                        self.received = true;
                     }
-                    Err(_) => {
+                    Err(e) => {
+                        self.ended |= e.is_disconnected();
                         // No data available right now
                         self.is_active = false;
+                        self.level = Level::SILENT;
                         return None;
                     }
                 }
             } else {
                 self.is_active = false;
+                self.level = Level::SILENT;
                 return None;
             }
         }
 
         if self.pos < self.buffer.len() {
-            let slice = &self.buffer[self.pos..];
+            let start = self.pos;
             self.pos = self.buffer.len();
+            let slice = &self.buffer[start..];
 
             // Check if the audio is non-silent
             let is_silent = slice.iter().all(|&s| s.abs() < 10); // Threshold for silence
             self.is_active = !is_silent;
+            self.level = Level::measure(slice);
 
             Some(slice)
         } else {
             self.is_active = false;
+            self.level = Level::SILENT;
             None
         }
     }
@@ -91,22 +183,64 @@ impl Source {
     }
 }
 
+/// What one source of a mixer is doing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceStatus {
+    pub input: String,
+    pub gain_db: f32,
+    pub priority: i32,
+    pub duck_db: f32,
+    /// How far the source is ducked right now, in dB
+    pub ducked_by: f32,
+    /// Whether the source played anything but silence in the last mix
+    pub active: bool,
+    pub level: Level,
+    #[serde(default)]
+    pub muted: bool,
+    /// Times the source ran dry while the others kept playing
+    #[serde(default)]
+    pub underruns: u64,
+}
+
 pub struct Mixer {
     pub sources: Vec<Source>,
+    pub level: Level,          // Level of the last mix, after gain and ducking
+    pub title: Option<String>, // What the dominant source last said it played
 }
 
 impl Mixer {
     pub fn new(sources: Vec<Source>) -> Self {
-        Self { sources }
+        Self {
+            sources,
+            level: Level::SILENT,
+            title: None,
+        }
     }
 
+    /// Start the sources that aren't running yet, except those on demand
     pub fn start(&mut self) -> Result<(), crate::input::InputError> {
-        for source in &mut self.sources {
+        for source in self
+            .sources
+            .iter_mut()
+            .filter(|s| !s.on_demand && !s.is_running())
+        {
             source.start()?;
         }
         Ok(())
     }
 
+    /// Start the sources fed by `input` again from the beginning, or for the
+    /// first time if they are on demand; false if none is
+    pub fn play(&mut self, input: &str) -> Result<bool, crate::input::InputError> {
+        let mut found = false;
+        for source in self.sources.iter_mut().filter(|s| s.input_id == input) {
+            source.stop()?;
+            source.start()?;
+            found = true;
+        }
+        Ok(found)
+    }
+
     pub fn stop(&mut self) -> Result<(), crate::input::InputError> {
         for source in &mut self.sources {
             source.stop()?;
@@ -115,51 +249,127 @@ impl Mixer {
     }
 
     pub async fn mix_next(&mut self) -> Option<AudioBuffer> {
+        if let Some(mix) = self.try_mix() {
+            return Some(mix);
+        }
+
         // If no data is ready yet, wait a bit
-        if self.sources.iter_mut().all(|s| s.next_frames().is_none()) {
-            sleep(Duration::from_millis(10)).await;
+        sleep(Duration::from_millis(10)).await;
+        self.try_mix()
+    }
+
+    /// Mix whatever the sources have ready, without waiting; `None` if none
+    /// of them has anything
+    pub fn try_mix(&mut self) -> Option<AudioBuffer> {
+        let frames = self.pull_frames();
+        if frames.iter().all(Option::is_none) {
+            self.level = Level::SILENT;
+            return None;
+        }
+
+        // A source that was playing but has nothing while another is a whole
+        // buffer ahead has fallen behind, rather than just arriving late
+        let backlog = self.sources.iter().any(|s| s.buffered() > 0);
+        for (source, frames) in self.sources.iter_mut().zip(&frames) {
+            if source.delivered && frames.is_none() && backlog {
+                source.underruns += 1;
+            }
+            source.delivered = frames.is_some();
         }
 
         let mut mix: Option<Vec<f32>> = None;
 
-        // Check if any priority sources are active
-        let active_priority = self.sources.iter().any(|s| s.duck_priority && s.is_active);
-
-        for src in &mut self.sources {
-            // Get the values we need before borrowing
-            let gain_db = src.gain_db;
-            let is_priority = src.duck_priority;
-            let duck_db = src.duck_db;
-
-            if let Some(frames) = src.next_frames() {
-                // Calculate gain with soft-knee ducking if needed
-                let applied_gain_db = if active_priority && !is_priority {
-                    // Apply ducking with a soft knee
-                    gain_db - duck_db
-                } else {
-                    gain_db
-                };
-
-                let g = db_to_lin(applied_gain_db);
-
-                if mix.is_none() {
-                    mix = Some(frames.iter().map(|&s| s as f32 * g).collect());
-                } else if let Some(ref mut m) = mix {
-                    for (i, &sample) in frames.iter().enumerate() {
-                        if i < m.len() {
-                            m[i] += sample as f32 * g;
-                        }
+        for (index, frames) in frames.iter().enumerate() {
+            let Some(frames) = frames else {
+                continue;
+            };
+            if self.sources[index].muted {
+                continue;
+            }
+            // Ducking follows whichever sources are playing in this round
+            let g = db_to_lin(self.sources[index].gain_db - self.ducked_by(index));
+
+            if mix.is_none() {
+                mix = Some(frames.iter().map(|&s| s as f32 * g).collect());
+            } else if let Some(ref mut m) = mix {
+                for (i, &sample) in frames.iter().enumerate() {
+                    if i < m.len() {
+                        m[i] += sample as f32 * g;
                     }
                 }
             }
         }
 
-        let data = mix?;
-        Some(
-            data.into_iter()
-                .map(|f| f.clamp(i16::MIN as f32, i16::MAX as f32) as i16)
-                .collect(),
-        )
+        // Everything that played was muted
+        let mix = mix
+            .unwrap_or_else(|| vec![0.0; frames.iter().flatten().map(Vec::len).max().unwrap_or(0)]);
+        let data: AudioBuffer = mix
+            .into_iter()
+            .map(|f| f.clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+            .collect();
+        self.level = Level::measure(&data);
+
+        // Through silence, or a source with no title, the last one stands
+        if let Some(title) = self.dominant().and_then(|index| {
+            let source = &self.sources[index];
+            source.inner.title().or_else(|| source.title.clone())
+        }) {
+            self.title = Some(title);
+        }
+        Some(data)
+    }
+
+    // Take the next frames from every source, updating which are active
+    fn pull_frames(&mut self) -> Vec<Option<AudioBuffer>> {
+        self.sources
+            .iter_mut()
+            .map(|s| s.next_frames().map(<[i16]>::to_vec))
+            .collect()
+    }
+
+    /// How far the source at `index` is currently ducked, in dB: the deepest
+    /// `duck_db` of any active source with a higher priority
+    pub fn ducked_by(&self, index: usize) -> f32 {
+        let priority = self.sources[index].priority;
+        self.sources
+            .iter()
+            .filter(|s| s.is_active && !s.muted && s.priority > priority)
+            .map(|s| s.duck_db)
+            .fold(0.0, f32::max)
+    }
+
+    /// The source listeners hear most right now: of those playing, the one
+    /// with the highest priority, then the loudest
+    pub fn dominant(&self) -> Option<usize> {
+        self.sources
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_active && !s.muted)
+            .max_by(|(_, a), (_, b)| {
+                a.priority
+                    .cmp(&b.priority)
+                    .then((a.level.rms_db + a.gain_db).total_cmp(&(b.level.rms_db + b.gain_db)))
+            })
+            .map(|(index, _)| index)
+    }
+
+    /// Current state of every source, in mixing order
+    pub fn status(&self) -> Vec<SourceStatus> {
+        self.sources
+            .iter()
+            .enumerate()
+            .map(|(index, source)| SourceStatus {
+                input: source.input_id.clone(),
+                gain_db: source.gain_db,
+                priority: source.priority,
+                duck_db: source.duck_db,
+                ducked_by: self.ducked_by(index),
+                active: source.is_active,
+                level: source.level,
+                muted: source.muted,
+                underruns: source.underruns,
+            })
+            .collect()
     }
 
     // For testing - get the number of sources
@@ -208,4 +418,21 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_level() {
+        assert_eq!(Level::measure(&[]), Level::SILENT);
+        assert_eq!(Level::measure(&[0; 64]), Level::SILENT);
+
+        // A full-scale square wave peaks and averages at 0 dBFS
+        let level = Level::measure(&[i16::MIN, i16::MIN, i16::MIN, i16::MIN]);
+        assert!(level.rms_db.abs() < 0.01);
+        assert!(level.peak_db.abs() < 0.01);
+
+        // Half scale peaks at about -6 dBFS, with the RMS of one
+        // sample in four about 6 dB below that
+        let level = Level::measure(&[16384, 0, 0, 0]);
+        assert!((level.peak_db + 6.02).abs() < 0.01);
+        assert!((level.rms_db + 12.04).abs() < 0.01);
+    }
 }
@@ -0,0 +1,558 @@
+use crate::input::{AudioBuffer, Frame};
+use crate::output::{OutputError, PcmSink, SinkStatus};
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use libc::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+use log::{debug, error, info, warn};
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: u32 = 2;
+
+// Buffers of mix waiting for the device; enough for the longest latency
+// offset plus some slack, at the mixer's usual buffer size
+const QUEUE: usize = 512;
+
+// Least time between warnings about a device falling behind
+const WARN_EVERY: Duration = Duration::from_secs(1);
+
+// From alsa/pcm.h
+const SND_PCM_STREAM_PLAYBACK: c_int = 0;
+const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+const SND_PCM_FORMAT_S16_LE: c_int = 2;
+
+type Pcm = *mut c_void;
+type HwParams = *mut c_void;
+
+// The parts of libasound we use. It is loaded when the first device is
+// opened, so hosts without it can still run every other output.
+struct Alsa {
+    open: unsafe extern "C" fn(*mut Pcm, *const c_char, c_int, c_int) -> c_int,
+    close: unsafe extern "C" fn(Pcm) -> c_int,
+    drop: unsafe extern "C" fn(Pcm) -> c_int,
+    writei: unsafe extern "C" fn(Pcm, *const c_void, c_ulong) -> c_long,
+    recover: unsafe extern "C" fn(Pcm, c_int, c_int) -> c_int,
+    strerror: unsafe extern "C" fn(c_int) -> *const c_char,
+    hw_params_malloc: unsafe extern "C" fn(*mut HwParams) -> c_int,
+    hw_params_free: unsafe extern "C" fn(HwParams),
+    hw_params_any: unsafe extern "C" fn(Pcm, HwParams) -> c_int,
+    hw_params_set_access: unsafe extern "C" fn(Pcm, HwParams, c_int) -> c_int,
+    hw_params_set_format: unsafe extern "C" fn(Pcm, HwParams, c_int) -> c_int,
+    hw_params_set_channels: unsafe extern "C" fn(Pcm, HwParams, c_uint) -> c_int,
+    hw_params_set_rate: unsafe extern "C" fn(Pcm, HwParams, c_uint, c_int) -> c_int,
+    hw_params_set_period_size_near:
+        unsafe extern "C" fn(Pcm, HwParams, *mut c_ulong, *mut c_int) -> c_int,
+    hw_params_set_buffer_size_near: unsafe extern "C" fn(Pcm, HwParams, *mut c_ulong) -> c_int,
+    hw_params: unsafe extern "C" fn(Pcm, HwParams) -> c_int,
+}
+
+impl Alsa {
+    fn get() -> Result<&'static Alsa, OutputError> {
+        static ALSA: OnceLock<Result<Alsa, String>> = OnceLock::new();
+        ALSA.get_or_init(|| unsafe { Alsa::load() })
+            .as_ref()
+            .map_err(|e| OutputError::DeviceNotFound(e.clone()))
+    }
+
+    unsafe fn load() -> Result<Alsa, String> {
+        let library = libc::dlopen(c"libasound.so.2".as_ptr(), libc::RTLD_NOW);
+        if library.is_null() {
+            return Err("libasound.so.2 could not be loaded; is alsa-lib installed?".to_string());
+        }
+        // Each symbol is transmuted to its C signature, as in alsa/pcm.h
+        macro_rules! symbol {
+            ($name:literal as $signature:ty) => {{
+                let symbol = libc::dlsym(library, concat!($name, "\0").as_ptr().cast());
+                if symbol.is_null() {
+                    return Err(format!("libasound has no {}", $name));
+                }
+                std::mem::transmute::<*mut c_void, $signature>(symbol)
+            }};
+        }
+        Ok(Alsa {
+            open: symbol!(
+                "snd_pcm_open"
+                    as unsafe extern "C" fn(*mut Pcm, *const c_char, c_int, c_int) -> c_int
+            ),
+            close: symbol!("snd_pcm_close" as unsafe extern "C" fn(Pcm) -> c_int),
+            drop: symbol!("snd_pcm_drop" as unsafe extern "C" fn(Pcm) -> c_int),
+            writei: symbol!(
+                "snd_pcm_writei" as unsafe extern "C" fn(Pcm, *const c_void, c_ulong) -> c_long
+            ),
+            recover: symbol!("snd_pcm_recover" as unsafe extern "C" fn(Pcm, c_int, c_int) -> c_int),
+            strerror: symbol!("snd_strerror" as unsafe extern "C" fn(c_int) -> *const c_char),
+            hw_params_malloc: symbol!(
+                "snd_pcm_hw_params_malloc" as unsafe extern "C" fn(*mut HwParams) -> c_int
+            ),
+            hw_params_free: symbol!("snd_pcm_hw_params_free" as unsafe extern "C" fn(HwParams)),
+            hw_params_any: symbol!(
+                "snd_pcm_hw_params_any" as unsafe extern "C" fn(Pcm, HwParams) -> c_int
+            ),
+            hw_params_set_access: symbol!(
+                "snd_pcm_hw_params_set_access"
+                    as unsafe extern "C" fn(Pcm, HwParams, c_int) -> c_int
+            ),
+            hw_params_set_format: symbol!(
+                "snd_pcm_hw_params_set_format"
+                    as unsafe extern "C" fn(Pcm, HwParams, c_int) -> c_int
+            ),
+            hw_params_set_channels: symbol!(
+                "snd_pcm_hw_params_set_channels"
+                    as unsafe extern "C" fn(Pcm, HwParams, c_uint) -> c_int
+            ),
+            hw_params_set_rate: symbol!(
+                "snd_pcm_hw_params_set_rate"
+                    as unsafe extern "C" fn(Pcm, HwParams, c_uint, c_int) -> c_int
+            ),
+            hw_params_set_period_size_near: symbol!(
+                "snd_pcm_hw_params_set_period_size_near"
+                    as unsafe extern "C" fn(Pcm, HwParams, *mut c_ulong, *mut c_int) -> c_int
+            ),
+            hw_params_set_buffer_size_near: symbol!(
+                "snd_pcm_hw_params_set_buffer_size_near"
+                    as unsafe extern "C" fn(Pcm, HwParams, *mut c_ulong) -> c_int
+            ),
+            hw_params: symbol!("snd_pcm_hw_params" as unsafe extern "C" fn(Pcm, HwParams) -> c_int),
+        })
+    }
+
+    fn error(&self, code: c_int) -> String {
+        unsafe { CStr::from_ptr((self.strerror)(code)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+// An open playback device, configured for our format
+struct Device {
+    alsa: &'static Alsa,
+    pcm: Pcm,
+    period_frames: u64,
+    buffer_frames: u64,
+}
+
+// The handle is only ever used by one thread at a time
+unsafe impl Send for Device {}
+
+impl Device {
+    fn open(name: &str, period_frames: u32, buffer_frames: u32) -> Result<Self, OutputError> {
+        let alsa = Alsa::get()?;
+        let c_name = CString::new(name)
+            .map_err(|_| OutputError::DeviceNotFound(format!("Invalid device '{}'", name)))?;
+        let mut pcm = std::ptr::null_mut();
+        let opened = unsafe { (alsa.open)(&mut pcm, c_name.as_ptr(), SND_PCM_STREAM_PLAYBACK, 0) };
+        if opened < 0 {
+            return Err(OutputError::DeviceNotFound(format!(
+                "{}: {}",
+                name,
+                alsa.error(opened)
+            )));
+        }
+        let mut device = Device {
+            alsa,
+            pcm,
+            period_frames: u64::from(period_frames),
+            buffer_frames: u64::from(buffer_frames),
+        };
+        device
+            .configure()
+            .map_err(|(step, code)| {
+                OutputError::StreamSetup(format!(
+                    "{}: failed to set {}: {}",
+                    name,
+                    step,
+                    alsa.error(code)
+                ))
+            })
+            .map(|()| device)
+    }
+
+    // Set our format and the sizes asked for, or the nearest the device
+    // supports; on failure, returns the step that failed
+    fn configure(&mut self) -> Result<(), (&'static str, c_int)> {
+        let alsa = self.alsa;
+        let check = |step, code: c_int| if code < 0 { Err((step, code)) } else { Ok(()) };
+        let mut params = std::ptr::null_mut();
+        check("hardware parameters", unsafe {
+            (alsa.hw_params_malloc)(&mut params)
+        })?;
+        let mut period = self.period_frames as c_ulong;
+        let mut buffer = self.buffer_frames as c_ulong;
+        let result = unsafe {
+            check(
+                "hardware parameters",
+                (alsa.hw_params_any)(self.pcm, params),
+            )
+            .and_then(|()| {
+                check(
+                    "interleaved access",
+                    (alsa.hw_params_set_access)(self.pcm, params, SND_PCM_ACCESS_RW_INTERLEAVED),
+                )
+            })
+            .and_then(|()| {
+                check(
+                    "format S16_LE",
+                    (alsa.hw_params_set_format)(self.pcm, params, SND_PCM_FORMAT_S16_LE),
+                )
+            })
+            .and_then(|()| {
+                check(
+                    "two channels",
+                    (alsa.hw_params_set_channels)(self.pcm, params, CHANNELS),
+                )
+            })
+            .and_then(|()| {
+                check(
+                    "rate 44100 Hz",
+                    (alsa.hw_params_set_rate)(self.pcm, params, SAMPLE_RATE, 0),
+                )
+            })
+            .and_then(|()| {
+                check(
+                    "buffer size",
+                    (alsa.hw_params_set_buffer_size_near)(self.pcm, params, &mut buffer),
+                )
+            })
+            .and_then(|()| {
+                check(
+                    "period size",
+                    (alsa.hw_params_set_period_size_near)(
+                        self.pcm,
+                        params,
+                        &mut period,
+                        std::ptr::null_mut(),
+                    ),
+                )
+            })
+            .and_then(|()| check("hardware parameters", (alsa.hw_params)(self.pcm, params)))
+        };
+        unsafe { (alsa.hw_params_free)(params) };
+        self.period_frames = period as u64;
+        self.buffer_frames = buffer as u64;
+        result
+    }
+
+    // Write every frame of `pcm`, blocking while the device is full. When
+    // the device runs dry it starts again with `padding`. Returns the times
+    // it ran dry.
+    fn write(&mut self, pcm: &[Frame], padding: &[Frame]) -> Result<u64, String> {
+        let (alsa, handle) = (self.alsa, self.pcm);
+        write_all(
+            pcm,
+            padding,
+            &mut |pcm: &[Frame]| unsafe {
+                (alsa.writei)(
+                    handle,
+                    pcm.as_ptr().cast(),
+                    (pcm.len() / CHANNELS as usize) as c_ulong,
+                )
+            },
+            // Prepares the device again after an underrun or a suspend
+            &mut |code| unsafe { (alsa.recover)(handle, code, 1) },
+        )
+        .map_err(|code| alsa.error(code))
+    }
+}
+
+// The write loop, given the device's writei and recover; a failure to
+// recover returns the error that caused it
+fn write_all(
+    mut pcm: &[Frame],
+    padding: &[Frame],
+    writei: &mut dyn FnMut(&[Frame]) -> c_long,
+    recover: &mut dyn FnMut(c_int) -> c_int,
+) -> Result<u64, c_int> {
+    let mut underruns = 0;
+    while !pcm.is_empty() {
+        let written = writei(pcm);
+        if written >= 0 {
+            pcm = &pcm[written as usize * CHANNELS as usize..];
+            continue;
+        }
+        let code = written as c_int;
+        if recover(code) < 0 {
+            return Err(code);
+        }
+        if code == -libc::EPIPE {
+            underruns += 1 + write_all(padding, &[], writei, recover)?;
+        }
+    }
+    Ok(underruns)
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe {
+            (self.alsa.drop)(self.pcm);
+            (self.alsa.close)(self.pcm);
+        }
+    }
+}
+
+// What the playback thread has done
+#[derive(Default)]
+struct Playback {
+    frames: u64,
+    underruns: u64,
+    error: Option<String>,
+}
+
+/// Plays an output's mix on a local ALSA device, from a thread of its own
+/// so a slow device never holds up the mixer
+pub struct AlsaOutput {
+    device: String,
+    sender: Option<Sender<AudioBuffer>>,
+    thread: Option<thread::JoinHandle<()>>,
+    playback: Arc<Mutex<Playback>>,
+    dropped: Dropped,
+}
+
+// Buffers dropped while the device was behind, summed up for the log at
+// most once every `WARN_EVERY`
+#[derive(Default)]
+struct Dropped {
+    unreported: u64,
+    warned: Option<Instant>,
+}
+
+impl Dropped {
+    // Count a dropped buffer, returning how many to warn about if it's time
+    fn add(&mut self, now: Instant) -> Option<u64> {
+        self.unreported += 1;
+        if self
+            .warned
+            .is_some_and(|warned| now.duration_since(warned) < WARN_EVERY)
+        {
+            return None;
+        }
+        self.warned = Some(now);
+        Some(std::mem::take(&mut self.unreported))
+    }
+}
+
+impl AlsaOutput {
+    /// Open `device`, asking for the given period and buffer sizes in
+    /// frames, and delay everything played on it by `latency_ms`
+    pub fn new(
+        device: &str,
+        period_frames: u32,
+        buffer_frames: u32,
+        latency_ms: u32,
+    ) -> Result<Self, OutputError> {
+        let opened = Device::open(device, period_frames, buffer_frames)?;
+        info!(
+            "Opened ALSA device {} with periods of {} frames in a buffer of {}",
+            device, opened.period_frames, opened.buffer_frames
+        );
+        let (sender, receiver) = crossbeam_channel::bounded(QUEUE);
+        let playback = Arc::new(Mutex::new(Playback::default()));
+        // In u64, as only config validation bounds `latency_ms`
+        let latency_samples =
+            u64::from(SAMPLE_RATE) * u64::from(latency_ms) / 1000 * u64::from(CHANNELS);
+        let latency = vec![0; latency_samples as usize];
+
+        let state = playback.clone();
+        let name = device.to_string();
+        let thread = thread::Builder::new()
+            .name(format!("alsa {}", device))
+            .spawn(move || play(opened, receiver, latency, state, &name))
+            .map_err(|e| OutputError::StreamSetup(e.to_string()))?;
+
+        Ok(Self {
+            device: device.to_string(),
+            sender: Some(sender),
+            thread: Some(thread),
+            playback,
+            dropped: Dropped::default(),
+        })
+    }
+}
+
+// Play buffers as they arrive until the output is closed, behind the
+// latency offset
+fn play(
+    mut device: Device,
+    receiver: Receiver<AudioBuffer>,
+    latency: Vec<Frame>,
+    playback: Arc<Mutex<Playback>>,
+    name: &str,
+) {
+    let mut started = false;
+    for pcm in receiver {
+        let written = match started {
+            true => device.write(&pcm, &latency),
+            false => device
+                .write(&latency, &[])
+                .and_then(|_| device.write(&pcm, &latency)),
+        };
+        started = true;
+        match written {
+            Ok(underruns) => {
+                let mut playback = playback.lock().unwrap();
+                playback.frames += (pcm.len() / CHANNELS as usize) as u64;
+                if underruns > 0 {
+                    debug!("ALSA device {} underran", name);
+                    playback.underruns += underruns;
+                }
+            }
+            Err(e) => {
+                error!("ALSA device {} failed: {}", name, e);
+                playback.lock().unwrap().error = Some(e);
+                return;
+            }
+        }
+    }
+}
+
+impl PcmSink for AlsaOutput {
+    fn write(&mut self, pcm: &[Frame]) -> Result<(), OutputError> {
+        let Some(sender) = &self.sender else {
+            return Err(OutputError::Write(format!("{} is closed", self.device)));
+        };
+        match sender.try_send(pcm.to_vec()) {
+            Ok(()) => Ok(()),
+            // The device fell far behind; what it misses is an underrun
+            Err(TrySendError::Full(_)) => {
+                self.playback.lock().unwrap().underruns += 1;
+                if let Some(dropped) = self.dropped.add(Instant::now()) {
+                    warn!(
+                        "ALSA device {} is behind, dropped {} buffers of the mix",
+                        self.device, dropped
+                    );
+                }
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(OutputError::Write(
+                self.playback
+                    .lock()
+                    .unwrap()
+                    .error
+                    .clone()
+                    .unwrap_or_else(|| format!("{} stopped", self.device)),
+            )),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), OutputError> {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            thread
+                .join()
+                .map_err(|_| OutputError::Write(format!("{} playback panicked", self.device)))?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> SinkStatus {
+        let playback = self.playback.lock().unwrap();
+        SinkStatus {
+            healthy: playback.error.is_none(),
+            frames: playback.frames,
+            underruns: Some(playback.underruns),
+            error: playback.error.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Drop for AlsaOutput {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ALSA's `null` device takes audio in real time and discards it
+    fn open(latency_ms: u32) -> Option<AlsaOutput> {
+        match AlsaOutput::new("null", 256, 1024, latency_ms) {
+            Ok(output) => Some(output),
+            Err(e) => {
+                eprintln!("Skipping, no ALSA null device: {}", e);
+                None
+            }
+        }
+    }
+
+    fn wait_for(output: &AlsaOutput, frames: u64) {
+        let start = Instant::now();
+        while output.status().frames < frames {
+            assert!(start.elapsed() < Duration::from_secs(5), "Device stalled");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_dropped_warnings() {
+        let mut dropped = Dropped::default();
+        let start = Instant::now();
+        assert_eq!(dropped.add(start), Some(1));
+        for i in 1..50 {
+            assert_eq!(dropped.add(start + Duration::from_millis(i * 10)), None);
+        }
+        assert_eq!(dropped.add(start + WARN_EVERY), Some(50));
+    }
+
+    #[test]
+    fn test_null_device() {
+        let Some(mut output) = open(50) else {
+            return;
+        };
+        let pcm = vec![1000; 441 * 2];
+        for _ in 0..10 {
+            output.write(&pcm).unwrap();
+        }
+        wait_for(&output, 4410);
+        let status = output.status();
+        assert!(status.healthy);
+        assert_eq!(status.frames, 4410);
+
+        output.close().unwrap();
+        assert!(output.write(&pcm).is_err());
+
+        let error = AlsaOutput::new("no_such_device", 256, 1024, 0)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("no_such_device"));
+    }
+
+    #[test]
+    fn test_underrun_recovery() {
+        // A device that takes 2 frames at a time and runs dry once, after
+        // the first write
+        let mut played = Vec::new();
+        let mut calls = 0;
+        let mut writei = |pcm: &[Frame]| {
+            calls += 1;
+            if calls == 2 {
+                return -libc::EPIPE as c_long;
+            }
+            let frames = pcm.len().min(4);
+            played.extend_from_slice(&pcm[..frames]);
+            frames as c_long / 2
+        };
+        let mut recovered = Vec::new();
+        let mut recover = |code| {
+            recovered.push(code);
+            0
+        };
+        let underruns = write_all(&[1, 1, 2, 2, 3, 3], &[0, 0], &mut writei, &mut recover);
+        assert_eq!(underruns, Ok(1));
+        assert_eq!(recovered, vec![-libc::EPIPE]);
+        // Playback starts again behind the padding
+        assert_eq!(played, vec![1, 1, 2, 2, 0, 0, 3, 3]);
+
+        // Errors it cannot recover from are returned
+        let underruns = write_all(
+            &[1, 1],
+            &[],
+            &mut |_| -libc::EBADFD as c_long,
+            &mut |code| code,
+        );
+        assert_eq!(underruns, Err(-libc::EBADFD));
+    }
+}
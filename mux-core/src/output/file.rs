@@ -0,0 +1,479 @@
+use crate::config::{Codec, Output, OutputKind};
+use crate::encoder::{create_encoder, Encoder, Flac};
+use crate::input::Frame;
+use crate::output::{OutputError, PcmSink, SinkStatus};
+use chrono::format::{Item, StrftimeItems};
+use log::{info, warn};
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Records an output's mix to files, starting a new one by age or size and
+/// deleting the oldest beyond a limit
+pub struct FileOutput {
+    id: String,
+    template: String,
+    format: Codec,
+    bitrate_kbps: u32,
+    quality: Option<u8>,
+    rotate_after: Option<Duration>,
+    rotate_bytes: Option<u64>,
+    keep_files: Option<usize>,
+    recording: Option<Recording>,
+    frames: u64,
+    error: Option<String>,
+}
+
+// The file currently being written
+struct Recording {
+    path: PathBuf,
+    writer: Writer,
+    started: Instant,
+    bytes: u64,
+}
+
+enum Writer {
+    Wav(hound::WavWriter<BufWriter<File>>),
+    Flac(BufWriter<File>, Flac),
+    // Formats with nothing to fill in once the file is done
+    Stream(BufWriter<File>, Box<dyn Encoder>),
+}
+
+/// Check that a path template only uses strftime fields chrono knows
+pub fn check_template(template: &str) -> Result<(), String> {
+    if StrftimeItems::new(template).any(|item| item == Item::Error) {
+        return Err(format!(
+            "'{}' has an unknown or incomplete % field",
+            template
+        ));
+    }
+    Ok(())
+}
+
+impl FileOutput {
+    pub fn new(output: &Output) -> Result<Self, OutputError> {
+        let OutputKind::File {
+            path,
+            format,
+            bitrate_kbps,
+            quality,
+            rotate_sec,
+            rotate_mb,
+            keep_files,
+        } = &output.kind
+        else {
+            return Err(OutputError::StreamSetup(format!(
+                "Output '{}' is not a file output",
+                output.id
+            )));
+        };
+        check_template(path).map_err(OutputError::StreamSetup)?;
+        let format = format.or_else(|| Codec::from_path(path)).ok_or_else(|| {
+            OutputError::StreamSetup(format!("No format for recording to {}", path))
+        })?;
+
+        Ok(Self {
+            id: output.id.clone(),
+            template: path.clone(),
+            format,
+            bitrate_kbps: *bitrate_kbps,
+            quality: *quality,
+            rotate_after: rotate_sec.map(Duration::from_secs),
+            rotate_bytes: rotate_mb.map(|mb| mb * 1_000_000),
+            keep_files: *keep_files,
+            recording: None,
+            frames: 0,
+            error: None,
+        })
+    }
+
+    // Start a file named after the current local time, next to any earlier
+    // one of the same name rather than over it
+    fn open(&self) -> io::Result<Recording> {
+        let mut name = String::new();
+        write!(name, "{}", chrono::Local::now().format(&self.template))
+            .map_err(|_| io::Error::other(format!("Invalid path template {}", self.template)))?;
+        let base = PathBuf::from(name);
+        if let Some(parent) = base.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let mut suffix = 0;
+        let (path, file) = loop {
+            let path = if suffix == 0 {
+                base.clone()
+            } else {
+                with_suffix(&base, suffix)
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+                Err(e) => return Err(e),
+            }
+        };
+
+        let file = BufWriter::new(file);
+        let (writer, bytes) = match self.format {
+            Codec::Wav => {
+                let spec = hound::WavSpec {
+                    channels: 2,
+                    sample_rate: 44100,
+                    bits_per_sample: 16,
+                    sample_format: hound::SampleFormat::Int,
+                };
+                let writer = hound::WavWriter::new(file, spec).map_err(hound_error)?;
+                (Writer::Wav(writer), 44)
+            }
+            Codec::Flac => {
+                let mut file = file;
+                let encoder = Flac::new();
+                let header = encoder.header();
+                file.write_all(&header)?;
+                (Writer::Flac(file, encoder), header.len() as u64)
+            }
+            Codec::Mp3 | Codec::Opus | Codec::OggFlac | Codec::Aac => {
+                let mut file = file;
+                let encoder = create_encoder(self.format, self.bitrate_kbps, self.quality)
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                let header = encoder.header();
+                file.write_all(&header)?;
+                (Writer::Stream(file, encoder), header.len() as u64)
+            }
+        };
+        info!("Output '{}' recording to {}", self.id, path.display());
+
+        Ok(Recording {
+            path,
+            writer,
+            started: Instant::now(),
+            bytes,
+        })
+    }
+
+    fn record(&mut self, pcm: &[Frame]) -> io::Result<()> {
+        let mut recording = match self.recording.take() {
+            Some(recording) => recording,
+            None => self.open()?,
+        };
+        recording.bytes += recording.writer.write(pcm)?;
+
+        let full = self
+            .rotate_bytes
+            .is_some_and(|limit| recording.bytes >= limit);
+        let old = self
+            .rotate_after
+            .is_some_and(|age| recording.started.elapsed() >= age);
+        if full || old {
+            self.finish(recording)?;
+        } else {
+            self.recording = Some(recording);
+        }
+        Ok(())
+    }
+
+    // Finish a file, then make room for the next one
+    fn finish(&self, recording: Recording) -> io::Result<()> {
+        recording.writer.finish()?;
+        if let Some(keep) = self.keep_files {
+            self.prune(keep)?;
+        }
+        Ok(())
+    }
+
+    // Delete the oldest recordings the template could have named, so that
+    // `keep` are left
+    fn prune(&self, keep: usize) -> io::Result<()> {
+        let pattern = template_pattern(&self.template);
+        let paths =
+            glob::glob(&pattern).map_err(|e| io::Error::other(format!("{}: {}", pattern, e)))?;
+        let mut recordings: Vec<(SystemTime, PathBuf)> = paths
+            .filter_map(Result::ok)
+            .filter_map(|path| {
+                let metadata = fs::metadata(&path).ok()?;
+                metadata
+                    .is_file()
+                    .then(|| (metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), path))
+            })
+            .collect();
+        recordings.sort();
+
+        let excess = recordings.len().saturating_sub(keep);
+        for (_, path) in recordings.into_iter().take(excess) {
+            info!(
+                "Output '{}' deleting old recording {}",
+                self.id,
+                path.display()
+            );
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to delete {}: {}", path.display(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PcmSink for FileOutput {
+    fn write(&mut self, pcm: &[Frame]) -> Result<(), OutputError> {
+        match self.record(pcm) {
+            Ok(()) => {
+                self.frames += pcm.len() as u64 / 2;
+                self.error = None;
+                Ok(())
+            }
+            Err(e) => {
+                // The file is given up on; the next write starts another
+                let message = e.to_string();
+                self.error = Some(message.clone());
+                Err(OutputError::Write(message))
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), OutputError> {
+        match self.recording.take() {
+            Some(recording) => self
+                .finish(recording)
+                .map_err(|e| OutputError::Write(e.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn status(&self) -> SinkStatus {
+        SinkStatus {
+            healthy: self.error.is_none(),
+            frames: self.frames,
+            file: self
+                .recording
+                .as_ref()
+                .map(|r| r.path.display().to_string()),
+            error: self.error.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Drop for FileOutput {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            warn!("Output '{}' failed to finish its recording: {}", self.id, e);
+        }
+    }
+}
+
+impl Writer {
+    // Write interleaved stereo samples, returning how many bytes that took
+    fn write(&mut self, pcm: &[Frame]) -> io::Result<u64> {
+        match self {
+            Writer::Wav(writer) => {
+                let mut samples = writer.get_i16_writer(pcm.len() as u32);
+                for &sample in pcm {
+                    samples.write_sample(sample);
+                }
+                samples.flush().map_err(hound_error)?;
+                Ok(pcm.len() as u64 * 2)
+            }
+            Writer::Flac(file, encoder) => {
+                let data = encoder
+                    .encode(pcm)
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                file.write_all(&data)?;
+                Ok(data.len() as u64)
+            }
+            Writer::Stream(file, encoder) => {
+                let data = encoder
+                    .encode(pcm)
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                file.write_all(&data)?;
+                Ok(data.len() as u64)
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Writer::Wav(writer) => writer.finalize().map_err(hound_error),
+            Writer::Flac(mut file, mut encoder) => {
+                let data = encoder
+                    .flush()
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                file.write_all(&data)?;
+                // Now that the length is known, fill it in
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(&encoder.header())?;
+                file.flush()
+            }
+            Writer::Stream(mut file, mut encoder) => {
+                let data = encoder
+                    .flush()
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                file.write_all(&data)?;
+                file.flush()
+            }
+        }
+    }
+}
+
+fn hound_error(e: hound::Error) -> io::Error {
+    match e {
+        hound::Error::IoError(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+// `name.wav` becomes `name-1.wav`
+fn with_suffix(path: &Path, suffix: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, suffix),
+    };
+    path.with_file_name(name)
+}
+
+// A glob matching every path the template can produce, including the
+// suffixed ones: each field becomes `*`, as does the end of the file stem
+fn template_pattern(template: &str) -> String {
+    let path = Path::new(template);
+    let extension = path.extension().map(|e| e.to_string_lossy().into_owned());
+    let stem = match &extension {
+        Some(extension) => &template[..template.len() - extension.len() - 1],
+        None => template,
+    };
+
+    let mut pattern = String::new();
+    let mut chars = stem.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            pattern.push_str(&glob::Pattern::escape(&c.to_string()));
+            continue;
+        }
+        // Padding and width modifiers come before the field letter
+        let mut field = chars.next();
+        while matches!(field, Some('-' | '_' | '0'..='9' | '.' | ':' | '#')) {
+            field = chars.next();
+        }
+        match field {
+            Some('%') => pattern.push('%'),
+            _ => push_star(&mut pattern),
+        }
+    }
+    push_star(&mut pattern);
+    if let Some(extension) = extension {
+        pattern.push('.');
+        pattern.push_str(&glob::Pattern::escape(&extension));
+    }
+    pattern
+}
+
+// `**` means any number of directories to glob, so never write two
+fn push_star(pattern: &mut String) {
+    if !pattern.ends_with('*') {
+        pattern.push('*');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn file_output(dir: &Path, settings: &str) -> FileOutput {
+        let config = Config::check(&format!(
+            "[[outputs]]\nid = \"recorder\"\nkind = \"file\"\npath = \"{}/%Y/rec-%H%M%S.wav\"\n{}",
+            dir.display(),
+            settings
+        ))
+        .into_result()
+        .unwrap();
+        FileOutput::new(&config.outputs[0]).unwrap()
+    }
+
+    #[test]
+    fn test_records_rotates_and_prunes() {
+        let dir = tempfile::tempdir().unwrap();
+        // Every file is 44 bytes of header and a single 4000 byte buffer
+        let mut output = file_output(dir.path(), "rotate_mb = 1\nkeep_files = 2");
+        output.rotate_bytes = Some(4000);
+        let pcm: Vec<Frame> = (0..2000).map(|i| i as Frame).collect();
+
+        for _ in 0..4 {
+            output.write(&pcm).unwrap();
+            // Give each file a distinct modification time
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let status = output.status();
+        assert!(status.healthy);
+        assert_eq!(status.frames, 4000);
+        assert_eq!(status.file, None);
+
+        // Files started in the same second get suffixes, and only the two
+        // newest are kept
+        let year = chrono::Local::now().format("%Y").to_string();
+        let mut files: Vec<PathBuf> = fs::read_dir(dir.path().join(year))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        for file in files {
+            let mut reader = hound::WavReader::open(&file).unwrap();
+            assert_eq!(reader.spec().channels, 2);
+            let samples: Vec<Frame> = reader.samples::<i16>().map(Result::unwrap).collect();
+            assert_eq!(samples, pcm);
+        }
+    }
+
+    #[test]
+    fn test_flac_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut output = file_output(dir.path(), "format = \"flac\"");
+        let pcm: Vec<Frame> = (0..10000).map(|i| (i * 3) as Frame).collect();
+        output.write(&pcm).unwrap();
+
+        let path = PathBuf::from(output.status().file.unwrap());
+        output.close().unwrap();
+        let mut reader = claxon::FlacReader::open(&path).unwrap();
+        assert_eq!(reader.streaminfo().samples, Some(5000));
+        let samples: Vec<Frame> = reader.samples().map(|s| s.unwrap() as Frame).collect();
+        assert_eq!(samples, pcm);
+    }
+
+    #[test]
+    fn test_write_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        // A file where the recording's directory should be
+        fs::write(dir.path().join("blocked"), b"").unwrap();
+        let mut output = file_output(&dir.path().join("blocked"), "");
+
+        assert!(output.write(&[0, 0]).is_err());
+        let status = output.status();
+        assert!(!status.healthy);
+        assert!(status.error.is_some());
+    }
+
+    #[test]
+    fn test_template_pattern() {
+        assert_eq!(
+            template_pattern("/rec/%Y/%m/kitchen-%Y%m%d-%-H.flac"),
+            "/rec/*/*/kitchen-*-*.flac"
+        );
+        assert_eq!(
+            template_pattern("/rec/[a] 100%%.wav"),
+            "/rec/[[]a[]] 100%*.wav"
+        );
+        assert_eq!(template_pattern("/rec/kitchen"), "/rec/kitchen*");
+        assert_eq!(
+            with_suffix(Path::new("/rec/kitchen.wav"), 2),
+            Path::new("/rec/kitchen-2.wav")
+        );
+        assert!(check_template("/rec/%Y-%m-%d.wav").is_ok());
+        assert!(check_template("/rec/%Q.wav").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use crate::config::{Output, OutputKind};
+use crate::encoder::{create_encoder, Encoder};
+use crate::hls::{Playlist, PLAYLIST};
+use crate::input::Frame;
+use crate::output::{OutputError, PcmSink, SinkStatus};
+use crate::stream::{HttpStreamer, Stream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Encodes an output's mix to its own endpoint on a stream server, as one
+/// long response or as HLS segments
+pub struct HttpOutput {
+    target: Target,
+    encoder: Box<dyn Encoder>,
+    frames: u64,
+    error: Option<String>,
+}
+
+impl HttpOutput {
+    /// Serve the output's stream from `streamer`, at the output's path
+    pub fn new(output: &Output, streamer: &HttpStreamer) -> Result<Self, OutputError> {
+        let (
+            OutputKind::Http {
+                codec,
+                bitrate_kbps,
+                quality,
+                hls,
+                segment_sec,
+                window,
+                ..
+            },
+            Some(path),
+        ) = (&output.kind, output.stream_path())
+        else {
+            return Err(OutputError::StreamSetup(format!(
+                "Output '{}' is not an http output",
+                output.id
+            )));
+        };
+
+        let encoder = create_encoder(*codec, *bitrate_kbps, *quality)
+            .map_err(|e| OutputError::StreamSetup(e.to_string()))?;
+        let target = match path.strip_suffix(&format!("/{}", PLAYLIST)) {
+            Some(dir) if *hls => streamer
+                .add_playlist(
+                    dir,
+                    Playlist::new(
+                        encoder.content_type(),
+                        codec.extension(),
+                        Duration::from_secs(u64::from(*segment_sec)),
+                        *window,
+                    ),
+                )
+                .map(Target::Playlist),
+            _ => streamer
+                .add_stream(&path, encoder.content_type(), encoder.header())
+                .map(Target::Stream),
+        }
+        .map_err(|e| OutputError::StreamSetup(e.to_string()))?;
+
+        Ok(Self {
+            target,
+            encoder,
+            frames: 0,
+            error: None,
+        })
+    }
+
+    fn encode(&mut self, pcm: &[Frame]) -> Result<(), OutputError> {
+        match self.encoder.encode(pcm) {
+            Ok(data) => {
+                let frames = pcm.len() as u64 / 2;
+                self.target.send(data, frames);
+                self.frames += frames;
+                self.error = None;
+                Ok(())
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                Err(OutputError::Write(e.to_string()))
+            }
+        }
+    }
+}
+
+enum Target {
+    Stream(Arc<Stream>),
+    Playlist(Arc<Playlist>),
+}
+
+impl Target {
+    fn send(&self, data: Vec<u8>, frames: u64) {
+        match self {
+            Target::Stream(stream) if !data.is_empty() => stream.send(data),
+            Target::Stream(_) => {}
+            // Even audio the encoder holds on to counts towards a segment
+            Target::Playlist(playlist) => playlist.send(&data, frames),
+        }
+    }
+}
+
+impl PcmSink for HttpOutput {
+    fn write(&mut self, pcm: &[Frame]) -> Result<(), OutputError> {
+        // Audio is encoded a segment's worth at most, so segments are cut
+        // on the target length and never run past it
+        let mut rest = pcm;
+        while let Target::Playlist(playlist) = &self.target {
+            let left = playlist.frames_left() as usize * 2;
+            if rest.len() <= left {
+                break;
+            }
+            let (segment, next) = rest.split_at(left);
+            self.encode(segment)?;
+            rest = next;
+        }
+        self.encode(rest)
+    }
+
+    fn close(&mut self) -> Result<(), OutputError> {
+        let data = self
+            .encoder
+            .flush()
+            .map_err(|e| OutputError::Write(e.to_string()))?;
+        self.target.send(data, 0);
+        Ok(())
+    }
+
+    fn status(&self) -> SinkStatus {
+        SinkStatus {
+            healthy: self.error.is_none(),
+            frames: self.frames,
+            // HLS players come and go with every segment, so aren't counted
+            clients: match &self.target {
+                Target::Stream(stream) => Some(stream.clients()),
+                Target::Playlist(_) => None,
+            },
+            error: self.error.clone(),
+            ..Default::default()
+        }
+    }
+
+    // Only plain streams carry ICY metadata
+    fn set_title(&mut self, title: &str) {
+        if let Target::Stream(stream) = &self.target {
+            stream.set_title(title);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_wav_stream() {
+        let config = Config::check(
+            r#"
+[[outputs]]
+id = "garage"
+kind = "http"
+codec = "wav"
+"#,
+        )
+        .into_result()
+        .unwrap();
+        let streamer = HttpStreamer::bind("127.0.0.1:0".parse().unwrap());
+        let mut output = HttpOutput::new(&config.outputs[0], &streamer).unwrap();
+        assert!(HttpOutput::new(&config.outputs[0], &streamer).is_err());
+        streamer.start().await.unwrap();
+
+        let mut socket = TcpStream::connect(streamer.local_addr().unwrap())
+            .await
+            .unwrap();
+        socket
+            .write_all(b"GET /streams/garage HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        while output.status().clients != Some(1) {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+
+        let pcm: Vec<Frame> = (0..2000).map(|i| i as Frame - 1000).collect();
+        output.write(&pcm).unwrap();
+        assert_eq!(output.status().frames, 1000);
+
+        // Whatever the client reads after the response head plays as WAV
+        let mut response = Vec::new();
+        let wav = loop {
+            let body = response
+                .windows(4)
+                .position(|w| w == b"\r\n\r\n")
+                .map(|i| &response[i + 4..]);
+            if let Some(body) = body.filter(|body| body.len() >= 44 + 4000) {
+                break body.to_vec();
+            }
+            let mut buffer = [0u8; 4096];
+            let n = socket.read(&mut buffer).await.unwrap();
+            assert!(n > 0, "Stream ended early");
+            response.extend_from_slice(&buffer[..n]);
+        };
+        let reader = hound::WavReader::new(std::io::Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        let samples: Vec<Frame> = reader
+            .into_samples::<i16>()
+            .take(pcm.len())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(samples, pcm);
+    }
+
+    async fn get(streamer: &HttpStreamer, path: &str) -> (String, Vec<u8>) {
+        let mut socket = TcpStream::connect(streamer.local_addr().unwrap())
+            .await
+            .unwrap();
+        socket
+            .write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        socket.read_to_end(&mut response).await.unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let body = response.split_off(end + 4);
+        (String::from_utf8(response).unwrap(), body)
+    }
+
+    #[tokio::test]
+    async fn test_hls() {
+        let config = Config::check(
+            r#"
+[[outputs]]
+id = "garage"
+kind = "http"
+codec = "aac"
+hls = true
+segment_sec = 1
+window = 3
+"#,
+        )
+        .into_result()
+        .unwrap();
+        assert_eq!(
+            config.outputs[0].stream_path().as_deref(),
+            Some("/streams/garage/index.m3u8")
+        );
+        let streamer = HttpStreamer::bind("127.0.0.1:0".parse().unwrap());
+        let mut output = HttpOutput::new(&config.outputs[0], &streamer).unwrap();
+        assert!(streamer
+            .add_stream("/streams/garage/index.m3u8", "audio/aac", Vec::new())
+            .is_err());
+        streamer.start().await.unwrap();
+
+        // Four and a half seconds make four segments, whole writes or not
+        let pcm = vec![0; 13_230 * 2];
+        for _ in 0..15 {
+            output.write(&pcm).unwrap();
+        }
+        assert_eq!(output.status().clients, None);
+
+        let (head, body) = get(&streamer, "/streams/garage/index.m3u8").await;
+        assert!(head.contains("Content-Type: application/vnd.apple.mpegurl\r\n"));
+        assert!(head.contains("Cache-Control: no-cache\r\n"));
+        let playlist = String::from_utf8(body).unwrap();
+        assert!(
+            playlist.contains("#EXT-X-MEDIA-SEQUENCE:1\n"),
+            "{}",
+            playlist
+        );
+        assert!(
+            playlist.ends_with("#EXTINF:1.000,\n3.aac\n"),
+            "{}",
+            playlist
+        );
+
+        let (head, body) = get(&streamer, "/streams/garage/3.aac").await;
+        assert!(head.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(head.contains("Content-Type: audio/aac\r\n"));
+        assert!(head.contains("Cache-Control: public, max-age=5\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        // The timestamp tag, then ADTS frames
+        assert!(body.starts_with(b"ID3"));
+        assert_eq!(&body[73..75], &[0xFF, 0xF1]);
+
+        let (head, _) = get(&streamer, "/streams/garage/4.aac").await;
+        assert!(head.starts_with("HTTP/1.0 404 Not Found\r\n"));
+    }
+}
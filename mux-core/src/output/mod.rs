@@ -1,9 +1,21 @@
 // Output module for sonos-mux
+pub mod alsa;
+pub mod file;
+pub mod http;
+pub mod icecast;
+pub mod null;
+pub mod rtp;
 pub mod sonos;
+pub mod upnp;
 
+use crate::config::{Output, OutputKind};
+use crate::input::Frame;
+use crate::mixer::Level;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt::Debug;
+use std::time::Duration;
 
 /// Common trait for all output destinations
 #[async_trait]
@@ -21,6 +33,111 @@ pub trait AudioOutput: Debug + Send + Sync {
     async fn health_check(&self) -> bool;
 }
 
+/// What a renderer is doing, as its AVTransport service names it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TransportState {
+    Playing,
+    Stopped,
+    PausedPlayback,
+    Transitioning,
+    NoMediaPresent,
+}
+
+impl TransportState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransportState::Playing => "PLAYING",
+            TransportState::Stopped => "STOPPED",
+            TransportState::PausedPlayback => "PAUSED_PLAYBACK",
+            TransportState::Transitioning => "TRANSITIONING",
+            TransportState::NoMediaPresent => "NO_MEDIA_PRESENT",
+        }
+    }
+
+    /// Parse a `CurrentTransportState` value
+    pub fn parse(state: &str) -> Option<Self> {
+        match state {
+            "PLAYING" => Some(TransportState::Playing),
+            "STOPPED" => Some(TransportState::Stopped),
+            "PAUSED_PLAYBACK" => Some(TransportState::PausedPlayback),
+            "TRANSITIONING" => Some(TransportState::Transitioning),
+            "NO_MEDIA_PRESENT" => Some(TransportState::NoMediaPresent),
+            _ => None,
+        }
+    }
+}
+
+/// An output that takes its mix as PCM, on the mixing thread, rather than
+/// playing a stream from us
+pub trait PcmSink: Send {
+    /// Take the next buffer of interleaved stereo samples
+    fn write(&mut self, pcm: &[Frame]) -> Result<(), OutputError>;
+
+    /// Finish whatever has been written, e.g. on shutdown
+    fn close(&mut self) -> Result<(), OutputError>;
+
+    /// What the sink has done so far
+    fn status(&self) -> SinkStatus;
+
+    /// Tell listeners that `title` is playing, for sinks that can
+    fn set_title(&mut self, _title: &str) {}
+}
+
+/// State of a PCM sink, for status and health checks
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SinkStatus {
+    /// Whether the last write succeeded
+    pub healthy: bool,
+    /// Stereo frames written
+    pub frames: u64,
+    /// Times the mix arrived too late to play without a gap, for outputs
+    /// that play in real time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub underruns: Option<u64>,
+    /// Listeners connected, for outputs that serve a stream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clients: Option<usize>,
+    /// Smoothed variation in when buffers arrive, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter_ms: Option<f64>,
+    /// Loudest RMS and peak levels taken, for outputs that measure them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<Level>,
+    /// The file being written, for outputs that record
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Why the last write failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Create the PCM sink for an output, if it is one that takes PCM without
+/// help from the daemon; `http` outputs need a stream server, so the daemon
+/// attaches theirs with `Router::attach_sink`
+pub fn create_sink(output: &Output) -> Result<Option<Box<dyn PcmSink>>, OutputError> {
+    match &output.kind {
+        OutputKind::Alsa {
+            device,
+            period_frames,
+            buffer_frames,
+            latency_ms,
+        } => Ok(Some(Box::new(alsa::AlsaOutput::new(
+            device,
+            *period_frames,
+            *buffer_frames,
+            *latency_ms,
+        )?))),
+        OutputKind::Icecast { .. } => Ok(Some(Box::new(icecast::IcecastOutput::new(output)?))),
+        OutputKind::Rtp { .. } => Ok(Some(Box::new(rtp::RtpOutput::new(output)?))),
+        OutputKind::File { .. } => Ok(Some(Box::new(file::FileOutput::new(output)?))),
+        OutputKind::Null { buffer_ms } => Ok(Some(Box::new(null::NullOutput::new(
+            Duration::from_millis(u64::from(*buffer_ms)),
+        )))),
+        OutputKind::Sonos { .. } | OutputKind::Upnp { .. } | OutputKind::Http { .. } => Ok(None),
+    }
+}
+
 /// Output error types
 #[derive(Debug, thiserror::Error)]
 pub enum OutputError {
@@ -35,4 +152,7 @@ pub enum OutputError {
 
     #[error("Device not found: {0}")]
     DeviceNotFound(String),
+
+    #[error("Write error: {0}")]
+    Write(String),
 }
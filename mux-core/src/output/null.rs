@@ -0,0 +1,139 @@
+use crate::input::Frame;
+use crate::mixer::Level;
+use crate::output::{OutputError, PcmSink, SinkStatus};
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: f64 = 44100.0;
+
+/// Takes an output's mix the way a sound card would, in real time, and
+/// discards it, keeping count of what arrived and how late
+pub struct NullOutput {
+    // How much audio the simulated device holds
+    buffer: Duration,
+    frames: u64,
+    underruns: u64,
+    // When the audio taken so far finishes playing
+    played_until: Option<Instant>,
+    // When the last buffer arrived, and how long it plays for
+    last: Option<(Instant, Duration)>,
+    jitter: f64,
+    level: Option<Level>,
+}
+
+impl NullOutput {
+    pub fn new(buffer: Duration) -> Self {
+        Self {
+            buffer,
+            frames: 0,
+            underruns: 0,
+            played_until: None,
+            last: None,
+            jitter: 0.0,
+            level: None,
+        }
+    }
+
+    fn take(&mut self, pcm: &[Frame], now: Instant) {
+        let frames = pcm.len() as u64 / 2;
+        let duration = Duration::from_secs_f64(frames as f64 / SAMPLE_RATE);
+
+        // Playback starts once the first buffer arrives, a device buffer
+        // later; a buffer arriving after the device ran dry is an underrun,
+        // and playback starts over
+        let played_until = match self.played_until {
+            Some(until) if until < now => {
+                self.underruns += 1;
+                now + self.buffer
+            }
+            Some(until) => until,
+            None => now + self.buffer,
+        };
+        // A real device would block a writer that runs ahead; here the
+        // surplus is simply dropped
+        self.played_until = Some((played_until + duration).min(now + self.buffer + duration));
+
+        // Interarrival jitter, smoothed as in RFC 3550: how far each gap
+        // between buffers is from the length of the buffer before it
+        if let Some((last, last_duration)) = self.last {
+            let gap = now.duration_since(last).as_secs_f64();
+            let deviation = (gap - last_duration.as_secs_f64()).abs();
+            self.jitter += (deviation - self.jitter) / 16.0;
+        }
+        self.last = Some((now, duration));
+
+        let level = Level::measure(pcm);
+        self.level = Some(match self.level {
+            Some(loudest) => Level {
+                rms_db: loudest.rms_db.max(level.rms_db),
+                peak_db: loudest.peak_db.max(level.peak_db),
+            },
+            None => level,
+        });
+        self.frames += frames;
+    }
+}
+
+impl PcmSink for NullOutput {
+    fn write(&mut self, pcm: &[Frame]) -> Result<(), OutputError> {
+        self.take(pcm, Instant::now());
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn status(&self) -> SinkStatus {
+        SinkStatus {
+            healthy: true,
+            frames: self.frames,
+            underruns: Some(self.underruns),
+            jitter_ms: self.last.map(|_| self.jitter * 1000.0),
+            level: self.level,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_underruns_and_jitter() {
+        let mut output = NullOutput::new(Duration::from_millis(100));
+        assert_eq!(output.status().jitter_ms, None);
+
+        // 4410 frames play for 100 ms
+        let pcm = vec![8192; 4410 * 2];
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // On time, then late but within the device buffer
+        output.take(&pcm, at(0));
+        output.take(&pcm, at(100));
+        output.take(&pcm, at(250));
+        let status = output.status();
+        assert_eq!(status.frames, 4410 * 3);
+        assert_eq!(status.underruns, Some(0));
+        let jitter = status.jitter_ms.unwrap();
+        assert!((jitter - 50.0 / 16.0).abs() < 0.01, "jitter {}", jitter);
+
+        // The device has played everything by 400 ms
+        output.take(&pcm, at(450));
+        assert_eq!(output.status().underruns, Some(1));
+        output.take(&pcm, at(550));
+        assert_eq!(output.status().underruns, Some(1));
+
+        // Running ahead never counts as an underrun later on
+        for _ in 0..10 {
+            output.take(&pcm, at(560));
+        }
+        output.take(&pcm, at(700));
+        assert_eq!(output.status().underruns, Some(1));
+
+        let level = output.status().level.unwrap();
+        assert!((level.peak_db - -12.04).abs() < 0.01);
+        assert!(output.status().healthy);
+    }
+}
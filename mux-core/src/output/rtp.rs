@@ -0,0 +1,370 @@
+use crate::config::{Output, OutputKind, RtpPayload};
+use crate::encoder::Opus;
+use crate::input::Frame;
+use crate::output::{OutputError, PcmSink, SinkStatus};
+use log::info;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Dynamic payload type for Opus; L16 stereo at 44.1 kHz has a static one
+const OPUS_PAYLOAD_TYPE: u8 = 96;
+const L16_PAYLOAD_TYPE: u8 = 10;
+
+/// Sends an output's mix as RTP, to a unicast or multicast address
+pub struct RtpOutput {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    packetizer: Packetizer,
+    sequence: u16,
+    timestamp: u32,
+    ssrc: u32,
+    sdp: String,
+    frames: u64,
+    error: Option<String>,
+}
+
+enum Packetizer {
+    // Samples waiting for a whole packet, and how many frames one holds
+    L16 { pending: Vec<Frame>, frames: usize },
+    Opus(Opus),
+}
+
+impl RtpOutput {
+    /// Open a socket for the output, and write its SDP file if it has one
+    pub fn new(output: &Output) -> Result<Self, OutputError> {
+        let OutputKind::Rtp {
+            address,
+            payload,
+            ptime_ms,
+            ttl,
+            bitrate_kbps,
+            sdp,
+        } = &output.kind
+        else {
+            return Err(OutputError::StreamSetup(format!(
+                "Output '{}' is not an rtp output",
+                output.id
+            )));
+        };
+        let destination: SocketAddr = address
+            .parse()
+            .map_err(|_| OutputError::StreamSetup(format!("Invalid address: {}", address)))?;
+        let ptime = ptime_ms.unwrap_or_else(|| payload.default_ptime());
+        let packetizer = match payload {
+            // Whole frames, so a packet plays a little short of its ptime
+            RtpPayload::L16 => Packetizer::L16 {
+                pending: Vec::new(),
+                frames: 44100 * ptime as usize / 1000,
+            },
+            RtpPayload::Opus => Packetizer::Opus(
+                Opus::new(*bitrate_kbps, ptime)
+                    .map_err(|e| OutputError::StreamSetup(e.to_string()))?,
+            ),
+        };
+
+        let setup = |e: std::io::Error| OutputError::StreamSetup(format!("{}: {}", address, e));
+        let any: IpAddr = match destination {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((any, 0)).map_err(setup)?;
+        if let IpAddr::V4(ip) = destination.ip() {
+            match ip.is_multicast() {
+                true => socket.set_multicast_ttl_v4(*ttl),
+                false => socket.set_ttl(*ttl),
+            }
+            .map_err(setup)?;
+        }
+
+        // The address packets leave from, as a receiver sees it
+        let origin = UdpSocket::bind((any, 0))
+            .and_then(|probe| {
+                probe.connect(destination)?;
+                probe.local_addr()
+            })
+            .map_err(setup)?
+            .ip();
+
+        let ssrc = ssrc();
+        let sdp_text =
+            session_description(&output.id, destination, origin, *payload, ptime, *ttl, ssrc);
+        if let Some(path) = sdp {
+            fs::write(path, &sdp_text)
+                .map_err(|e| OutputError::StreamSetup(format!("{}: {}", path, e)))?;
+            info!("Wrote the SDP for output '{}' to {}", output.id, path);
+        }
+
+        Ok(Self {
+            socket,
+            destination,
+            packetizer,
+            // Random starting points, as RFC 3550 asks
+            sequence: ssrc as u16,
+            timestamp: ssrc.rotate_left(16),
+            ssrc,
+            sdp: sdp_text,
+            frames: 0,
+            error: None,
+        })
+    }
+
+    /// The session description a receiver needs to play the stream
+    pub fn sdp(&self) -> &str {
+        &self.sdp
+    }
+
+    fn send(&mut self, payload_type: u8, payload: &[u8]) -> std::io::Result<()> {
+        let mut packet = Vec::with_capacity(12 + payload.len());
+        // Version 2, no padding, extension or CSRCs, no marker
+        packet.push(0x80);
+        packet.push(payload_type);
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&self.timestamp.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        packet.extend_from_slice(payload);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.socket.send_to(&packet, self.destination)?;
+        Ok(())
+    }
+
+    fn packetize(&mut self, pcm: &[Frame]) -> std::io::Result<()> {
+        let packets: Vec<(Vec<u8>, u32)> = match &mut self.packetizer {
+            Packetizer::L16 { pending, frames } => {
+                pending.extend_from_slice(pcm);
+                let samples = *frames * 2;
+                let whole = pending.len() / samples * samples;
+                pending
+                    .drain(..whole)
+                    .collect::<Vec<_>>()
+                    .chunks(samples)
+                    .map(|chunk| {
+                        let payload = chunk.iter().flat_map(|s| s.to_be_bytes()).collect();
+                        (payload, *frames as u32)
+                    })
+                    .collect()
+            }
+            // Opus timestamps count at 48 kHz, the rate it encodes at
+            Packetizer::Opus(encoder) => {
+                let ticks = encoder.frame_samples() as u32;
+                encoder
+                    .encode(pcm)
+                    .map_err(|e| std::io::Error::other(e.to_string()))?
+                    .into_iter()
+                    .map(|packet| (packet, ticks))
+                    .collect()
+            }
+        };
+        let payload_type = match self.packetizer {
+            Packetizer::L16 { .. } => L16_PAYLOAD_TYPE,
+            Packetizer::Opus(_) => OPUS_PAYLOAD_TYPE,
+        };
+        for (payload, ticks) in packets {
+            self.send(payload_type, &payload)?;
+            self.timestamp = self.timestamp.wrapping_add(ticks);
+        }
+        Ok(())
+    }
+}
+
+impl PcmSink for RtpOutput {
+    fn write(&mut self, pcm: &[Frame]) -> Result<(), OutputError> {
+        self.frames += pcm.len() as u64 / 2;
+        match self.packetize(pcm) {
+            Ok(()) => {
+                self.error = None;
+                Ok(())
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                Err(OutputError::Write(e.to_string()))
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn status(&self) -> SinkStatus {
+        SinkStatus {
+            healthy: self.error.is_none(),
+            frames: self.frames,
+            error: self.error.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+// The SDP for a stream, per RFC 4566, with Opus described as in RFC 7587
+fn session_description(
+    id: &str,
+    destination: SocketAddr,
+    origin: IpAddr,
+    payload: RtpPayload,
+    ptime: u32,
+    ttl: u32,
+    ssrc: u32,
+) -> String {
+    let family = |ip: IpAddr| if ip.is_ipv4() { "IP4" } else { "IP6" };
+    // IPv4 multicast connections carry their TTL
+    let connection = match destination.ip() {
+        IpAddr::V4(ip) if ip.is_multicast() => format!("{}/{}", ip, ttl),
+        ip => ip.to_string(),
+    };
+    let (payload_type, attributes) = match payload {
+        RtpPayload::L16 => (L16_PAYLOAD_TYPE, "a=rtpmap:10 L16/44100/2\r\n".to_string()),
+        RtpPayload::Opus => (
+            OPUS_PAYLOAD_TYPE,
+            format!(
+                "a=rtpmap:{0} opus/48000/2\r\na=fmtp:{0} stereo=1; sprop-stereo=1\r\n",
+                OPUS_PAYLOAD_TYPE
+            ),
+        ),
+    };
+    format!(
+        "v=0\r\n\
+         o=- {ssrc} 1 IN {} {origin}\r\n\
+         s={id}\r\n\
+         c=IN {} {connection}\r\n\
+         t=0 0\r\n\
+         m=audio {} RTP/AVP {payload_type}\r\n\
+         {attributes}\
+         a=ptime:{ptime}\r\n\
+         a=sendonly\r\n",
+        family(origin),
+        family(destination.ip()),
+        destination.port(),
+    )
+}
+
+// A stream's identifier, which only has to differ from other senders'
+fn ssrc() -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.subsec_nanos() ^ t.as_secs() as u32)
+        .unwrap_or_default();
+    nanos ^ std::process::id().rotate_left(16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::time::Duration;
+
+    fn config(port: u16, extra: &str) -> Output {
+        Config::check(&format!(
+            r#"
+[[outputs]]
+id = "kitchen"
+kind = "rtp"
+address = "127.0.0.1:{}"
+{}
+"#,
+            port, extra
+        ))
+        .into_result()
+        .unwrap()
+        .outputs
+        .remove(0)
+    }
+
+    fn receiver() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+    }
+
+    #[test]
+    fn test_l16_packets() {
+        let receiver = receiver();
+        let port = receiver.local_addr().unwrap().port();
+        let mut output = RtpOutput::new(&config(port, "")).unwrap();
+
+        // 5 ms is 220 frames; a buffer and a half makes one packet
+        let pcm: Vec<Frame> = (0..330 * 2).map(|i| i as Frame - 300).collect();
+        output.write(&pcm).unwrap();
+        output.write(&pcm).unwrap();
+        assert_eq!(output.status().frames, 660);
+        assert!(output.status().healthy);
+
+        let mut packet = [0u8; 2048];
+        let len = receiver.recv(&mut packet).unwrap();
+        assert_eq!(len, 12 + 220 * 4);
+        assert_eq!(packet[0], 0x80);
+        assert_eq!(packet[1], 10);
+        assert_eq!(&packet[12..16], &[0xFE, 0xD4, 0xFE, 0xD5]);
+        let first = (
+            u16::from_be_bytes([packet[2], packet[3]]),
+            u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+        );
+
+        // Later packets follow on, from the same source
+        let ssrc = packet[8..12].to_vec();
+        for n in 1..3u16 {
+            receiver.recv(&mut packet).unwrap();
+            assert_eq!(&packet[8..12], &ssrc[..]);
+            assert_eq!(
+                u16::from_be_bytes([packet[2], packet[3]]),
+                first.0.wrapping_add(n)
+            );
+            assert_eq!(
+                u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+                first.1.wrapping_add(220 * n as u32)
+            );
+        }
+    }
+
+    #[test]
+    fn test_opus_packets_and_sdp() {
+        let receiver = receiver();
+        let port = receiver.local_addr().unwrap().port();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kitchen.sdp");
+        let extra = format!(
+            "payload = \"opus\"\nbitrate_kbps = 64\nsdp = \"{}\"",
+            path.display()
+        );
+        let mut output = RtpOutput::new(&config(port, &extra)).unwrap();
+
+        let sdp = fs::read_to_string(&path).unwrap();
+        assert_eq!(sdp, output.sdp());
+        assert!(sdp.starts_with("v=0\r\no=- "));
+        assert!(sdp.contains("s=kitchen\r\n"));
+        assert!(sdp.contains("c=IN IP4 127.0.0.1\r\n"));
+        assert!(sdp.contains(&format!("m=audio {} RTP/AVP 96\r\n", port)));
+        assert!(sdp.contains("a=rtpmap:96 opus/48000/2\r\n"));
+        assert!(sdp.contains("a=ptime:20\r\n"));
+
+        output.write(&vec![0; 882 * 4]).unwrap();
+        let mut packet = [0u8; 2048];
+        let len = receiver.recv(&mut packet).unwrap();
+        assert_eq!(len, 12 + 64 * 20 / 8);
+        assert_eq!(packet[1], 96);
+        let first = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        receiver.recv(&mut packet).unwrap();
+        let second = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        assert_eq!(second.wrapping_sub(first), 960);
+    }
+
+    #[test]
+    fn test_multicast_sdp() {
+        let sdp = session_description(
+            "porch",
+            "239.255.77.1:5004".parse().unwrap(),
+            "192.168.1.20".parse().unwrap(),
+            RtpPayload::L16,
+            5,
+            4,
+            42,
+        );
+        assert_eq!(
+            sdp,
+            "v=0\r\no=- 42 1 IN IP4 192.168.1.20\r\ns=porch\r\nc=IN IP4 239.255.77.1/4\r\nt=0 0\r\n\
+             m=audio 5004 RTP/AVP 10\r\na=rtpmap:10 L16/44100/2\r\na=ptime:5\r\na=sendonly\r\n"
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use crate::output::{AudioOutput, OutputError};
+use crate::config::Secrets;
+use crate::output::{AudioOutput, OutputError, TransportState};
 use async_trait::async_trait;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
@@ -32,6 +33,8 @@ pub struct SonosOutput {
     group_coordinator: Option<String>,
     /// Grouped with other rooms
     grouped_with: Vec<String>,
+    /// Interpolated secrets, kept out of what is logged about the room
+    secrets: Secrets,
 }
 
 impl SonosOutput {
@@ -46,9 +49,15 @@ impl SonosOutput {
             healthy: false,
             group_coordinator: None,
             grouped_with: Vec::new(),
+            secrets: Secrets::default(),
         }
     }
 
+    // The room name as logs show it
+    fn label(&self) -> String {
+        self.secrets.redact(&self.room)
+    }
+
     /// Get the room name
     pub fn room(&self) -> &str {
         &self.room
@@ -74,10 +83,20 @@ impl SonosOutput {
         &self.grouped_with
     }
 
+    /// What the speaker is doing: playing our stream once it has been set
+    /// on a reachable device
+    pub fn transport_state(&self) -> TransportState {
+        match (&self.stream_url, self.healthy) {
+            (None, _) => TransportState::NoMediaPresent,
+            (Some(_), true) => TransportState::Playing,
+            (Some(_), false) => TransportState::Stopped,
+        }
+    }
+
     /// Discover the Sonos device by room name
     async fn discover_device(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
         // This is a placeholder for the actual discovery logic using sonor
-        info!("Discovering Sonos device for room: {}", self.room);
+        info!("Discovering Sonos device for room: {}", self.label());
 
         // Simulate discovery - in real implementation, we would:
         // 1. Use SSDP to find all Sonos devices
@@ -86,7 +105,7 @@ impl SonosOutput {
 
         // For now, we'll just pretend we found it at a fake IP
         let ip = format!("192.168.1.{}", 100 + self.room.len() % 100);
-        info!("Found device for room '{}' at IP: {}", self.room, ip);
+        info!("Found device for room '{}' at IP: {}", self.label(), ip);
         self.ip_address = Some(ip);
         self.healthy = true;
 
@@ -96,13 +115,14 @@ impl SonosOutput {
     /// Set up device grouping
     async fn setup_group(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
         if self.grouped_with.is_empty() {
-            debug!("No grouping configured for room '{}'", self.room);
+            debug!("No grouping configured for room '{}'", self.label());
             return Ok(());
         }
 
         info!(
             "Setting up group for '{}' with rooms: {:?}",
-            self.room, self.grouped_with
+            self.label(),
+            self.grouped_with
         );
 
         // In a real implementation, we would use sonor to:
@@ -123,7 +143,7 @@ impl AudioOutput for SonosOutput {
         // Set up grouping if configured
         self.setup_group().await?;
 
-        info!("Sonos output initialized for room: {}", self.room);
+        info!("Sonos output initialized for room: {}", self.label());
         self.last_connection = Some(Instant::now());
         self.healthy = true;
 
@@ -132,10 +152,10 @@ impl AudioOutput for SonosOutput {
 
     async fn set_stream(&mut self, url: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
         if self.ip_address.is_none() {
-            return Err(Box::new(OutputError::DeviceNotFound(self.room.clone())));
+            return Err(Box::new(OutputError::DeviceNotFound(self.label())));
         }
 
-        info!("Setting stream URL for room '{}' to: {}", self.room, url);
+        info!("Setting stream URL for room '{}' to: {}", self.label(), url);
 
         // In a real implementation, we would:
         // 1. Use SOAP/UPnP to call SetAVTransportURI on the device
@@ -152,7 +172,7 @@ impl AudioOutput for SonosOutput {
     async fn keep_alive(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
         // If we've never connected or don't have an IP, try discovery
         if self.ip_address.is_none() {
-            warn!("No IP address for room '{}', rediscovering", self.room);
+            warn!("No IP address for room '{}', rediscovering", self.label());
             self.discover_device().await?;
         }
 
@@ -160,7 +180,7 @@ impl AudioOutput for SonosOutput {
         if let Some(last) = self.last_connection {
             if last.elapsed() > Duration::from_secs(60) {
                 if let Some(url) = &self.stream_url.clone() {
-                    info!("Reconnecting stream for room '{}'", self.room);
+                    info!("Reconnecting stream for room '{}'", self.label());
                     self.set_stream(url).await?;
                 }
             }
@@ -169,7 +189,7 @@ impl AudioOutput for SonosOutput {
         // Verify grouping is still correct
         self.setup_group().await?;
 
-        debug!("Keep-alive check completed for room '{}'", self.room);
+        debug!("Keep-alive check completed for room '{}'", self.label());
         self.healthy = true;
 
         Ok(())
@@ -186,6 +206,7 @@ pub struct SonosHealth {
     pub room: String,
     pub ip_address: Option<String>,
     pub healthy: bool,
+    pub transport: TransportState,
     pub last_connection: Option<u64>, // timestamp
     pub grouped_with: Vec<String>,
 }
@@ -194,6 +215,7 @@ pub struct SonosHealth {
 #[derive(Debug, Default)]
 pub struct SonosManager {
     rooms: HashMap<String, Arc<Mutex<SonosOutput>>>,
+    secrets: Secrets,
 }
 
 impl SonosManager {
@@ -202,9 +224,18 @@ impl SonosManager {
         Self::default()
     }
 
+    /// A manager that redacts `secrets` from the room names it logs
+    pub fn with_secrets(secrets: Secrets) -> Self {
+        Self {
+            secrets,
+            ..Self::default()
+        }
+    }
+
     /// Add a room to manage
     pub fn add_room(&mut self, room: String, buffer_sec: Option<u32>) {
-        let output = SonosOutput::new(room.clone(), buffer_sec);
+        let mut output = SonosOutput::new(room.clone(), buffer_sec);
+        output.secrets = self.secrets.clone();
         self.rooms.insert(room, Arc::new(Mutex::new(output)));
     }
 
@@ -213,9 +244,13 @@ impl SonosManager {
         for (room, output) in &self.rooms {
             let mut output = output.lock().await;
             match output.initialize().await {
-                Ok(_) => info!("Initialized room: {}", room),
+                Ok(_) => info!("Initialized room: {}", self.secrets.redact(room)),
                 Err(e) => {
-                    error!("Failed to initialize room {}: {}", room, e);
+                    error!(
+                        "Failed to initialize room {}: {}",
+                        self.secrets.redact(room),
+                        e
+                    );
                     // Continue with other rooms even if one fails
                 }
             }
@@ -226,6 +261,7 @@ impl SonosManager {
     /// Start the keep-alive task
     pub fn start_keep_alive_task(&self) -> mpsc::Sender<()> {
         let rooms = self.rooms.clone();
+        let secrets = self.secrets.clone();
         let (tx, mut rx) = mpsc::channel::<()>(1);
 
         tokio::spawn(async move {
@@ -236,7 +272,7 @@ impl SonosManager {
                     _ = interval.tick() => {
                         debug!("Running keep-alive checks for all rooms");
                         for (room_name, room_arc) in &rooms {
-                            let room_name = room_name.clone();
+                            let room_name = secrets.redact(room_name);
                             let room_arc = room_arc.clone();
 
                             // Process each room in its own task to avoid holding locks across awaits
@@ -271,6 +307,7 @@ impl SonosManager {
                 room: room.clone(),
                 ip_address: output.ip_address.clone(),
                 healthy: output.healthy,
+                transport: output.transport_state(),
                 last_connection: output.last_connection.map(|t| t.elapsed().as_secs()),
                 grouped_with: output.grouped_with.clone(),
             });
@@ -288,7 +325,7 @@ impl SonosManager {
         let output = self
             .rooms
             .get(room)
-            .ok_or_else(|| OutputError::DeviceNotFound(room.to_string()))?;
+            .ok_or_else(|| OutputError::DeviceNotFound(self.secrets.redact(room)))?;
 
         let mut output = output.lock().await;
         output.set_stream(url).await
@@ -305,6 +342,24 @@ mod tests {
         assert_eq!(output.buffer_sec(), 5);
         assert_eq!(output.room(), "Living Room");
         assert!(output.ip_address().is_none());
+        assert_eq!(output.transport_state(), TransportState::NoMediaPresent);
+    }
+
+    #[tokio::test]
+    async fn test_room_names_redacted() {
+        let mut secrets = Secrets::default();
+        secrets.add("Hunter's Den");
+        let mut manager = SonosManager::with_secrets(secrets);
+        manager.add_room("Hunter's Den".to_string(), None);
+
+        let output = manager.rooms["Hunter's Den"].clone();
+        assert_eq!(output.lock().await.room(), "Hunter's Den");
+        assert_eq!(output.lock().await.label(), "********");
+        let error = manager
+            .set_stream("Hunter's Den 2", "http://mux/stream.mp3")
+            .await
+            .unwrap_err();
+        assert!(!error.to_string().contains("Hunter"));
     }
 
     #[tokio::test]
@@ -0,0 +1,832 @@
+use crate::config::{Output, OutputKind};
+use crate::output::{AudioOutput, OutputError, TransportState};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time;
+
+/// Device type every renderer we can play to advertises
+pub const MEDIA_RENDERER: &str = "urn:schemas-upnp-org:device:MediaRenderer:1";
+
+const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+
+/// Where SSDP searches are sent
+pub const SSDP_ADDR: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900));
+
+// How long renderers get to answer a search, and requests to complete
+const SEARCH_WAIT: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// How often renderers are checked on
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How a renderer is picked out on the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Friendly name, compared ignoring case
+    Name(String),
+    /// Unique device name, with or without its `uuid:` prefix
+    Udn(String),
+}
+
+impl Target {
+    /// The target an `upnp` output names
+    pub fn from_output(output: &Output) -> Option<Self> {
+        match &output.kind {
+            OutputKind::Upnp { udn: Some(udn), .. } => Some(Target::Udn(udn.clone())),
+            OutputKind::Upnp {
+                name: Some(name), ..
+            } => Some(Target::Name(name.clone())),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, renderer: &Renderer) -> bool {
+        match self {
+            Target::Name(name) => renderer.name.eq_ignore_ascii_case(name),
+            Target::Udn(udn) => {
+                let bare = |udn: &str| udn.trim_start_matches("uuid:").to_ascii_lowercase();
+                bare(&renderer.udn) == bare(udn)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Name(name) => write!(f, "'{}'", name),
+            Target::Udn(udn) => write!(f, "{}", udn),
+        }
+    }
+}
+
+/// A MediaRenderer, as its device description describes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Renderer {
+    pub name: String,
+    pub udn: String,
+    /// URL of the device description
+    pub location: String,
+    // Absolute URL of the AVTransport control endpoint
+    control_url: String,
+}
+
+impl Renderer {
+    /// Fetch and parse the device description at `location`
+    pub async fn describe(location: &str) -> Result<Self, OutputError> {
+        let (status, body) = request("GET", location, &[], "")
+            .await
+            .map_err(|e| OutputError::Discovery(format!("{}: {}", location, e)))?;
+        if status != 200 {
+            return Err(OutputError::Discovery(format!(
+                "{}: HTTP status {}",
+                location, status
+            )));
+        }
+        parse_description(location, &body)
+    }
+
+    /// Point the renderer at `url`, which serves `content_type`
+    pub async fn set_uri(&self, url: &str, content_type: &str) -> Result<(), OutputError> {
+        let metadata = format!(
+            concat!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" "#,
+                r#"xmlns:dc="http://purl.org/dc/elements/1.1/" "#,
+                r#"xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">"#,
+                r#"<item id="0" parentID="-1" restricted="1"><dc:title>sonos-mux</dc:title>"#,
+                r#"<upnp:class>object.item.audioItem.audioBroadcast</upnp:class>"#,
+                r#"<res protocolInfo="http-get:*:{}:*">{}</res></item></DIDL-Lite>"#
+            ),
+            content_type,
+            escape(url)
+        );
+        self.call(
+            "SetAVTransportURI",
+            &format!(
+                "<CurrentURI>{}</CurrentURI><CurrentURIMetaData>{}</CurrentURIMetaData>",
+                escape(url),
+                escape(&metadata)
+            ),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn play(&self) -> Result<(), OutputError> {
+        self.call("Play", "<Speed>1</Speed>").await.map(|_| ())
+    }
+
+    /// What the renderer is doing now
+    pub async fn transport_state(&self) -> Result<TransportState, OutputError> {
+        let response = self.call("GetTransportInfo", "").await?;
+        let state = find_text(&response, "CurrentTransportState").unwrap_or_default();
+        TransportState::parse(&state).ok_or_else(|| {
+            OutputError::Connection(format!(
+                "{} reported unknown transport state '{}'",
+                self.name, state
+            ))
+        })
+    }
+
+    // Invoke an AVTransport action on instance 0, returning the response
+    async fn call(&self, action: &str, arguments: &str) -> Result<String, OutputError> {
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" "#,
+                r#"s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body>"#,
+                r#"<u:{action} xmlns:u="{service}"><InstanceID>0</InstanceID>{arguments}</u:{action}>"#,
+                r#"</s:Body></s:Envelope>"#
+            ),
+            action = action,
+            service = AV_TRANSPORT,
+            arguments = arguments
+        );
+        let soap_action = format!("\"{}#{}\"", AV_TRANSPORT, action);
+        let headers = [
+            ("Content-Type", "text/xml; charset=\"utf-8\""),
+            ("SOAPACTION", soap_action.as_str()),
+        ];
+        let (status, response) = request("POST", &self.control_url, &headers, &body)
+            .await
+            .map_err(|e| OutputError::Connection(format!("{}: {}", self.name, e)))?;
+        if status != 200 {
+            // Faults name the UPnP error, when the device says
+            let reason = find_text(&response, "errorDescription")
+                .or_else(|| find_text(&response, "errorCode"))
+                .unwrap_or_else(|| format!("HTTP status {}", status));
+            return Err(OutputError::Connection(format!(
+                "{} on {} failed: {}",
+                action, self.name, reason
+            )));
+        }
+        Ok(response)
+    }
+}
+
+/// Search for MediaRenderers by sending an SSDP search to `ssdp`, returning
+/// the description URL of every one that answers within `wait`
+pub async fn search(ssdp: SocketAddr, wait: Duration) -> Result<Vec<String>, OutputError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .map_err(|e| OutputError::Discovery(e.to_string()))?;
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: {}\r\nST: {}\r\n\r\n",
+        ssdp,
+        wait.as_secs().max(1),
+        MEDIA_RENDERER
+    );
+    socket
+        .send_to(search.as_bytes(), ssdp)
+        .await
+        .map_err(|e| OutputError::Discovery(format!("SSDP search failed: {}", e)))?;
+
+    let mut locations = Vec::new();
+    let deadline = time::Instant::now() + wait;
+    let mut buffer = [0u8; 2048];
+    while let Ok(Ok((n, from))) = time::timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+        let response = String::from_utf8_lossy(&buffer[..n]);
+        match header(&response, "location") {
+            Some(location) if !locations.iter().any(|l| l == location) => {
+                debug!("SSDP: renderer at {} answered from {}", location, from);
+                locations.push(location.to_string());
+            }
+            _ => {}
+        }
+    }
+    Ok(locations)
+}
+
+/// Find the renderer `target` names among those answering a search
+pub async fn find(
+    target: &Target,
+    ssdp: SocketAddr,
+    wait: Duration,
+) -> Result<Renderer, OutputError> {
+    let locations = search(ssdp, wait).await?;
+    for location in &locations {
+        match Renderer::describe(location).await {
+            Ok(renderer) if target.matches(&renderer) => return Ok(renderer),
+            Ok(renderer) => debug!("Skipping renderer '{}' at {}", renderer.name, location),
+            Err(e) => debug!("Skipping renderer at {}: {}", location, e),
+        }
+    }
+    Err(OutputError::DeviceNotFound(format!(
+        "No MediaRenderer {} among {} found",
+        target,
+        locations.len()
+    )))
+}
+
+/// A UPnP/DLNA MediaRenderer playing our stream
+#[derive(Debug)]
+pub struct UpnpOutput {
+    id: String,
+    target: Target,
+    ssdp: SocketAddr,
+    search_wait: Duration,
+    renderer: Option<Renderer>,
+    stream_url: Option<String>,
+    transport: TransportState,
+    last_connection: Option<Instant>,
+    healthy: bool,
+}
+
+impl UpnpOutput {
+    /// Create an output for the renderer `target` names
+    pub fn new(id: String, target: Target) -> Self {
+        Self {
+            id,
+            target,
+            ssdp: SSDP_ADDR,
+            search_wait: SEARCH_WAIT,
+            renderer: None,
+            stream_url: None,
+            transport: TransportState::NoMediaPresent,
+            last_connection: None,
+            healthy: false,
+        }
+    }
+
+    /// Search by sending to `ssdp` rather than the SSDP multicast group
+    pub fn with_search(mut self, ssdp: SocketAddr, wait: Duration) -> Self {
+        self.ssdp = ssdp;
+        self.search_wait = wait;
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The renderer, once found
+    pub fn renderer(&self) -> Option<&Renderer> {
+        self.renderer.as_ref()
+    }
+
+    pub fn transport_state(&self) -> TransportState {
+        self.transport
+    }
+
+    // The renderer, found again if it was lost
+    async fn connect(&mut self) -> Result<Renderer, OutputError> {
+        if let Some(renderer) = &self.renderer {
+            return Ok(renderer.clone());
+        }
+        info!("Searching for UPnP renderer {}", self.target);
+        let renderer = find(&self.target, self.ssdp, self.search_wait).await?;
+        info!(
+            "Found renderer {} ('{}') at {}",
+            renderer.udn, renderer.name, renderer.location
+        );
+        self.renderer = Some(renderer.clone());
+        Ok(renderer)
+    }
+
+    // Give up on the renderer after a failure, so the next attempt finds it
+    // again, e.g. at a new address
+    fn lost<E>(&mut self, e: E) -> E {
+        self.renderer = None;
+        self.healthy = false;
+        e
+    }
+
+    async fn push_stream(&mut self, url: &str) -> Result<(), OutputError> {
+        let renderer = self.connect().await?;
+        let pushed = async {
+            renderer.set_uri(url, "audio/mpeg").await?;
+            renderer.play().await
+        };
+        pushed.await.map_err(|e| self.lost(e))?;
+        self.transport = TransportState::Playing;
+        self.last_connection = Some(Instant::now());
+        self.healthy = true;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AudioOutput for UpnpOutput {
+    async fn initialize(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.connect().await.map_err(|e| self.lost(e))?;
+        self.last_connection = Some(Instant::now());
+        self.healthy = true;
+        Ok(())
+    }
+
+    async fn set_stream(&mut self, url: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        info!("Setting stream for output '{}' to: {}", self.id, url);
+        self.stream_url = Some(url.to_string());
+        Ok(self.push_stream(url).await?)
+    }
+
+    async fn keep_alive(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let renderer = self.connect().await.map_err(|e| self.lost(e))?;
+        let state = renderer.transport_state().await.map_err(|e| self.lost(e))?;
+        self.transport = state;
+        self.last_connection = Some(Instant::now());
+        self.healthy = true;
+
+        // A renderer that dropped the stream gets it back; one paused from
+        // its own remote is left alone
+        if let Some(url) = self.stream_url.clone() {
+            if matches!(
+                state,
+                TransportState::Stopped | TransportState::NoMediaPresent
+            ) {
+                info!(
+                    "Renderer for output '{}' is {}, restarting the stream",
+                    self.id,
+                    state.as_str()
+                );
+                self.push_stream(&url).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn health_check(&self) -> bool {
+        self.healthy
+    }
+}
+
+/// Health status for a UPnP output
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpnpHealth {
+    pub output: String,
+    /// Friendly name of the renderer, once found
+    pub name: Option<String>,
+    pub udn: Option<String>,
+    pub location: Option<String>,
+    pub healthy: bool,
+    pub transport: TransportState,
+    pub last_connection: Option<u64>, // seconds ago
+}
+
+/// Keeps every `upnp` output found and playing, like `SonosManager` does
+/// for Sonos rooms
+#[derive(Debug, Default)]
+pub struct UpnpManager {
+    outputs: HashMap<String, Arc<Mutex<UpnpOutput>>>,
+}
+
+impl UpnpManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_output(&mut self, output: UpnpOutput) {
+        self.outputs
+            .insert(output.id.clone(), Arc::new(Mutex::new(output)));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Find every renderer and point it at `url`; one that fails is retried
+    /// by the keep-alive task
+    pub async fn start_all(&self, url: &str) {
+        for (id, output) in &self.outputs {
+            let mut output = output.lock().await;
+            if let Err(e) = output.set_stream(url).await {
+                error!("Failed to start output '{}': {}", id, e);
+            }
+        }
+    }
+
+    /// Check on every renderer until the sender is dropped or sent to
+    pub fn start_keep_alive_task(&self) -> mpsc::Sender<()> {
+        let outputs = self.outputs.clone();
+        let (tx, mut rx) = mpsc::channel::<()>(1);
+
+        tokio::spawn(async move {
+            let mut interval = time::interval(KEEP_ALIVE_INTERVAL);
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        for (id, output) in &outputs {
+                            let id = id.clone();
+                            let output = output.clone();
+                            tokio::spawn(async move {
+                                if let Err(e) = output.lock().await.keep_alive().await {
+                                    warn!("Keep-alive failed for output '{}': {}", id, e);
+                                }
+                            });
+                        }
+                    }
+                    _ = rx.recv() => break,
+                }
+            }
+        });
+
+        tx
+    }
+
+    /// Health of every output
+    pub async fn health_status(&self) -> Vec<UpnpHealth> {
+        let mut status = Vec::new();
+        for (id, output) in &self.outputs {
+            let output = output.lock().await;
+            status.push(UpnpHealth {
+                output: id.clone(),
+                name: output.renderer.as_ref().map(|r| r.name.clone()),
+                udn: output.renderer.as_ref().map(|r| r.udn.clone()),
+                location: output.renderer.as_ref().map(|r| r.location.clone()),
+                healthy: output.healthy,
+                transport: output.transport,
+                last_connection: output.last_connection.map(|t| t.elapsed().as_secs()),
+            });
+        }
+        status
+    }
+}
+
+// Find the MediaRenderer in a device description, which may be the root
+// device or one embedded in it
+fn parse_description(location: &str, xml: &str) -> Result<Renderer, OutputError> {
+    let invalid = |reason: &str| OutputError::Discovery(format!("{}: {}", location, reason));
+    let document = roxmltree::Document::parse(xml).map_err(|e| invalid(&e.to_string()))?;
+    let child_text = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|n| n.tag_name().name() == name)
+            .and_then(|n| n.text())
+            .map(|text| text.trim().to_string())
+    };
+
+    let device = document
+        .descendants()
+        .filter(|n| n.tag_name().name() == "device")
+        .find(|n| {
+            child_text(*n, "deviceType")
+                .is_some_and(|t| t.starts_with("urn:schemas-upnp-org:device:MediaRenderer:"))
+        })
+        .ok_or_else(|| invalid("not a MediaRenderer"))?;
+    let control_url = device
+        .children()
+        .filter(|n| n.tag_name().name() == "serviceList")
+        .flat_map(|n| n.children())
+        .find(|n| {
+            child_text(*n, "serviceType")
+                .is_some_and(|t| t.starts_with("urn:schemas-upnp-org:service:AVTransport:"))
+        })
+        .and_then(|n| child_text(n, "controlURL"))
+        .ok_or_else(|| invalid("no AVTransport service"))?;
+    let base = document
+        .root_element()
+        .children()
+        .find(|n| n.tag_name().name() == "URLBase")
+        .and_then(|n| n.text())
+        .map(str::trim)
+        .unwrap_or(location);
+
+    Ok(Renderer {
+        name: child_text(device, "friendlyName").unwrap_or_default(),
+        udn: child_text(device, "UDN").ok_or_else(|| invalid("no UDN"))?,
+        location: location.to_string(),
+        control_url: resolve(base, &control_url),
+    })
+}
+
+// Resolve a URL from a description against its base
+fn resolve(base: &str, url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return url.to_string();
+    }
+    let scheme_end = base.find("://").map_or(0, |i| i + 3);
+    let origin_end = base[scheme_end..]
+        .find('/')
+        .map_or(base.len(), |i| scheme_end + i);
+    if url.starts_with('/') {
+        format!("{}{}", &base[..origin_end], url)
+    } else {
+        let directory_end = base[origin_end..]
+            .rfind('/')
+            .map_or(base.len(), |i| origin_end + i);
+        format!("{}/{}", &base[..directory_end], url)
+    }
+}
+
+// Text of the first element named `name`, in any namespace
+fn find_text(xml: &str, name: &str) -> Option<String> {
+    let document = roxmltree::Document::parse(xml).ok()?;
+    let node = document
+        .descendants()
+        .find(|n| n.tag_name().name() == name)?;
+    Some(node.text().unwrap_or_default().trim().to_string())
+}
+
+// Value of a header in an HTTP-style message, matched ignoring case
+fn header<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    message.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// One HTTP/1.1 request over a fresh connection, returning the status and body
+async fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> std::io::Result<(u16, String)> {
+    let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, reason);
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid("only http:// URLs are supported"))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let exchange = async {
+        let mut stream = TcpStream::connect(&address).await?;
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            host,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = time::timeout(REQUEST_TIMEOUT, exchange)
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "request timed out"))??;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| invalid("malformed response"))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid("malformed status line"))?;
+    let body = match header(head, "transfer-encoding") {
+        Some(encoding) if encoding.eq_ignore_ascii_case("chunked") => dechunk(body),
+        _ => body.to_string(),
+    };
+    Ok((status, body))
+}
+
+fn dechunk(mut body: &str) -> String {
+    let mut decoded = String::new();
+    while let Some((size, rest)) = body.split_once("\r\n") {
+        let size =
+            usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16).unwrap_or(0);
+        if size == 0 || rest.len() < size {
+            break;
+        }
+        decoded.push_str(&rest[..size]);
+        body = rest[size..].trim_start_matches("\r\n");
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:MediaServer:1</deviceType>
+    <friendlyName>Not this one</friendlyName>
+    <UDN>uuid:server</UDN>
+    <deviceList>
+      <device>
+        <deviceType>urn:schemas-upnp-org:device:MediaRenderer:1</deviceType>
+        <friendlyName>Denon AVR</friendlyName>
+        <UDN>uuid:5f9ec1b3-ed59-79bb-4530-745e1c44e2b6</UDN>
+        <serviceList>
+          <service>
+            <serviceType>urn:schemas-upnp-org:service:RenderingControl:1</serviceType>
+            <controlURL>/RenderingControl/ctrl</controlURL>
+          </service>
+          <service>
+            <serviceType>urn:schemas-upnp-org:service:AVTransport:1</serviceType>
+            <controlURL>AVTransport/ctrl</controlURL>
+          </service>
+        </serviceList>
+      </device>
+    </deviceList>
+  </device>
+</root>"#;
+
+    // A renderer answering searches, serving its description and recording
+    // the actions called on it
+    struct FakeRenderer {
+        ssdp: SocketAddr,
+        actions: Arc<std::sync::Mutex<Vec<(String, String)>>>,
+        state: Arc<std::sync::Mutex<&'static str>>,
+    }
+
+    impl FakeRenderer {
+        async fn start() -> Self {
+            let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let location = format!("http://{}/upnp/description.xml", http.local_addr().unwrap());
+            let actions = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let state = Arc::new(std::sync::Mutex::new("STOPPED"));
+
+            let ssdp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let ssdp_addr = ssdp.local_addr().unwrap();
+            tokio::spawn(async move {
+                let mut buffer = [0u8; 2048];
+                while let Ok((n, from)) = ssdp.recv_from(&mut buffer).await {
+                    let search = String::from_utf8_lossy(&buffer[..n]).to_string();
+                    assert_eq!(header(&search, "st"), Some(MEDIA_RENDERER));
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\nLocation: {}\r\nST: {}\r\n\r\n",
+                        location, MEDIA_RENDERER
+                    );
+                    ssdp.send_to(response.as_bytes(), from).await.unwrap();
+                }
+            });
+
+            let (recorded, current) = (actions.clone(), state.clone());
+            tokio::spawn(async move {
+                while let Ok((mut socket, _)) = http.accept().await {
+                    let (recorded, current) = (recorded.clone(), current.clone());
+                    tokio::spawn(async move {
+                        let mut request = Vec::new();
+                        let mut buffer = [0u8; 4096];
+                        // Read the head, then as much body as it announces
+                        loop {
+                            let n = socket.read(&mut buffer).await.unwrap();
+                            request.extend_from_slice(&buffer[..n]);
+                            let text = String::from_utf8_lossy(&request).to_string();
+                            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                                let length: usize = header(head, "content-length")
+                                    .map_or(0, |l| l.parse().unwrap());
+                                if body.len() >= length {
+                                    break;
+                                }
+                            }
+                        }
+                        let text = String::from_utf8_lossy(&request).to_string();
+                        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+
+                        let (status, response) = if head.starts_with("GET /upnp/description.xml ") {
+                            ("200 OK", DESCRIPTION.to_string())
+                        } else if head.starts_with("POST /upnp/AVTransport/ctrl ") {
+                            let action = header(head, "soapaction").unwrap();
+                            let action = action.trim_matches('"').rsplit('#').next().unwrap();
+                            recorded
+                                .lock()
+                                .unwrap()
+                                .push((action.to_string(), body.to_string()));
+                            if action == "Play" {
+                                *current.lock().unwrap() = "PLAYING";
+                            }
+                            let state = *current.lock().unwrap();
+                            (
+                                "200 OK",
+                                format!(
+                                    concat!(
+                                        r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>"#,
+                                        r#"<u:{}Response xmlns:u="{}"><CurrentTransportState>{}</CurrentTransportState>"#,
+                                        r#"</u:{}Response></s:Body></s:Envelope>"#
+                                    ),
+                                    action, AV_TRANSPORT, state, action
+                                ),
+                            )
+                        } else {
+                            ("404 Not Found", String::new())
+                        };
+                        let response = format!(
+                            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            status,
+                            response.len(),
+                            response
+                        );
+                        socket.write_all(response.as_bytes()).await.unwrap();
+                    });
+                }
+            });
+
+            Self {
+                ssdp: ssdp_addr,
+                actions,
+                state,
+            }
+        }
+
+        fn output(&self, target: Target) -> UpnpOutput {
+            UpnpOutput::new("avr".to_string(), target)
+                .with_search(self.ssdp, Duration::from_millis(200))
+        }
+
+        fn actions(&self) -> Vec<String> {
+            self.actions
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(action, _)| action.clone())
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_finds_and_plays() {
+        let fake = FakeRenderer::start().await;
+
+        let mut output = fake.output(Target::Name("denon avr".to_string()));
+        output.initialize().await.unwrap();
+        let renderer = output.renderer().unwrap();
+        assert_eq!(renderer.udn, "uuid:5f9ec1b3-ed59-79bb-4530-745e1c44e2b6");
+        assert!(renderer.control_url.ends_with("/upnp/AVTransport/ctrl"));
+
+        output
+            .set_stream("http://mux:8000/stream.mp3?a=1&b=2")
+            .await
+            .unwrap();
+        assert_eq!(fake.actions(), vec!["SetAVTransportURI", "Play"]);
+        let (_, body) = fake.actions.lock().unwrap()[0].clone();
+        assert!(body.contains("<CurrentURI>http://mux:8000/stream.mp3?a=1&amp;b=2</CurrentURI>"));
+        assert!(body.contains("&lt;DIDL-Lite"));
+        assert_eq!(output.transport_state(), TransportState::Playing);
+
+        // A renderer that stopped gets the stream again
+        output.keep_alive().await.unwrap();
+        assert_eq!(fake.actions().len(), 3);
+        *fake.state.lock().unwrap() = "STOPPED";
+        output.keep_alive().await.unwrap();
+        assert_eq!(
+            fake.actions()[3..],
+            ["GetTransportInfo", "SetAVTransportURI", "Play"]
+        );
+        assert!(output.health_check().await);
+    }
+
+    #[tokio::test]
+    async fn test_targets() {
+        let fake = FakeRenderer::start().await;
+
+        let mut output = fake.output(Target::Udn(
+            "5F9EC1B3-ED59-79BB-4530-745E1C44E2B6".to_string(),
+        ));
+        output.initialize().await.unwrap();
+        assert_eq!(output.renderer().unwrap().name, "Denon AVR");
+
+        let mut output = fake.output(Target::Name("WiiM".to_string()));
+        let error = output.initialize().await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("No MediaRenderer 'WiiM' among 1"));
+        assert!(!output.health_check().await);
+    }
+
+    #[test]
+    fn test_helpers() {
+        let base = "http://10.0.0.5:49152/upnp/description.xml";
+        assert_eq!(resolve(base, "/ctrl"), "http://10.0.0.5:49152/ctrl");
+        assert_eq!(resolve(base, "ctrl"), "http://10.0.0.5:49152/upnp/ctrl");
+        assert_eq!(
+            resolve("http://10.0.0.5:49152", "ctrl"),
+            "http://10.0.0.5:49152/ctrl"
+        );
+        assert_eq!(resolve(base, "http://other/ctrl"), "http://other/ctrl");
+        assert_eq!(dechunk("4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n"), "Wikipedia");
+        assert_eq!(
+            parse_description(base, "<root/>").unwrap_err().to_string(),
+            format!("Device discovery error: {}: not a MediaRenderer", base)
+        );
+    }
+}
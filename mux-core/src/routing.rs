@@ -1,22 +1,120 @@
-use crate::config::{Config, Route};
-use crate::input::{create_input, AudioInput};
-use crate::mixer::{Mixer, Source};
+use crate::config::{Config, Input, InputKind, Output, OutputKind, Route, GAIN_DB_RANGE};
+use crate::input::{create_input, AudioBuffer, AudioInput};
+use crate::mixer::{Level, Mixer, Source, SourceStatus};
+use crate::output::TransportState;
+use crate::output::{create_sink, OutputError, PcmSink};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
 pub struct Router {
     // Maps output ID to its mixer
     pub output_mixers: HashMap<String, Mixer>,
+    // The configured inputs and outputs, in config order, for status
+    inputs: Vec<Input>,
+    outputs: Vec<Output>,
+    // Why inputs that could not be created are left out, by input ID
+    failed: HashMap<String, String>,
+    // Outputs that take their mix as PCM, and why others that should could
+    // not be created, by output ID
+    sinks: HashMap<String, Box<dyn PcmSink>>,
+    failed_sinks: HashMap<String, String>,
+}
+
+/// An input, as seen across every output it is mixed into
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputStatus {
+    pub id: String,
+    pub kind: String,
+    /// Whether the input is running for at least one output
+    pub running: bool,
+    /// Buffers waiting to be mixed, on the output furthest behind
+    pub buffered: usize,
+    /// Loudest level across the outputs, before gain
+    pub level: Level,
+    pub outputs: Vec<String>,
+    /// Why the input could not be created, if it couldn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An output and the mix sent to it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputStatus {
+    pub id: String,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    pub inputs: Vec<String>,
+    pub level: Level,
+    /// Whether the device is reachable, where the daemon knows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthy: Option<bool>,
+    /// What the speaker is doing, for Sonos rooms and UPnP renderers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<TransportState>,
+    /// Listeners connected to the output's stream, for outputs that serve one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clients: Option<usize>,
+    /// The file being written, for outputs that record
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Stereo frames taken, for outputs the daemon writes to itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frames: Option<u64>,
+    /// Times the mix arrived too late, for outputs that play in real time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub underruns: Option<u64>,
+    /// Smoothed variation in when the mix arrives, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter_ms: Option<f64>,
+    /// Why the output could not be created or last failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One input mixed into one output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteStatus {
+    pub output: String,
+    #[serde(flatten)]
+    pub source: SourceStatus,
+}
+
+/// The level of an output's mix and of each source in it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputLevels {
+    pub output: String,
+    pub level: Level,
+    pub sources: Vec<SourceStatus>,
 }
 
 impl Router {
     pub fn new(config: &Config) -> Result<Self, crate::input::InputError> {
+        let mut router = Self::without_sinks(config)?;
+        router.open_sinks();
+        Ok(router)
+    }
+
+    /// The inputs and mixers for `config`, with no outputs opened yet, so
+    /// a reload can check the inputs before touching the running outputs
+    pub fn without_sinks(config: &Config) -> Result<Self, crate::input::InputError> {
         let mut output_mixers = HashMap::new();
 
-        // First, create all inputs
+        // First, create all inputs; one that fails is left out of the mix
+        // rather than stopping the others
         let mut inputs: HashMap<String, Box<dyn AudioInput>> = HashMap::new();
+        let mut failed = HashMap::new();
         for input_config in &config.inputs {
-            let input = create_input(input_config)?;
-            inputs.insert(input_config.id.clone(), input);
+            match create_input(input_config) {
+                Ok(input) => {
+                    inputs.insert(input_config.id.clone(), input);
+                }
+                Err(e) => {
+                    warn!("Leaving out input '{}': {}", input_config.id, e);
+                    failed.insert(input_config.id.clone(), e.to_string());
+                }
+            }
         }
 
         // Find all outputs used in routes
@@ -52,16 +150,27 @@ impl Router {
                 let input_id = &route.input;
                 let input = match inputs.get(input_id) {
                     Some(input) => input.clone(),
-                    None => continue, // Should not happen if config is validated
+                    None => continue, // Failed to create
                 };
 
                 // Create the source with the route parameters
-                let source = Source::new(
+                let mut source = Source::new(
                     route.gain_db,
-                    route.duck_db > 0.0, // If duck_db is set, this is a priority source
-                    route.duck_db.abs(), // Use absolute value for ducking amount
+                    route.effective_priority(),
+                    route.duck_db, // Either sign ducks by the same amount
                     input,
                 );
+                source.input_id = input_id.clone();
+                if let Some(input) = config.inputs.iter().find(|input| &input.id == input_id) {
+                    source.title = input.title.clone();
+                    source.on_demand = matches!(
+                        input.kind,
+                        InputKind::File {
+                            on_demand: true,
+                            ..
+                        }
+                    );
+                }
 
                 sources.push(source);
             }
@@ -71,7 +180,123 @@ impl Router {
             output_mixers.insert(output.id.clone(), mixer);
         }
 
-        Ok(Self { output_mixers })
+        Ok(Self {
+            output_mixers,
+            inputs: config.inputs.clone(),
+            outputs: config.outputs.clone(),
+            failed,
+            sinks: HashMap::new(),
+            failed_sinks: HashMap::new(),
+        })
+    }
+
+    /// Open the outputs that take PCM and have no sink yet; one that fails
+    /// is left out like a failed input
+    pub fn open_sinks(&mut self) {
+        for output in &self.outputs {
+            if self.sinks.contains_key(&output.id) {
+                continue;
+            }
+            match create_sink(output) {
+                Ok(Some(sink)) => {
+                    self.sinks.insert(output.id.clone(), sink);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Leaving out output '{}': {}", output.id, e);
+                    self.failed_sinks.insert(output.id.clone(), e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Take over the sinks of `old`'s outputs that are configured the same
+    /// here, so they keep playing across a reload
+    pub fn keep_sinks(&mut self, old: &mut Router) {
+        for output in &self.outputs {
+            if !old.outputs.contains(output) {
+                continue;
+            }
+            if let Some(mut sink) = old.sinks.remove(&output.id) {
+                if let Some(title) = self.title(&output.id) {
+                    sink.set_title(&title);
+                }
+                self.sinks.insert(output.id.clone(), sink);
+            }
+        }
+    }
+
+    /// Take over the running sources of `old`'s inputs that are configured
+    /// the same here, on the outputs both route them to, so they keep
+    /// playing across a reload with this router's gains; `old` is left with
+    /// only what needs stopping, and starting this router only starts the rest
+    pub fn keep_inputs(&mut self, old: &mut Router) {
+        for (output, mixer) in self.output_mixers.iter_mut() {
+            let Some(old_mixer) = old.output_mixers.get_mut(output) else {
+                continue;
+            };
+            for source in mixer.sources.iter_mut() {
+                let same = self
+                    .inputs
+                    .iter()
+                    .find(|input| input.id == source.input_id)
+                    .is_some_and(|input| old.inputs.contains(input));
+                if !same {
+                    continue;
+                }
+                let Some(index) = old_mixer
+                    .sources
+                    .iter()
+                    .position(|s| s.input_id == source.input_id)
+                else {
+                    continue;
+                };
+                let mut kept = old_mixer.sources.remove(index);
+                kept.gain_db = source.gain_db;
+                kept.priority = source.priority;
+                kept.duck_db = source.duck_db;
+                kept.title = source.title.take();
+                *source = kept;
+            }
+            mixer.title = old_mixer.title.clone();
+        }
+    }
+
+    /// Whether `output` has a sink taking its mix
+    pub fn has_sink(&self, output: &str) -> bool {
+        self.sinks.contains_key(output)
+    }
+
+    /// Why `input` could not be created, if it couldn't
+    pub fn input_error(&self, input: &str) -> Option<&str> {
+        self.failed.get(input).map(String::as_str)
+    }
+
+    /// Why `input` isn't playing, if it should be: it could not be created,
+    /// isn't running, or stopped before sending any audio. On-demand sources
+    /// are left out, as they only run when played
+    pub fn input_failure(&self, input: &str) -> Option<String> {
+        if let Some(e) = self.failed.get(input) {
+            return Some(e.clone());
+        }
+        self.output_mixers
+            .values()
+            .flat_map(|mixer| &mixer.sources)
+            .filter(|source| source.input_id == input && !source.on_demand)
+            .find_map(|source| {
+                if !source.is_running() {
+                    Some("not running".to_string())
+                } else if source.failed() {
+                    Some("stopped before sending any audio".to_string())
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// The configured outputs, in config order
+    pub fn output_configs(&self) -> &[Output] {
+        &self.outputs
     }
 
     pub fn start(&mut self) -> Result<(), crate::input::InputError> {
@@ -85,14 +310,221 @@ impl Router {
         for mixer in self.output_mixers.values_mut() {
             mixer.stop()?;
         }
+        for (id, sink) in self.sinks.iter_mut() {
+            if let Err(e) = sink.close() {
+                warn!("Failed to close output '{}': {}", id, e);
+            }
+        }
         Ok(())
     }
+
+    /// Feed an output's mix to a sink the caller made, or record why it
+    /// couldn't be made
+    pub fn attach_sink(&mut self, output: &str, sink: Result<Box<dyn PcmSink>, OutputError>) {
+        match sink {
+            Ok(mut sink) => {
+                if let Some(title) = self.title(output) {
+                    sink.set_title(&title);
+                }
+                self.failed_sinks.remove(output);
+                self.sinks.insert(output.to_string(), sink);
+            }
+            Err(e) => {
+                warn!("Leaving out output '{}': {}", output, e);
+                self.sinks.remove(output);
+                self.failed_sinks.insert(output.to_string(), e.to_string());
+            }
+        }
+    }
+
+    /// Mix whatever is ready for each output, writing it to the outputs
+    /// that take PCM; returns the mixes, by output ID
+    pub fn mix(&mut self) -> Vec<(String, AudioBuffer)> {
+        let mut mixes = Vec::new();
+        for (id, mixer) in self.output_mixers.iter_mut() {
+            let title = mixer.title.clone();
+            let Some(buffer) = mixer.try_mix() else {
+                continue;
+            };
+            if let Some(sink) = self.sinks.get_mut(id) {
+                if let Some(title) = mixer
+                    .title
+                    .as_deref()
+                    .filter(|t| Some(*t) != title.as_deref())
+                {
+                    sink.set_title(title);
+                }
+                let healthy = sink.status().healthy;
+                match sink.write(&buffer) {
+                    // Only log when the output starts or stops failing
+                    Err(e) if healthy => warn!("Output '{}' failed: {}", id, e),
+                    Ok(()) if !healthy => info!("Output '{}' recovered", id),
+                    _ => {}
+                }
+            }
+            mixes.push((id.clone(), buffer));
+        }
+        mixes
+    }
+
+    /// Play `input` from the start on every output it is routed to, e.g. an
+    /// on-demand chime; `None` if it isn't mixed anywhere
+    pub fn play(&mut self, input: &str) -> Option<Result<InputStatus, crate::input::InputError>> {
+        let mut found = false;
+        for mixer in self.output_mixers.values_mut() {
+            match mixer.play(input) {
+                Ok(played) => found |= played,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        found.then(|| {
+            Ok(self
+                .inputs()
+                .into_iter()
+                .find(|status| status.id == input)
+                .expect("A mixed input is configured"))
+        })
+    }
+
+    /// What is playing on an output, as its dominant input last said
+    pub fn title(&self, output: &str) -> Option<String> {
+        self.output_mixers.get(output)?.title.clone()
+    }
+
+    /// Every configured input, in config order
+    pub fn inputs(&self) -> Vec<InputStatus> {
+        self.inputs
+            .iter()
+            .map(|input| {
+                let mut status = InputStatus {
+                    id: input.id.clone(),
+                    kind: input.kind.name().to_string(),
+                    running: false,
+                    buffered: 0,
+                    level: Level::SILENT,
+                    outputs: Vec::new(),
+                    error: self.failed.get(&input.id).cloned(),
+                };
+                for (output, mixer) in self.mixers() {
+                    for source in mixer.sources.iter().filter(|s| s.input_id == input.id) {
+                        status.running |= source.is_running();
+                        status.buffered = status.buffered.max(source.buffered());
+                        status.level.rms_db = status.level.rms_db.max(source.level.rms_db);
+                        status.level.peak_db = status.level.peak_db.max(source.level.peak_db);
+                        status.outputs.push(output.id.clone());
+                    }
+                }
+                status
+            })
+            .collect()
+    }
+
+    /// Every configured output, in config order
+    pub fn outputs(&self) -> Vec<OutputStatus> {
+        self.outputs
+            .iter()
+            .map(|output| {
+                let mixer = self.output_mixers.get(&output.id);
+                let sink = self.sinks.get(&output.id).map(|sink| sink.status());
+                OutputStatus {
+                    id: output.id.clone(),
+                    kind: output.kind.name().to_string(),
+                    room: match &output.kind {
+                        OutputKind::Sonos { room, .. } => Some(room.clone()),
+                        OutputKind::Upnp { .. }
+                        | OutputKind::Alsa { .. }
+                        | OutputKind::Http { .. }
+                        | OutputKind::Icecast { .. }
+                        | OutputKind::Rtp { .. }
+                        | OutputKind::File { .. }
+                        | OutputKind::Null { .. } => None,
+                    },
+                    inputs: mixer
+                        .map(|m| m.sources.iter().map(|s| s.input_id.clone()).collect())
+                        .unwrap_or_default(),
+                    level: mixer.map_or(Level::SILENT, |m| m.level),
+                    healthy: match &sink {
+                        Some(sink) => Some(sink.healthy),
+                        None if self.failed_sinks.contains_key(&output.id) => Some(false),
+                        None => None,
+                    },
+                    transport: None,
+                    clients: sink.as_ref().and_then(|sink| sink.clients),
+                    file: sink.as_ref().and_then(|sink| sink.file.clone()),
+                    frames: sink.as_ref().map(|sink| sink.frames),
+                    underruns: sink.as_ref().and_then(|sink| sink.underruns),
+                    jitter_ms: sink.as_ref().and_then(|sink| sink.jitter_ms),
+                    error: match sink {
+                        Some(sink) => sink.error,
+                        None => self.failed_sinks.get(&output.id).cloned(),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Every input-output pair being mixed, by output in config order
+    pub fn routes(&self) -> Vec<RouteStatus> {
+        self.mixers()
+            .flat_map(|(output, mixer)| {
+                mixer.status().into_iter().map(|source| RouteStatus {
+                    output: output.id.clone(),
+                    source,
+                })
+            })
+            .collect()
+    }
+
+    /// Change the gain or mute state of the route from `input` to `output`
+    /// until the daemon restarts, holding the gain to what configs allow;
+    /// `None` if there is no such route
+    pub fn set_route(
+        &mut self,
+        input: &str,
+        output: &str,
+        gain_db: Option<f32>,
+        muted: Option<bool>,
+    ) -> Option<RouteStatus> {
+        let mixer = self.output_mixers.get_mut(output)?;
+        let index = mixer.sources.iter().position(|s| s.input_id == input)?;
+        let source = &mut mixer.sources[index];
+        if let Some(gain_db) = gain_db {
+            source.gain_db = gain_db.clamp(*GAIN_DB_RANGE.start(), *GAIN_DB_RANGE.end());
+        }
+        if let Some(muted) = muted {
+            source.muted = muted;
+        }
+        Some(RouteStatus {
+            output: output.to_string(),
+            source: mixer.status().swap_remove(index),
+        })
+    }
+
+    /// Levels of every output's mix and its sources, in config order
+    pub fn levels(&self) -> Vec<OutputLevels> {
+        self.mixers()
+            .map(|(output, mixer)| OutputLevels {
+                output: output.id.clone(),
+                level: mixer.level,
+                sources: mixer.status(),
+            })
+            .collect()
+    }
+
+    // Outputs that have a mixer, in config order
+    fn mixers(&self) -> impl Iterator<Item = (&Output, &Mixer)> {
+        self.outputs.iter().filter_map(|output| {
+            self.output_mixers
+                .get(&output.id)
+                .map(|mixer| (output, mixer))
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{Config, Input, Output, Route};
+    use crate::config::{Codec, Config, Input, InputKind, Output, OutputKind, Route};
 
     #[test]
     fn test_router_creation() {
@@ -101,54 +533,56 @@ mod tests {
             inputs: vec![
                 Input {
                     id: "silence1".to_string(),
-                    kind: "silence".to_string(),
-                    device: None,
-                    path: None,
-                    loop_playback: None,
-                    url: None,
+                    title: None,
+                    kind: InputKind::Silence {},
                 },
                 Input {
                     id: "silence2".to_string(),
-                    kind: "silence".to_string(),
-                    device: None,
-                    path: None,
-                    loop_playback: None,
-                    url: None,
+                    title: None,
+                    kind: InputKind::Silence {},
                 },
             ],
             outputs: vec![
                 Output {
                     id: "output1".to_string(),
-                    kind: "sonos".to_string(),
-                    room: Some("Living Room".to_string()),
-                    buffer_sec: None,
-                    host: None,
-                    port: None,
+                    kind: OutputKind::Sonos {
+                        room: "Living Room".to_string(),
+                        buffer_sec: 3,
+                        codec: Codec::Mp3,
+                        bitrate_kbps: 128,
+                        quality: None,
+                    },
                 },
                 Output {
                     id: "output2".to_string(),
-                    kind: "sonos".to_string(),
-                    room: Some("Bedroom".to_string()),
-                    buffer_sec: None,
-                    host: None,
-                    port: None,
+                    kind: OutputKind::Sonos {
+                        room: "Bedroom".to_string(),
+                        buffer_sec: 3,
+                        codec: Codec::Mp3,
+                        bitrate_kbps: 128,
+                        quality: None,
+                    },
                 },
             ],
             routes: vec![
                 Route {
+                    id: None,
                     input: "silence1".to_string(),
                     outputs: vec!["output1".to_string()],
                     gain_db: 0.0,
                     duck_db: 0.0,
+                    priority: None,
                 },
                 Route {
+                    id: None,
                     input: "silence2".to_string(),
                     outputs: vec!["output1".to_string(), "output2".to_string()],
                     gain_db: -6.0,
                     duck_db: 12.0,
+                    priority: None,
                 },
             ],
-            logging: None,
+            ..Default::default()
         };
 
         // Create the router
@@ -168,5 +602,318 @@ mod tests {
             router.output_mixers.get("output2").unwrap().source_count(),
             1
         );
+
+        // Status follows config order, before anything is started
+        let inputs = router.inputs();
+        assert_eq!(inputs[0].id, "silence1");
+        assert_eq!(inputs[0].outputs, vec!["output1"]);
+        assert_eq!(inputs[1].outputs, vec!["output1", "output2"]);
+        assert!(!inputs[1].running);
+
+        let outputs = router.outputs();
+        assert_eq!(outputs[0].room.as_deref(), Some("Living Room"));
+        assert_eq!(outputs[0].inputs, vec!["silence1", "silence2"]);
+
+        let routes = router.routes();
+        assert_eq!(routes.len(), 3);
+        assert_eq!(routes[1].output, "output1");
+        assert_eq!(routes[1].source.input, "silence2");
+        assert_eq!(routes[1].source.priority, 1);
+        assert_eq!(routes[1].source.duck_db, 12.0);
+        assert_eq!(routes[1].source.ducked_by, 0.0);
+
+        let levels = router.levels();
+        assert_eq!(levels[1].output, "output2");
+        assert_eq!(levels[1].level, Level::SILENT);
+    }
+
+    #[test]
+    fn test_status_while_mixing() {
+        let config = Config::check(
+            r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+gain_db = -6.0
+"#,
+        )
+        .into_result()
+        .unwrap();
+        let mut router = Router::new(&config).unwrap();
+        router.start().unwrap();
+
+        let mixer = router.output_mixers.get_mut("kitchen").unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+        while mixer.try_mix().is_none() {
+            assert!(std::time::Instant::now() < deadline, "No audio mixed");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let inputs = router.inputs();
+        assert!(inputs[0].running);
+        assert_eq!(inputs[0].level, Level::SILENT);
+        let routes = router.routes();
+        assert!(!routes[0].source.active);
+        assert_eq!(routes[0].source.gain_db, -6.0);
+
+        router.stop().unwrap();
+        assert!(!router.inputs()[0].running);
+    }
+
+    #[test]
+    fn test_failed_input_is_left_out() {
+        let config = Config::check(
+            r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[inputs]]
+id = "alert"
+kind = "file"
+path = "/nonexistent/alert.pcm"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+
+[[routes]]
+input = "alert"
+outputs = ["kitchen"]
+duck_db = -12.0
+"#,
+        )
+        .into_result()
+        .unwrap();
+        let router = Router::new(&config).unwrap();
+
+        assert_eq!(router.output_mixers["kitchen"].source_count(), 1);
+        let inputs = router.inputs();
+        assert_eq!(inputs[0].error, None);
+        assert!(inputs[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("does not exist"));
+        assert!(inputs[1].outputs.is_empty());
+    }
+
+    #[test]
+    fn test_on_demand_input() {
+        let chime = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(chime.path(), [1, 0, 2, 0]).unwrap();
+        let config = Config::check(&format!(
+            r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[inputs]]
+id = "chime"
+kind = "file"
+path = "{}"
+on_demand = true
+
+[[outputs]]
+id = "kitchen"
+kind = "null"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+
+[[routes]]
+input = "chime"
+outputs = ["kitchen"]
+"#,
+            chime.path().display()
+        ))
+        .into_result()
+        .unwrap();
+        let mut router = Router::new(&config).unwrap();
+        router.start().unwrap();
+
+        // The chime waits until it is played
+        let inputs = router.inputs();
+        assert!(inputs[0].running);
+        assert!(!inputs[1].running);
+        let played = router.play("chime").unwrap().unwrap();
+        assert!(played.running);
+        assert!(router.play("doorbell").is_none());
+
+        router.stop().unwrap();
+    }
+
+    #[test]
+    fn test_recorder_output() {
+        let dir = tempfile::tempdir().unwrap();
+        // A file where the second recorder's directory should be
+        std::fs::write(dir.path().join("blocker"), b"").unwrap();
+        let config = Config::check(&format!(
+            r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[outputs]]
+id = "archive"
+kind = "file"
+path = "{}/archive.wav"
+
+[[outputs]]
+id = "blocked"
+kind = "file"
+path = "{}/blocker/blocked.wav"
+
+[[routes]]
+input = "music"
+outputs = ["archive", "blocked"]
+"#,
+            dir.path().display(),
+            dir.path().display()
+        ))
+        .into_result()
+        .unwrap();
+        let mut router = Router::new(&config).unwrap();
+        router.start().unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+        while router.mix().len() < 2 {
+            assert!(std::time::Instant::now() < deadline, "No audio mixed");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let outputs = router.outputs();
+        let file = dir.path().join("archive.wav");
+        assert_eq!(outputs[0].healthy, Some(true));
+        assert_eq!(outputs[0].file, Some(file.display().to_string()));
+        assert_eq!(outputs[1].healthy, Some(false));
+        assert!(outputs[1].error.is_some());
+
+        router.stop().unwrap();
+        let reader = hound::WavReader::open(&file).unwrap();
+        assert!(reader.duration() > 0);
+        assert_eq!(router.outputs()[0].file, None);
+    }
+
+    #[test]
+    fn test_keep_sinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = |input: &str, second: &str| {
+            Config::check(&format!(
+                r#"
+[[inputs]]
+id = "{input}"
+kind = "silence"
+
+[[outputs]]
+id = "archive"
+kind = "file"
+path = "{dir}/archive.wav"
+
+[[outputs]]
+id = "backup"
+kind = "file"
+path = "{dir}/{second}"
+
+[[routes]]
+input = "{input}"
+outputs = ["archive", "backup"]
+"#,
+                dir = dir.path().display()
+            ))
+            .into_result()
+            .unwrap()
+        };
+        let mut old = Router::new(&config("music", "backup.wav")).unwrap();
+
+        // Only the output configured the same carries over; the other is
+        // opened afresh
+        let mut new = Router::without_sinks(&config("ambient", "backup-2.wav")).unwrap();
+        assert!(!new.has_sink("archive"));
+        new.keep_sinks(&mut old);
+        assert!(new.has_sink("archive"));
+        assert!(!new.has_sink("backup"));
+        assert!(!old.has_sink("archive"));
+        assert!(old.has_sink("backup"));
+        new.open_sinks();
+        assert!(new.has_sink("backup"));
+
+        old.stop().unwrap();
+        new.stop().unwrap();
+    }
+
+    #[test]
+    fn test_keep_inputs() {
+        let config = |second: &str, gain_db: f32| {
+            Config::check(&format!(
+                r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[inputs]]
+id = "{second}"
+kind = "silence"
+
+[[outputs]]
+id = "den"
+kind = "null"
+
+[[routes]]
+input = "music"
+outputs = ["den"]
+gain_db = {gain_db}
+
+[[routes]]
+input = "{second}"
+outputs = ["den"]
+"#
+            ))
+            .into_result()
+            .unwrap()
+        };
+        let mut old = Router::new(&config("ambient", 0.0)).unwrap();
+        old.start().unwrap();
+
+        // The unchanged input keeps running with the new gain; the new one
+        // waits for `start`, and only the dropped one is left to stop
+        let mut new = Router::new(&config("radio", -6.0)).unwrap();
+        new.keep_inputs(&mut old);
+        let running = |router: &Router| -> Vec<_> {
+            router
+                .inputs()
+                .into_iter()
+                .map(|input| (input.id, input.running))
+                .collect()
+        };
+        assert_eq!(
+            running(&new),
+            vec![("music".to_string(), true), ("radio".to_string(), false)]
+        );
+        assert_eq!(new.output_mixers["den"].sources[0].gain_db, -6.0);
+        assert_eq!(
+            running(&old),
+            vec![("music".to_string(), false), ("ambient".to_string(), true)]
+        );
+        old.stop().unwrap();
+        new.start().unwrap();
+        assert_eq!(new.input_failure("radio"), None);
+        new.stop().unwrap();
+        assert_eq!(new.input_failure("radio").as_deref(), Some("not running"));
     }
 }
@@ -1,4 +1,16 @@
+use crate::config::Codec;
+use crate::hls::{Playlist, PLAYLIST};
+use crate::icy;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch};
 
 #[derive(Debug, Error)]
 pub enum StreamError {
@@ -7,66 +19,469 @@ pub enum StreamError {
 
     #[error("Failed to send data: {0}")]
     Send(String),
+
+    #[error("Stream path already in use: {0}")]
+    PathInUse(String),
+}
+
+/// Path of the main stream, the one fed by `HttpStreamer::send`, named for
+/// its codec: `/stream.mp3` for MP3, `/stream.flac` for FLAC and so on
+pub fn main_path(codec: Codec) -> String {
+    format!("/stream.{}", codec.extension())
 }
 
-// For Sprint 2, we'll create a mock implementation of the HTTP streamer
-// This allows us to test the pipeline without actually starting an HTTP server
+// Chunks a listener can fall behind by before it skips ahead
+const BACKLOG: usize = 256;
+
+// Longest request head we read before giving up on a client
+const MAX_HEAD: usize = 8192;
+
+/// Serves encoded audio over HTTP, one endpoint per path, to any number of
+/// listeners; each gets the audio from when it connects. HLS playlists are
+/// served too, each with its segments in the same directory
 pub struct HttpStreamer {
-    port: u16,
-    bytes_sent: std::sync::atomic::AtomicUsize,
+    addr: SocketAddr,
+    bytes_sent: AtomicUsize,
+    streams: Arc<Mutex<HashMap<String, Arc<Stream>>>>,
+    // By the directory their playlist and segments are in
+    playlists: Arc<Mutex<HashMap<String, Arc<Playlist>>>>,
+    // Where `send` goes, once the main stream is added
+    main: Mutex<Option<String>>,
+    local_addr: Mutex<Option<SocketAddr>>,
+    shutdown: watch::Sender<bool>,
+}
+
+/// One stream endpoint
+pub struct Stream {
+    content_type: String,
+    // Sent to every listener before any audio, e.g. a FLAC STREAMINFO
+    header: Vec<u8>,
+    sender: broadcast::Sender<Arc<Vec<u8>>>,
+    clients: AtomicUsize,
+    // What is playing, for listeners that ask for ICY metadata
+    title: Mutex<Option<String>>,
+    // Set once the stream is removed, to let its listeners go
+    closed: watch::Sender<bool>,
 }
 
 impl HttpStreamer {
+    /// A server on every interface, with an MP3 main stream
     pub fn new(port: u16) -> Self {
+        let streamer = Self::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port));
+        streamer
+            .add_main(Codec::Mp3, Vec::new())
+            .expect("A new streamer has no streams");
+        streamer
+    }
+
+    /// A server on `addr` with no streams yet
+    pub fn bind(addr: SocketAddr) -> Self {
         HttpStreamer {
-            port,
-            bytes_sent: std::sync::atomic::AtomicUsize::new(0),
+            addr,
+            bytes_sent: AtomicUsize::new(0),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            playlists: Arc::new(Mutex::new(HashMap::new())),
+            main: Mutex::new(None),
+            local_addr: Mutex::new(None),
+            shutdown: watch::channel(false).0,
         }
     }
 
+    /// Serve a new stream at `path`
+    pub fn add_stream(
+        &self,
+        path: &str,
+        content_type: &str,
+        header: Vec<u8>,
+    ) -> Result<Arc<Stream>, StreamError> {
+        let mut streams = self.streams.lock().unwrap();
+        let playlists = self.playlists.lock().unwrap();
+        if streams.contains_key(path) || playlist_for(&playlists, path).is_some() {
+            return Err(StreamError::PathInUse(path.to_string()));
+        }
+        let stream = Arc::new(Stream {
+            content_type: content_type.to_string(),
+            header,
+            sender: broadcast::channel(BACKLOG).0,
+            clients: AtomicUsize::new(0),
+            title: Mutex::new(None),
+            closed: watch::channel(false).0,
+        });
+        streams.insert(path.to_string(), stream.clone());
+        Ok(stream)
+    }
+
+    /// Serve the main stream, in `codec`, at its `main_path`
+    pub fn add_main(&self, codec: Codec, header: Vec<u8>) -> Result<Arc<Stream>, StreamError> {
+        let path = main_path(codec);
+        let stream = self.add_stream(&path, codec.content_type(), header)?;
+        *self.main.lock().unwrap() = Some(path);
+        Ok(stream)
+    }
+
+    /// The main stream's path, once it is added
+    pub fn main(&self) -> Option<String> {
+        self.main.lock().unwrap().clone()
+    }
+
+    /// Serve an HLS playlist at `<dir>/index.m3u8`, with its segments next
+    /// to it
+    pub fn add_playlist(
+        &self,
+        dir: &str,
+        playlist: Playlist,
+    ) -> Result<Arc<Playlist>, StreamError> {
+        let streams = self.streams.lock().unwrap();
+        let mut playlists = self.playlists.lock().unwrap();
+        let path = format!("{}/{}", dir, PLAYLIST);
+        if playlists.contains_key(dir) || streams.contains_key(&path) {
+            return Err(StreamError::PathInUse(path));
+        }
+        let playlist = Arc::new(playlist);
+        playlists.insert(dir.to_string(), playlist.clone());
+        Ok(playlist)
+    }
+
+    /// The stream served at `path`, if any
+    pub fn stream(&self, path: &str) -> Option<Arc<Stream>> {
+        self.streams.lock().unwrap().get(path).cloned()
+    }
+
+    /// Stop serving the stream or playlist at `path`, disconnecting its
+    /// listeners, so it can be added again
+    pub fn remove(&self, path: &str) -> bool {
+        if let Some(stream) = self.streams.lock().unwrap().remove(path) {
+            stream.closed.send_replace(true);
+            return true;
+        }
+        path.strip_suffix(&format!("/{}", PLAYLIST))
+            .is_some_and(|dir| self.playlists.lock().unwrap().remove(dir).is_some())
+    }
+
+    /// Start listening; connections are served on the current runtime
     pub async fn start(&self) -> Result<(), StreamError> {
-        // In a real implementation, this would start an HTTP server
-        println!("Mock HTTP streamer started on port {}", self.port);
-        println!(
-            "In a real implementation, stream would be available at http://localhost:{}/stream.mp3",
-            self.port
-        );
+        let listener = TcpListener::bind(self.addr)
+            .await
+            .map_err(|e| StreamError::ServerStart(format!("{}: {}", self.addr, e)))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| StreamError::ServerStart(e.to_string()))?;
+        *self.local_addr.lock().unwrap() = Some(local_addr);
+        info!("HTTP streamer listening on {}", local_addr);
+
+        let streams = self.streams.clone();
+        let playlists = self.playlists.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let (socket, peer) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!("Failed to accept a stream listener: {}", e);
+                            continue;
+                        }
+                    },
+                    _ = shutdown.changed() => break,
+                };
+                let streams = streams.clone();
+                let playlists = playlists.clone();
+                let shutdown = shutdown.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(socket, streams, playlists, shutdown).await {
+                        debug!("Stream listener {} left: {}", peer, e);
+                    }
+                });
+            }
+        });
         Ok(())
     }
 
+    /// Send audio to everyone listening to the main stream
     pub fn send(&self, data: Vec<u8>) -> Result<(), StreamError> {
-        // In a real implementation, this would send data to connected clients
-        // For now, we'll just track how many bytes we've "sent"
-        let bytes_sent = self
-            .bytes_sent
-            .fetch_add(data.len(), std::sync::atomic::Ordering::SeqCst);
-        println!(
-            "Mock HTTP streamer: sent {} bytes, total: {}",
-            data.len(),
-            bytes_sent + data.len()
-        );
+        let stream = self
+            .main()
+            .and_then(|path| self.stream(&path))
+            .ok_or_else(|| StreamError::Send("No main stream".to_string()))?;
+        self.bytes_sent.fetch_add(data.len(), Ordering::SeqCst);
+        stream.send(data);
         Ok(())
     }
 
+    /// Tell everyone listening to the main stream that `title` is playing
+    pub fn set_title(&self, title: &str) {
+        if let Some(stream) = self.main().and_then(|path| self.stream(&path)) {
+            stream.set_title(title);
+        }
+    }
+
+    /// Stop listening and disconnect every listener
     pub async fn stop(&self) -> Result<(), StreamError> {
-        // In a real implementation, this would stop the HTTP server
-        println!("Mock HTTP streamer stopped");
+        self.shutdown.send_replace(true);
+        info!("HTTP streamer stopped");
         Ok(())
     }
 
+    /// Bytes handed to `send`
     pub fn bytes_sent(&self) -> usize {
-        self.bytes_sent.load(std::sync::atomic::Ordering::SeqCst)
+        self.bytes_sent.load(Ordering::SeqCst)
+    }
+
+    /// Listeners currently connected, across every stream
+    pub fn clients(&self) -> usize {
+        self.streams
+            .lock()
+            .unwrap()
+            .values()
+            .map(|stream| stream.clients())
+            .sum()
+    }
+
+    /// The address being listened on, once started
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.local_addr.lock().unwrap()
     }
 }
 
+impl Stream {
+    /// Send audio to everyone listening; with nobody listening it is dropped
+    pub fn send(&self, data: Vec<u8>) {
+        let _ = self.sender.send(Arc::new(data));
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// Announce `title` as playing to listeners that take ICY metadata, from
+    /// their next metadata block
+    pub fn set_title(&self, title: &str) {
+        let mut current = self.title.lock().unwrap();
+        if current.as_deref() != Some(title) {
+            *current = Some(title.to_string());
+        }
+    }
+
+    /// What is playing, once known
+    pub fn title(&self) -> Option<String> {
+        self.title.lock().unwrap().clone()
+    }
+
+    /// Listeners currently connected
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::SeqCst)
+    }
+}
+
+// Counts a listener for as long as it is connected
+struct Listener<'a>(&'a AtomicUsize);
+
+impl<'a> Listener<'a> {
+    fn new(clients: &'a AtomicUsize) -> Self {
+        clients.fetch_add(1, Ordering::SeqCst);
+        Self(clients)
+    }
+}
+
+impl Drop for Listener<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Answer one request, streaming until the listener or the server goes away
+async fn serve(
+    mut socket: TcpStream,
+    streams: Arc<Mutex<HashMap<String, Arc<Stream>>>>,
+    playlists: Arc<Mutex<HashMap<String, Arc<Playlist>>>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let head = read_head(&mut socket).await?;
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line
+        .next()
+        .unwrap_or_default()
+        .split('?')
+        .next()
+        .unwrap_or_default();
+
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut socket, "405 Method Not Allowed").await;
+    }
+    let playlist = playlist_for(&playlists.lock().unwrap(), path);
+    if let Some((playlist, name)) = playlist {
+        return serve_playlist(&mut socket, method, &playlist, &name).await;
+    }
+    let Some(stream) = streams.lock().unwrap().get(path).cloned() else {
+        return respond(&mut socket, "404 Not Found").await;
+    };
+
+    // Players that show what is playing ask for it between the audio
+    let mut metadata = wants_metadata(&head).then(icy::Writer::default);
+
+    // Subscribe before anything is written, so no audio after the header
+    // is missed
+    let mut audio = stream.sender.subscribe();
+    let mut closed = stream.closed.subscribe();
+    let _listener = Listener::new(&stream.clients);
+    let head = format!(
+        "HTTP/1.0 200 OK\r\nContent-Type: {}\r\nCache-Control: no-cache, no-store\r\n{}Connection: close\r\n\r\n",
+        stream.content_type,
+        match metadata {
+            Some(_) => format!("icy-metaint: {}\r\n", icy::METAINT),
+            None => String::new(),
+        }
+    );
+    socket.write_all(head.as_bytes()).await?;
+    if method == "HEAD" {
+        return Ok(());
+    }
+    let mut write = |data: &[u8]| match &mut metadata {
+        Some(writer) => writer.interleave(data, stream.title().as_deref()),
+        None => data.to_vec(),
+    };
+    socket.write_all(&write(&stream.header)).await?;
+
+    loop {
+        tokio::select! {
+            data = audio.recv() => match data {
+                Ok(data) => socket.write_all(&write(&data)).await?,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Stream listener fell behind, skipping {} chunks", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            _ = shutdown.changed() => return Ok(()),
+            _ = closed.changed() => return Ok(()),
+        }
+    }
+}
+
+// Whether a request asks for ICY metadata, with `Icy-MetaData: 1`
+fn wants_metadata(head: &str) -> bool {
+    head.lines().skip(1).any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("icy-metadata") && value.trim() == "1"
+        })
+    })
+}
+
+// The playlist whose directory `path` is in, and the file it names there
+fn playlist_for(
+    playlists: &HashMap<String, Arc<Playlist>>,
+    path: &str,
+) -> Option<(Arc<Playlist>, String)> {
+    let (dir, name) = path.rsplit_once('/')?;
+    playlists
+        .get(dir)
+        .map(|playlist| (playlist.clone(), name.to_string()))
+}
+
+// Answer a request for a playlist or one of its segments. The playlist
+// changes with every segment, so it is never cached; a segment never
+// changes while it is kept
+async fn serve_playlist(
+    socket: &mut TcpStream,
+    method: &str,
+    playlist: &Playlist,
+    name: &str,
+) -> io::Result<()> {
+    let (content_type, cache, body) = if name == PLAYLIST {
+        (
+            "application/vnd.apple.mpegurl",
+            "no-cache".to_string(),
+            Arc::new(playlist.playlist().into_bytes()),
+        )
+    } else if let Some(segment) = playlist.segment(name) {
+        (
+            playlist.content_type(),
+            format!("public, max-age={}", playlist.retention().as_secs()),
+            segment,
+        )
+    } else {
+        return respond(socket, "404 Not Found").await;
+    };
+
+    let head = format!(
+        "HTTP/1.0 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        content_type,
+        body.len(),
+        cache
+    );
+    socket.write_all(head.as_bytes()).await?;
+    if method == "GET" {
+        socket.write_all(&body).await?;
+    }
+    Ok(())
+}
+
+// Read up to the blank line ending a request's headers
+async fn read_head(socket: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Request head too long",
+            ));
+        }
+        let n = socket.read(&mut buffer).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buffer[..n]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+async fn respond(socket: &mut TcpStream, status: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.0 {}\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n{}\n",
+        status, status
+    );
+    socket.write_all(response.as_bytes()).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
+
+    async fn listen(addr: SocketAddr, path: &str) -> (TcpStream, String) {
+        request(
+            addr,
+            &format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path),
+        )
+        .await
+    }
+
+    async fn request(addr: SocketAddr, request: &str) -> (TcpStream, String) {
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
+        // A byte at a time, so nothing after the head is consumed
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(socket.read_u8().await.unwrap());
+        }
+        (socket, String::from_utf8(head).unwrap())
+    }
+
+    async fn wait_for_clients(streamer: &HttpStreamer, clients: usize) {
+        for _ in 0..200 {
+            if streamer.clients() == clients {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("Expected {} clients, have {}", clients, streamer.clients());
+    }
 
     #[tokio::test]
     async fn test_http_streamer() {
         // Create and start the streamer
-        let streamer = HttpStreamer::new(8000);
+        let streamer = HttpStreamer::new(0);
         streamer.start().await.unwrap();
 
         // Generate some test MP3 data
@@ -78,10 +493,99 @@ mod tests {
         // Send the data
         streamer.send(test_data.clone()).unwrap();
 
-        // Check that we've "sent" the right number of bytes
+        // Check that we've sent the right number of bytes
         assert_eq!(streamer.bytes_sent(), test_data.len());
 
         // Stop the streamer
         streamer.stop().await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_streams_by_path() {
+        let streamer = HttpStreamer::bind("127.0.0.1:0".parse().unwrap());
+        let flac = streamer
+            .add_stream("/streams/kitchen", "audio/flac", b"fLaC".to_vec())
+            .unwrap();
+        assert!(matches!(
+            streamer.add_stream("/streams/kitchen", "audio/flac", Vec::new()),
+            Err(StreamError::PathInUse(_))
+        ));
+        // The main stream is named for its codec
+        streamer.add_main(Codec::Flac, Vec::new()).unwrap();
+        assert_eq!(streamer.main().as_deref(), Some("/stream.flac"));
+        streamer.start().await.unwrap();
+        let addr = streamer.local_addr().unwrap();
+
+        let (mut socket, head) = listen(addr, "/streams/kitchen?listener=vlc").await;
+        assert!(head.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(head.contains("Content-Type: audio/flac\r\n"));
+        wait_for_clients(&streamer, 1).await;
+        assert_eq!(flac.clients(), 1);
+
+        // The header comes first, then audio sent since connecting
+        flac.send(vec![1, 2, 3]);
+        let mut received = [0u8; 7];
+        socket.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"fLaC\x01\x02\x03");
+
+        let (_, head) = listen(addr, "/streams/garage").await;
+        assert!(head.starts_with("HTTP/1.0 404 Not Found\r\n"));
+        let (_, head) = listen(addr, "/stream.mp3").await;
+        assert!(head.starts_with("HTTP/1.0 404 Not Found\r\n"));
+        let (_, head) = listen(addr, "/stream.flac").await;
+        assert!(head.contains("Content-Type: audio/flac\r\n"));
+
+        // A removed stream lets its listeners go, and its path can be used
+        // again
+        assert!(!streamer.remove("/streams/old"));
+        streamer
+            .add_stream("/streams/old", "audio/mpeg", Vec::new())
+            .unwrap();
+        let (mut removed_listener, _) = listen(addr, "/streams/old").await;
+        assert!(streamer.remove("/streams/old"));
+        assert_eq!(removed_listener.read(&mut received).await.unwrap(), 0);
+        streamer
+            .add_stream("/streams/old", "audio/mpeg", Vec::new())
+            .unwrap();
+
+        // Stopping disconnects listeners
+        streamer.stop().await.unwrap();
+        assert_eq!(socket.read(&mut received).await.unwrap(), 0);
+        wait_for_clients(&streamer, 0).await;
+    }
+
+    #[tokio::test]
+    async fn test_icy_metadata() {
+        let streamer = HttpStreamer::new(0);
+        streamer.start().await.unwrap();
+        let addr = streamer.local_addr().unwrap();
+        streamer.set_title("Miles Davis - So What");
+
+        let (mut socket, head) = request(
+            addr,
+            "GET /stream.mp3 HTTP/1.1\r\nHost: test\r\nicy-metadata: 1\r\n\r\n",
+        )
+        .await;
+        assert!(head.contains("icy-metaint: 16000\r\n"));
+        let (_plain, head) = listen(addr, &main_path(Codec::Mp3)).await;
+        assert!(!head.contains("icy-metaint"));
+        wait_for_clients(&streamer, 2).await;
+
+        // The title follows the first METAINT bytes of audio
+        streamer.send(vec![1; icy::METAINT + 4]).unwrap();
+        let mut received = vec![0u8; icy::METAINT + 1];
+        socket.read_exact(&mut received).await.unwrap();
+        assert_eq!(received[icy::METAINT], 3);
+        let mut block = [0u8; 48];
+        socket.read_exact(&mut block).await.unwrap();
+        assert_eq!(
+            icy::stream_title(&block).as_deref(),
+            Some("Miles Davis - So What")
+        );
+        let mut rest = [0u8; 4];
+        socket.read_exact(&mut rest).await.unwrap();
+        assert_eq!(rest, [1; 4]);
+
+        streamer.stop().await.unwrap();
+    }
 }
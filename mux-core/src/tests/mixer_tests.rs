@@ -1,5 +1,6 @@
+use crate::input::silence::SilenceInput;
 use crate::input::{AudioBuffer, AudioInput, InputError};
-use crate::mixer::{lin_to_db, Mixer, Source};
+use crate::mixer::{db_to_lin, lin_to_db, Mixer, Source};
 use crossbeam_channel::Sender;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -107,7 +108,7 @@ async fn test_gain_accuracy() {
     let gain_values = [-20.0, -10.0, -6.0, -3.0, 0.0];
 
     for &gain_db in &gain_values {
-        let src = Source::new(gain_db, false, 0.0, Box::new(tone_input.clone()));
+        let src = Source::new(gain_db, 0, 0.0, Box::new(tone_input.clone()));
 
         let mut mixer = Mixer::new(vec![src]);
         mixer.start().unwrap();
@@ -151,15 +152,15 @@ async fn test_ducking_speed() {
 
     // Create sources: music (normal) and alert (priority)
     let music_src = Source::new(
-        0.0,   // 0dB gain
-        false, // Not priority
-        0.0,   // No ducking
+        0.0, // 0dB gain
+        0,   // Lowest priority
+        0.0, // No ducking
         Box::new(music),
     );
 
     let alert_src = Source::new(
         0.0,  // 0dB gain
-        true, // Priority
+        1,    // Ducks priority 0
         12.0, // 12dB ducking
         Box::new(alert),
     );
@@ -188,3 +189,137 @@ async fn test_ducking_speed() {
 
     mixer.stop().unwrap();
 }
+
+#[tokio::test]
+async fn test_three_priority_tiers() {
+    // Music at the bottom, chimes above it and emergency alerts on top; the
+    // chime uses the negative duck_db form
+    let source =
+        |priority, duck_db| Source::new(0.0, priority, duck_db, Box::new(SilenceInput::default()));
+    let mut mixer = Mixer::new(vec![
+        source(0, 0.0),   // music
+        source(0, 0.0),   // second music stream
+        source(1, -12.0), // chime
+        source(2, 30.0),  // emergency alert
+    ]);
+
+    // Mix one round in which each given source plays a constant level
+    async fn mix(mixer: &mut Mixer, levels: [i16; 4]) -> f32 {
+        for (source, &level) in mixer.sources.iter_mut().zip(&levels) {
+            if level != 0 {
+                source.set_test_buffer(vec![level; 64]);
+            }
+        }
+        let buffer = mixer.mix_next().await.expect("No buffer received");
+        buffer[0] as f32
+    }
+
+    // Equal priorities mix at full level
+    let out = mix(&mut mixer, [1000, 2000, 0, 0]).await;
+    assert!((out - 3000.0).abs() <= 1.0, "got {}", out);
+
+    // The chime ducks both music streams by 12 dB
+    let out = mix(&mut mixer, [1000, 2000, 4000, 0]).await;
+    let expected = 3000.0 * db_to_lin(-12.0) + 4000.0;
+    assert!((out - expected).abs() <= 1.0, "got {}", out);
+    assert_eq!(mixer.ducked_by(0), 12.0);
+    assert_eq!(mixer.ducked_by(2), 0.0);
+
+    // The alert ducks everything below it, by its own depth
+    let out = mix(&mut mixer, [1000, 2000, 4000, 8000]).await;
+    let expected = 7000.0 * db_to_lin(-30.0) + 8000.0;
+    assert!((out - expected).abs() <= 1.0, "got {}", out);
+    assert_eq!(mixer.ducked_by(0), 30.0);
+    assert_eq!(mixer.ducked_by(2), 30.0);
+
+    // Once the alert ends the chime takes over again
+    let out = mix(&mut mixer, [1000, 0, 4000, 0]).await;
+    let expected = 1000.0 * db_to_lin(-12.0) + 4000.0;
+    assert!((out - expected).abs() <= 1.0, "got {}", out);
+
+    // Ducking ends with the last higher-priority source
+    let out = mix(&mut mixer, [1000, 0, 0, 0]).await;
+    assert!((out - 1000.0).abs() <= 1.0, "got {}", out);
+}
+
+#[tokio::test]
+async fn test_mute_and_underruns() {
+    let source = |priority, duck_db| {
+        let mut source = Source::new(0.0, priority, duck_db, Box::new(SilenceInput::default()));
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        source.receiver = Some(receiver);
+        (source, sender)
+    };
+    let (music, music_tx) = source(0, 0.0);
+    let (chime, chime_tx) = source(1, 12.0);
+    let mut mixer = Mixer::new(vec![music, chime]);
+
+    // A muted source neither plays nor ducks the others
+    mixer.sources[1].muted = true;
+    music_tx.send(vec![1000; 64]).unwrap();
+    chime_tx.send(vec![4000; 64]).unwrap();
+    let buffer = mixer.try_mix().expect("No buffer received");
+    assert_eq!(buffer[0], 1000);
+    assert_eq!(mixer.ducked_by(0), 0.0);
+    assert!(mixer.status()[1].muted);
+
+    // A round in which everything that played is muted is silent
+    mixer.sources[0].muted = true;
+    music_tx.send(vec![1000; 64]).unwrap();
+    chime_tx.send(vec![4000; 64]).unwrap();
+    let buffer = mixer.try_mix().expect("No buffer received");
+    assert_eq!(buffer, vec![0; 64]);
+
+    // The chime running dry while the music is a buffer ahead is an
+    // underrun; both running dry together is not
+    mixer.sources[0].muted = false;
+    for _ in 0..2 {
+        music_tx.send(vec![1000; 64]).unwrap();
+    }
+    mixer.try_mix().expect("No buffer received");
+    assert_eq!(mixer.status()[1].underruns, 1);
+    mixer.try_mix().expect("No buffer received");
+    assert!(mixer.try_mix().is_none());
+    assert_eq!(mixer.status()[0].underruns, 0);
+    assert_eq!(mixer.status()[1].underruns, 1);
+}
+
+#[tokio::test]
+async fn test_dominant_title() {
+    let source = |priority, title: Option<&str>| {
+        let mut source = Source::new(0.0, priority, 12.0, Box::new(SilenceInput::default()));
+        source.title = title.map(str::to_string);
+        source
+    };
+    let mut mixer = Mixer::new(vec![
+        source(0, Some("Radio")),
+        source(0, Some("Playlist")),
+        source(1, None),
+    ]);
+
+    async fn mix(mixer: &mut Mixer, levels: [i16; 3]) -> Option<usize> {
+        for (source, &level) in mixer.sources.iter_mut().zip(&levels) {
+            if level != 0 {
+                source.set_test_buffer(vec![level; 64]);
+            }
+        }
+        mixer.mix_next().await.expect("No buffer received");
+        mixer.dominant()
+    }
+
+    // Of equal priorities the loudest is heard most
+    assert_eq!(mix(&mut mixer, [1000, 2000, 0]).await, Some(1));
+    assert_eq!(mixer.title.as_deref(), Some("Playlist"));
+    assert_eq!(mix(&mut mixer, [2000, 1000, 0]).await, Some(0));
+    assert_eq!(mixer.title.as_deref(), Some("Radio"));
+
+    // A higher priority wins however quiet, but one with no title leaves
+    // the last one standing
+    assert_eq!(mix(&mut mixer, [2000, 1000, 100]).await, Some(2));
+    assert_eq!(mixer.title.as_deref(), Some("Radio"));
+
+    // Muted sources aren't heard
+    mixer.sources[0].muted = true;
+    assert_eq!(mix(&mut mixer, [2000, 1000, 0]).await, Some(1));
+    assert_eq!(mixer.title.as_deref(), Some("Playlist"));
+}
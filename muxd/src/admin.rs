@@ -1,13 +1,29 @@
-use log::{error, info};
-use mux_core::{Config, SonosManager};
+use crate::auth::Auth;
+use crate::history::History;
+use crate::metrics::{Metrics, ReloadOutcome, ReloadSource};
+use crate::reload::{self, ReloadTrigger};
+use log::{error, info, warn};
+use mux_core::admin::{
+    ConfigParams, HelloParams, HelloResult, LoadedConfig, Method, ReloadParams, Request, Response,
+    Role, RpcError, PROTOCOL_VERSION,
+};
+use mux_core::{Config, HttpStreamer, Router, SonosManager, UpnpManager};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, UnixListener};
 use tokio::sync::Mutex;
 
+// Longest request line a client may send, and longest configuration after
+// a legacy command; longer ones close the connection
+const MAX_LINE: u64 = 1024 * 1024;
+
+/// Reply to a legacy line command
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AdminResponse {
     pub success: bool,
@@ -17,29 +33,128 @@ pub struct AdminResponse {
 pub struct AdminServer {
     config_path: Option<String>,
     sonos_manager: Arc<Mutex<SonosManager>>,
-    reload_trigger: Arc<Mutex<Option<tokio::sync::mpsc::Sender<Config>>>>,
+    reload_trigger: ReloadTrigger,
+    // The configuration the daemon is running, for `diff`
+    current: Arc<Mutex<Config>>,
+    history: Arc<Mutex<Option<History>>>,
+    metrics: Arc<Metrics>,
+    // The running mixers, for status
+    router: Arc<std::sync::Mutex<Router>>,
+    // Credentials for TCP clients
+    auth: Arc<Auth>,
+    // The output whose mix is streamed, and the streamer, for client counts
+    stream: Option<(String, Arc<HttpStreamer>)>,
+    // Renderers of `upnp` outputs, for status
+    upnp: Option<Arc<UpnpManager>>,
+}
+
+/// Owner, group and mode to give the Unix socket, where set
+#[derive(Debug, Clone, Default)]
+pub struct SocketPermissions {
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+    pub mode: Option<u32>,
+}
+
+impl SocketPermissions {
+    /// Resolve user and group names, or numeric IDs, and an octal mode
+    pub fn resolve(
+        owner: Option<&str>,
+        group: Option<&str>,
+        mode: Option<&str>,
+    ) -> Result<Self, String> {
+        let owner = owner
+            .map(|owner| match owner.parse() {
+                Ok(uid) => Ok(uid),
+                Err(_) => match nix::unistd::User::from_name(owner) {
+                    Ok(Some(user)) => Ok(user.uid.as_raw()),
+                    _ => Err(format!("Unknown user: {}", owner)),
+                },
+            })
+            .transpose()?;
+        let group = group
+            .map(|group| match group.parse() {
+                Ok(gid) => Ok(gid),
+                Err(_) => match nix::unistd::Group::from_name(group) {
+                    Ok(Some(group)) => Ok(group.gid.as_raw()),
+                    _ => Err(format!("Unknown group: {}", group)),
+                },
+            })
+            .transpose()?;
+        let mode = mode
+            .map(|mode| match u32::from_str_radix(mode, 8) {
+                Ok(mode) if mode <= 0o777 => Ok(mode),
+                _ => Err(format!("Invalid socket mode `{}`, expected e.g. 660", mode)),
+            })
+            .transpose()?;
+        Ok(Self { owner, group, mode })
+    }
+
+    fn apply(&self, path: &str) -> io::Result<()> {
+        if self.owner.is_some() || self.group.is_some() {
+            std::os::unix::fs::chown(path, self.owner, self.group)?;
+        }
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
 }
 
 impl AdminServer {
     pub fn new(
         config_path: Option<String>,
         sonos_manager: Arc<Mutex<SonosManager>>,
-        reload_trigger: Arc<Mutex<Option<tokio::sync::mpsc::Sender<Config>>>>,
+        reload_trigger: ReloadTrigger,
+        current: Arc<Mutex<Config>>,
+        history: Arc<Mutex<Option<History>>>,
+        metrics: Arc<Metrics>,
+        router: Arc<std::sync::Mutex<Router>>,
     ) -> Self {
         Self {
             config_path,
             sonos_manager,
             reload_trigger,
+            current,
+            history,
+            metrics,
+            router,
+            auth: Arc::new(Auth::default()),
+            stream: None,
+            upnp: None,
         }
     }
 
-    pub async fn start_unix(&self, socket_path: &str) -> io::Result<()> {
+    /// Require TCP clients to authenticate as `auth` says
+    pub fn with_auth(mut self, auth: Arc<Auth>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Report the renderers `manager` keeps playing for `upnp` outputs
+    pub fn with_upnp(mut self, manager: Arc<UpnpManager>) -> Self {
+        self.upnp = Some(manager);
+        self
+    }
+
+    /// Report `streamer`'s listeners as those of `output`
+    pub fn with_stream(mut self, output: String, streamer: Arc<HttpStreamer>) -> Self {
+        self.stream = Some((output, streamer));
+        self
+    }
+
+    pub async fn start_unix(
+        &self,
+        socket_path: &str,
+        permissions: &SocketPermissions,
+    ) -> io::Result<()> {
         // Remove the socket file if it exists
         if Path::new(socket_path).exists() {
             tokio::fs::remove_file(socket_path).await?;
         }
 
         let listener = UnixListener::bind(socket_path)?;
+        permissions.apply(socket_path)?;
         info!("Admin server started on Unix socket: {}", socket_path);
 
         loop {
@@ -47,7 +162,9 @@ impl AdminServer {
                 Ok((stream, _addr)) => {
                     let server = self.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = server.handle_connection(stream).await {
+                        // Whoever can open the socket has full control
+                        let result = server.handle_connection(stream, Some(Role::Control)).await;
+                        if let Err(e) = result {
                             error!("Error handling admin connection: {}", e);
                         }
                     });
@@ -59,16 +176,43 @@ impl AdminServer {
         }
     }
 
-    pub async fn start_tcp(&self, port: u16) -> io::Result<()> {
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).await?;
-        info!("Admin server started on TCP port: {}", port);
+    pub async fn start_tcp(&self, addr: SocketAddr) -> io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        self.auth
+            .check_exposure("The TCP admin server", addr.ip().is_loopback());
+        info!(
+            "Admin server started on TCP {}{}",
+            addr,
+            if self.auth.tls().is_some() {
+                " with TLS"
+            } else {
+                ""
+            }
+        );
 
         loop {
             match listener.accept().await {
-                Ok((stream, _addr)) => {
+                Ok((stream, peer)) => {
                     let server = self.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = server.handle_connection(stream).await {
+                        let result = match server.auth.tls() {
+                            Some(acceptor) => match acceptor.accept(stream).await {
+                                Ok(stream) => {
+                                    let certificates = stream.get_ref().1.peer_certificates();
+                                    let role = server.auth.peer_role(certificates);
+                                    server.handle_connection(stream, role).await
+                                }
+                                Err(e) => {
+                                    warn!("TLS handshake with {} failed: {}", peer, e);
+                                    Ok(())
+                                }
+                            },
+                            None => {
+                                let role = server.auth.peer_role(None);
+                                server.handle_connection(stream, role).await
+                            }
+                        };
+                        if let Err(e) = result {
                             error!("Error handling admin connection: {}", e);
                         }
                     });
@@ -80,188 +224,955 @@ impl AdminServer {
         }
     }
 
-    async fn handle_connection<T>(&self, stream: T) -> io::Result<()>
+    // Serve one connection; `peer` is the role the transport grants before
+    // any token is sent
+    async fn handle_connection<T>(&self, stream: T, peer: Option<Role>) -> io::Result<()>
     where
         T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
     {
         let mut reader = BufReader::new(stream);
         let mut line = String::new();
 
-        // Read the command
-        reader.read_line(&mut line).await?;
+        // Read the first request, or a legacy command
+        read_line(&mut reader, &mut line).await?;
 
-        let line = line.trim();
-        let parts: Vec<&str> = line.split_whitespace().collect();
+        if line.trim_start().starts_with('{') {
+            self.serve_rpc(reader, line, peer).await
+        } else {
+            self.serve_legacy(reader, &line, peer).await
+        }
+    }
 
+    // Answer JSON-RPC requests, one per line, until the client disconnects
+    async fn serve_rpc<T>(
+        &self,
+        mut reader: BufReader<T>,
+        mut line: String,
+        peer: Option<Role>,
+    ) -> io::Result<()>
+    where
+        T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        // Set once the handshake succeeds
+        let mut role = None;
+        loop {
+            if !line.trim().is_empty() {
+                let response = match Request::parse(line.trim()) {
+                    Err((id, error)) => Response::new(id, Err(error)),
+                    Ok((id, Method::Hello(params))) => {
+                        let result = self.hello(params, peer).map(|(granted, value)| {
+                            role = Some(granted);
+                            value
+                        });
+                        Response::new(id, result)
+                    }
+                    Ok((id, _)) if role.is_none() => Response::new(
+                        id,
+                        Err(RpcError::new(
+                            RpcError::HANDSHAKE_REQUIRED,
+                            "Send `hello` before any other request",
+                        )),
+                    ),
+                    Ok((id, method)) => {
+                        let result = match authorize(role, &method) {
+                            Ok(()) => self.call(method).await,
+                            Err(e) => Err(e),
+                        };
+                        Response::new(id, result)
+                    }
+                };
+
+                let mut out = serde_json::to_vec(&response)?;
+                out.push(b'\n');
+                let writer = reader.get_mut();
+                writer.write_all(&out).await?;
+                writer.flush().await?;
+            }
+
+            line.clear();
+            if read_line(&mut reader, &mut line).await? == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    // Compatibility shim for the whitespace-separated commands that came
+    // before the JSON protocol: one command per connection, `apply` and
+    // `diff` followed by the configuration until the end of input
+    async fn serve_legacy<T>(
+        &self,
+        mut reader: BufReader<T>,
+        line: &str,
+        peer: Option<Role>,
+    ) -> io::Result<()>
+    where
+        T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let parts: Vec<&str> = line.split_whitespace().collect();
         if parts.is_empty() {
             return Ok(());
         }
 
-        let response = match parts[0] {
-            "version" => AdminResponse {
-                success: true,
-                message: format!("sonos-mux v{}", mux_core::version()),
-            },
-            "reload" => {
-                if parts.len() < 2 && self.config_path.is_none() {
-                    AdminResponse {
-                        success: false,
-                        message: "No config file specified".to_string(),
-                    }
+        let method = match parts[0] {
+            "version" => Ok(Method::Version),
+            "reload" => Ok(Method::Reload(ReloadParams {
+                path: parts.get(1).map(|path| path.to_string()),
+            })),
+            "apply" | "diff" => {
+                let config = read_config(&mut reader).await?;
+                info!("Received config content of length: {}", config.len());
+                let params = ConfigParams { config };
+                Ok(if parts[0] == "apply" {
+                    Method::Apply(params)
                 } else {
-                    let config_path = if parts.len() >= 2 {
-                        parts[1]
-                    } else {
-                        self.config_path.as_ref().unwrap()
-                    };
-
-                    match Config::load(config_path) {
-                        Ok(config) => {
-                            let reload_trigger = self.reload_trigger.lock().await;
-                            if let Some(trigger) = &*reload_trigger {
-                                if let Err(e) = trigger.send(config).await {
-                                    AdminResponse {
-                                        success: false,
-                                        message: format!("Failed to send reload: {}", e),
-                                    }
-                                } else {
-                                    AdminResponse {
-                                        success: true,
-                                        message: "Configuration reloaded successfully".to_string(),
-                                    }
-                                }
-                            } else {
-                                AdminResponse {
-                                    success: false,
-                                    message: "Reload trigger not available".to_string(),
-                                }
-                            }
-                        }
-                        Err(e) => AdminResponse {
-                            success: false,
-                            message: format!("Failed to load config: {}", e),
-                        },
-                    }
-                }
+                    Method::Diff(params)
+                })
             }
-            "apply" => {
-                // Read the config content from the connection
-                let mut config_content = String::new();
-                let mut content_line = String::new();
-
-                // Read until we get a non-empty line (configuration content)
-                loop {
-                    match reader.read_line(&mut content_line).await {
-                        Ok(bytes) if bytes > 0 => {
-                            // Skip empty lines
-                            if content_line.trim().is_empty() {
-                                content_line.clear();
-                                continue;
-                            }
+            "history" => Ok(Method::History),
+            "rollback" => Method::parse(
+                "rollback",
+                match parts.get(1) {
+                    Some(n) => json!({ "n": n.parse::<usize>().unwrap_or(0) }),
+                    None => Value::Null,
+                },
+            ),
+            "stats" => Ok(Method::Stats),
+            "inputs" => Ok(Method::Inputs),
+            "outputs" => Ok(Method::Outputs),
+            "routes" => Ok(Method::Routes),
+            "levels" => Ok(Method::Levels),
+            "config" => Ok(Method::Config),
+            _ => Err(RpcError::new(
+                RpcError::METHOD_NOT_FOUND,
+                format!("Unknown command: {}", parts[0]),
+            )),
+        };
 
-                            // Start accumulating config content
-                            config_content.push_str(&content_line);
-                            content_line.clear();
-                            break;
-                        }
-                        Ok(_) | Err(_) => {
-                            // End of input or error
-                            break;
-                        }
-                    }
-                }
+        // Legacy commands carry no token
+        let response = match method {
+            Ok(method) => {
+                let result = match authorize(peer, &method) {
+                    Ok(()) => self.call(method.clone()).await,
+                    Err(e) => Err(e),
+                };
+                legacy_response(&method, result)
+            }
+            Err(e) => legacy_response(&Method::Version, Err(e)),
+        };
 
-                // Continue reading the rest of the content
-                loop {
-                    match reader.read_line(&mut content_line).await {
-                        Ok(bytes) if bytes > 0 => {
-                            // Empty line marks the end of the config
-                            if content_line.trim().is_empty() {
-                                break;
-                            }
+        // Serialize and send the response
+        let response_json = serde_json::to_string(&response)?;
+        let mut writer = reader.into_inner();
+        writer.write_all(response_json.as_bytes()).await?;
+        writer.flush().await?;
 
-                            config_content.push_str(&content_line);
-                            content_line.clear();
-                        }
-                        Ok(_) | Err(_) => {
-                            // End of input or error
-                            break;
-                        }
+        Ok(())
+    }
+
+    // Run one request
+    async fn call(&self, method: Method) -> Result<Value, RpcError> {
+        match method {
+            Method::Hello(_) => Err(RpcError::new(
+                RpcError::INVALID_REQUEST,
+                "`hello` is only valid as the first request",
+            )),
+            Method::Version => Ok(json!({ "version": mux_core::version() })),
+            Method::Reload(params) => self.reload(params.path).await,
+            Method::Apply(params) => {
+                let config = match self.parse_config(&params.config) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        self.metrics
+                            .record_reload(ReloadSource::Apply, ReloadOutcome::Invalid);
+                        return Err(e);
                     }
+                };
+                let plan = self.current.lock().await.diff(&config);
+                self.send(config, ReloadSource::Apply).await?;
+                to_value(&plan)
+            }
+            Method::Diff(params) => {
+                let config = self.parse_config(&params.config)?;
+                let plan = self.current.lock().await.diff(&config);
+                to_value(&plan)
+            }
+            Method::History => match &*self.history.lock().await {
+                Some(history) => to_value(&history.entries().collect::<Vec<_>>()),
+                None => Err(no_history()),
+            },
+            Method::Rollback(params) => {
+                if params.n == 0 {
+                    return Err(RpcError::new(
+                        RpcError::INVALID_PARAMS,
+                        "Usage: rollback [n], where n >= 1 entries back",
+                    ));
                 }
-
-                info!(
-                    "Received config content of length: {}",
-                    config_content.len()
-                );
-
-                if config_content.is_empty() {
-                    AdminResponse {
-                        success: false,
-                        message: "No configuration provided".to_string(),
+                let loaded = match &*self.history.lock().await {
+                    Some(history) => history.get(params.n),
+                    None => return Err(no_history()),
+                };
+                let (entry, config) = loaded.map_err(|e| {
+                    RpcError::new(
+                        RpcError::INVALID_PARAMS,
+                        format!("Failed to load config history: {}", e),
+                    )
+                })?;
+                self.send(config, ReloadSource::Rollback).await?;
+                to_value(&entry)
+            }
+            Method::Stats => {
+                let manager = self.sonos_manager.lock().await;
+                to_value(&manager.health_status().await)
+            }
+            Method::Inputs => to_value(&self.router()?.inputs()),
+            Method::Outputs => {
+                let mut outputs = self.router()?.outputs();
+                let health = self.sonos_manager.lock().await.health_status().await;
+                let renderers = match &self.upnp {
+                    Some(manager) => manager.health_status().await,
+                    None => Vec::new(),
+                };
+                let current = self.current.lock().await;
+                for output in &mut outputs {
+                    if let Some(room) = &mut output.room {
+                        let status = health.iter().find(|status| status.room == *room);
+                        output.healthy = status.map(|status| status.healthy);
+                        output.transport = status.map(|status| status.transport);
+                        *room = current.redact(room);
                     }
-                } else {
-                    match Config::from_reader(config_content.as_bytes()) {
-                        Ok(config) => {
-                            let reload_trigger = self.reload_trigger.lock().await;
-                            if let Some(trigger) = &*reload_trigger {
-                                if let Err(e) = trigger.send(config).await {
-                                    AdminResponse {
-                                        success: false,
-                                        message: format!("Failed to send reload: {}", e),
-                                    }
-                                } else {
-                                    AdminResponse {
-                                        success: true,
-                                        message: "Configuration applied successfully".to_string(),
-                                    }
-                                }
-                            } else {
-                                AdminResponse {
-                                    success: false,
-                                    message: "Reload trigger not available".to_string(),
-                                }
-                            }
+                    if let Some(status) = renderers.iter().find(|s| s.output == output.id) {
+                        output.room = status.name.clone();
+                        output.healthy = Some(status.healthy);
+                        output.transport = Some(status.transport);
+                    }
+                    // Outputs with a stream of their own already count its
+                    // listeners
+                    if let Some((id, streamer)) = &self.stream {
+                        if *id == output.id && output.clients.is_none() {
+                            output.clients = streamer
+                                .main()
+                                .and_then(|path| streamer.stream(&path))
+                                .map(|s| s.clients());
                         }
-                        Err(e) => AdminResponse {
-                            success: false,
-                            message: format!("Failed to parse config: {}", e),
-                        },
                     }
                 }
+                to_value(&outputs)
             }
-            "stats" => {
-                let manager = self.sonos_manager.lock().await;
-                let status = manager.health_status().await;
-
-                AdminResponse {
-                    success: true,
-                    message: serde_json::to_string_pretty(&status)
-                        .unwrap_or_else(|_| "{}".to_string()),
+            Method::Routes => to_value(&self.router()?.routes()),
+            Method::Levels => to_value(&self.router()?.levels()),
+            Method::Config => self.loaded_config().await,
+            Method::SetRoute(params) => {
+                let route = self.router()?.set_route(
+                    &params.input,
+                    &params.output,
+                    params.gain_db,
+                    params.muted,
+                );
+                match route {
+                    Some(route) => {
+                        info!(
+                            "Route {} -> {} set to {:.1} dB{}",
+                            params.input,
+                            params.output,
+                            route.source.gain_db,
+                            if route.source.muted { ", muted" } else { "" }
+                        );
+                        to_value(&route)
+                    }
+                    None => Err(RpcError::new(
+                        RpcError::INVALID_PARAMS,
+                        format!("No route from {} to {}", params.input, params.output),
+                    )),
                 }
             }
-            _ => AdminResponse {
-                success: false,
-                message: format!("Unknown command: {}", parts[0]),
+            Method::Play(params) => match self.router()?.play(&params.input) {
+                Some(Ok(input)) => {
+                    info!("Playing input {}", params.input);
+                    to_value(&input)
+                }
+                Some(Err(e)) => Err(RpcError::new(
+                    RpcError::INTERNAL_ERROR,
+                    format!("Failed to play {}: {}", params.input, e),
+                )),
+                None => Err(RpcError::new(
+                    RpcError::INVALID_PARAMS,
+                    format!("No input {} is being mixed", params.input),
+                )),
             },
+        }
+    }
+
+    // The running mixers; the lock is only held by the mixing thread for
+    // one round at a time
+    fn router(&self) -> Result<std::sync::MutexGuard<'_, Router>, RpcError> {
+        self.router
+            .lock()
+            .map_err(|_| RpcError::new(RpcError::UNAVAILABLE, "The mixer has stopped"))
+    }
+
+    async fn loaded_config(&self) -> Result<Value, RpcError> {
+        let mut config = self.current.lock().await.clone();
+        let entry = match &*self.history.lock().await {
+            Some(history) => history.entries().next().cloned(),
+            None => None,
         };
 
-        // Serialize and send the response
-        let response_json = serde_json::to_string(&response)?;
-        let mut writer = reader.into_inner();
-        writer.write_all(response_json.as_bytes()).await?;
-        writer.flush().await?;
+        let files = config
+            .files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        // Includes are already merged in
+        config.include.clear();
+        let content = toml::to_string(&config)
+            .map_err(|e| RpcError::new(RpcError::INTERNAL_ERROR, e.to_string()))?;
 
-        Ok(())
+        to_value(&LoadedConfig {
+            files,
+            history_id: entry.as_ref().map(|entry| entry.id),
+            applied_at: entry.as_ref().map(|entry| entry.timestamp),
+            source: entry.map(|entry| entry.source.as_str().to_string()),
+            config: config.redact(&content),
+        })
+    }
+
+    async fn reload(&self, path: Option<String>) -> Result<Value, RpcError> {
+        let Some(config_path) = path.or_else(|| self.config_path.clone()) else {
+            return Err(RpcError::new(
+                RpcError::INVALID_PARAMS,
+                "No config file specified",
+            ));
+        };
+
+        match Config::load(&config_path) {
+            Ok(config) => {
+                let plan = self.current.lock().await.diff(&config);
+                self.send(config, ReloadSource::Reload).await?;
+                to_value(&plan)
+            }
+            Err(e) => {
+                self.metrics
+                    .record_reload(ReloadSource::Reload, ReloadOutcome::Invalid);
+                Err(RpcError::new(
+                    RpcError::INVALID_CONFIG,
+                    format!("Failed to load config: {}", e),
+                ))
+            }
+        }
+    }
+
+    // Hand a configuration to the reload loop, answering once it has been
+    // applied or rolled back
+    async fn send(&self, config: Config, source: ReloadSource) -> Result<(), RpcError> {
+        if let Err(e) = reload::check_restart(&*self.current.lock().await, &config) {
+            self.metrics.record_reload(source, ReloadOutcome::Invalid);
+            return Err(RpcError::new(
+                RpcError::INVALID_CONFIG,
+                format!("The configuration needs a restart to apply: {}", e),
+            ));
+        }
+        let outcome = reload::request(&self.reload_trigger, config, source)
+            .await
+            .map_err(|e| RpcError::new(RpcError::UNAVAILABLE, e))?;
+        self.metrics.record_reload(source, outcome);
+        match outcome {
+            ReloadOutcome::RolledBack => Err(RpcError::new(
+                RpcError::ROLLED_BACK,
+                "The configuration failed to start, the previous one is still running",
+            )),
+            ReloadOutcome::Invalid => Err(RpcError::new(
+                RpcError::INVALID_CONFIG,
+                "The configuration needs a restart to apply",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Validate a configuration sent by `apply` or `diff`
+    fn parse_config(&self, content: &str) -> Result<Config, RpcError> {
+        if content.trim().is_empty() {
+            return Err(RpcError::new(
+                RpcError::INVALID_PARAMS,
+                "No configuration provided",
+            ));
+        }
+
+        // Resolve includes next to the daemon's own config file
+        let result = match &self.config_path {
+            Some(path) => Config::check_at(content, path).into_result(),
+            None => Config::from_reader(content.as_bytes()),
+        };
+        result.map_err(|e| {
+            RpcError::new(
+                RpcError::INVALID_CONFIG,
+                format!("Failed to parse config: {}", e),
+            )
+        })
+    }
+
+    // Agree on the protocol and work out the connection's role
+    fn hello(&self, params: HelloParams, peer: Option<Role>) -> Result<(Role, Value), RpcError> {
+        check_protocol(&params)?;
+        let Some(role) = self.auth.role(peer, params.token.as_deref()) else {
+            warn!(
+                "Admin client {} failed to authenticate",
+                params.client.as_deref().unwrap_or("(unknown)")
+            );
+            return Err(RpcError::new(
+                RpcError::UNAUTHORIZED,
+                "A valid token or client certificate is required",
+            ));
+        };
+        let value = to_value(&HelloResult {
+            protocol: PROTOCOL_VERSION,
+            server: format!("muxd {}", mux_core::version()),
+            role,
+        })?;
+        Ok((role, value))
+    }
+}
+
+// Check that `role` allows `method`
+fn authorize(role: Option<Role>, method: &Method) -> Result<(), RpcError> {
+    let required = method.required_role();
+    match role {
+        Some(role) if role >= required => Ok(()),
+        Some(role) => Err(RpcError::new(
+            RpcError::FORBIDDEN,
+            format!(
+                "`{}` needs the {} role, this connection has {}",
+                method.name(),
+                required,
+                role
+            ),
+        )),
+        None => Err(RpcError::new(
+            RpcError::UNAUTHORIZED,
+            "Authentication required: use the JSON protocol with a token",
+        )),
     }
 }
 
+fn check_protocol(params: &HelloParams) -> Result<(), RpcError> {
+    if params.protocol != PROTOCOL_VERSION {
+        warn!(
+            "Admin client {} speaks unsupported protocol version {}",
+            params.client.as_deref().unwrap_or("(unknown)"),
+            params.protocol
+        );
+        return Err(RpcError::new(
+            RpcError::UNSUPPORTED_PROTOCOL,
+            format!(
+                "Protocol version {} is not supported, use {}",
+                params.protocol, PROTOCOL_VERSION
+            ),
+        )
+        .with_data(json!({ "supported": [PROTOCOL_VERSION] })));
+    }
+    Ok(())
+}
+
+fn no_history() -> RpcError {
+    RpcError::new(RpcError::UNAVAILABLE, "Config history is not available")
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::new(RpcError::INTERNAL_ERROR, e.to_string()))
+}
+
+// Phrase a result the way the legacy commands did
+fn legacy_response(method: &Method, result: Result<Value, RpcError>) -> AdminResponse {
+    let value = match result {
+        Ok(value) => value,
+        Err(e) => {
+            return AdminResponse {
+                success: false,
+                message: e.message,
+            }
+        }
+    };
+    let message = match method {
+        Method::Version => format!("sonos-mux v{}", mux_core::version()),
+        Method::Reload(_) => "Configuration reloaded successfully".to_string(),
+        Method::Apply(_) => "Configuration applied successfully".to_string(),
+        Method::Rollback(_) => format!(
+            "Rolling back to configuration #{} ({})",
+            value["id"],
+            value["source"].as_str().unwrap_or_default()
+        ),
+        Method::Stats
+        | Method::Inputs
+        | Method::Outputs
+        | Method::Routes
+        | Method::Levels
+        | Method::Config => {
+            serde_json::to_string_pretty(&value).unwrap_or_else(|_| "{}".to_string())
+        }
+        _ => value.to_string(),
+    };
+    AdminResponse {
+        success: true,
+        message,
+    }
+}
+
+// Read one line of at most `MAX_LINE` bytes into `line`; a longer one is an
+// error, so the connection is dropped rather than buffered without end
+async fn read_line<R>(reader: &mut BufReader<R>, line: &mut String) -> io::Result<usize>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let read = (&mut *reader).take(MAX_LINE).read_line(line).await?;
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Line longer than {} bytes", MAX_LINE),
+        ));
+    }
+    Ok(read)
+}
+
+// Read a configuration sent after the command line the way the legacy
+// clients frame it: blank lines before it are skipped, and it ends at the
+// next blank line or when the client closes its side
+async fn read_config<R>(reader: &mut BufReader<R>) -> io::Result<String>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let mut config_content = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if read_line(reader, &mut line).await? == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            if config_content.is_empty() {
+                continue;
+            }
+            break;
+        }
+        config_content.push_str(&line);
+        if config_content.len() as u64 > MAX_LINE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Configuration longer than {} bytes", MAX_LINE),
+            ));
+        }
+    }
+    Ok(config_content)
+}
+
 impl Clone for AdminServer {
     fn clone(&self) -> Self {
         Self {
             config_path: self.config_path.clone(),
             sonos_manager: self.sonos_manager.clone(),
             reload_trigger: self.reload_trigger.clone(),
+            current: self.current.clone(),
+            history: self.history.clone(),
+            metrics: self.metrics.clone(),
+            router: self.router.clone(),
+            auth: self.auth.clone(),
+            stream: self.stream.clone(),
+            upnp: self.upnp.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::read_certificates;
+    use crate::auth::tests::Pki;
+    use crate::reload::ReloadRequest;
+    use mux_core::admin::RouteParams;
+    use mux_core::config::{Change, Plan};
+    use mux_core::{InputStatus, OutputLevels, OutputStatus, RouteStatus};
+    use tokio_rustls::rustls::{ClientConfig, PrivateKey, RootCertStore, ServerName};
+    use tokio_rustls::TlsConnector;
+
+    const CONFIG: &str = r#"[[inputs]]
+id = "music"
+kind = "silence"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+"#;
+
+    fn server() -> (
+        AdminServer,
+        tokio::sync::mpsc::UnboundedReceiver<(Config, ReloadSource)>,
+    ) {
+        answering(ReloadOutcome::Success)
+    }
+
+    // A server whose reloads all end with `outcome`, passing on what it was
+    // asked to apply
+    fn answering(
+        outcome: ReloadOutcome,
+    ) -> (
+        AdminServer,
+        tokio::sync::mpsc::UnboundedReceiver<(Config, ReloadSource)>,
+    ) {
+        let (tx, mut requests) = tokio::sync::mpsc::channel::<ReloadRequest>(1);
+        let (applied, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some((config, source, done)) = requests.recv().await {
+                let _ = done.send(outcome);
+                let _ = applied.send((config, source));
+            }
+        });
+        let config = Config::check(CONFIG).into_result().unwrap();
+        let router = Router::new(&config).unwrap();
+        let server = AdminServer::new(
+            None,
+            Arc::new(Mutex::new(SonosManager::new())),
+            Arc::new(Mutex::new(Some(tx))),
+            Arc::new(Mutex::new(config)),
+            Arc::new(Mutex::new(None)),
+            Arc::new(Metrics::new()),
+            Arc::new(std::sync::Mutex::new(router)),
+        );
+        (server, rx)
+    }
+
+    async fn send(server: &AdminServer, request: &str) -> AdminResponse {
+        let (mut client, daemon) = tokio::io::duplex(64 * 1024);
+        client.write_all(request.as_bytes()).await.unwrap();
+        server
+            .handle_connection(daemon, Some(Role::Control))
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    async fn call<T>(client: &mut BufReader<T>, id: u64, method: Method) -> Response
+    where
+        T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let mut line = serde_json::to_string(&Request::new(id, &method)).unwrap();
+        line.push('\n');
+        client.get_mut().write_all(line.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        client.read_line(&mut response).await.unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_diff_does_not_apply() {
+        let (server, mut rx) = server();
+
+        let next = CONFIG.replace(
+            "outputs = [\"kitchen\"]",
+            "outputs = [\"kitchen\"]\ngain_db = -6.0",
+        );
+        // Legacy clients end the configuration with a blank line, and keep
+        // the connection open for the answer
+        let response = send(&server, &format!("diff\n{}\n", next.replace("\n\n", "\n"))).await;
+        assert!(response.success, "{}", response.message);
+        let plan: Plan = serde_json::from_str(&response.message).unwrap();
+        assert!(matches!(
+            plan.changes.as_slice(),
+            [Change::RouteRegained { input, .. }] if input == "music"
+        ));
+        assert!(plan.interrupted.is_empty());
+        assert!(rx.try_recv().is_err());
+
+        let response = send(&server, "diff\n[[inputs]\n\n").await;
+        assert!(!response.success);
+        assert!(response.message.starts_with("Failed to parse config"));
+    }
+
+    #[tokio::test]
+    async fn test_long_lines_close_the_connection() {
+        let (server, _rx) = server();
+        let (mut client, daemon) = tokio::io::duplex(64 * 1024);
+        let connection = tokio::spawn(async move { server.handle_connection(daemon, None).await });
+        // Nothing is answered, not even the handshake
+        let _ = client.write_all(&vec![b'{'; MAX_LINE as usize + 1]).await;
+        let error = connection.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_rolled_back_apply() {
+        let (server, mut rx) = answering(ReloadOutcome::RolledBack);
+        let next = CONFIG.replace("\"music\"", "\"ambient\"");
+        let response = send(&server, &format!("apply\n{}\n", next.replace("\n\n", "\n"))).await;
+        assert!(!response.success);
+        assert!(response.message.contains("previous one is still running"));
+        assert!(rx.recv().await.is_some());
+        assert!(server
+            .metrics
+            .render()
+            .contains("source=\"apply\",outcome=\"rolled_back\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_json_rpc() {
+        let (server, mut rx) = server();
+        let (client, daemon) = tokio::io::duplex(64 * 1024);
+        let connection =
+            tokio::spawn(
+                async move { server.handle_connection(daemon, Some(Role::Control)).await },
+            );
+        let mut client = BufReader::new(client);
+
+        // Nothing but `hello` is answered before the handshake
+        let response = call(&mut client, 1, Method::Stats).await;
+        assert_eq!(
+            response.into_result::<Value>().unwrap_err().code,
+            RpcError::HANDSHAKE_REQUIRED
+        );
+
+        let hello = |protocol| {
+            Method::Hello(HelloParams {
+                protocol,
+                client: Some("test".to_string()),
+                token: None,
+            })
+        };
+        let response = call(&mut client, 2, hello(99)).await;
+        let error = response.into_result::<HelloResult>().unwrap_err();
+        assert_eq!(error.code, RpcError::UNSUPPORTED_PROTOCOL);
+        assert_eq!(error.data, Some(json!({ "supported": [1] })));
+
+        let response = call(&mut client, 3, hello(PROTOCOL_VERSION)).await;
+        assert_eq!(response.id, json!(3));
+        let result: HelloResult = response.into_result().unwrap();
+        assert_eq!(result.protocol, PROTOCOL_VERSION);
+
+        // Blank lines in the config survive, unlike with the line protocol
+        let next = CONFIG.replace("\n\n", "\n\n\n");
+        let next = format!("{}\n[[inputs]]\nid = \"chime\"\nkind = \"silence\"\n", next);
+        let response = call(
+            &mut client,
+            4,
+            Method::Apply(ConfigParams {
+                config: next.clone(),
+            }),
+        )
+        .await;
+        let plan: Plan = response.into_result().unwrap();
+        assert_eq!(plan.changes.len(), 1);
+        let (applied, source) = rx.recv().await.unwrap();
+        assert_eq!(applied.inputs.len(), 2);
+        assert_eq!(source, ReloadSource::Apply);
+
+        let response = call(
+            &mut client,
+            5,
+            Method::Apply(ConfigParams {
+                config: "[[inputs]\n".to_string(),
+            }),
+        )
+        .await;
+        assert_eq!(
+            response.into_result::<Plan>().unwrap_err().code,
+            RpcError::INVALID_CONFIG
+        );
+
+        let response = call(&mut client, 6, Method::History).await;
+        assert_eq!(
+            response.into_result::<Value>().unwrap_err().code,
+            RpcError::UNAVAILABLE
+        );
+
+        drop(client);
+        connection.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_inspection() {
+        let (server, _rx) = server();
+        let (client, daemon) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move { server.handle_connection(daemon, Some(Role::Control)).await });
+        let mut client = BufReader::new(client);
+        let hello = Method::Hello(HelloParams {
+            protocol: PROTOCOL_VERSION,
+            client: None,
+            token: None,
+        });
+        call(&mut client, 1, hello).await;
+
+        let response = call(&mut client, 2, Method::Inputs).await;
+        let inputs: Vec<InputStatus> = response.into_result().unwrap();
+        assert_eq!(inputs[0].id, "music");
+        assert_eq!(inputs[0].outputs, vec!["kitchen"]);
+
+        // The test manager knows nothing of the room
+        let response = call(&mut client, 3, Method::Outputs).await;
+        let outputs: Vec<OutputStatus> = response.into_result().unwrap();
+        assert_eq!(outputs[0].room.as_deref(), Some("Kitchen"));
+        assert_eq!(outputs[0].healthy, None);
+
+        let response = call(&mut client, 4, Method::Routes).await;
+        let routes: Vec<RouteStatus> = response.into_result().unwrap();
+        assert_eq!(routes[0].output, "kitchen");
+        assert_eq!(routes[0].source.input, "music");
+
+        let response = call(&mut client, 5, Method::Levels).await;
+        let levels: Vec<OutputLevels> = response.into_result().unwrap();
+        assert_eq!(levels[0].sources.len(), 1);
+
+        let route = |input: &str, gain_db, muted| {
+            Method::SetRoute(RouteParams {
+                input: input.to_string(),
+                output: "kitchen".to_string(),
+                gain_db,
+                muted,
+            })
+        };
+        let response = call(&mut client, 7, route("music", Some(-9.0), Some(true))).await;
+        let route_status: RouteStatus = response.into_result().unwrap();
+        assert_eq!(route_status.source.gain_db, -9.0);
+        assert!(route_status.source.muted);
+        let response = call(&mut client, 8, route("music", None, Some(false))).await;
+        let route_status: RouteStatus = response.into_result().unwrap();
+        assert_eq!(route_status.source.gain_db, -9.0);
+        assert!(!route_status.source.muted);
+        // Gains stop where a config's would
+        let response = call(&mut client, 10, route("music", Some(40.0), None)).await;
+        let route_status: RouteStatus = response.into_result().unwrap();
+        assert_eq!(route_status.source.gain_db, 24.0);
+        let response = call(&mut client, 9, route("doorbell", Some(0.0), None)).await;
+        let error = response.into_result::<RouteStatus>().unwrap_err();
+        assert_eq!(error.code, RpcError::INVALID_PARAMS);
+
+        let response = call(&mut client, 6, Method::Config).await;
+        let loaded: LoadedConfig = response.into_result().unwrap();
+        assert_eq!(loaded.history_id, None);
+        let config = Config::check(&loaded.config).into_result().unwrap();
+        assert_eq!(config.routes[0].outputs, vec!["kitchen"]);
+    }
+
+    #[tokio::test]
+    async fn test_auth() {
+        let pki = Pki::new();
+        pki.issue("dashboard", &[]);
+        let auth = Arc::new(
+            Auth::load(&pki.args(&[("s3cret", Role::Control), ("peek", Role::Read)])).unwrap(),
+        );
+        let (server, _rx) = server();
+        let server = server.with_auth(auth.clone());
+        let hello = |token: Option<&str>| {
+            Method::Hello(HelloParams {
+                protocol: PROTOCOL_VERSION,
+                client: None,
+                token: token.map(str::to_string),
+            })
+        };
+        let apply = || {
+            Method::Apply(ConfigParams {
+                config: CONFIG.to_string(),
+            })
+        };
+
+        // Without a certificate, only a token gets a client in
+        let (client, daemon) = tokio::io::duplex(64 * 1024);
+        let connection = server.clone();
+        tokio::spawn(async move { connection.handle_connection(daemon, None).await });
+        let mut client = BufReader::new(client);
+        let response = call(&mut client, 1, hello(None)).await;
+        let error = response.into_result::<HelloResult>().unwrap_err();
+        assert_eq!(error.code, RpcError::UNAUTHORIZED);
+        let response = call(&mut client, 2, Method::Stats).await;
+        let error = response.into_result::<Value>().unwrap_err();
+        assert_eq!(error.code, RpcError::HANDSHAKE_REQUIRED);
+
+        let response = call(&mut client, 3, hello(Some("peek"))).await;
+        assert_eq!(
+            response.into_result::<HelloResult>().unwrap().role,
+            Role::Read
+        );
+        let response = call(&mut client, 4, Method::Version).await;
+        assert!(response.into_result::<Value>().is_ok());
+        let response = call(&mut client, 5, apply()).await;
+        let error = response.into_result::<Plan>().unwrap_err();
+        assert_eq!(error.code, RpcError::FORBIDDEN);
+
+        // Legacy commands carry no token
+        let (mut legacy, daemon) = tokio::io::duplex(64 * 1024);
+        legacy.write_all(b"version\n").await.unwrap();
+        legacy.shutdown().await.unwrap();
+        server.handle_connection(daemon, None).await.unwrap();
+        let mut response = String::new();
+        legacy.read_to_string(&mut response).await.unwrap();
+        let response: AdminResponse = serde_json::from_str(&response).unwrap();
+        assert!(!response.success);
+
+        // A client certificate is enough for the read-only role
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&read_certificates(&pki.path("ca.pem")).unwrap()[0])
+            .unwrap();
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                read_certificates(&pki.path("dashboard.pem")).unwrap(),
+                PrivateKey(
+                    rustls_pemfile::pkcs8_private_keys(&mut std::io::BufReader::new(
+                        std::fs::File::open(pki.path("dashboard.key")).unwrap(),
+                    ))
+                    .unwrap()
+                    .remove(0),
+                ),
+            )
+            .unwrap();
+        let (client, daemon) = tokio::io::duplex(64 * 1024);
+        let name = ServerName::try_from("localhost").unwrap();
+        let (client, daemon) = tokio::join!(
+            TlsConnector::from(Arc::new(config)).connect(name, client),
+            auth.tls().unwrap().accept(daemon),
+        );
+        let daemon = daemon.unwrap();
+        let peer = auth.peer_role(daemon.get_ref().1.peer_certificates());
+        tokio::spawn(async move { server.handle_connection(daemon, peer).await });
+        let mut client = BufReader::new(client.unwrap());
+        let response = call(&mut client, 1, hello(None)).await;
+        assert_eq!(
+            response.into_result::<HelloResult>().unwrap().role,
+            Role::Read
+        );
+
+        // ...and a control token raises it
+        let response = call(&mut client, 2, hello(Some("s3cret"))).await;
+        assert_eq!(
+            response.into_result::<HelloResult>().unwrap().role,
+            Role::Control
+        );
+        let response = call(&mut client, 3, apply()).await;
+        assert!(response.into_result::<Plan>().is_ok());
+    }
+
+    #[test]
+    fn test_socket_permissions() {
+        let permissions = SocketPermissions::resolve(Some("root"), Some("0"), Some("640")).unwrap();
+        assert_eq!(permissions.owner, Some(0));
+        assert_eq!(permissions.group, Some(0));
+        assert_eq!(permissions.mode, Some(0o640));
+        assert!(SocketPermissions::resolve(Some("no-such-user"), None, None).is_err());
+        assert!(SocketPermissions::resolve(None, None, Some("rw-rw----")).is_err());
+        assert!(SocketPermissions::resolve(None, None, Some("1777")).is_err());
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sock");
+        std::fs::write(&path, "").unwrap();
+        let mode = SocketPermissions::resolve(None, None, Some("600")).unwrap();
+        mode.apply(path.to_str().unwrap()).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,341 @@
+use clap::Args;
+use log::warn;
+use mux_core::admin::Role;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient,
+};
+use tokio_rustls::rustls::{self, Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+/// Credentials for the TCP admin server and the HTTP API
+#[derive(Args, Debug, Clone, Default)]
+pub struct AuthArgs {
+    /// File of tokens granting the control role, one per line
+    #[arg(long)]
+    pub token_file: Option<PathBuf>,
+
+    /// File of tokens granting the read-only role, one per line
+    #[arg(long)]
+    pub read_token_file: Option<PathBuf>,
+
+    /// Certificate chain to serve TLS with, in PEM
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key for --tls-cert, in PEM
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// CA that client certificates must be signed by, in PEM; enables mTLS
+    #[arg(long, requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Common name of a client certificate granted the control role; other
+    /// client certificates get the read-only role
+    #[arg(long = "tls-control-name", value_name = "NAME")]
+    pub tls_control_names: Vec<String>,
+}
+
+/// Who may use the TCP admin server and the HTTP API
+///
+/// With neither tokens nor a client CA configured, every client has the
+/// control role, as before authentication existed. Otherwise a client needs
+/// a token or a client certificate, and gets the highest role of the two.
+/// The Unix socket is guarded by its file permissions instead.
+#[derive(Default)]
+pub struct Auth {
+    tokens: Vec<(String, Role)>,
+    tls: Option<TlsAcceptor>,
+    client_ca: bool,
+    control_names: Vec<String>,
+}
+
+impl Auth {
+    pub fn load(args: &AuthArgs) -> io::Result<Self> {
+        let mut tokens = Vec::new();
+        if let Some(path) = &args.token_file {
+            tokens.extend(read_tokens(path)?.into_iter().map(|t| (t, Role::Control)));
+        }
+        if let Some(path) = &args.read_token_file {
+            tokens.extend(read_tokens(path)?.into_iter().map(|t| (t, Role::Read)));
+        }
+
+        let tls = match (&args.tls_cert, &args.tls_key) {
+            (Some(cert), Some(key)) => Some(TlsAcceptor::from(Arc::new(server_config(
+                cert,
+                key,
+                args.tls_client_ca.as_deref(),
+                // Clients with a token don't need a certificate too
+                tokens.is_empty(),
+            )?))),
+            _ => None,
+        };
+
+        Ok(Self {
+            tokens,
+            tls,
+            client_ca: args.tls_client_ca.is_some(),
+            control_names: args.tls_control_names.clone(),
+        })
+    }
+
+    /// Whether clients must prove who they are
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || self.client_ca
+    }
+
+    pub fn tls(&self) -> Option<&TlsAcceptor> {
+        self.tls.as_ref()
+    }
+
+    /// The role a connection has before sending a token: everything with
+    /// authentication off, otherwise whatever its client certificate grants
+    pub fn peer_role(&self, certificates: Option<&[Certificate]>) -> Option<Role> {
+        if !self.is_enabled() {
+            return Some(Role::Control);
+        }
+        // The TLS handshake already checked the chain against the CA
+        let certificate = certificates?.first()?;
+        let name = common_name(&certificate.0);
+        match name {
+            Some(name) if self.control_names.contains(&name) => Some(Role::Control),
+            _ => Some(Role::Read),
+        }
+    }
+
+    /// The role `token` grants, if any
+    pub fn token_role(&self, token: &str) -> Option<Role> {
+        // Compare every token in full, so timing gives nothing away
+        self.tokens
+            .iter()
+            .filter(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|(_, role)| *role)
+            .max()
+    }
+
+    /// The highest role of a connection's certificate and token
+    pub fn role(&self, peer: Option<Role>, token: Option<&str>) -> Option<Role> {
+        peer.max(token.and_then(|token| self.token_role(token)))
+    }
+
+    /// Warn about settings that leave credentials exposed
+    pub fn check_exposure(&self, what: &str, loopback: bool) {
+        if !self.tokens.is_empty() && self.tls.is_none() && !loopback {
+            warn!(
+                "{} accepts tokens without TLS; they are sent in clear text",
+                what
+            );
+        }
+        if !self.is_enabled() && !loopback {
+            warn!(
+                "{} is reachable from other hosts without authentication",
+                what
+            );
+        }
+    }
+}
+
+// Tokens are one per line; blank lines and `#` comments are ignored
+fn read_tokens(path: &Path) -> io::Result<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let tokens: Vec<String> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    if tokens.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no tokens", path.display()),
+        ));
+    }
+    Ok(tokens)
+}
+
+fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+    require_client_cert: bool,
+) -> io::Result<ServerConfig> {
+    let invalid = |e: rustls::Error| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+
+    let certificates = read_certificates(cert)?;
+    let key = read_key(key)?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(path)? {
+                roots.add(&certificate).map_err(invalid)?;
+            }
+            builder.with_client_cert_verifier(if require_client_cert {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            })
+        }
+        None => builder.with_no_client_auth(),
+    };
+    builder.with_single_cert(certificates, key).map_err(invalid)
+}
+
+pub(crate) fn read_certificates(path: &Path) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certificates: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certificates.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no certificates", path.display()),
+        ));
+    }
+    Ok(certificates)
+}
+
+fn read_key(path: &Path) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: no private key", path.display()),
+                ))
+            }
+        }
+    }
+}
+
+fn common_name(der: &[u8]) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(der).ok()?;
+    let name = certificate.subject().iter_common_name().next()?;
+    name.as_str().ok().map(str::to_string)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    /// A CA, a server certificate for localhost and client certificates, as
+    /// PEM files in a temporary directory
+    pub struct Pki {
+        pub dir: TempDir,
+        ca: rcgen::Certificate,
+    }
+
+    impl Pki {
+        pub fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::new());
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "sonos-mux test CA");
+            let ca = rcgen::Certificate::from_params(params).unwrap();
+
+            let pki = Self {
+                dir: TempDir::new().unwrap(),
+                ca,
+            };
+            fs::write(pki.path("ca.pem"), pki.ca.serialize_pem().unwrap()).unwrap();
+            pki.issue("server", &["localhost"]);
+            pki
+        }
+
+        /// Write `<name>.pem` and `<name>.key`, with `name` as common name
+        pub fn issue(&self, name: &str, hosts: &[&str]) {
+            let mut params =
+                CertificateParams::new(hosts.iter().map(|h| h.to_string()).collect::<Vec<_>>());
+            params.distinguished_name.push(DnType::CommonName, name);
+            let certificate = rcgen::Certificate::from_params(params).unwrap();
+            fs::write(
+                self.path(&format!("{}.pem", name)),
+                certificate.serialize_pem_with_signer(&self.ca).unwrap(),
+            )
+            .unwrap();
+            fs::write(
+                self.path(&format!("{}.key", name)),
+                certificate.serialize_private_key_pem(),
+            )
+            .unwrap();
+        }
+
+        pub fn path(&self, file: &str) -> PathBuf {
+            self.dir.path().join(file)
+        }
+
+        pub fn args(&self, tokens: &[(&str, Role)]) -> AuthArgs {
+            let write = |file: &str, role: Role| {
+                let mut f = File::create(self.path(file)).unwrap();
+                writeln!(f, "# {} tokens", role).unwrap();
+                for (token, _) in tokens.iter().filter(|(_, r)| *r == role) {
+                    writeln!(f, "{}", token).unwrap();
+                }
+                tokens
+                    .iter()
+                    .any(|(_, r)| *r == role)
+                    .then(|| self.path(file))
+            };
+            AuthArgs {
+                token_file: write("control.tokens", Role::Control),
+                read_token_file: write("read.tokens", Role::Read),
+                tls_cert: Some(self.path("server.pem")),
+                tls_key: Some(self.path("server.key")),
+                tls_client_ca: Some(self.path("ca.pem")),
+                tls_control_names: vec!["operator".to_string()],
+            }
+        }
+    }
+
+    #[test]
+    fn test_roles() {
+        let pki = Pki::new();
+        pki.issue("operator", &[]);
+        pki.issue("dashboard", &[]);
+
+        // Authentication is off without tokens or a client CA
+        let auth = Auth::load(&AuthArgs::default()).unwrap();
+        assert!(!auth.is_enabled());
+        assert_eq!(auth.peer_role(None), Some(Role::Control));
+
+        let auth =
+            Auth::load(&pki.args(&[("s3cret", Role::Control), ("peek", Role::Read)])).unwrap();
+        assert!(auth.is_enabled());
+        assert!(auth.tls().is_some());
+        assert_eq!(auth.peer_role(None), None);
+        assert_eq!(auth.token_role("s3cret"), Some(Role::Control));
+        assert_eq!(auth.token_role("peek"), Some(Role::Read));
+        assert_eq!(auth.token_role("s3cre"), None);
+        assert_eq!(auth.token_role("# control tokens"), None);
+
+        let cert = |name: &str| read_certificates(&pki.path(&format!("{}.pem", name))).unwrap();
+        let operator = auth.peer_role(Some(&cert("operator")));
+        assert_eq!(operator, Some(Role::Control));
+        let dashboard = auth.peer_role(Some(&cert("dashboard")));
+        assert_eq!(dashboard, Some(Role::Read));
+
+        // A token can raise a certificate's role but not lower it
+        assert_eq!(auth.role(dashboard, Some("s3cret")), Some(Role::Control));
+        assert_eq!(auth.role(operator, Some("peek")), Some(Role::Control));
+        assert_eq!(auth.role(None, Some("wrong")), None);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::metrics::ReloadSource;
+use log::warn;
+use mux_core::{Config, ConfigError};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX: &str = "history.json";
+
+/// One configuration the daemon ran
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Sequence number, increasing across restarts
+    pub id: u64,
+    /// When the configuration was applied, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub source: ReloadSource,
+}
+
+/// Ring of the last applied configurations, kept in a state directory
+///
+/// Each configuration is stored fully resolved, with includes merged and
+/// variables substituted, so it can be restored even if the files it came
+/// from have changed since. The files can contain secrets and are only
+/// readable by the daemon's user.
+pub struct History {
+    dir: PathBuf,
+    limit: usize,
+    // Oldest first
+    entries: Vec<Entry>,
+}
+
+impl History {
+    /// Open the history in `dir`, creating the directory if needed
+    pub fn open(dir: &Path, limit: usize) -> io::Result<Self> {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+
+        let entries = match fs::read_to_string(dir.join(INDEX)) {
+            Ok(index) => serde_json::from_str(&index).unwrap_or_else(|e| {
+                warn!("Ignoring corrupt config history index: {}", e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            limit: limit.max(1),
+            entries,
+        })
+    }
+
+    /// Entries newest first; index 0 is the running configuration
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().rev()
+    }
+
+    /// Record `config` as the running configuration, dropping the oldest
+    /// entries beyond the limit
+    pub fn record(&mut self, config: &Config, source: ReloadSource) -> io::Result<Entry> {
+        let entry = Entry {
+            id: self.entries.last().map_or(1, |last| last.id + 1),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            source,
+        };
+
+        // Includes are already merged in
+        let mut config = config.clone();
+        config.include.clear();
+        let content = toml::to_string(&config).map_err(io::Error::other)?;
+        // Values are already substituted; keep them from being expanded again
+        let content = content.replace("${", "$${");
+        write_private(&self.path(entry.id), content.as_bytes())?;
+
+        self.entries.push(entry.clone());
+        while self.entries.len() > self.limit {
+            let old = self.entries.remove(0);
+            if let Err(e) = fs::remove_file(self.path(old.id)) {
+                warn!("Failed to remove old config history entry: {}", e);
+            }
+        }
+
+        let index = serde_json::to_vec_pretty(&self.entries)?;
+        write_private(&self.dir.join(INDEX), &index)?;
+        Ok(entry)
+    }
+
+    /// Load the configuration `n` entries back; 0 is the running one
+    pub fn get(&self, n: usize) -> Result<(Entry, Config), ConfigError> {
+        let entry = self
+            .entries()
+            .nth(n)
+            .cloned()
+            .ok_or_else(|| ConfigError::Validation(format!("No config history entry {}", n)))?;
+        let config = Config::load(self.path(entry.id))?;
+        Ok((entry, config))
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.toml", id))
+    }
+}
+
+// Write a file only the owner can read, replacing it atomically
+fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config(gain_db: f32) -> Config {
+        Config::check(&format!(
+            r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+gain_db = {:.1}
+"#,
+            gain_db
+        ))
+        .into_result()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ring() {
+        let dir = TempDir::new().unwrap();
+        let mut history = History::open(dir.path(), 3).unwrap();
+        for (i, source) in [
+            ReloadSource::Startup,
+            ReloadSource::Apply,
+            ReloadSource::Watcher,
+            ReloadSource::Sighup,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            history.record(&config(-(i as f32)), source).unwrap();
+        }
+
+        let ids: Vec<_> = history.entries().map(|e| e.id).collect();
+        assert_eq!(ids, vec![4, 3, 2]);
+        assert!(!dir.path().join("1.toml").exists());
+
+        let (entry, restored) = history.get(1).unwrap();
+        assert_eq!(entry.source, ReloadSource::Watcher);
+        assert_eq!(restored.routes[0].gain_db, -2.0);
+        assert!(history.get(3).is_err());
+
+        // Entries survive a restart and numbering continues
+        let mut history = History::open(dir.path(), 3).unwrap();
+        assert_eq!(history.entries().count(), 3);
+        let entry = history
+            .record(&config(0.0), ReloadSource::Rollback)
+            .unwrap();
+        assert_eq!(entry.id, 5);
+    }
+}
@@ -0,0 +1,383 @@
+use crate::auth::Auth;
+use crate::metrics::{render_outputs, Metrics};
+use log::{debug, error, info, warn};
+use mux_core::admin::Role;
+use mux_core::{OutputKind, Router, SonosManager, UpnpManager};
+use serde::Serialize;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, Mutex};
+use warp::http::StatusCode;
+use warp::hyper::server::conn::Http;
+use warp::{Filter, Rejection, Reply};
+
+#[derive(Debug, Serialize, Clone)]
+struct HealthResponse {
+    status: String,
+    version: String,
+    uptime_sec: u64,
+    outputs: Vec<OutputHealth>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct OutputHealth {
+    id: String,
+    kind: String,
+    room: Option<String>,
+    healthy: bool,
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// The HTTP API: `/healthz` and `/metrics`
+#[derive(Clone)]
+pub struct HttpServer {
+    sonos_manager: Arc<Mutex<SonosManager>>,
+    metrics: Arc<Metrics>,
+    start_time: Instant,
+    auth: Arc<Auth>,
+    // Origins allowed to call the API from a browser; empty allows any
+    cors_origins: Vec<String>,
+    // The configured outputs, as reloads leave them, and the health of
+    // those the daemon writes to itself
+    router: Option<Arc<std::sync::Mutex<Router>>>,
+    upnp: Option<Arc<UpnpManager>>,
+}
+
+impl HttpServer {
+    pub fn new(
+        sonos_manager: Arc<Mutex<SonosManager>>,
+        metrics: Arc<Metrics>,
+        start_time: Instant,
+        auth: Arc<Auth>,
+        cors_origins: Vec<String>,
+    ) -> Self {
+        Self {
+            sonos_manager,
+            metrics,
+            start_time,
+            auth,
+            cors_origins,
+            router: None,
+            upnp: None,
+        }
+    }
+
+    /// Report the outputs `router` is configured with, and those that take
+    /// PCM, like recorders, as it sees them
+    pub fn with_router(mut self, router: Arc<std::sync::Mutex<Router>>) -> Self {
+        self.router = Some(router);
+        self
+    }
+
+    /// Report `upnp` outputs as `manager` finds their renderers
+    pub fn with_upnp(mut self, manager: Arc<UpnpManager>) -> Self {
+        self.upnp = Some(manager);
+        self
+    }
+
+    /// Serve on `addr` until `shutdown` fires
+    pub async fn run(
+        self,
+        addr: SocketAddr,
+        hostname: &str,
+        mut shutdown: oneshot::Receiver<()>,
+    ) -> io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        self.auth
+            .check_exposure("The HTTP API", addr.ip().is_loopback());
+        info!(
+            "Health check endpoint available at {}://{}:{}/healthz",
+            if self.auth.tls().is_some() {
+                "https"
+            } else {
+                "http"
+            },
+            hostname,
+            listener.local_addr()?.port()
+        );
+
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("Error accepting HTTP connection: {}", e);
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => return Ok(()),
+            };
+
+            // Each connection gets the routes for what its certificate grants
+            let server = self.clone();
+            tokio::spawn(async move {
+                let result = match server.auth.tls() {
+                    Some(acceptor) => match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            let role = server
+                                .auth
+                                .peer_role(stream.get_ref().1.peer_certificates());
+                            let service = warp::service(server.routes(role));
+                            Http::new().serve_connection(stream, service).await
+                        }
+                        Err(e) => {
+                            warn!("TLS handshake with {} failed: {}", peer, e);
+                            return;
+                        }
+                    },
+                    None => {
+                        let service = warp::service(server.routes(server.auth.peer_role(None)));
+                        Http::new().serve_connection(stream, service).await
+                    }
+                };
+                if let Err(e) = result {
+                    debug!("HTTP connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    // The API for a connection whose transport grants `peer`
+    fn routes(
+        &self,
+        peer: Option<Role>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        let health_check = {
+            let server = self.clone();
+            warp::path("healthz").and_then(move || {
+                let server = server.clone();
+                async move { Ok::<_, Rejection>(warp::reply::json(&server.health().await)) }
+            })
+        };
+
+        let metrics_endpoint = {
+            let metrics = self.metrics.clone();
+            let router = self.router.clone();
+            warp::path("metrics").map(move || {
+                let mut body = metrics.render();
+                if let Some(outputs) = router.as_ref().and_then(|r| r.lock().ok()) {
+                    body.push_str(&render_outputs(&outputs.outputs()));
+                }
+                warp::reply::with_header(body, "content-type", "text/plain; version=0.0.4")
+            })
+        };
+
+        // Every endpoint is read-only, so any role will do
+        let cors = warp::cors()
+            .allow_methods(["GET"])
+            .allow_headers(["authorization"]);
+        let cors = if self.cors_origins.is_empty() {
+            cors.allow_any_origin()
+        } else {
+            cors.allow_origins(self.cors_origins.iter().map(String::as_str))
+        };
+        authorize(self.auth.clone(), peer)
+            .and(health_check.or(metrics_endpoint))
+            .with(cors)
+            .recover(unauthorized)
+    }
+
+    async fn health(&self) -> HealthResponse {
+        let uptime_sec = self.start_time.elapsed().as_secs();
+        let manager = self.sonos_manager.lock().await;
+        let sonos_status = manager.health_status().await;
+        let (configured, router_status) = match &self.router {
+            Some(router) => router
+                .lock()
+                .map(|r| (r.output_configs().to_vec(), r.outputs()))
+                .unwrap_or_default(),
+            None => Default::default(),
+        };
+        let upnp_status = match &self.upnp {
+            Some(manager) => manager.health_status().await,
+            None => Vec::new(),
+        };
+
+        let mut outputs = Vec::new();
+        for output_config in &configured {
+            let (room, healthy) = match &output_config.kind {
+                OutputKind::Sonos { room, .. } => (
+                    Some(room.clone()),
+                    sonos_status.iter().any(|s| &s.room == room && s.healthy),
+                ),
+                OutputKind::Upnp { .. } => {
+                    let status = upnp_status.iter().find(|s| s.output == output_config.id);
+                    (
+                        status.and_then(|s| s.name.clone()),
+                        status.is_some_and(|s| s.healthy),
+                    )
+                }
+                // Outputs the daemon feeds itself, as the router sees them
+                OutputKind::Alsa { .. }
+                | OutputKind::Http { .. }
+                | OutputKind::Icecast { .. }
+                | OutputKind::Rtp { .. }
+                | OutputKind::File { .. }
+                | OutputKind::Null { .. } => (
+                    None,
+                    router_status
+                        .iter()
+                        .find(|s| s.id == output_config.id)
+                        .and_then(|s| s.healthy)
+                        .unwrap_or(true),
+                ),
+            };
+
+            outputs.push(OutputHealth {
+                id: output_config.id.clone(),
+                kind: output_config.kind.name().to_string(),
+                room,
+                healthy,
+            });
+        }
+
+        let all_healthy = outputs.iter().all(|o| o.healthy);
+
+        HealthResponse {
+            status: if all_healthy {
+                "ok".to_string()
+            } else {
+                "degraded".to_string()
+            },
+            version: mux_core::version().to_string(),
+            uptime_sec,
+            outputs,
+        }
+    }
+}
+
+// Pass requests from clients with a role, from their certificate or an
+// `Authorization: Bearer` token
+fn authorize(
+    auth: Arc<Auth>,
+    peer: Option<Role>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let auth = auth.clone();
+            async move {
+                let token = header
+                    .as_deref()
+                    .and_then(|header| header.strip_prefix("Bearer "))
+                    .map(str::trim);
+                match auth.role(peer, token) {
+                    Some(_) => Ok(()),
+                    None => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+async fn unauthorized(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+    if rejection.find::<Unauthorized>().is_none() {
+        return Err(rejection);
+    }
+    let body = warp::reply::json(&serde_json::json!({
+        "error": "A valid token or client certificate is required",
+    }));
+    Ok(warp::reply::with_header(
+        warp::reply::with_status(body, StatusCode::UNAUTHORIZED),
+        "www-authenticate",
+        "Bearer",
+    )
+    .into_response())
+}
+
+/// Check a `--cors-origin` value, which warp would otherwise panic on
+pub fn parse_origin(origin: &str) -> Result<String, String> {
+    let valid = ["http://", "https://"].iter().any(|scheme| {
+        origin
+            .strip_prefix(scheme)
+            .is_some_and(|host| !host.is_empty() && !host.contains('/'))
+    });
+    if valid && warp::http::HeaderValue::from_str(origin).is_ok() {
+        Ok(origin.to_string())
+    } else {
+        Err(format!(
+            "Invalid origin `{}`, expected e.g. https://dashboard.local:3000",
+            origin
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::tests::Pki;
+
+    fn server(auth: Auth, cors_origins: Vec<String>) -> HttpServer {
+        HttpServer::new(
+            Arc::new(Mutex::new(SonosManager::new())),
+            Arc::new(Metrics::new()),
+            Instant::now(),
+            Arc::new(auth),
+            cors_origins,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_tokens_and_cors() {
+        let pki = Pki::new();
+        let mut args = pki.args(&[("peek", Role::Read)]);
+        // Plain HTTP, tokens only
+        args.tls_cert = None;
+        args.tls_key = None;
+        args.tls_client_ca = None;
+        let server = server(
+            Auth::load(&args).unwrap(),
+            vec!["https://dashboard.local".to_string()],
+        );
+        let routes = server.routes(server.auth.peer_role(None));
+
+        let response = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["www-authenticate"], "Bearer");
+
+        let response = warp::test::request()
+            .path("/healthz")
+            .header("authorization", "Bearer peek")
+            .header("origin", "https://dashboard.local")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://dashboard.local"
+        );
+
+        let response = warp::test::request()
+            .path("/healthz")
+            .header("authorization", "Bearer peek")
+            .header("origin", "https://evil.example")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Without authentication configured everything is open
+        let server = server_without_auth();
+        let routes = server.routes(server.auth.peer_role(None));
+        let response = warp::test::request().path("/healthz").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    fn server_without_auth() -> HttpServer {
+        server(Auth::default(), Vec::new())
+    }
+
+    #[test]
+    fn test_parse_origin() {
+        assert!(parse_origin("https://dashboard.local:3000").is_ok());
+        assert!(parse_origin("http://10.0.0.2").is_ok());
+        assert!(parse_origin("dashboard.local").is_err());
+        assert!(parse_origin("https://dashboard.local/app").is_err());
+        assert!(parse_origin("https://").is_err());
+    }
+}
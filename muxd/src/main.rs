@@ -1,8 +1,15 @@
 use clap::Parser;
-use crossbeam_channel::bounded;
 use log::{error, info, warn};
-use mux_core::{AudioBuffer, Config, HttpStreamer, Lame, MuxError, SonosManager};
-use serde::Serialize;
+use mux_core::config::Codec;
+use mux_core::output::http::HttpOutput;
+use mux_core::output::upnp::Target;
+use mux_core::stream::main_path;
+use mux_core::{
+    create_encoder, Config, HttpStreamer, Level, MuxError, OutputError, OutputKind, PcmSink,
+    Router, SonosManager, UpnpManager, UpnpOutput,
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
@@ -12,9 +19,21 @@ use std::thread;
 use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 use tokio::sync::{mpsc, oneshot, Mutex};
-use warp::Filter;
 
 mod admin;
+mod auth;
+mod history;
+mod http;
+mod metrics;
+mod reload;
+mod watcher;
+
+use admin::SocketPermissions;
+use auth::{Auth, AuthArgs};
+use history::History;
+use http::HttpServer;
+use metrics::{Metrics, ReloadOutcome, ReloadSource};
+use reload::{AttachSinks, ReloadRequest, ReloadTrigger, Reloader};
 
 #[derive(Parser)]
 #[command(author, version, about = "Sonos audio multiplexer daemon")]
@@ -27,49 +46,173 @@ struct Args {
     #[arg(long, default_value = "/run/sonos-mux.sock")]
     socket: String,
 
+    /// Owner of the Unix socket, as a user name or ID
+    #[arg(long)]
+    socket_owner: Option<String>,
+
+    /// Group of the Unix socket, as a group name or ID
+    #[arg(long)]
+    socket_group: Option<String>,
+
+    /// Permissions of the Unix socket, in octal (e.g. 660)
+    #[arg(long)]
+    socket_mode: Option<String>,
+
     /// TCP port for admin commands (0 to disable)
     #[arg(long, default_value = "8383")]
     admin_port: u16,
-}
 
-// Calculate RMS loudness of audio buffer
-fn calculate_loudness(buffer: &[i16]) -> f32 {
-    if buffer.is_empty() {
-        return -60.0; // Silent
-    }
+    /// Address the TCP admin server listens on
+    #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    admin_bind: IpAddr,
 
-    let sum_squares: f64 = buffer
-        .iter()
-        .map(|&sample| {
-            let sample_f64 = f64::from(sample) / 32768.0;
-            sample_f64 * sample_f64
-        })
-        .sum();
+    /// Port for the health and metrics API
+    #[arg(long, default_value = "8080")]
+    http_port: u16,
 
-    let rms = (sum_squares / buffer.len() as f64).sqrt();
+    /// Address the health and metrics API listens on
+    #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    http_bind: IpAddr,
 
-    // Convert to dB, with floor of -60 dB
-    if rms > 0.0 {
-        (20.0 * rms.log10()) as f32
-    } else {
-        -60.0
-    }
+    /// Port of the stream server, which also serves `http` outputs
+    /// without a port of their own
+    #[arg(long, default_value = "8000")]
+    stream_port: u16,
+
+    /// Address the stream server listens on
+    #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    stream_bind: IpAddr,
+
+    /// Origin allowed to call the HTTP API from a browser; repeat for more.
+    /// Any origin is allowed if none is given
+    #[arg(long = "cors-origin", value_name = "ORIGIN", value_parser = http::parse_origin)]
+    cors_origins: Vec<String>,
+
+    #[command(flatten)]
+    auth: AuthArgs,
+
+    /// Reload automatically when the config file or one it includes changes
+    #[arg(long)]
+    watch: bool,
+
+    /// How long config files must be quiet before a watched change is loaded
+    #[arg(long, default_value = "500")]
+    watch_debounce_ms: u64,
+
+    /// Directory for daemon state, such as the config history
+    #[arg(long, default_value = "/var/lib/sonos-mux")]
+    state_dir: PathBuf,
+
+    /// How many applied configurations to keep for rollback
+    #[arg(long, default_value = "10")]
+    history_size: usize,
+
+    /// How long a new configuration's inputs get to start before it is
+    /// rolled back
+    #[arg(long, default_value = "5000")]
+    rollback_grace_ms: u64,
 }
 
-#[derive(Debug, Serialize, Clone)]
-struct HealthResponse {
-    status: String,
-    version: String,
-    uptime_sec: u64,
-    outputs: Vec<OutputHealth>,
+// The stream servers `http` outputs opened on ports of their own
+type Streamers = Arc<std::sync::Mutex<HashMap<SocketAddr, Arc<HttpStreamer>>>>;
+
+// Serve the `http` outputs in `config` that have no sink yet, from the main
+// stream server or from one of their own when they name another port. The
+// paths of those in `previous` that the router doesn't carry on with are let
+// go first, so a changed output can take its path again
+fn attach_http_outputs(
+    previous: Option<&Config>,
+    config: &Config,
+    router: &mut Router,
+    streamer: &Arc<HttpStreamer>,
+    main_port: u16,
+    streamers: &Streamers,
+    rt: &Runtime,
+) {
+    // Where an output is served from; `None` for the main server
+    let addr = |host: &Option<String>, port: &Option<u16>| {
+        let Some(port) = port.filter(|port| *port != main_port) else {
+            return Ok(None);
+        };
+        host.as_deref()
+            .unwrap_or("0.0.0.0")
+            .parse::<IpAddr>()
+            .map(|ip| Some(SocketAddr::new(ip, port)))
+            .map_err(|e| OutputError::StreamSetup(format!("Invalid host: {}", e)))
+    };
+
+    for output in previous.map_or(&[][..], |previous| &previous.outputs) {
+        let (OutputKind::Http { host, port, .. }, Some(path)) =
+            (&output.kind, output.stream_path())
+        else {
+            continue;
+        };
+        if router.has_sink(&output.id) {
+            continue;
+        }
+        let server = match addr(host, port) {
+            Ok(None) => Some(streamer.clone()),
+            Ok(Some(addr)) => streamers.lock().unwrap().get(&addr).cloned(),
+            Err(_) => None,
+        };
+        if let Some(server) = server {
+            server.remove(&path);
+        }
+    }
+
+    for output in &config.outputs {
+        let OutputKind::Http { host, port, .. } = &output.kind else {
+            continue;
+        };
+        if router.has_sink(&output.id) {
+            continue;
+        }
+        let server = addr(host, port).map(|addr| match addr {
+            None => streamer.clone(),
+            Some(addr) => streamers
+                .lock()
+                .unwrap()
+                .entry(addr)
+                .or_insert_with(|| {
+                    let server = Arc::new(HttpStreamer::bind(addr));
+                    if let Err(e) = rt.block_on(server.start()) {
+                        error!("Failed to start a stream server: {}", e);
+                    }
+                    server
+                })
+                .clone(),
+        });
+        let sink = server.and_then(|server| HttpOutput::new(output, &server));
+        if sink.is_ok() {
+            info!(
+                "Output '{}' streams at {}",
+                output.id,
+                output.stream_path().unwrap_or_default()
+            );
+        }
+        router.attach_sink(
+            &output.id,
+            sink.map(|sink| Box::new(sink) as Box<dyn PcmSink>),
+        );
+    }
 }
 
-#[derive(Debug, Serialize, Clone)]
-struct OutputHealth {
-    id: String,
-    kind: String,
-    room: Option<String>,
-    healthy: bool,
+// Log lints, deprecated versions and other warnings found while loading
+fn log_config_warnings(config: &Config) {
+    for warning in &config.warnings {
+        let file = warning.file.as_deref().unwrap_or("config");
+        let path = warning.path.as_deref().unwrap_or("");
+        match &warning.suggestion {
+            Some(suggestion) => warn!(
+                "Config warning [{}] in {} at {}: {} ({})",
+                warning.code, file, path, warning.message, suggestion
+            ),
+            None => warn!(
+                "Config warning [{}] in {} at {}: {}",
+                warning.code, file, path, warning.message
+            ),
+        }
+    }
 }
 
 fn main() -> Result<(), MuxError> {
@@ -89,6 +232,10 @@ fn main() -> Result<(), MuxError> {
     let config = Config::load(&args.config)?;
 
     info!("Configuration loaded successfully!");
+    for path in config.files.iter().skip(1) {
+        info!("Included configuration: {}", path.display());
+    }
+    log_config_warnings(&config);
     info!("Inputs: {}", config.inputs.len());
     info!("Outputs: {}", config.outputs.len());
     info!("Routes: {}", config.routes.len());
@@ -106,27 +253,45 @@ fn main() -> Result<(), MuxError> {
         ));
     }
 
+    // Check credentials and socket settings before starting anything
+    let auth = Arc::new(
+        Auth::load(&args.auth)
+            .map_err(|e| MuxError::Internal(format!("Failed to load credentials: {}", e)))?,
+    );
+    let socket_permissions = SocketPermissions::resolve(
+        args.socket_owner.as_deref(),
+        args.socket_group.as_deref(),
+        args.socket_mode.as_deref(),
+    )
+    .map_err(MuxError::Internal)?;
+
     // Create the Sonos manager
-    let mut sonos_manager = SonosManager::new();
+    let mut sonos_manager = SonosManager::with_secrets(config.secrets.clone());
 
     // Add all Sonos outputs to the manager
     for output_config in &config.outputs {
-        if output_config.kind == "sonos" {
-            if let Some(room) = &output_config.room {
-                info!("Adding Sonos room: {}", room);
-                sonos_manager.add_room(room.clone(), output_config.buffer_sec);
-            } else {
-                warn!(
-                    "Sonos output '{}' has no room name, skipping",
-                    output_config.id
-                );
-            }
+        if let OutputKind::Sonos {
+            room, buffer_sec, ..
+        } = &output_config.kind
+        {
+            info!("Adding Sonos room: {}", config.redact(room));
+            sonos_manager.add_room(room.clone(), Some(*buffer_sec));
         }
     }
 
     // Wrap the manager in Arc<Mutex> for sharing
     let sonos_manager = Arc::new(tokio::sync::Mutex::new(sonos_manager));
 
+    // Other UPnP renderers, found by name or UDN
+    let mut upnp_manager = UpnpManager::new();
+    for output_config in &config.outputs {
+        if let Some(target) = Target::from_output(output_config) {
+            info!("Adding UPnP renderer: {}", target);
+            upnp_manager.add_output(UpnpOutput::new(output_config.id.clone(), target));
+        }
+    }
+    let upnp_manager = Arc::new(upnp_manager);
+
     // Create tokio runtime
     let rt = Arc::new(
         Runtime::new()
@@ -152,25 +317,63 @@ fn main() -> Result<(), MuxError> {
         manager.start_keep_alive_task()
     });
 
-    // For Sprint 2+4, we'll implement a pipeline:
-    // 1. Create an audio input from the first input in the config
-    // 2. Create an MP3 encoder
-    // 3. Create an HTTP streamer
-    // 4. Set up Sonos to play the stream
+    // The pipeline:
+    // 1. Mix the inputs routed to each output, recording those that are files
+    // 2. Encode one output's mix, as that output asks
+    // 3. Serve it with the HTTP streamer
+    // 4. Set up Sonos and other UPnP renderers to play the stream
 
-    // For now, use the first input
-    let input_config = &config.inputs[0];
-    info!("Using input: {} ({})", input_config.id, input_config.kind);
+    // Create the mixers, one per routed output
+    let router = Router::new(&config)?;
+    info!(
+        "Mixing {} input(s) into {} output(s)",
+        config.inputs.len(),
+        router.output_mixers.len()
+    );
 
-    // Create the audio input
-    let mut audio_input = mux_core::input::create_input(input_config)?;
+    // All rooms play the same stream for now: the mix of the first routed
+    // speaker, or of any other routed output that isn't a recorder
+    let stream_output = reload::stream_output(&config).map(|output| output.id.clone());
+    match &stream_output {
+        Some(id) => info!("Streaming the mix for output: {}", id),
+        None => warn!("No routes to any output, streaming nothing"),
+    }
 
-    // Create the MP3 encoder
-    let mut encoder = Lame::new(128)?; // 128 kbps
+    // Rooms and renderers play the stream the way the streamed output asks,
+    // MP3 at 128 kbps if it doesn't say
+    let (codec, bitrate_kbps, quality) = stream_output
+        .as_ref()
+        .and_then(|id| config.outputs.iter().find(|output| &output.id == id))
+        .and_then(|output| output.kind.encoding())
+        .unwrap_or((Codec::Mp3, 128, None));
+    let mut encoder = create_encoder(codec, bitrate_kbps, quality)?;
 
     // Create the HTTP streamer
-    let http_port = 8000; // Default port
-    let streamer = HttpStreamer::new(http_port);
+    let http_port = args.stream_port;
+    let streamer = Arc::new(HttpStreamer::bind(SocketAddr::new(
+        args.stream_bind,
+        http_port,
+    )));
+    streamer
+        .add_main(codec, encoder.header())
+        .map_err(MuxError::Stream)?;
+    let stream_path = main_path(codec);
+
+    // `http` outputs stream from the main server, or from one of their own
+    // when they name another port; reloads attach them the same way
+    let streamers: Streamers = Arc::default();
+    let attach_sinks: AttachSinks = {
+        let streamer = streamer.clone();
+        let streamers = streamers.clone();
+        let rt = rt.clone();
+        Arc::new(move |previous, config, router| {
+            attach_http_outputs(
+                previous, config, router, &streamer, http_port, &streamers, &rt,
+            )
+        })
+    };
+    let mut router = router;
+    attach_sinks(None, &config, &mut router);
 
     // Start the HTTP streamer
     rt_health.block_on(async {
@@ -180,8 +383,8 @@ fn main() -> Result<(), MuxError> {
 
     info!("HTTP streamer started on port {}", http_port);
     info!(
-        "Stream available at http://localhost:{}/stream.mp3",
-        http_port
+        "Stream available at http://localhost:{}{}",
+        http_port, stream_path
     );
 
     // Get the stream URL for Sonos
@@ -189,163 +392,140 @@ fn main() -> Result<(), MuxError> {
         .map(|h| h.to_string_lossy().to_string())
         .unwrap_or_else(|_| "localhost".to_string());
 
-    let stream_url = format!("http://{}:{}/stream.mp3", hostname, http_port);
+    let stream_url = format!("http://{}:{}{}", hostname, http_port, stream_path);
     info!("Full stream URL: {}", stream_url);
 
     // Set the stream URL on all Sonos outputs
     rt_health.block_on(async {
         let manager = sonos_manager.lock().await;
         for output_config in &config.outputs {
-            if output_config.kind == "sonos" {
-                if let Some(room) = &output_config.room {
-                    info!("Setting stream for room '{}' to {}", room, stream_url);
-                    if let Err(e) = manager.set_stream(room, &stream_url).await {
-                        warn!("Failed to set stream for room '{}': {}", room, e);
-                    }
+            if let OutputKind::Sonos { room, .. } = &output_config.kind {
+                info!(
+                    "Setting stream for room '{}' to {}",
+                    config.redact(room),
+                    stream_url
+                );
+                if let Err(e) = manager.set_stream(room, &stream_url).await {
+                    warn!(
+                        "Failed to set stream for room '{}': {}",
+                        config.redact(room),
+                        e
+                    );
                 }
             }
         }
     });
 
-    // Create channels for the pipeline
-    let (audio_sender, audio_receiver) = bounded::<AudioBuffer>(10);
+    // Point the UPnP renderers at it too, and keep them playing it
+    let upnp_keep_alive_tx = (!upnp_manager.is_empty()).then(|| {
+        rt_health.block_on(async {
+            upnp_manager.start_all(&stream_url).await;
+            upnp_manager.start_keep_alive_task()
+        })
+    });
 
     // Flag to signal shutdown
     let running = Arc::new(AtomicBool::new(true));
     let running_clone = running.clone();
 
-    // Start the audio input
-    audio_input.start(audio_sender)?;
+    // Start the inputs; the admin server reads their state for status
+    router.start()?;
+    let router = Arc::new(std::sync::Mutex::new(router));
+    let router_processor = router.clone();
 
     // Start time for uptime tracking
     let start_time = Instant::now();
 
-    // Create a snapshot of outputs for health check
-    let config_outputs = config.outputs.clone();
-
-    // Set up health check endpoint
-    let sonos_manager_health = sonos_manager.clone();
-
-    let health_check = warp::path("healthz").and_then(move || {
-        let uptime_sec = start_time.elapsed().as_secs();
-        let manager_clone = sonos_manager_health.clone();
-        let outputs_clone = config_outputs.clone();
-
-        async move {
-            let manager = manager_clone.lock().await;
-            let sonos_status = manager.health_status().await;
-
-            let mut outputs = Vec::new();
-            for output_config in &outputs_clone {
-                let healthy = match output_config.kind.as_str() {
-                    "sonos" => {
-                        if let Some(room) = &output_config.room {
-                            sonos_status.iter().any(|s| &s.room == room && s.healthy)
-                        } else {
-                            false
-                        }
-                    }
-                    _ => true, // Other output types (like HTTP) are assumed healthy
-                };
-
-                outputs.push(OutputHealth {
-                    id: output_config.id.clone(),
-                    kind: output_config.kind.clone(),
-                    room: output_config.room.clone(),
-                    healthy,
-                });
-            }
-
-            let all_healthy = outputs.iter().all(|o| o.healthy);
-
-            let response = HealthResponse {
-                status: if all_healthy {
-                    "ok".to_string()
-                } else {
-                    "degraded".to_string()
-                },
-                version: mux_core::version().to_string(),
-                uptime_sec,
-                outputs,
-            };
-
-            Ok::<_, warp::Rejection>(warp::reply::json(&response))
-        }
-    });
-
-    // Start the health check server
+    // Start the health and metrics API
+    let metrics = Arc::new(Metrics::new());
     let (health_tx, health_rx) = oneshot::channel();
     // Use Arc to share the sender between threads
     let health_tx = Arc::new(tokio::sync::Mutex::new(Some(health_tx)));
 
-    let health_server = async move {
-        let routes = health_check.with(warp::cors().allow_any_origin());
-        let (addr, server) =
-            warp::serve(routes).bind_with_graceful_shutdown(([0, 0, 0, 0], 8080), async move {
-                health_rx.await.ok();
-            });
-
-        info!(
-            "Health check endpoint available at http://{}:{}/healthz",
-            hostname,
-            addr.port()
-        );
-        server.await;
-    };
-
-    rt_health.spawn(health_server);
+    let http_server = HttpServer::new(
+        sonos_manager.clone(),
+        metrics.clone(),
+        start_time,
+        auth.clone(),
+        args.cors_origins.clone(),
+    )
+    .with_router(router.clone())
+    .with_upnp(upnp_manager.clone());
+    let http_addr = SocketAddr::new(args.http_bind, args.http_port);
+    rt_health.spawn(async move {
+        if let Err(e) = http_server.run(http_addr, &hostname, health_rx).await {
+            error!("Failed to start the HTTP API: {}", e);
+        }
+    });
 
     // Create a thread to process audio and send to the streamer
+    let streamer_processor = streamer.clone();
+    let stream_output_processor = stream_output.clone();
+    let streamers_processor = streamers.clone();
     let processor_thread = thread::spawn(move || {
+        let streamer = streamer_processor;
+        let stream_output = stream_output_processor;
         let mut last_stats = Instant::now();
         let mut total_bytes_sent = 0;
-        let mut max_loudness = -60.0f32;
+        let mut max_loudness = Level::SILENT.rms_db;
 
         while running_clone.load(Ordering::SeqCst) {
-            // Receive audio data
-            match audio_receiver.recv_timeout(Duration::from_millis(100)) {
-                Ok(buffer) => {
-                    // Calculate loudness
-                    let loudness = calculate_loudness(&buffer);
-                    max_loudness = max_loudness.max(loudness);
-
-                    // Encode to MP3
-                    match encoder.encode(&buffer) {
-                        Ok(mp3_data) => {
-                            // Get the length before sending
-                            let data_len = mp3_data.len();
-
-                            // Send to streamer
-                            if let Err(e) = streamer.send(mp3_data) {
-                                error!("Failed to send MP3 data: {}", e);
-                            } else {
-                                total_bytes_sent += data_len;
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to encode audio: {}", e);
-                        }
-                    }
+            // Mix every output, so their levels stay current and recorders
+            // get their audio, keeping the streamed one
+            let (streamed, title) = {
+                let mut router = router_processor.lock().unwrap();
+                let streamed = router
+                    .mix()
+                    .into_iter()
+                    .find(|(id, _)| stream_output.as_ref() == Some(id))
+                    .map(|(_, buffer)| buffer);
+                let title = stream_output.as_deref().and_then(|id| router.title(id));
+                (streamed, title)
+            };
 
-                    // Log stats every 10 seconds
-                    let now = Instant::now();
-                    if now.duration_since(last_stats) >= Duration::from_secs(10) {
-                        info!(
-                            "Stats: loudness={:.1} dB, bytes_sent={} bytes",
-                            max_loudness, total_bytes_sent
-                        );
-                        last_stats = now;
-                        max_loudness = -60.0;
+            // Rooms that ask for ICY metadata show what the mix is playing
+            if let Some(title) = &title {
+                streamer.set_title(title);
+            }
+
+            let Some(buffer) = streamed else {
+                // No data ready yet, wait a bit
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            };
+
+            // Calculate loudness
+            let loudness = Level::measure(&buffer).rms_db;
+            max_loudness = max_loudness.max(loudness);
+
+            // Encode it for the stream
+            match encoder.encode(&buffer) {
+                Ok(data) => {
+                    // Get the length before sending
+                    let data_len = data.len();
+
+                    // Send to streamer
+                    if let Err(e) = streamer.send(data) {
+                        error!("Failed to send encoded audio: {}", e);
+                    } else {
+                        total_bytes_sent += data_len;
                     }
                 }
-                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
-                    // Timeout, just continue
-                }
-                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
-                    // Channel closed, exit the loop
-                    break;
+                Err(e) => {
+                    error!("Failed to encode audio: {}", e);
                 }
             }
+
+            // Log stats every 10 seconds
+            let now = Instant::now();
+            if now.duration_since(last_stats) >= Duration::from_secs(10) {
+                info!(
+                    "Stats: loudness={:.1} dB, bytes_sent={} bytes",
+                    max_loudness, total_bytes_sent
+                );
+                last_stats = now;
+                max_loudness = Level::SILENT.rms_db;
+            }
         }
 
         // Flush the encoder
@@ -355,9 +535,18 @@ fn main() -> Result<(), MuxError> {
             }
         }
 
-        // Stop the streamer
+        // Stop the streamers
+        let servers: Vec<_> = streamers_processor
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
         rt_processor.block_on(async {
             let _ = streamer.stop().await;
+            for server in servers {
+                let _ = server.stop().await;
+            }
         });
     });
 
@@ -365,22 +554,60 @@ fn main() -> Result<(), MuxError> {
     let health_tx_clone = health_tx.clone();
 
     // Set up the admin server
-    let (reload_tx, mut reload_rx) = mpsc::channel::<Config>(10);
-    let reload_trigger = Arc::new(Mutex::new(Some(reload_tx)));
+    let (reload_tx, mut reload_rx) = mpsc::channel::<ReloadRequest>(10);
+    let reload_trigger: ReloadTrigger = Arc::new(Mutex::new(Some(reload_tx)));
+
+    // The running configuration, which new ones are diffed against
+    let current_config = Arc::new(Mutex::new(config.clone()));
+
+    // The last applied configurations, for rollback
+    let history = match History::open(&args.state_dir, args.history_size) {
+        Ok(history) => Some(history),
+        Err(e) => {
+            warn!(
+                "Config history disabled, cannot use state directory {}: {}",
+                args.state_dir.display(),
+                e
+            );
+            None
+        }
+    };
+    let history = Arc::new(Mutex::new(history));
+    let reloader = Reloader::new(
+        current_config.clone(),
+        router.clone(),
+        attach_sinks,
+        history.clone(),
+        Duration::from_millis(args.rollback_grace_ms),
+    );
+    rt.block_on(reloader.record(&config, ReloadSource::Startup));
 
     // Start the admin server
     let admin_server = admin::AdminServer::new(
         Some(args.config.to_string_lossy().to_string()),
         sonos_manager.clone(),
         reload_trigger.clone(),
-    );
+        current_config.clone(),
+        history.clone(),
+        metrics.clone(),
+        router.clone(),
+    )
+    .with_auth(auth)
+    .with_upnp(upnp_manager.clone());
+    let admin_server = match stream_output {
+        Some(output) => admin_server.with_stream(output, streamer),
+        None => admin_server,
+    };
 
     // Start the Unix socket admin server
     if !args.socket.is_empty() {
         let admin_server_clone = admin_server.clone();
         let socket_path = args.socket.clone();
         rt_health.spawn(async move {
-            if let Err(e) = admin_server_clone.start_unix(&socket_path).await {
+            if let Err(e) = admin_server_clone
+                .start_unix(&socket_path, &socket_permissions)
+                .await
+            {
                 error!("Failed to start Unix socket admin server: {}", e);
             }
         });
@@ -389,9 +616,9 @@ fn main() -> Result<(), MuxError> {
     // Start the TCP admin server if port is non-zero
     if args.admin_port > 0 {
         let admin_server_clone = admin_server.clone();
-        let admin_port = args.admin_port;
+        let admin_addr = SocketAddr::new(args.admin_bind, args.admin_port);
         rt_health.spawn(async move {
-            if let Err(e) = admin_server_clone.start_tcp(admin_port).await {
+            if let Err(e) = admin_server_clone.start_tcp(admin_addr).await {
                 error!("Failed to start TCP admin server: {}", e);
             }
         });
@@ -414,6 +641,7 @@ fn main() -> Result<(), MuxError> {
 
         let reload_trigger = reload_trigger_sighup.clone();
         let config_path = config_path.clone();
+        let metrics = metrics.clone();
 
         rt_sighup.spawn(async move {
             loop {
@@ -422,26 +650,53 @@ fn main() -> Result<(), MuxError> {
                 info!("Received SIGHUP, reloading configuration...");
 
                 // Try to load the config
+                // Includes are resolved again, so changes to any included
+                // file are picked up too
                 match Config::load(&config_path) {
                     Ok(config) => {
-                        // Send the config to the reload channel
-                        let reload_trigger = reload_trigger.lock().await;
-                        if let Some(trigger) = &*reload_trigger {
-                            if let Err(e) = trigger.send(config).await {
-                                error!("Failed to send reload: {}", e);
-                            } else {
-                                info!("Configuration reload triggered");
-                            }
+                        info!(
+                            "Read configuration from {} file(s): {}",
+                            config.files.len(),
+                            config
+                                .files
+                                .iter()
+                                .map(|path| path.display().to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        );
+                        // Send the config to the reload loop, and count how
+                        // applying it went
+                        match reload::request(&reload_trigger, config, ReloadSource::Sighup).await {
+                            Ok(outcome) => metrics.record_reload(ReloadSource::Sighup, outcome),
+                            Err(e) => error!("Failed to reload: {}", e),
                         }
                     }
                     Err(e) => {
                         error!("Failed to reload configuration: {}", e);
+                        metrics.record_reload(ReloadSource::Sighup, ReloadOutcome::Invalid);
                     }
                 }
             }
         });
     }
 
+    // Watch the config file and its includes for changes
+    if args.watch {
+        let watcher = watcher::ConfigWatcher::new(
+            args.config.clone(),
+            Duration::from_millis(args.watch_debounce_ms),
+            reload_trigger.clone(),
+            metrics.clone(),
+        );
+        let config = config.clone();
+        info!("Watching configuration files for changes");
+        rt.spawn(async move {
+            if let Err(e) = watcher.run(&config).await {
+                error!("Failed to watch configuration files: {}", e);
+            }
+        });
+    }
+
     // Clone running for Ctrl+C handler
     let running_ctrlc = running.clone();
     let reload_trigger_ctrlc = reload_trigger.clone();
@@ -451,8 +706,11 @@ fn main() -> Result<(), MuxError> {
         info!("Received Ctrl+C, shutting down...");
         running_ctrlc.store(false, Ordering::SeqCst);
 
-        // Stop the keep-alive task
+        // Stop the keep-alive tasks
         drop(keep_alive_tx.send(()));
+        if let Some(tx) = &upnp_keep_alive_tx {
+            drop(tx.send(()));
+        }
 
         // Stop the health server - take the sender out of the Option
         let _ = rt_health.block_on(async {
@@ -477,24 +735,19 @@ fn main() -> Result<(), MuxError> {
     thread::spawn(move || {
         rt_reload.block_on(async {
             while running_reload.load(Ordering::SeqCst) {
-                if let Some(new_config) = reload_rx.recv().await {
-                    info!("Received new configuration, applying...");
-
-                    // Here we would apply the new configuration
-                    // For now, let's just log it
+                if let Some((new_config, source, done)) = reload_rx.recv().await {
+                    info!(
+                        "Received new configuration ({}), applying...",
+                        source.as_str()
+                    );
+                    log_config_warnings(&new_config);
                     info!(
                         "New configuration has {} inputs, {} outputs, and {} routes",
                         new_config.inputs.len(),
                         new_config.outputs.len(),
                         new_config.routes.len()
                     );
-
-                    // TODO: Implement actual hot-reloading here
-                    // This would involve:
-                    // 1. Updating the audio input(s) if needed
-                    // 2. Updating the Sonos manager with new outputs
-                    // 3. Updating routing configuration
-                    // All while maintaining audio continuity
+                    let _ = done.send(reloader.apply(new_config, source).await);
                 }
             }
         });
@@ -505,8 +758,11 @@ fn main() -> Result<(), MuxError> {
         .join()
         .map_err(|_| MuxError::Internal("Failed to join processor thread".to_string()))?;
 
-    // Stop the audio input
-    audio_input.stop()?;
+    // Stop the inputs
+    router
+        .lock()
+        .map_err(|_| MuxError::Internal("Router lock poisoned".to_string()))?
+        .stop()?;
 
     info!("Shutdown complete");
     Ok(())
@@ -0,0 +1,233 @@
+use mux_core::OutputStatus;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What triggered a configuration reload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReloadSource {
+    /// The configuration the daemon started with
+    Startup,
+    Sighup,
+    Reload,
+    Apply,
+    Watcher,
+    /// A `rollback` command, or a new configuration that failed to start
+    Rollback,
+}
+
+impl ReloadSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReloadSource::Startup => "startup",
+            ReloadSource::Sighup => "sighup",
+            ReloadSource::Reload => "reload",
+            ReloadSource::Apply => "apply",
+            ReloadSource::Watcher => "watcher",
+            ReloadSource::Rollback => "rollback",
+        }
+    }
+}
+
+/// How a configuration reload ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReloadOutcome {
+    /// The new configuration was valid and handed to the daemon
+    Success,
+    /// The new configuration was rejected; the previous one stays active
+    Invalid,
+    /// The files changed on disk but the configuration they describe didn't
+    Unchanged,
+    /// The new configuration failed to start and the previous one was restored
+    RolledBack,
+}
+
+impl ReloadOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReloadOutcome::Success => "success",
+            ReloadOutcome::Invalid => "invalid",
+            ReloadOutcome::Unchanged => "unchanged",
+            ReloadOutcome::RolledBack => "rolled_back",
+        }
+    }
+}
+
+/// Daemon counters, exposed in the Prometheus text format on `/metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    reloads: Mutex<BTreeMap<(ReloadSource, ReloadOutcome), u64>>,
+    // Time and result of the last reload that wasn't a no-op
+    last_reload: Mutex<Option<(SystemTime, bool)>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_reload(&self, source: ReloadSource, outcome: ReloadOutcome) {
+        *self
+            .reloads
+            .lock()
+            .unwrap()
+            .entry((source, outcome))
+            .or_default() += 1;
+
+        if outcome != ReloadOutcome::Unchanged {
+            *self.last_reload.lock().unwrap() =
+                Some((SystemTime::now(), outcome == ReloadOutcome::Success));
+        }
+    }
+
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP muxd_config_reloads_total Configuration reloads by trigger and outcome"
+        );
+        let _ = writeln!(out, "# TYPE muxd_config_reloads_total counter");
+        for ((source, outcome), count) in self.reloads.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "muxd_config_reloads_total{{source=\"{}\",outcome=\"{}\"}} {}",
+                source.as_str(),
+                outcome.as_str(),
+                count
+            );
+        }
+
+        if let Some((time, success)) = *self.last_reload.lock().unwrap() {
+            let timestamp = time
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "# HELP muxd_config_last_reload_success Whether the last configuration reload succeeded"
+            );
+            let _ = writeln!(out, "# TYPE muxd_config_last_reload_success gauge");
+            let _ = writeln!(out, "muxd_config_last_reload_success {}", success as u8);
+            let _ = writeln!(
+                out,
+                "# HELP muxd_config_last_reload_timestamp_seconds When the last configuration reload happened"
+            );
+            let _ = writeln!(
+                out,
+                "# TYPE muxd_config_last_reload_timestamp_seconds gauge"
+            );
+            let _ = writeln!(
+                out,
+                "muxd_config_last_reload_timestamp_seconds {:.3}",
+                timestamp
+            );
+        }
+
+        out
+    }
+}
+
+// Name, type, help and value of a metric with one sample per output
+type Family = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&OutputStatus) -> Option<f64>,
+);
+
+/// Render counters for the outputs the daemon writes to itself, like
+/// recorders and `null` outputs
+pub fn render_outputs(outputs: &[OutputStatus]) -> String {
+    let mut out = String::new();
+    let families: [Family; 3] = [
+        (
+            "muxd_output_frames_total",
+            "counter",
+            "Stereo frames taken by an output",
+            |o| o.frames.map(|f| f as f64),
+        ),
+        (
+            "muxd_output_underruns_total",
+            "counter",
+            "Times an output's mix arrived too late to play without a gap",
+            |o| o.underruns.map(|u| u as f64),
+        ),
+        (
+            "muxd_output_jitter_seconds",
+            "gauge",
+            "Smoothed variation in when an output's mix arrives",
+            |o| o.jitter_ms.map(|j| j / 1000.0),
+        ),
+    ];
+    for (name, kind, help, value) in families {
+        let values: Vec<(&str, f64)> = outputs
+            .iter()
+            .filter_map(|o| value(o).map(|v| (o.id.as_str(), v)))
+            .collect();
+        if values.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (id, value) in values {
+            let _ = writeln!(out, "{}{{output=\"{}\"}} {}", name, id, value);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_reloads() {
+        let metrics = Metrics::new();
+        assert!(!metrics.render().contains("last_reload"));
+
+        metrics.record_reload(ReloadSource::Watcher, ReloadOutcome::Success);
+        metrics.record_reload(ReloadSource::Watcher, ReloadOutcome::Invalid);
+        metrics.record_reload(ReloadSource::Watcher, ReloadOutcome::Invalid);
+        metrics.record_reload(ReloadSource::Apply, ReloadOutcome::Unchanged);
+
+        let out = metrics.render();
+        assert!(
+            out.contains("muxd_config_reloads_total{source=\"watcher\",outcome=\"invalid\"} 2\n")
+        );
+        assert!(
+            out.contains("muxd_config_reloads_total{source=\"apply\",outcome=\"unchanged\"} 1\n")
+        );
+        assert!(out.contains("muxd_config_last_reload_success 0\n"));
+    }
+
+    #[test]
+    fn test_render_outputs() {
+        let config = mux_core::Config::check(
+            r#"
+[[outputs]]
+id = "bench"
+kind = "null"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+"#,
+        )
+        .into_result()
+        .unwrap();
+        let router = mux_core::Router::new(&config).unwrap();
+
+        let out = render_outputs(&router.outputs());
+        assert!(out.contains("muxd_output_frames_total{output=\"bench\"} 0\n"));
+        assert!(out.contains("muxd_output_underruns_total{output=\"bench\"} 0\n"));
+        assert!(!out.contains("kitchen"));
+        // No buffer has arrived to measure jitter from
+        assert!(!out.contains("jitter"));
+    }
+}
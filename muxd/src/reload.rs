@@ -0,0 +1,481 @@
+use crate::history::History;
+use crate::metrics::{ReloadOutcome, ReloadSource};
+use log::{error, info, warn};
+use mux_core::config::{Change, Plan};
+use mux_core::{Config, InputError, Output, OutputKind, Router};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+/// A configuration to switch to, what triggered it, and where to say how
+/// that went
+pub type ReloadRequest = (Config, ReloadSource, oneshot::Sender<ReloadOutcome>);
+
+/// Where reloads are sent; cleared on shutdown
+pub type ReloadTrigger = Arc<Mutex<Option<mpsc::Sender<ReloadRequest>>>>;
+
+/// Hand `config` to the reload loop and wait until it has been applied or
+/// rolled back
+pub async fn request(
+    trigger: &ReloadTrigger,
+    config: Config,
+    source: ReloadSource,
+) -> Result<ReloadOutcome, String> {
+    // Not held while the reload runs, so shutdown can clear it meanwhile
+    let sender = trigger
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| "Reload trigger not available".to_string())?;
+    let (done, outcome) = oneshot::channel();
+    sender
+        .send((config, source, done))
+        .await
+        .map_err(|e| format!("Failed to send reload: {}", e))?;
+    outcome
+        .await
+        .map_err(|_| "The daemon stopped before the reload finished".to_string())
+}
+
+/// Feeds a router the outputs it can't open by itself, e.g. `http` streams
+/// on the daemon's servers; given the configuration being left, if any, so
+/// what it served can be let go
+pub type AttachSinks = Arc<dyn Fn(Option<&Config>, &Config, &mut Router) + Send + Sync>;
+
+/// Makes new configurations the running one: builds a router for each and
+/// swaps it in for the live one, keeping the previous configuration playing
+/// if its inputs fail to start. Inputs and outputs configured the same keep
+/// running throughout. Only configurations that were swapped in are
+/// recorded in the history
+#[derive(Clone)]
+pub struct Reloader {
+    current: Arc<Mutex<Config>>,
+    router: Arc<std::sync::Mutex<Router>>,
+    attach: AttachSinks,
+    history: Arc<Mutex<Option<History>>>,
+    grace: Duration,
+}
+
+impl Reloader {
+    pub fn new(
+        current: Arc<Mutex<Config>>,
+        router: Arc<std::sync::Mutex<Router>>,
+        attach: AttachSinks,
+        history: Arc<Mutex<Option<History>>>,
+        grace: Duration,
+    ) -> Self {
+        Self {
+            current,
+            router,
+            attach,
+            history,
+            grace,
+        }
+    }
+
+    /// Switch to `config`, returning how that went for the caller to record
+    pub async fn apply(&self, config: Config, source: ReloadSource) -> ReloadOutcome {
+        let mut current = self.current.lock().await;
+        let plan = current.diff(&config);
+        if plan.is_empty() {
+            info!("Configuration unchanged, nothing to apply");
+            return ReloadOutcome::Unchanged;
+        }
+        for change in &plan.changes {
+            info!("Config change: {:?}", change);
+        }
+        if !plan.interrupted.is_empty() {
+            info!("Interrupted rooms: {}", plan.interrupted.join(", "));
+        }
+        if let Err(e) = check_restart(&current, &config) {
+            error!("Not applying the new configuration: {}", e);
+            return ReloadOutcome::Invalid;
+        }
+
+        let swapped = {
+            let router = self.router.clone();
+            let attach = self.attach.clone();
+            let previous = current.clone();
+            let config = config.clone();
+            let grace = self.grace;
+            tokio::task::spawn_blocking(move || {
+                swap(&router, &attach, &previous, &config, &plan, grace)
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("reload failed: {}", e)))
+        };
+        if let Err(e) = swapped {
+            error!(
+                "New configuration failed to start, keeping the previous one: {}",
+                e
+            );
+            return ReloadOutcome::RolledBack;
+        }
+
+        *current = config;
+        self.record(&current, source).await;
+        ReloadOutcome::Success
+    }
+
+    /// Save `config` to the history, if the daemon keeps one
+    pub async fn record(&self, config: &Config, source: ReloadSource) {
+        if let Some(history) = &mut *self.history.lock().await {
+            match history.record(config, source) {
+                Ok(entry) => info!(
+                    "Saved configuration #{} ({}) to history",
+                    entry.id,
+                    source.as_str()
+                ),
+                Err(e) => error!("Failed to save configuration history: {}", e),
+            }
+        }
+    }
+}
+
+/// The output whose mix rooms and renderers play: the first routed speaker,
+/// or any other routed output that isn't a recorder
+pub fn stream_output(config: &Config) -> Option<&Output> {
+    config
+        .outputs
+        .iter()
+        .filter(|output| {
+            config
+                .routes
+                .iter()
+                .any(|route| route.outputs.contains(&output.id))
+        })
+        .filter(|output| !matches!(output.kind, OutputKind::File { .. }))
+        .min_by_key(|output| {
+            !matches!(
+                output.kind,
+                OutputKind::Sonos { .. } | OutputKind::Upnp { .. }
+            )
+        })
+}
+
+/// Why the running daemon can't switch from `previous` to `config`, if it
+/// can't: rooms, renderers and the stream they play are only set up at
+/// startup, so changing them takes a restart
+pub fn check_restart(previous: &Config, config: &Config) -> Result<(), String> {
+    let speakers = |config: &Config| -> Vec<Output> {
+        config
+            .outputs
+            .iter()
+            .filter(|output| {
+                matches!(
+                    output.kind,
+                    OutputKind::Sonos { .. } | OutputKind::Upnp { .. }
+                )
+            })
+            .cloned()
+            .collect()
+    };
+    let (from, to) = (speakers(previous), speakers(config));
+    if let Some(output) = from
+        .iter()
+        .find(|output| !to.contains(output))
+        .or_else(|| to.iter().find(|output| !from.contains(output)))
+    {
+        return Err(format!(
+            "output '{}' is a Sonos room or UPnP renderer, which only changes on a restart",
+            output.id
+        ));
+    }
+
+    let streamed = |config: &Config| {
+        stream_output(config).map(|output| (output.id.clone(), output.kind.encoding()))
+    };
+    if streamed(previous) != streamed(config) {
+        return Err(format!(
+            "the stream rooms play would change from output '{}' to '{}', which only happens on a restart",
+            stream_output(previous).map_or("none", |output| &output.id),
+            stream_output(config).map_or("none", |output| &output.id)
+        ));
+    }
+    Ok(())
+}
+
+// How often the inputs a reload starts are checked during the grace period
+const GRACE_POLL: Duration = Duration::from_millis(50);
+
+// Build a router for `config` and swap it in for the live one. Inputs the
+// plan adds or restarts must be created, start, and keep playing for `grace`;
+// otherwise a router for `previous` is swapped back in
+fn swap(
+    live: &std::sync::Mutex<Router>,
+    attach: &AttachSinks,
+    previous: &Config,
+    config: &Config,
+    plan: &Plan,
+    grace: Duration,
+) -> Result<(), String> {
+    let next = Router::without_sinks(config).map_err(|e| e.to_string())?;
+    let started: Vec<&str> = plan
+        .changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::InputAdded { id, .. } | Change::InputRestarted { id, .. } => Some(id.as_str()),
+            _ => None,
+        })
+        .collect();
+    for id in &started {
+        if let Some(e) = next.input_error(id) {
+            return Err(format!("input '{}': {}", id, e));
+        }
+    }
+
+    // Put the previous configuration back the same way
+    let restore = |live: &mut Router| {
+        let result = Router::without_sinks(previous)
+            .and_then(|restored| replace(live, restored, attach, config, previous));
+        if let Err(e) = result {
+            error!("Failed to restart the previous configuration: {}", e);
+        }
+    };
+
+    {
+        let mut live = live.lock().unwrap();
+        if let Err(e) = replace(&mut live, next, attach, previous, config) {
+            restore(&mut live);
+            return Err(e.to_string());
+        }
+    }
+
+    // The new router plays meanwhile, so the mix carries on while the
+    // inputs it started are watched
+    let deadline = Instant::now() + grace;
+    loop {
+        let mut live = live.lock().unwrap();
+        let failure = started.iter().find_map(|id| {
+            live.input_failure(id)
+                .map(|e| format!("input '{}': {}", id, e))
+        });
+        if let Some(e) = failure {
+            restore(&mut live);
+            return Err(e);
+        }
+        drop(live);
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        std::thread::sleep(GRACE_POLL.min(deadline - now));
+    }
+}
+
+// Make `next` the live router. Inputs and outputs configured the same carry
+// on; the rest are stopped before their replacements start, as devices and
+// mounts take one user at a time
+fn replace(
+    live: &mut Router,
+    mut next: Router,
+    attach: &AttachSinks,
+    from: &Config,
+    to: &Config,
+) -> Result<(), InputError> {
+    next.keep_inputs(live);
+    next.keep_sinks(live);
+    if let Err(e) = live.stop() {
+        warn!("Failed to stop the previous inputs: {}", e);
+    }
+    attach(Some(from), to, &mut next);
+    next.open_sinks();
+    let started = next.start();
+    *live = next;
+    started
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+"#;
+
+    // A reloader running `config`, with a short grace period
+    fn reloader(dir: &TempDir, config: &str) -> (Reloader, Arc<std::sync::Mutex<Router>>) {
+        let running = Config::check(config).into_result().unwrap();
+        let mut router = Router::new(&running).unwrap();
+        router.start().unwrap();
+        let router = Arc::new(std::sync::Mutex::new(router));
+        let reloader = Reloader::new(
+            Arc::new(Mutex::new(running)),
+            router.clone(),
+            Arc::new(|_: Option<&Config>, _: &Config, _: &mut Router| {}),
+            Arc::new(Mutex::new(Some(History::open(dir.path(), 10).unwrap()))),
+            Duration::from_millis(200),
+        );
+        (reloader, router)
+    }
+
+    #[tokio::test]
+    async fn test_rolls_back_when_inputs_fail() {
+        let dir = TempDir::new().unwrap();
+        let (reloader, router) = reloader(&dir, CONFIG);
+        let running = reloader.current.lock().await.clone();
+        reloader.record(&running, ReloadSource::Startup).await;
+
+        // A new silence input starts fine
+        let good = Config::check(&CONFIG.replace("\"music\"", "\"ambient\""))
+            .into_result()
+            .unwrap();
+        assert_eq!(
+            reloader.apply(good.clone(), ReloadSource::Apply).await,
+            ReloadOutcome::Success
+        );
+        assert_eq!(
+            reloader.apply(good.clone(), ReloadSource::Sighup).await,
+            ReloadOutcome::Unchanged
+        );
+        let inputs = |router: &std::sync::Mutex<Router>| -> Vec<_> {
+            let router = router.lock().unwrap();
+            router
+                .inputs()
+                .into_iter()
+                .map(|input| (input.id, input.running))
+                .collect()
+        };
+        assert_eq!(inputs(&router), vec![("ambient".to_string(), true)]);
+
+        // A file input whose file is missing doesn't
+        let bad = Config::check(&format!(
+            "{}\n[[inputs]]\nid = \"alert\"\nkind = \"file\"\npath = \"{}\"\n",
+            CONFIG,
+            dir.path().join("missing.pcm").display()
+        ))
+        .into_result()
+        .unwrap();
+        assert_eq!(
+            reloader.apply(bad, ReloadSource::Watcher).await,
+            ReloadOutcome::RolledBack
+        );
+        assert!(reloader.current.lock().await.diff(&good).is_empty());
+        assert_eq!(inputs(&router), vec![("ambient".to_string(), true)]);
+
+        let history = reloader.history.lock().await;
+        let sources: Vec<_> = history
+            .as_ref()
+            .unwrap()
+            .entries()
+            .map(|entry| entry.source)
+            .collect();
+        assert_eq!(sources, vec![ReloadSource::Apply, ReloadSource::Startup]);
+        drop(history);
+        router.lock().unwrap().stop().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keeps_unchanged_inputs_playing() {
+        let dir = TempDir::new().unwrap();
+        let (reloader, router) = reloader(&dir, CONFIG);
+        router
+            .lock()
+            .unwrap()
+            .output_mixers
+            .get_mut("kitchen")
+            .unwrap()
+            .sources[0]
+            .muted = true;
+
+        // Only the gain changes, so the same source carries on
+        let louder = Config::check(&format!("{}gain_db = -6.0\n", CONFIG))
+            .into_result()
+            .unwrap();
+        assert_eq!(
+            reloader.apply(louder, ReloadSource::Apply).await,
+            ReloadOutcome::Success
+        );
+        let router = router.lock().unwrap();
+        let source = &router.output_mixers["kitchen"].sources[0];
+        assert!(source.is_running());
+        assert!(source.muted);
+        assert_eq!(source.gain_db, -6.0);
+        drop(router);
+    }
+
+    #[tokio::test]
+    async fn test_rolls_back_inputs_that_stop() {
+        let dir = TempDir::new().unwrap();
+        let (reloader, router) = reloader(&dir, CONFIG);
+
+        // Mix meanwhile, as the daemon does, so a stopped input is noticed
+        let mixing = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let mixer = {
+            let (router, mixing) = (router.clone(), mixing.clone());
+            std::thread::spawn(move || {
+                while mixing.load(std::sync::atomic::Ordering::SeqCst) {
+                    router.lock().unwrap().mix();
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            })
+        };
+
+        // An empty file starts, then ends without sending anything
+        let empty = dir.path().join("empty.pcm");
+        std::fs::write(&empty, b"").unwrap();
+        let config = Config::check(&format!(
+            "{}\n[[inputs]]\nid = \"alert\"\nkind = \"file\"\npath = \"{}\"\n\n[[routes]]\ninput = \"alert\"\noutputs = [\"kitchen\"]\n",
+            CONFIG,
+            empty.display()
+        ))
+        .into_result()
+        .unwrap();
+        assert_eq!(
+            reloader.apply(config, ReloadSource::Apply).await,
+            ReloadOutcome::RolledBack
+        );
+        mixing.store(false, std::sync::atomic::Ordering::SeqCst);
+        mixer.join().unwrap();
+
+        let router = router.lock().unwrap();
+        let inputs: Vec<_> = router
+            .inputs()
+            .into_iter()
+            .map(|input| (input.id, input.running))
+            .collect();
+        assert_eq!(inputs, vec![("music".to_string(), true)]);
+        drop(router);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_speaker_changes() {
+        let dir = TempDir::new().unwrap();
+        let (reloader, _router) = reloader(&dir, CONFIG);
+        let running = reloader.current.lock().await.clone();
+
+        let moved = Config::check(&CONFIG.replace("\"Kitchen\"", "\"Office\""))
+            .into_result()
+            .unwrap();
+        assert!(check_restart(&running, &moved)
+            .unwrap_err()
+            .contains("output 'kitchen'"));
+        assert_eq!(
+            reloader.apply(moved, ReloadSource::Apply).await,
+            ReloadOutcome::Invalid
+        );
+        assert!(reloader.current.lock().await.diff(&running).is_empty());
+
+        // Recorders can come and go
+        let recorded = Config::check(&format!(
+            "{}\n[[outputs]]\nid = \"archive\"\nkind = \"null\"\n",
+            CONFIG
+        ))
+        .into_result()
+        .unwrap();
+        assert_eq!(check_restart(&running, &recorded), Ok(()));
+    }
+}
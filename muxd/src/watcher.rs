@@ -0,0 +1,248 @@
+use crate::metrics::{Metrics, ReloadOutcome, ReloadSource};
+use crate::reload::{self, ReloadTrigger};
+use log::{debug, error, info};
+use mux_core::Config;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Reloads the configuration when its file, or any file it includes, changes
+/// on disk
+///
+/// Parent directories are watched rather than the files themselves, so
+/// editors that save by renaming a new file over the old one keep working.
+/// Events are debounced until the files have been quiet for a while, which
+/// also covers truncate-then-write saves.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    debounce: Duration,
+    reload_trigger: ReloadTrigger,
+    metrics: Arc<Metrics>,
+}
+
+impl ConfigWatcher {
+    pub fn new(
+        path: PathBuf,
+        debounce: Duration,
+        reload_trigger: ReloadTrigger,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            path,
+            debounce,
+            reload_trigger,
+            metrics,
+        }
+    }
+
+    /// Watch until the reload trigger is cleared, starting from the last
+    /// good configuration
+    pub async fn run(self, config: &Config) -> notify::Result<()> {
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) => {
+                    let _ = event_tx.send(event);
+                }
+                Err(e) => error!("Config watcher error: {}", e),
+            })?;
+
+        let mut watched = Watched::default();
+        watched.update(&mut watcher, &self.path, config)?;
+        let mut current = fingerprint(config);
+
+        while let Some(event) = event_rx.recv().await {
+            if !watched.is_relevant(&event) {
+                continue;
+            }
+
+            // Wait until the files have settled
+            loop {
+                match tokio::time::timeout(self.debounce, event_rx.recv()).await {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => return Ok(()),
+                    Err(_) => break,
+                }
+            }
+
+            if self.reload_trigger.lock().await.is_none() {
+                break;
+            }
+
+            info!("Configuration changed on disk, reloading...");
+            let outcome = match Config::load(&self.path) {
+                Ok(config) => {
+                    if let Err(e) = watched.update(&mut watcher, &self.path, &config) {
+                        error!("Failed to watch included config files: {}", e);
+                    }
+
+                    let next = fingerprint(&config);
+                    if next == current {
+                        info!("Configuration is unchanged, nothing to reload");
+                        ReloadOutcome::Unchanged
+                    } else {
+                        current = next;
+                        self.send(config).await
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "Changed configuration is invalid, keeping the current one: {}",
+                        e
+                    );
+                    ReloadOutcome::Invalid
+                }
+            };
+            self.metrics.record_reload(ReloadSource::Watcher, outcome);
+        }
+
+        Ok(())
+    }
+
+    async fn send(&self, config: Config) -> ReloadOutcome {
+        match reload::request(&self.reload_trigger, config, ReloadSource::Watcher).await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                error!("Failed to reload: {}", e);
+                ReloadOutcome::Invalid
+            }
+        }
+    }
+}
+
+/// The files making up the configuration and the directories watched for them
+#[derive(Default)]
+struct Watched {
+    files: HashSet<PathBuf>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl Watched {
+    // Start watching the directory of every file in `config`; includes may
+    // have changed since the last load
+    fn update(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        path: &Path,
+        config: &Config,
+    ) -> notify::Result<()> {
+        self.files = config.files.iter().cloned().collect();
+        self.files
+            .insert(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+
+        for file in &self.files {
+            let Some(dir) = file.parent() else {
+                continue;
+            };
+            if self.dirs.insert(dir.to_path_buf()) {
+                debug!("Watching {} for config changes", dir.display());
+                watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Whether `event` may change the configuration: a write to one of its
+    // files, or any `.toml` file appearing in a watched directory, which a
+    // glob include might pick up
+    fn is_relevant(&self, event: &Event) -> bool {
+        if matches!(event.kind, EventKind::Access(_)) {
+            return false;
+        }
+        event.paths.iter().any(|path| {
+            self.files.contains(path)
+                || (path.extension().is_some_and(|ext| ext == "toml")
+                    && path.parent().is_some_and(|dir| self.dirs.contains(dir)))
+        })
+    }
+}
+
+// Identifies what a configuration describes, regardless of formatting
+fn fingerprint(config: &Config) -> String {
+    serde_json::to_string(config).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reload::ReloadRequest;
+    use std::fs;
+    use tempfile::TempDir;
+    use tokio::sync::Mutex;
+
+    const CONFIG: &str = r#"
+[[inputs]]
+id = "music"
+kind = "silence"
+
+[[outputs]]
+id = "kitchen"
+kind = "sonos"
+room = "Kitchen"
+
+[[routes]]
+input = "music"
+outputs = ["kitchen"]
+"#;
+
+    // The next configuration sent, answering that it was applied
+    async fn next(rx: &mut mpsc::Receiver<ReloadRequest>) -> Option<Config> {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .ok()
+            .flatten()
+            .map(|(config, _, done)| {
+                let _ = done.send(ReloadOutcome::Success);
+                config
+            })
+    }
+
+    #[tokio::test]
+    async fn test_reloads_on_change() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, CONFIG).unwrap();
+        let config = Config::load(&path).unwrap();
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let metrics = Arc::new(Metrics::new());
+        let watcher = ConfigWatcher::new(
+            path.clone(),
+            Duration::from_millis(100),
+            Arc::new(Mutex::new(Some(tx))),
+            metrics.clone(),
+        );
+        tokio::spawn(async move { watcher.run(&config).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Rename a new file over the old one, like most editors do
+        let temp = dir.path().join(".config.toml.swp");
+        fs::write(&temp, CONFIG.replace("Kitchen", "Office")).unwrap();
+        fs::rename(&temp, &path).unwrap();
+        let config = next(&mut rx).await.expect("config was reloaded");
+        assert_eq!(config.outputs[0].kind.name(), "sonos");
+        assert!(format!("{:?}", config.outputs[0]).contains("Office"));
+
+        // A broken save is rejected and nothing is sent
+        fs::write(&path, "[[inputs]\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(rx.try_recv().is_err());
+
+        // Fixing it is picked up again
+        fs::write(&path, CONFIG).unwrap();
+        assert!(next(&mut rx).await.is_some());
+
+        // The outcome is recorded once the reload answers
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let mut out = metrics.render();
+        while !out.contains("source=\"watcher\",outcome=\"success\"} 2") {
+            assert!(std::time::Instant::now() < deadline, "{}", out);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            out = metrics.render();
+        }
+        assert!(out.contains("source=\"watcher\",outcome=\"invalid\"} 1"));
+    }
+}
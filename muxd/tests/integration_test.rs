@@ -9,7 +9,7 @@ fn test_muxd_starts_and_stops() {
 
     // Start muxd in a separate process
     let mut child = Command::new("cargo")
-        .args(&[
+        .args([
             "run",
             "--bin",
             "muxd",